@@ -0,0 +1,30 @@
+//! Runs a tight counting loop and reports how many instructions per second the emulator executes.
+//!
+//! Run this in release mode: `cargo run --release --example loop_bench`.
+//! Pass `--no-decode-cache` to run it without the decoded-instruction cache.
+
+use std::time::Instant;
+
+use emu::{cpu::Cpu, rom::Rom};
+
+fn main() -> anyhow::Result<()> {
+    #[rustfmt::skip]
+    let program: [u32; 4] = [
+        0x7ff00113, // addi x2, x0, 2047
+        0x00c11113, // slli x2, x2, 12
+        0x00108093, // addi x1, x1, 1
+        0xfe209ee3, // bne  x1, x2, -4
+    ];
+    let mut bytes: Vec<u8> = program.iter().flat_map(|inst| inst.to_le_bytes()).collect();
+    let mut rom = Rom::from_raw(&mut bytes, 0x80000000)?;
+
+    let decode_cache = !std::env::args().any(|arg| arg == "--no-decode-cache");
+    let mut cpu = Cpu::builder(&mut rom).decode_cache(decode_cache).build()?;
+    let start = Instant::now();
+    cpu.run()?;
+    let elapsed = start.elapsed();
+
+    let mips = cpu.instret() as f64 / elapsed.as_secs_f64() / 1_000_000.0;
+    println!("Executed {} instructions in {elapsed:.2?} ({mips:.1} MIPS)", cpu.instret());
+    Ok(())
+}
@@ -0,0 +1,27 @@
+//! Creates a [Cpu] from an owned buffer, so it does not borrow the program's bytes
+//! and can be returned from the function that loaded them.
+
+use emu::{cpu::Cpu, rom::Rom};
+
+/// Loads a program that counts `a0` up to 10 into a ROM that owns its bytes.
+fn load() -> anyhow::Result<Cpu<'static>> {
+    #[rustfmt::skip]
+    let program: [u32; 4] = [
+        0x00a00293, // addi x5, x0, 10
+        0x00150513, // addi x10, x10, 1
+        0xfe551ee3, // bne  x10, x5, -4
+        0x00100073, // ebreak
+    ];
+    let bytes: Vec<u8> = program.iter().flat_map(|inst| inst.to_le_bytes()).collect();
+    let end = 0x80000000 + bytes.len() as u32;
+    let rom = Rom::from_vec(bytes, 0x80000000, end);
+
+    Ok(Cpu::builder(rom).on_ebreak(Box::new(|cpu| cpu.abort())).build()?)
+}
+
+fn main() -> anyhow::Result<()> {
+    let mut cpu = load()?;
+    cpu.run()?;
+    println!("a0 = {}", cpu.registers().a0());
+    Ok(())
+}
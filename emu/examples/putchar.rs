@@ -0,0 +1,36 @@
+//! Maps a putchar device at `0x10000000` and runs a program that prints "Hi" through it.
+
+use emu::{cpu::Cpu, device::Device, rom::Rom, uxlen};
+
+/// A device that prints every byte written to it to stdout.
+struct Putchar;
+
+impl Device for Putchar {
+    fn read(&mut self, _offset: uxlen) -> u8 {
+        0
+    }
+
+    fn write(&mut self, _offset: uxlen, value: u8) {
+        print!("{}", value as char);
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    #[rustfmt::skip]
+    let program: [u32; 8] = [
+        0x00100093, // addi x1, x0, 1
+        0x01c09093, // slli x1, x1, 28
+        0x04800113, // addi x2, x0, 'H'
+        0x00208023, // sb   x2, 0(x1)
+        0x06900113, // addi x2, x0, 'i'
+        0x00208023, // sb   x2, 0(x1)
+        0x00a00113, // addi x2, x0, '\n'
+        0x00208023, // sb   x2, 0(x1)
+    ];
+    let mut bytes: Vec<u8> = program.iter().flat_map(|inst| inst.to_le_bytes()).collect();
+    let mut rom = Rom::from_raw(&mut bytes, 0x80000000)?;
+
+    let mut cpu = Cpu::builder(&mut rom).device(0x10000000..0x10000001, Putchar).build()?;
+    cpu.run()?;
+    Ok(())
+}
@@ -0,0 +1,410 @@
+//! A small assembler for building RV32IM programs in Rust, without hand-encoding instruction words.
+
+use alloc::vec::Vec;
+
+use crate::reg::Reg;
+
+const OP_LUI: u32 = 0b0110111;
+const OP_AUIPC: u32 = 0b0010111;
+const OP_JAL: u32 = 0b1101111;
+const OP_JALR: u32 = 0b1100111;
+const OP_BRANCH: u32 = 0b1100011;
+const OP_LOAD: u32 = 0b0000011;
+const OP_STORE: u32 = 0b0100011;
+const OP_IMM: u32 = 0b0010011;
+const OP: u32 = 0b0110011;
+const OP_SYSTEM: u32 = 0b1110011;
+
+/// Emits the encodings of RV32I, M extension and CSR instructions, in little-endian order.
+///
+/// Immediates are given as the value the instruction uses, so branch and jump offsets are
+/// in bytes relative to the branch or jump itself.
+///
+/// # Panics
+///
+/// The instruction methods panic if an immediate does not fit in its instruction,
+/// or if a branch or jump offset is odd.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Assembler {
+    bytes: Vec<u8>,
+}
+
+impl Assembler {
+    /// Creates an empty program.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The size of the program so far in bytes, which is the offset of the next instruction.
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Whether no instructions have been emitted yet.
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// The encoded program.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    /// Emits a raw instruction word.
+    pub fn word(mut self, word: u32) -> Self {
+        self.bytes.extend_from_slice(&word.to_le_bytes());
+        self
+    }
+
+    pub fn lui(self, rd: Reg, imm: i32) -> Self {
+        self.u_type(OP_LUI, rd, imm)
+    }
+
+    pub fn auipc(self, rd: Reg, imm: i32) -> Self {
+        self.u_type(OP_AUIPC, rd, imm)
+    }
+
+    pub fn jal(self, rd: Reg, offset: i32) -> Self {
+        assert_imm(offset, 21, "jal offset");
+        assert!(offset % 2 == 0, "jal offset {offset} is not a multiple of 2");
+        let imm = offset as u32;
+        let imm = (imm >> 20 & 1) << 31
+            | (imm >> 1 & 0x3ff) << 21
+            | (imm >> 11 & 1) << 20
+            | (imm >> 12 & 0xff) << 12;
+        self.word(imm | reg(rd) << 7 | OP_JAL)
+    }
+
+    pub fn jalr(self, rd: Reg, rs1: Reg, imm: i32) -> Self {
+        self.i_type(OP_JALR, 0b000, rd, rs1, imm)
+    }
+
+    pub fn beq(self, rs1: Reg, rs2: Reg, offset: i32) -> Self {
+        self.b_type(0b000, rs1, rs2, offset)
+    }
+
+    pub fn bne(self, rs1: Reg, rs2: Reg, offset: i32) -> Self {
+        self.b_type(0b001, rs1, rs2, offset)
+    }
+
+    pub fn blt(self, rs1: Reg, rs2: Reg, offset: i32) -> Self {
+        self.b_type(0b100, rs1, rs2, offset)
+    }
+
+    pub fn bge(self, rs1: Reg, rs2: Reg, offset: i32) -> Self {
+        self.b_type(0b101, rs1, rs2, offset)
+    }
+
+    pub fn bltu(self, rs1: Reg, rs2: Reg, offset: i32) -> Self {
+        self.b_type(0b110, rs1, rs2, offset)
+    }
+
+    pub fn bgeu(self, rs1: Reg, rs2: Reg, offset: i32) -> Self {
+        self.b_type(0b111, rs1, rs2, offset)
+    }
+
+    pub fn lb(self, rd: Reg, rs1: Reg, imm: i32) -> Self {
+        self.i_type(OP_LOAD, 0b000, rd, rs1, imm)
+    }
+
+    pub fn lh(self, rd: Reg, rs1: Reg, imm: i32) -> Self {
+        self.i_type(OP_LOAD, 0b001, rd, rs1, imm)
+    }
+
+    pub fn lw(self, rd: Reg, rs1: Reg, imm: i32) -> Self {
+        self.i_type(OP_LOAD, 0b010, rd, rs1, imm)
+    }
+
+    pub fn lbu(self, rd: Reg, rs1: Reg, imm: i32) -> Self {
+        self.i_type(OP_LOAD, 0b100, rd, rs1, imm)
+    }
+
+    pub fn lhu(self, rd: Reg, rs1: Reg, imm: i32) -> Self {
+        self.i_type(OP_LOAD, 0b101, rd, rs1, imm)
+    }
+
+    pub fn sb(self, rs2: Reg, rs1: Reg, imm: i32) -> Self {
+        self.s_type(0b000, rs1, rs2, imm)
+    }
+
+    pub fn sh(self, rs2: Reg, rs1: Reg, imm: i32) -> Self {
+        self.s_type(0b001, rs1, rs2, imm)
+    }
+
+    pub fn sw(self, rs2: Reg, rs1: Reg, imm: i32) -> Self {
+        self.s_type(0b010, rs1, rs2, imm)
+    }
+
+    pub fn addi(self, rd: Reg, rs1: Reg, imm: i32) -> Self {
+        self.i_type(OP_IMM, 0b000, rd, rs1, imm)
+    }
+
+    pub fn slti(self, rd: Reg, rs1: Reg, imm: i32) -> Self {
+        self.i_type(OP_IMM, 0b010, rd, rs1, imm)
+    }
+
+    pub fn sltiu(self, rd: Reg, rs1: Reg, imm: i32) -> Self {
+        self.i_type(OP_IMM, 0b011, rd, rs1, imm)
+    }
+
+    pub fn xori(self, rd: Reg, rs1: Reg, imm: i32) -> Self {
+        self.i_type(OP_IMM, 0b100, rd, rs1, imm)
+    }
+
+    pub fn ori(self, rd: Reg, rs1: Reg, imm: i32) -> Self {
+        self.i_type(OP_IMM, 0b110, rd, rs1, imm)
+    }
+
+    pub fn andi(self, rd: Reg, rs1: Reg, imm: i32) -> Self {
+        self.i_type(OP_IMM, 0b111, rd, rs1, imm)
+    }
+
+    pub fn slli(self, rd: Reg, rs1: Reg, shamt: u32) -> Self {
+        self.shift(0b001, 0b0000000, rd, rs1, shamt)
+    }
+
+    pub fn srli(self, rd: Reg, rs1: Reg, shamt: u32) -> Self {
+        self.shift(0b101, 0b0000000, rd, rs1, shamt)
+    }
+
+    pub fn srai(self, rd: Reg, rs1: Reg, shamt: u32) -> Self {
+        self.shift(0b101, 0b0100000, rd, rs1, shamt)
+    }
+
+    pub fn add(self, rd: Reg, rs1: Reg, rs2: Reg) -> Self {
+        self.r_type(0b000, 0b0000000, rd, rs1, rs2)
+    }
+
+    pub fn sub(self, rd: Reg, rs1: Reg, rs2: Reg) -> Self {
+        self.r_type(0b000, 0b0100000, rd, rs1, rs2)
+    }
+
+    pub fn sll(self, rd: Reg, rs1: Reg, rs2: Reg) -> Self {
+        self.r_type(0b001, 0b0000000, rd, rs1, rs2)
+    }
+
+    pub fn slt(self, rd: Reg, rs1: Reg, rs2: Reg) -> Self {
+        self.r_type(0b010, 0b0000000, rd, rs1, rs2)
+    }
+
+    pub fn sltu(self, rd: Reg, rs1: Reg, rs2: Reg) -> Self {
+        self.r_type(0b011, 0b0000000, rd, rs1, rs2)
+    }
+
+    pub fn xor(self, rd: Reg, rs1: Reg, rs2: Reg) -> Self {
+        self.r_type(0b100, 0b0000000, rd, rs1, rs2)
+    }
+
+    pub fn srl(self, rd: Reg, rs1: Reg, rs2: Reg) -> Self {
+        self.r_type(0b101, 0b0000000, rd, rs1, rs2)
+    }
+
+    pub fn sra(self, rd: Reg, rs1: Reg, rs2: Reg) -> Self {
+        self.r_type(0b101, 0b0100000, rd, rs1, rs2)
+    }
+
+    pub fn or(self, rd: Reg, rs1: Reg, rs2: Reg) -> Self {
+        self.r_type(0b110, 0b0000000, rd, rs1, rs2)
+    }
+
+    pub fn and(self, rd: Reg, rs1: Reg, rs2: Reg) -> Self {
+        self.r_type(0b111, 0b0000000, rd, rs1, rs2)
+    }
+
+    pub fn mul(self, rd: Reg, rs1: Reg, rs2: Reg) -> Self {
+        self.r_type(0b000, 0b0000001, rd, rs1, rs2)
+    }
+
+    pub fn mulh(self, rd: Reg, rs1: Reg, rs2: Reg) -> Self {
+        self.r_type(0b001, 0b0000001, rd, rs1, rs2)
+    }
+
+    pub fn mulhsu(self, rd: Reg, rs1: Reg, rs2: Reg) -> Self {
+        self.r_type(0b010, 0b0000001, rd, rs1, rs2)
+    }
+
+    pub fn mulhu(self, rd: Reg, rs1: Reg, rs2: Reg) -> Self {
+        self.r_type(0b011, 0b0000001, rd, rs1, rs2)
+    }
+
+    pub fn div(self, rd: Reg, rs1: Reg, rs2: Reg) -> Self {
+        self.r_type(0b100, 0b0000001, rd, rs1, rs2)
+    }
+
+    pub fn divu(self, rd: Reg, rs1: Reg, rs2: Reg) -> Self {
+        self.r_type(0b101, 0b0000001, rd, rs1, rs2)
+    }
+
+    pub fn rem(self, rd: Reg, rs1: Reg, rs2: Reg) -> Self {
+        self.r_type(0b110, 0b0000001, rd, rs1, rs2)
+    }
+
+    pub fn remu(self, rd: Reg, rs1: Reg, rs2: Reg) -> Self {
+        self.r_type(0b111, 0b0000001, rd, rs1, rs2)
+    }
+
+    pub fn csrrw(self, rd: Reg, csr: u16, rs1: Reg) -> Self {
+        self.csr(0b001, rd, csr, rs1)
+    }
+
+    pub fn csrrs(self, rd: Reg, csr: u16, rs1: Reg) -> Self {
+        self.csr(0b010, rd, csr, rs1)
+    }
+
+    pub fn csrrc(self, rd: Reg, csr: u16, rs1: Reg) -> Self {
+        self.csr(0b011, rd, csr, rs1)
+    }
+
+    /// Emits `addi zero, zero, 0`, the canonical NOP.
+    pub fn nop(self) -> Self {
+        self.addi(Reg::Zero, Reg::Zero, 0)
+    }
+
+    pub fn ecall(self) -> Self {
+        self.word(OP_SYSTEM)
+    }
+
+    pub fn ebreak(self) -> Self {
+        self.word(1 << 20 | OP_SYSTEM)
+    }
+
+    fn r_type(self, funct3: u32, funct7: u32, rd: Reg, rs1: Reg, rs2: Reg) -> Self {
+        self.word(funct7 << 25 | reg(rs2) << 20 | reg(rs1) << 15 | funct3 << 12 | reg(rd) << 7 | OP)
+    }
+
+    fn csr(self, funct3: u32, rd: Reg, csr: u16, rs1: Reg) -> Self {
+        assert!(csr < 1 << 12, "CSR number {csr:#x} does not fit in 12 bits");
+        self.word((csr as u32) << 20 | reg(rs1) << 15 | funct3 << 12 | reg(rd) << 7 | OP_SYSTEM)
+    }
+
+    fn i_type(self, opcode: u32, funct3: u32, rd: Reg, rs1: Reg, imm: i32) -> Self {
+        assert_imm(imm, 12, "I-type immediate");
+        let imm = imm as u32 & 0xfff;
+        self.word(imm << 20 | reg(rs1) << 15 | funct3 << 12 | reg(rd) << 7 | opcode)
+    }
+
+    fn shift(self, funct3: u32, funct7: u32, rd: Reg, rs1: Reg, shamt: u32) -> Self {
+        assert!(shamt < 32, "shift amount {shamt} does not fit in 5 bits");
+        self.word(
+            funct7 << 25 | shamt << 20 | reg(rs1) << 15 | funct3 << 12 | reg(rd) << 7 | OP_IMM,
+        )
+    }
+
+    fn s_type(self, funct3: u32, rs1: Reg, rs2: Reg, imm: i32) -> Self {
+        assert_imm(imm, 12, "S-type immediate");
+        let imm = imm as u32;
+        self.word(
+            (imm >> 5 & 0x7f) << 25
+                | reg(rs2) << 20
+                | reg(rs1) << 15
+                | funct3 << 12
+                | (imm & 0x1f) << 7
+                | OP_STORE,
+        )
+    }
+
+    fn b_type(self, funct3: u32, rs1: Reg, rs2: Reg, offset: i32) -> Self {
+        assert_imm(offset, 13, "branch offset");
+        assert!(offset % 2 == 0, "branch offset {offset} is not a multiple of 2");
+        let imm = offset as u32;
+        self.word(
+            (imm >> 12 & 1) << 31
+                | (imm >> 5 & 0x3f) << 25
+                | reg(rs2) << 20
+                | reg(rs1) << 15
+                | funct3 << 12
+                | (imm >> 1 & 0xf) << 8
+                | (imm >> 11 & 1) << 7
+                | OP_BRANCH,
+        )
+    }
+
+    /// Emits a U-type instruction. The immediate is the 20-bit field as written in assembly,
+    /// which ends up in the upper 20 bits of the result.
+    fn u_type(self, opcode: u32, rd: Reg, imm: i32) -> Self {
+        assert!((0..1 << 20).contains(&imm), "U-type immediate {imm:#x} does not fit in 20 bits");
+        self.word((imm as u32) << 12 | reg(rd) << 7 | opcode)
+    }
+}
+
+fn reg(reg: Reg) -> u32 {
+    reg.index() as u32
+}
+
+/// Panics if `imm` does not fit in a signed immediate of `bits` bits.
+fn assert_imm(imm: i32, bits: u32, what: &str) {
+    let limit = 1 << (bits - 1);
+    assert!((-limit..limit).contains(&imm), "{what} {imm} does not fit in {bits} bits");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inst::{Instruction, InstructionKind as I};
+
+    /// Decodes every instruction the assembler emitted.
+    fn kinds(program: Assembler) -> Vec<I> {
+        program
+            .into_bytes()
+            .chunks(Instruction::BYTES)
+            .map(|word| Instruction(u32::from_le_bytes(word.try_into().unwrap())).kind())
+            .collect()
+    }
+
+    #[test]
+    fn instructions_decode_as_their_kind() {
+        let (a0, a1, a2) = (Reg::A0, Reg::A1, Reg::A2);
+        #[rustfmt::skip]
+        let program = Assembler::new()
+            .lui(a0, 0x12345).auipc(a0, 0xfffff).jal(Reg::Ra, -2048).jalr(a0, a1, -1)
+            .beq(a0, a1, 4094).bne(a0, a1, -4096).blt(a0, a1, 8).bge(a0, a1, 8).bltu(a0, a1, 8).bgeu(a0, a1, 8)
+            .lb(a0, a1, -1).lh(a0, a1, 2).lw(a0, a1, 2047).lbu(a0, a1, -2048).lhu(a0, a1, 0)
+            .sb(a0, a1, -1).sh(a0, a1, 2).sw(a0, a1, 2047)
+            .addi(a0, a1, 1).slti(a0, a1, 1).sltiu(a0, a1, 1).xori(a0, a1, 1).ori(a0, a1, 1).andi(a0, a1, 1)
+            .slli(a0, a1, 31).srli(a0, a1, 31).srai(a0, a1, 31)
+            .add(a0, a1, a2).sub(a0, a1, a2).sll(a0, a1, a2).slt(a0, a1, a2).sltu(a0, a1, a2)
+            .xor(a0, a1, a2).srl(a0, a1, a2).sra(a0, a1, a2).or(a0, a1, a2).and(a0, a1, a2)
+            .mul(a0, a1, a2).mulh(a0, a1, a2).mulhsu(a0, a1, a2).mulhu(a0, a1, a2)
+            .div(a0, a1, a2).divu(a0, a1, a2).rem(a0, a1, a2).remu(a0, a1, a2)
+            .csrrw(a0, 0x340, a1).csrrs(a0, 0x340, a1).csrrc(a0, 0x340, a1)
+            .nop().ecall().ebreak();
+        #[rustfmt::skip]
+        let expected = [
+            I::Lui, I::Auipc, I::Jal, I::Jalr,
+            I::Beq, I::Bne, I::Blt, I::Bge, I::Bltu, I::Bgeu,
+            I::Lb, I::Lh, I::Lw, I::Lbu, I::Lhu,
+            I::Sb, I::Sh, I::Sw,
+            I::Addi, I::Slti, I::Sltiu, I::Xori, I::Ori, I::Andi,
+            I::Slli, I::Srli, I::Srai,
+            I::Add, I::Sub, I::Sll, I::Slt, I::Sltu,
+            I::Xor, I::Srl, I::Sra, I::Or, I::And,
+            I::Mul, I::Mulh, I::Mulhsu, I::Mulhu,
+            I::Div, I::Divu, I::Rem, I::Remu,
+            I::Csrrw, I::Csrrs, I::Csrrc,
+            I::Addi, I::ECall, I::EBreak,
+        ];
+        assert_eq!(kinds(program), expected);
+    }
+
+    #[test]
+    fn immediates_round_trip() {
+        let inst = |program: Assembler| {
+            Instruction(u32::from_le_bytes(program.into_bytes().try_into().unwrap()))
+        };
+        assert_eq!(inst(Assembler::new().addi(Reg::A0, Reg::A1, -2048)).imm_i(), -2048);
+        assert_eq!(inst(Assembler::new().sw(Reg::A0, Reg::A1, -3)).imm_s(), -3);
+        assert_eq!(inst(Assembler::new().beq(Reg::A0, Reg::A1, -8)).imm_b(), -8);
+        assert_eq!(inst(Assembler::new().beq(Reg::A0, Reg::A1, -4096)).imm_b(), -4096);
+        assert_eq!(inst(Assembler::new().bne(Reg::A0, Reg::A1, 4094)).imm_b(), 4094);
+        assert_eq!(inst(Assembler::new().jal(Reg::Ra, -2048)).imm_j(), -2048);
+        assert_eq!(inst(Assembler::new().jal(Reg::Ra, -(1 << 20))).imm_j(), -(1 << 20));
+        assert_eq!(inst(Assembler::new().jal(Reg::Ra, (1 << 20) - 2)).imm_j(), (1 << 20) - 2);
+        assert_eq!(inst(Assembler::new().lui(Reg::A0, 0x12345)).imm_u(), 0x12345);
+    }
+
+    #[test]
+    #[should_panic(expected = "does not fit")]
+    fn rejects_immediates_that_do_not_fit() {
+        let _ = Assembler::new().addi(Reg::A0, Reg::A0, 2048);
+    }
+}
@@ -0,0 +1,133 @@
+use alloc::{vec, vec::Vec};
+
+use crate::{device::Device, uxlen};
+
+/// The conventional base address of the CLINT, as used by QEMU's `virt` machine and Spike.
+pub const CLINT_BASE: uxlen = 0x0200_0000;
+/// The size of the CLINT's address range.
+pub const CLINT_SIZE: uxlen = 0x10000;
+
+/// The offset of the `msip` register of hart 0. The registers of the other harts follow it, 4 bytes apart.
+const MSIP: uxlen = 0x0000;
+/// The offset of the `mtimecmp` register of hart 0. The registers of the other harts follow it, 8 bytes apart.
+const MTIMECMP: uxlen = 0x4000;
+/// The offset of the `mtime` register.
+const MTIME: uxlen = 0xBFF8;
+
+/// The core-local interruptor, which provides the machine timer and software interrupts.
+///
+/// Every hart has its own `msip` and `mtimecmp` registers, so harts can interrupt each other.
+/// `mtime` is shared and incremented once for every step of the [Cpu][crate::cpu::Cpu].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Clint {
+    /// Whether a machine software interrupt is pending for each hart. Only the lowest bit of `msip` is writable.
+    msip: Vec<bool>,
+    /// The timer compare register of each hart. A timer interrupt is pending while `mtime >= mtimecmp`.
+    mtimecmp: Vec<u64>,
+    /// The real-time counter.
+    mtime: u64,
+}
+
+impl Clint {
+    /// Creates a CLINT for a single hart.
+    pub fn new() -> Self {
+        Self::with_harts(1)
+    }
+
+    /// Creates a CLINT with the registers for the given number of harts.
+    pub fn with_harts(harts: usize) -> Self {
+        // NOTE: `mtimecmp` is not reset by the hardware, so it starts at its maximum
+        //       to not raise a timer interrupt before the program has programmed it.
+        Self { msip: vec![false; harts], mtimecmp: vec![u64::MAX; harts], mtime: 0 }
+    }
+
+    /// Advances `mtime` by one tick.
+    pub fn tick(&mut self) {
+        self.mtime = self.mtime.wrapping_add(1);
+    }
+
+    pub fn mtime(&self) -> u64 {
+        self.mtime
+    }
+
+    /// The timer compare register of the given hart.
+    pub fn mtimecmp(&self, hart: usize) -> u64 {
+        self.mtimecmp[hart]
+    }
+
+    /// Whether the machine timer interrupt is pending for the given hart.
+    pub fn timer_pending(&self, hart: usize) -> bool {
+        self.mtime >= self.mtimecmp[hart]
+    }
+
+    /// Whether the machine software interrupt is pending for the given hart.
+    pub fn software_pending(&self, hart: usize) -> bool {
+        self.msip[hart]
+    }
+}
+
+impl Default for Clint {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Reads byte `offset` of a 64-bit register.
+fn read_u64_byte(value: u64, offset: uxlen) -> u8 {
+    value.to_le_bytes()[offset as usize]
+}
+
+/// Writes byte `offset` of a 64-bit register.
+fn write_u64_byte(value: &mut u64, offset: uxlen, byte: u8) {
+    let mut bytes = value.to_le_bytes();
+    bytes[offset as usize] = byte;
+    *value = u64::from_le_bytes(bytes);
+}
+
+impl Clint {
+    /// The end of the `msip` registers.
+    fn msip_end(&self) -> uxlen {
+        MSIP + 4 * self.msip.len() as uxlen
+    }
+
+    /// The end of the `mtimecmp` registers.
+    fn mtimecmp_end(&self) -> uxlen {
+        MTIMECMP + 8 * self.mtimecmp.len() as uxlen
+    }
+}
+
+impl Device for Clint {
+    fn read(&mut self, offset: uxlen) -> u8 {
+        match offset {
+            _ if (MSIP..self.msip_end()).contains(&offset) => {
+                let offset = offset - MSIP;
+                (offset.is_multiple_of(4) && self.msip[offset as usize / 4]) as u8
+            }
+            _ if (MTIMECMP..self.mtimecmp_end()).contains(&offset) => {
+                let offset = offset - MTIMECMP;
+                read_u64_byte(self.mtimecmp[offset as usize / 8], offset % 8)
+            }
+            _ if (MTIME..MTIME + 8).contains(&offset) => read_u64_byte(self.mtime, offset - MTIME),
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, offset: uxlen, value: u8) {
+        match offset {
+            _ if (MSIP..self.msip_end()).contains(&offset) => {
+                let offset = offset - MSIP;
+                if offset.is_multiple_of(4) {
+                    self.msip[offset as usize / 4] = value & 1 != 0;
+                }
+            }
+            _ if (MTIMECMP..self.mtimecmp_end()).contains(&offset) => {
+                let offset = offset - MTIMECMP;
+                write_u64_byte(&mut self.mtimecmp[offset as usize / 8], offset % 8, value)
+            }
+            _ if (MTIME..MTIME + 8).contains(&offset) => {
+                write_u64_byte(&mut self.mtime, offset - MTIME, value)
+            }
+            _ => {}
+        }
+    }
+}
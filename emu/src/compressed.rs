@@ -0,0 +1,286 @@
+use crate::inst::Instruction;
+
+/// Represents a 16-bit RISC-V compressed (RV32C) instruction.
+///
+/// Every compressed instruction has an equivalent 32-bit instruction
+/// it can be expanded into, so they are executed as such.
+#[derive(Debug, Clone, Copy)]
+pub struct CInstruction(pub u16);
+
+impl CInstruction {
+    pub const BYTES: usize = size_of::<u16>();
+
+    /// Whether the given instruction bits encode a compressed instruction.
+    ///
+    /// SPEC: The base 32-bit instructions have their lowest two bits set to 11.
+    ///       The 16-bit compressed instructions use the encodings 00, 01 and 10.
+    pub fn is_compressed(bits: u16) -> bool {
+        bits & 0b11 != 0b11
+    }
+
+    /// Expands the compressed instruction into its 32-bit equivalent.
+    ///
+    /// Returns `None` for illegal, reserved and unsupported encodings.
+    pub fn expand(&self) -> Option<Instruction> {
+        let c = self.0 as u32;
+
+        let bits = |hi: u32, lo: u32| (c >> lo) & ((1 << (hi - lo + 1)) - 1);
+
+        // Full 5-bit register specifiers.
+        let rd = bits(11, 7);
+        let rs2 = bits(6, 2);
+        // SPEC: The 3-bit rs1', rs2' and rd' fields specify the 8 most popular registers, x8 to x15.
+        let rd_ = bits(4, 2) + 8;
+        let rs1_ = bits(9, 7) + 8;
+        let rs2_ = rd_;
+
+        // The 6-bit immediate used by C.ADDI, C.LI, C.ANDI and C.LUI: `imm[5|4:0]` in bits 12 and 6:2.
+        let imm6 = sign_extend(bits(12, 12) << 5 | bits(6, 2), 6);
+        // The shift amount used by C.SLLI, C.SRLI and C.SRAI.
+        // SPEC: For RV32C, shamt[5] must be zero; the code points with shamt[5]=1 are designated for custom
+        //       extensions.
+        let shamt = (bits(12, 12) == 0).then_some(bits(6, 2));
+
+        let inst = match (bits(1, 0), bits(15, 13)) {
+            // C.ADDI4SPN: `addi rd', x2, nzuimm[9:2]`
+            (0b00, 0b000) => {
+                let imm = bits(10, 7) << 6 | bits(12, 11) << 4 | bits(5, 5) << 3 | bits(6, 6) << 2;
+                // SPEC: Code points with nzuimm=0 are reserved. This includes the all-zero instruction.
+                if imm == 0 {
+                    return None;
+                }
+                i_type(0b0010011, rd_, 0b000, 2, imm as i32)
+            }
+            // C.LW: `lw rd', offset[6:2](rs1')`
+            (0b00, 0b010) => {
+                let imm = bits(5, 5) << 6 | bits(12, 10) << 3 | bits(6, 6) << 2;
+                i_type(0b0000011, rd_, 0b010, rs1_, imm as i32)
+            }
+            // C.SW: `sw rs2', offset[6:2](rs1')`
+            (0b00, 0b110) => {
+                let imm = bits(5, 5) << 6 | bits(12, 10) << 3 | bits(6, 6) << 2;
+                s_type(0b0100011, 0b010, rs1_, rs2_, imm as i32)
+            }
+
+            // C.NOP / C.ADDI: `addi rd, rd, nzimm[5:0]`
+            (0b01, 0b000) => i_type(0b0010011, rd, 0b000, rd, imm6),
+            // C.JAL: `jal x1, offset[11:1]`
+            (0b01, 0b001) => j_type(1, cj_offset(c)),
+            // C.LI: `addi rd, x0, imm[5:0]`
+            (0b01, 0b010) => i_type(0b0010011, rd, 0b000, 0, imm6),
+            // C.ADDI16SP: `addi x2, x2, nzimm[9:4]`
+            (0b01, 0b011) if rd == 2 => {
+                let imm = bits(12, 12) << 9
+                    | bits(4, 3) << 7
+                    | bits(5, 5) << 6
+                    | bits(2, 2) << 5
+                    | bits(6, 6) << 4;
+                // SPEC: C.ADDI16SP is only valid when nzimm≠0; the code point with nzimm=0 is reserved.
+                if imm == 0 {
+                    return None;
+                }
+                i_type(0b0010011, 2, 0b000, 2, sign_extend(imm, 10))
+            }
+            // C.LUI: `lui rd, nzimm[17:12]`
+            (0b01, 0b011) => {
+                // SPEC: C.LUI is only valid when rd≠{x0,x2}, and when the immediate is not equal to zero.
+                if imm6 == 0 {
+                    return None;
+                }
+                u_type(0b0110111, rd, imm6)
+            }
+            (0b01, 0b100) => match (bits(11, 10), bits(12, 12), bits(6, 5)) {
+                // C.SRLI: `srli rd', rd', shamt[5:0]`
+                (0b00, _, _) => i_type(0b0010011, rs1_, 0b101, rs1_, shamt? as i32),
+                // C.SRAI: `srai rd', rd', shamt[5:0]`
+                (0b01, _, _) => {
+                    i_type(0b0010011, rs1_, 0b101, rs1_, (0b0100000 << 5 | shamt?) as i32)
+                }
+                // C.ANDI: `andi rd', rd', imm[5:0]`
+                (0b10, _, _) => i_type(0b0010011, rs1_, 0b111, rs1_, imm6),
+                // C.SUB: `sub rd', rd', rs2'`
+                (0b11, 0, 0b00) => r_type(rs1_, 0b000, rs1_, rs2_, 0b0100000),
+                // C.XOR: `xor rd', rd', rs2'`
+                (0b11, 0, 0b01) => r_type(rs1_, 0b100, rs1_, rs2_, 0b0000000),
+                // C.OR: `or rd', rd', rs2'`
+                (0b11, 0, 0b10) => r_type(rs1_, 0b110, rs1_, rs2_, 0b0000000),
+                // C.AND: `and rd', rd', rs2'`
+                (0b11, 0, 0b11) => r_type(rs1_, 0b111, rs1_, rs2_, 0b0000000),
+                _ => return None,
+            },
+            // C.J: `jal x0, offset[11:1]`
+            (0b01, 0b101) => j_type(0, cj_offset(c)),
+            // C.BEQZ: `beq rs1', x0, offset[8:1]`
+            (0b01, 0b110) => b_type(0b000, rs1_, 0, cb_offset(c)),
+            // C.BNEZ: `bne rs1', x0, offset[8:1]`
+            (0b01, 0b111) => b_type(0b001, rs1_, 0, cb_offset(c)),
+
+            // C.SLLI: `slli rd, rd, shamt[5:0]`
+            (0b10, 0b000) => i_type(0b0010011, rd, 0b001, rd, shamt? as i32),
+            // C.LWSP: `lw rd, offset[7:2](x2)`
+            (0b10, 0b010) => {
+                // SPEC: C.LWSP is only valid when rd≠x0; the code points with rd=x0 are reserved.
+                if rd == 0 {
+                    return None;
+                }
+                let imm = bits(3, 2) << 6 | bits(12, 12) << 5 | bits(6, 4) << 2;
+                i_type(0b0000011, rd, 0b010, 2, imm as i32)
+            }
+            (0b10, 0b100) => match (bits(12, 12), rd, rs2) {
+                // SPEC: C.JR is only valid when rs1≠x0; the code point with rs1=x0 is reserved.
+                (0, 0, 0) => return None,
+                // C.JR: `jalr x0, 0(rs1)`
+                (0, rs1, 0) => i_type(0b1100111, 0, 0b000, rs1, 0),
+                // C.MV: `add rd, x0, rs2`
+                (0, rd, rs2) => r_type(rd, 0b000, 0, rs2, 0b0000000),
+                // C.EBREAK: `ebreak`
+                (1, 0, 0) => i_type(0b1110011, 0, 0b000, 0, 1),
+                // C.JALR: `jalr x1, 0(rs1)`
+                (1, rs1, 0) => i_type(0b1100111, 1, 0b000, rs1, 0),
+                // C.ADD: `add rd, rd, rs2`
+                (_, rd, rs2) => r_type(rd, 0b000, rd, rs2, 0b0000000),
+            },
+            // C.SWSP: `sw rs2, offset[7:2](x2)`
+            (0b10, 0b110) => {
+                let imm = bits(8, 7) << 6 | bits(12, 9) << 2;
+                s_type(0b0100011, 0b010, 2, rs2, imm as i32)
+            }
+
+            // NOTE: The remaining encodings are either floating-point loads and stores, which are not supported,
+            //       or reserved.
+            _ => return None,
+        };
+
+        Some(Instruction(inst))
+    }
+}
+
+/// Decodes the jump offset of C.J and C.JAL: `offset[11|4|9:8|10|6|7|3:1|5]` in bits 12:2.
+#[rustfmt::skip]
+fn cj_offset(c: u32) -> i32 {
+    let imm11 =  ((c >> 12) & 0b1)   << 11;
+    let imm4 =   ((c >> 11) & 0b1)   << 4;
+    let imm9_8 = ((c >> 9)  & 0b11)  << 8;
+    let imm10 =  ((c >> 8)  & 0b1)   << 10;
+    let imm6 =   ((c >> 7)  & 0b1)   << 6;
+    let imm7 =   ((c >> 6)  & 0b1)   << 7;
+    let imm3_1 = ((c >> 3)  & 0b111) << 1;
+    let imm5 =   ((c >> 2)  & 0b1)   << 5;
+    sign_extend(imm11 | imm10 | imm9_8 | imm7 | imm6 | imm5 | imm4 | imm3_1, 12)
+}
+
+/// Decodes the branch offset of C.BEQZ and C.BNEZ: `offset[8|4:3]` in bits 12:10 and `offset[7:6|2:1|5]` in bits 6:2.
+#[rustfmt::skip]
+fn cb_offset(c: u32) -> i32 {
+    let imm8 =   ((c >> 12) & 0b1)  << 8;
+    let imm4_3 = ((c >> 10) & 0b11) << 3;
+    let imm7_6 = ((c >> 5)  & 0b11) << 6;
+    let imm2_1 = ((c >> 3)  & 0b11) << 1;
+    let imm5 =   ((c >> 2)  & 0b1)  << 5;
+    sign_extend(imm8 | imm7_6 | imm5 | imm4_3 | imm2_1, 9)
+}
+
+/// Encodes an R-type OP instruction.
+fn r_type(rd: u32, funct3: u32, rs1: u32, rs2: u32, funct7: u32) -> u32 {
+    funct7 << 25 | rs2 << 20 | rs1 << 15 | funct3 << 12 | rd << 7 | 0b0110011
+}
+
+/// Encodes an I-type instruction.
+fn i_type(opcode: u32, rd: u32, funct3: u32, rs1: u32, imm: i32) -> u32 {
+    (imm as u32 & 0xfff) << 20 | rs1 << 15 | funct3 << 12 | rd << 7 | opcode
+}
+
+/// Encodes an S-type instruction.
+fn s_type(opcode: u32, funct3: u32, rs1: u32, rs2: u32, imm: i32) -> u32 {
+    let imm = imm as u32;
+    ((imm >> 5) & 0x7f) << 25 | rs2 << 20 | rs1 << 15 | funct3 << 12 | (imm & 0x1f) << 7 | opcode
+}
+
+/// Encodes a B-type BRANCH instruction.
+#[rustfmt::skip]
+fn b_type(funct3: u32, rs1: u32, rs2: u32, imm: i32) -> u32 {
+    let imm = imm as u32;
+    let imm12 =   ((imm >> 12) & 0b1)      << 31;
+    let imm10_5 = ((imm >> 5)  & 0b111111) << 25;
+    let imm4_1 =  ((imm >> 1)  & 0b1111)   << 8;
+    let imm11 =   ((imm >> 11) & 0b1)      << 7;
+    imm12 | imm10_5 | rs2 << 20 | rs1 << 15 | funct3 << 12 | imm4_1 | imm11 | 0b1100011
+}
+
+/// Encodes a U-type instruction, where `imm` holds `imm[31:12]`.
+fn u_type(opcode: u32, rd: u32, imm: i32) -> u32 {
+    (imm as u32 & 0xfffff) << 12 | rd << 7 | opcode
+}
+
+/// Encodes a J-type JAL instruction.
+#[rustfmt::skip]
+fn j_type(rd: u32, imm: i32) -> u32 {
+    let imm = imm as u32;
+    let imm20 =    ((imm >> 20) & 0b1)          << 31;
+    let imm10_1 =  ((imm >> 1)  & 0b1111111111) << 21;
+    let imm11 =    ((imm >> 11) & 0b1)          << 20;
+    let imm19_12 = ((imm >> 12) & 0b11111111)   << 12;
+    imm20 | imm10_1 | imm11 | imm19_12 | rd << 7 | 0b1101111
+}
+
+/// Helper function to sign-extend a value after n bits.
+fn sign_extend(value: u32, bits: u32) -> i32 {
+    let shift = 32 - bits;
+    ((value as i32) << shift) >> shift
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::boxed::Box;
+
+    use crate::{
+        cpu::{Cpu, HaltReason},
+        rom::Rom,
+        uxlen,
+    };
+
+    const BASE: uxlen = 0x8000_0000;
+
+    fn expand(bits: u16) -> Option<u32> {
+        CInstruction(bits).expand().map(|inst| inst.0)
+    }
+
+    #[test]
+    fn expands_known_encodings() {
+        assert_eq!(expand(0x0085), Some(0x00108093)); // c.addi x1, 1      -> addi x1, x1, 1
+        assert_eq!(expand(0x4515), Some(0x00500513)); // c.li   x10, 5     -> addi x10, x0, 5
+        assert_eq!(expand(0x852e), Some(0x00b00533)); // c.mv   x10, x11   -> add  x10, x0, x11
+        assert_eq!(expand(0x8082), Some(0x00008067)); // c.jr   x1         -> jalr x0, 0(x1)
+        assert_eq!(expand(0x40c0), Some(0x0044a403)); // c.lw   x8, 4(x9)  -> lw   x8, 4(x9)
+        assert_eq!(expand(0xc080), Some(0x0084a023)); // c.sw   x8, 0(x9)  -> sw   x8, 0(x9)
+        assert_eq!(expand(0xc401), Some(0x00040463)); // c.beqz x8, 8      -> beq  x8, x0, 8
+        assert_eq!(expand(0x9002), Some(0x00100073)); // c.ebreak          -> ebreak
+    }
+
+    #[test]
+    fn c_ebreak_is_a_two_byte_breakpoint() {
+        let mut bytes = [
+            0x02, 0x90, // c.ebreak
+            0x15, 0x45, // c.li x10, 5
+        ];
+        let mut rom = Rom::new(&mut bytes, BASE, BASE + 4);
+
+        // Without a callback or a trap handler, the CPU halts at the breakpoint.
+        let mut cpu = Cpu::new(&mut rom, false).unwrap();
+        assert_eq!(cpu.run(), Ok(HaltReason::Ebreak));
+        assert_eq!(cpu.pc(), BASE);
+
+        // After the callback, execution continues with the next compressed instruction.
+        let mut cpu = Cpu::builder(&mut rom).on_ebreak(Box::new(|_| {})).build().unwrap();
+        assert_eq!(cpu.run(), Ok(HaltReason::EndOfRom));
+        assert_eq!(cpu.registers()[10], 5);
+        assert_eq!(cpu.instret(), 2);
+    }
+
+    #[test]
+    fn rejects_reserved_encodings() {
+        // SPEC: The all-zero instruction is defined to be illegal.
+        assert_eq!(expand(0x0000), None);
+    }
+}
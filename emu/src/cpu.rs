@@ -1,14 +1,245 @@
-use std::cell::Cell;
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    vec,
+    vec::Vec,
+};
+use core::{cell::Cell, ops::Range};
+#[cfg(feature = "std")]
+use std::{
+    format,
+    io::{Read, Write},
+    string::String,
+};
 
 use crate::{
+    clint::{CLINT_SIZE, Clint},
+    compressed::CInstruction,
+    csr::{
+        CYCLE, CYCLEH, Csrs, INSTRET, INSTRETH, MEDELEG, MHARTID, MIDELEG, MSTATUS_MIE,
+        MSTATUS_MPIE, MSTATUS_MPP, MSTATUS_MPP_SHIFT, MSTATUS_SIE, MSTATUS_SPIE, MSTATUS_SPP,
+        MSTATUS_TSR, MSTATUS_UBE, MSTATUSH_MBE, MSTATUSH_SBE, MTVEC_MODE_VECTORED, PrivilegeMode,
+        SATP, SATP_MODE, SSTATUS, TIME, TIMEH,
+    },
+    cycles::CycleCosts,
+    device::{Device, Ram},
+    error::EmuError,
     inst::{Instruction, InstructionKind},
     ixlen,
-    reg::Registers,
-    rom::Rom,
+    linux::InitialStack,
+    memory::{Access, MemoryMap, Permissions},
+    reg::{Reg, Registers},
+    rom::{MemError, Rom},
+    symbols::SymbolMap,
+    trap::{Exception, INTERRUPT_BIT, Interrupt, Trap},
     uxlen,
 };
+#[cfg(feature = "std")]
+use crate::{
+    disasm,
+    semihosting::{Semihosting, SyscallAbi},
+    uart::{UART_SIZE, UartDevice},
+};
+
+type HandleECall = dyn Fn(&mut Cpu);
+type HandleEBreak = dyn Fn(&Cpu);
+type HandleStep = dyn Fn(&Cpu, &Instruction);
+type HandleCommit = dyn Fn(&Cpu, &Instruction, &HistoryEntry);
+
+/// The result of a single [Cpu::step].
+#[derive(Debug, Clone, Copy)]
+pub struct StepOutcome {
+    /// Whether the CPU is still running after this step.
+    pub running: bool,
+    /// The instruction that was executed, if it could be fetched.
+    pub instruction: Option<Instruction>,
+    /// The trap that was taken during this step, if any.
+    pub trap: Option<Trap>,
+    /// The interrupt that was taken instead of executing an instruction during this step, if any.
+    pub interrupt: Option<Interrupt>,
+    /// Whether the CPU halted at an EBREAK instruction, because
+    /// there was neither an EBREAK callback nor a trap handler.
+    pub breakpoint: bool,
+    /// The watchpoint the executed instruction triggered, if any.
+    pub watchpoint: Option<WatchpointHit>,
+    /// The program counter after this step.
+    pub pc: uxlen,
+}
+
+/// The kind of memory access a watchpoint triggers on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+    /// Both reads and writes.
+    ReadWrite,
+}
+
+impl AccessKind {
+    fn includes(self, kind: AccessKind) -> bool {
+        self == AccessKind::ReadWrite || self == kind
+    }
+}
+
+/// A memory access that triggered a watchpoint, see [Cpu::add_watchpoint].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchpointHit {
+    /// The address of the access, which is the start of the word, halfword or byte that was accessed.
+    pub addr: uxlen,
+    /// Whether the access was a read or a write.
+    pub kind: AccessKind,
+    /// The value in memory before the access.
+    pub old: uxlen,
+    /// The value in memory after the access. For reads, this is the same as `old`.
+    pub new: uxlen,
+}
+
+/// Why [Cpu::run] returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HaltReason {
+    /// The program counter reached the end of the ROM.
+    EndOfRom,
+    /// The program exited with the given exit code, see [Cpu::exit_code].
+    Exited(uxlen),
+    /// The CPU was stopped with [Cpu::abort], usually from a callback.
+    Aborted,
+    /// The program executed an EBREAK instruction, while there was neither
+    /// an EBREAK callback nor a trap handler. The program counter is left at the EBREAK.
+    Ebreak,
+    /// The program counter reached a breakpoint, see [Cpu::add_breakpoint].
+    Breakpoint(uxlen),
+    /// The last executed instruction triggered a watchpoint, see [Cpu::add_watchpoint].
+    Watchpoint(WatchpointHit),
+    /// The program retired the number of instructions set with [CpuBuilder::max_instructions]
+    /// without finishing. The program counter is left at the next instruction, whose address this holds.
+    InstructionLimit(uxlen),
+}
+
+impl core::fmt::Display for HaltReason {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::EndOfRom => write!(f, "reached the end of the ROM"),
+            Self::Exited(exit_code) => write!(f, "exited with code {exit_code}"),
+            Self::Aborted => write!(f, "aborted"),
+            Self::Ebreak => write!(f, "stopped at an EBREAK"),
+            Self::Breakpoint(addr) => write!(f, "stopped at the breakpoint at {addr:#010x}"),
+            Self::Watchpoint(hit) => {
+                write!(f, "stopped at a {:?} watchpoint at {:#010x}", hit.kind, hit.addr)
+            }
+            Self::InstructionLimit(pc) => {
+                write!(f, "reached the instruction limit with the program counter at {pc:#010x}")
+            }
+        }
+    }
+}
+
+/// How often a conditional branch was taken, see [Cpu::branch_stats].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BranchStats {
+    /// The number of times the branch condition was true.
+    pub taken: u64,
+    /// The number of times the branch condition was false and execution fell through.
+    pub not_taken: u64,
+}
+
+/// How stores into memory that has already been fetched as an instruction are handled,
+/// see [CpuBuilder::code_writes].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CodeWritePolicy {
+    /// Code writes are allowed without being recorded.
+    #[default]
+    Allow,
+    /// Code writes are allowed, but recorded in [Cpu::code_writes] and reported on stderr.
+    Warn,
+    /// Code writes are recorded in [Cpu::code_writes] and raise a store access fault.
+    Fault,
+}
+
+/// A store into memory that had already been fetched as an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CodeWrite {
+    /// The address of the store instruction.
+    pub pc: uxlen,
+    /// The first address written that had been fetched as an instruction.
+    pub addr: uxlen,
+}
+
+/// A committed instruction, as recorded in the history (see [CpuBuilder::history])
+/// and passed to the callback registered with [CpuBuilder::on_commit].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HistoryEntry {
+    /// The address of the instruction.
+    pub pc: uxlen,
+    /// The instruction bits as they were fetched, which are only 16 bits for compressed instructions.
+    pub raw: u32,
+    /// The register the instruction changed, if any.
+    pub rd: Option<Reg>,
+    /// The value of `rd` before the instruction.
+    pub old: uxlen,
+    /// The value of `rd` after the instruction.
+    pub new: uxlen,
+}
+
+/// A snapshot of the architectural state of a [Cpu], taken with [Cpu::snapshot].
+///
+/// The state of mapped devices is not included.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CpuState {
+    pub regs: Registers,
+    pub pc: uxlen,
+    pub csrs: Csrs,
+    pub privilege: PrivilegeMode,
+    pub instret: u64,
+    /// The number of cycles the retired instructions took, see [CycleCosts].
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub cycles: u64,
+    /// The address reserved by the last LR.W instruction, if the reservation is still valid.
+    pub reservation: Option<uxlen>,
+    pub running: bool,
+    pub exit_code: Option<uxlen>,
+    /// The contents of the ROM.
+    pub memory: Vec<u8>,
+}
 
-type HandleECall = dyn Fn(&Cpu);
+/// The architectural state of a hart that is not selected, see [Cpu::select_hart].
+struct Hart {
+    regs: Registers,
+    pc: uxlen,
+    csrs: Box<Csrs>,
+    privilege: PrivilegeMode,
+    instret: u64,
+    cycles: u64,
+    reservation: Option<uxlen>,
+}
+
+impl Hart {
+    /// Creates a hart in the given state.
+    fn new(state: &CpuState) -> Self {
+        Self {
+            regs: state.regs,
+            pc: state.pc,
+            csrs: Box::new(state.csrs.clone()),
+            privilege: state.privilege,
+            instret: state.instret,
+            cycles: state.cycles,
+            reservation: state.reservation,
+        }
+    }
+}
+
+/// An instruction that has been fetched and decoded.
+#[derive(Clone, Copy)]
+struct Decoded {
+    inst: Instruction,
+    /// The instruction bits as they were fetched, before expanding compressed instructions.
+    raw: u32,
+    kind: InstructionKind,
+    /// Whether the instruction is a HINT, which executes as a nop.
+    hint: bool,
+    /// The length of the instruction in bytes.
+    len: uxlen,
+}
 
 /// Represents the RISC-V CPU.
 pub struct Cpu<'rom> {
@@ -17,304 +248,3932 @@ pub struct Cpu<'rom> {
     regs: Registers,
     /// The program counter. Holds the address of the current opcode.
     pc: uxlen,
-    /// The ROM containing the program.
-    rom: &'rom Rom<'rom>,
+    /// The control and status registers. These are boxed so switching harts does not copy them.
+    csrs: Box<Csrs>,
+    /// The privilege mode the hart is currently executing in.
+    privilege: PrivilegeMode,
+    /// The number of instructions that have been retired.
+    instret: u64,
+    /// The number of cycles the retired instructions took.
+    cycles: u64,
+    /// The ID of the selected hart, whose state the fields above hold.
+    hart_id: usize,
+    /// The state of every hart. The entry of the selected hart is outdated, as its state is held by the CPU itself.
+    harts: Vec<Hart>,
+    /// The ROM containing the program and the mapped RAM and devices, which all harts share.
+    memory: MemoryMap<'rom>,
+    /// The core-local interruptor providing timer and software interrupts, and the address range it is mapped at.
+    clint: Option<(Range<uxlen>, Clint)>,
+    /// The enabled ISA extensions.
+    extensions: Extensions,
+    /// Whether the base ISA is RV32E, which only has the registers `x0` to `x15`.
+    rv32e: bool,
+    /// Whether unknown instructions raise an illegal-instruction exception, instead of acting as a NOP.
+    strict_decode: bool,
+    /// The address reserved by the last LR.W instruction, if the reservation is still valid.
+    reservation: Option<uxlen>,
+    /// The address the instruction being executed jumps to, if it does, see [Cpu::step].
+    jump_target: Option<uxlen>,
+    /// Already decoded instructions in the ROM, indexed by their halfword offset, if caching is enabled.
+    /// Entries are invalidated when the ROM is written to.
+    decode_cache: Option<Vec<Option<Decoded>>>,
+    /// How many times each kind of instruction has been executed, if counting is enabled.
+    instruction_counts: Option<BTreeMap<InstructionKind, u64>>,
+    /// The number of cycles each kind of instruction takes, if set.
+    cycle_costs: Option<CycleCosts>,
+    /// How often each conditional branch was taken, by its address, if counting is enabled.
+    branch_stats: Option<BTreeMap<uxlen, BranchStats>>,
+    /// The last committed instructions, oldest first, and how many are kept, if recording is enabled.
+    history: Option<(VecDeque<HistoryEntry>, usize)>,
+    /// How stores into fetched instructions are handled.
+    code_write_policy: CodeWritePolicy,
+    /// Whether each halfword of the ROM has been fetched as (part of) an instruction,
+    /// if code writes are detected.
+    fetched: Option<Vec<bool>>,
+    /// The stores into fetched instructions, if code writes are detected.
+    code_writes: Vec<CodeWrite>,
 
     /// Whether or not the CPU is currently running.
+    /// This is a [Cell] so the EBREAK and step callbacks, which only borrow the CPU immutably,
+    /// can still stop it with [Cpu::abort].
     running: Cell<bool>,
+    /// The exit code the program reported through the HTIF `tohost` interface, if it has exited.
+    exit_code: Option<uxlen>,
+
+    /// The address of the HTIF `tohost` register, if the program has one.
+    tohost: Option<uxlen>,
 
     /// A callback function to run when the CPU encounters an ECALL instruction.
     handle_ecall: Option<Box<HandleECall>>,
+    /// A callback function to run when the CPU encounters an EBREAK instruction.
+    handle_ebreak: Option<Box<HandleEBreak>>,
+    /// A callback function to run before the CPU executes an instruction.
+    handle_step: Option<Box<HandleStep>>,
+    /// A callback function to run after the CPU commits an instruction.
+    handle_commit: Option<Box<HandleCommit>>,
+    /// The newlib system calls handled on the host, if enabled.
+    #[cfg(feature = "std")]
+    semihosting: Option<Semihosting>,
+
+    /// The number of retired instructions after which [Cpu::run] gives up, if any.
+    max_instructions: Option<u64>,
+    /// The lowest address a store through `sp` may write to, if the stack is guarded.
+    stack_limit: Option<uxlen>,
+    /// The address and contents of the initial stack of a Linux user-mode process, if any,
+    /// which is written again when the CPU is reset.
+    initial_stack: Option<(uxlen, Vec<u8>)>,
+
+    /// Addresses at which [Cpu::run] should stop before executing the instruction.
+    breakpoints: BTreeSet<uxlen>,
+    /// The breakpoint [Cpu::run] last stopped at, so resuming does not stop at it again.
+    stopped_at_breakpoint: Option<uxlen>,
+    /// Addresses at which a memory access makes [Cpu::run] stop after executing the instruction.
+    watchpoints: BTreeMap<uxlen, AccessKind>,
+    /// The watchpoint the current instruction triggered, if any.
+    watchpoint_hit: Option<WatchpointHit>,
+
+    /// The state the CPU was built in, including a pristine copy of the ROM, which [Cpu::reset] restores.
+    initial_state: CpuState,
 
     /// Whether to print information about the current instruction for each cycle.
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
     verbose: bool,
+    /// The symbols of the program, used to annotate addresses in verbose output, if known.
+    symbols: Option<SymbolMap>,
+    /// Whether to print every retired instruction in the format of Spike's instruction log.
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    trace_spike: bool,
+    /// Where verbose output and the Spike trace are written to.
+    #[cfg(feature = "std")]
+    trace_output: Box<dyn Write>,
 }
 
-impl<'rom> Cpu<'rom> {
-    /// Creates a new [Cpu] struct with the given ROM.
-    pub fn new(rom: &'rom Rom, verbose: bool) -> Self {
+/// The optional ISA extensions the [Cpu] supports.
+/// Instructions of disabled extensions raise an illegal-instruction exception.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Extensions {
+    /// The M extension for integer multiplication and division.
+    pub m: bool,
+    /// The A extension for atomic instructions.
+    pub a: bool,
+    /// The C extension for compressed instructions.
+    pub c: bool,
+}
+
+impl Extensions {
+    /// Whether instructions of the given kind are supported by the enabled extensions.
+    pub fn supports(&self, kind: InstructionKind) -> bool {
+        use InstructionKind as I;
+        match kind {
+            I::Mul | I::Mulh | I::Mulhsu | I::Mulhu | I::Div | I::Divu | I::Rem | I::Remu => self.m,
+            I::LrW
+            | I::ScW
+            | I::AmoswapW
+            | I::AmoaddW
+            | I::AmoxorW
+            | I::AmoandW
+            | I::AmoorW
+            | I::AmominW
+            | I::AmomaxW
+            | I::AmominuW
+            | I::AmomaxuW => self.a,
+            _ => true,
+        }
+    }
+}
+
+impl Default for Extensions {
+    fn default() -> Self {
+        Self { m: true, a: true, c: true }
+    }
+}
+
+/// A builder to configure and create a [Cpu].
+pub struct CpuBuilder<'rom> {
+    rom: Rom<'rom>,
+    verbose: bool,
+    trace_spike: bool,
+    symbols: Option<SymbolMap>,
+    entry: Option<uxlen>,
+    ram: Option<(uxlen, uxlen)>,
+    clint: Option<uxlen>,
+    tohost: Option<uxlen>,
+    extensions: Extensions,
+    rv32e: bool,
+    strict_decode: bool,
+    decode_cache: bool,
+    instruction_stats: bool,
+    cycle_costs: Option<CycleCosts>,
+    history: Option<usize>,
+    code_writes: CodeWritePolicy,
+    big_endian: bool,
+    harts: usize,
+    max_instructions: Option<u64>,
+    stack_pointer: Option<uxlen>,
+    stack_size: Option<uxlen>,
+    registers: Vec<(usize, uxlen)>,
+    initial_stack: Option<InitialStack>,
+    rom_permissions: Permissions,
+    /// The base, size, device and permissions of each mapped device.
+    devices: Vec<(uxlen, uxlen, Box<dyn Device>, Permissions)>,
+    handle_ecall: Option<Box<HandleECall>>,
+    handle_ebreak: Option<Box<HandleEBreak>>,
+    handle_step: Option<Box<HandleStep>>,
+    handle_commit: Option<Box<HandleCommit>>,
+    #[cfg(feature = "std")]
+    semihosting: bool,
+    #[cfg(feature = "std")]
+    linux_syscalls: bool,
+    #[cfg(feature = "std")]
+    heap_start: Option<uxlen>,
+    #[cfg(feature = "std")]
+    stdin: Option<Box<dyn Read>>,
+    #[cfg(feature = "std")]
+    trace_output: Option<Box<dyn Write>>,
+}
+
+impl<'rom> CpuBuilder<'rom> {
+    /// Creates a new [CpuBuilder] for a [Cpu] running the given ROM.
+    ///
+    /// Either pass a `&mut Rom` to keep the ROM, or an owned [Rom], like one created
+    /// with [Rom::from_vec], to create a [Cpu] that does not borrow any memory.
+    pub fn new(rom: impl Into<Rom<'rom>>) -> Self {
         Self {
-            regs: Registers::new(rom.size()),
-            pc: rom.start_addr(),
-            rom,
-            running: Cell::new(false),
+            rom: rom.into(),
+            verbose: false,
+            trace_spike: false,
+            symbols: None,
+            entry: None,
+            ram: None,
+            clint: None,
+            tohost: None,
+            extensions: Extensions::default(),
+            rv32e: false,
+            strict_decode: true,
+            decode_cache: true,
+            instruction_stats: false,
+            cycle_costs: None,
+            history: None,
+            code_writes: CodeWritePolicy::Allow,
+            big_endian: false,
+            harts: 1,
+            max_instructions: None,
+            stack_pointer: None,
+            stack_size: None,
+            registers: Vec::new(),
+            initial_stack: None,
+            rom_permissions: Permissions::RWX,
+            devices: Vec::new(),
             handle_ecall: None,
-            verbose,
+            handle_ebreak: None,
+            handle_step: None,
+            handle_commit: None,
+            #[cfg(feature = "std")]
+            semihosting: false,
+            #[cfg(feature = "std")]
+            linux_syscalls: false,
+            #[cfg(feature = "std")]
+            heap_start: None,
+            #[cfg(feature = "std")]
+            stdin: None,
+            #[cfg(feature = "std")]
+            trace_output: None,
         }
     }
 
-    pub fn on_ecall(mut self, f: Box<HandleECall>) -> Self {
-        self.handle_ecall = Some(f);
+    /// Whether to print information about the current instruction for each cycle.
+    /// Without the `std` feature this has no effect.
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
         self
     }
 
-    pub fn registers(&self) -> &Registers {
-        &self.regs
+    /// Whether to print every retired instruction in the format of the instruction log of
+    /// the Spike reference simulator, so the two can be compared with `diff`.
+    /// Without the `std` feature this has no effect.
+    pub fn trace_spike(mut self, trace_spike: bool) -> Self {
+        self.trace_spike = trace_spike;
+        self
     }
 
-    pub fn pc(&self) -> uxlen {
-        self.pc
+    /// Sets where the output of [CpuBuilder::verbose] and [CpuBuilder::trace_spike] is written to.
+    /// By default this is the host's stderr.
+    #[cfg(feature = "std")]
+    pub fn trace_output(mut self, output: impl Write + 'static) -> Self {
+        self.trace_output = Some(Box::new(output));
+        self
     }
 
-    pub fn rom(&self) -> &Rom {
-        &self.rom
+    /// Sets the address of the first instruction to execute.
+    /// By default this is the start of the ROM.
+    pub fn entry(mut self, entry: uxlen) -> Self {
+        self.entry = Some(entry);
+        self
     }
 
-    pub fn running(&self) -> bool {
-        self.running.get()
+    /// Maps `size` bytes of zero-initialized RAM at `base`.
+    ///
+    /// [CpuBuilder::build] fails if the RAM extends past the end of the address space.
+    pub fn ram(mut self, base: uxlen, size: uxlen) -> Self {
+        self.ram = Some((base, size));
+        self
     }
 
-    /// Starts the CPU cycle loop. It will infinitely run
-    /// the 'fetch, decode, execute' cycle until
-    /// the user stops the emulator explicitly,
-    /// or an unrecoverable error is encountered.
-    pub fn run(mut self) -> anyhow::Result<()> {
-        self.running.set(true);
+    /// Maps a [Clint] at `base`, which is conventionally [CLINT_BASE][crate::clint::CLINT_BASE].
+    /// Its `mtime` is incremented for every step, raising a machine timer interrupt once it reaches `mtimecmp`.
+    pub fn clint(mut self, base: uxlen) -> Self {
+        self.clint = Some(base);
+        self
+    }
 
-        while self.pc < self.rom.end_addr() && self.running() {
-            // Hard-wire the zero register to 0.
-            self.regs.set_zero(0);
+    /// Sets the address of the HTIF `tohost` register. Writes to it are interpreted
+    /// as host commands, allowing the program to print characters and exit.
+    pub fn tohost(mut self, tohost: uxlen) -> Self {
+        self.tohost = Some(tohost);
+        self
+    }
 
-            let instruction_addr = self.pc;
+    /// Sets the symbols of the program, which verbose output uses to annotate the program counter
+    /// and the targets of jumps and branches with the nearest symbol, like `<memcpy+0x10>`.
+    pub fn symbols(mut self, symbols: SymbolMap) -> Self {
+        self.symbols = Some(symbols);
+        self
+    }
 
-            // *Fetch* the current instruction.
-            let inst = self.fetch()?;
+    /// Sets the enabled ISA extensions. By default all supported extensions are enabled.
+    pub fn extensions(mut self, extensions: Extensions) -> Self {
+        self.extensions = extensions;
+        self
+    }
 
-            // FIXME: This is a temporary solution to stop test programs from running after finishing.
-            if inst == 0xC0001073 {
-                break;
-            }
+    /// Whether to use the RV32E base ISA instead of RV32I, which only has the 16 registers `x0` to `x15`.
+    /// Instructions that name any of the other registers raise an illegal-instruction exception.
+    /// Disabled by default.
+    pub fn rv32e(mut self, enabled: bool) -> Self {
+        self.rv32e = enabled;
+        self
+    }
 
-            // *Decode* the current instruction.
-            let instruction = self.decode(inst);
+    /// Whether unknown 32-bit instructions raise an illegal-instruction exception. Enabled by default.
+    ///
+    /// When disabled, they act as a NOP instead, which can get a program that was built for
+    /// a slightly different ISA running, but hides toolchain and emulator mismatches.
+    pub fn strict_decode(mut self, enabled: bool) -> Self {
+        self.strict_decode = enabled;
+        self
+    }
 
-            // *Execute* the current instruction.
-            self.execute(instruction, instruction_addr);
+    /// Whether to cache decoded instructions by their address. Enabled by default.
+    ///
+    /// Cached instructions are invalidated when the ROM is written to,
+    /// so self-modifying code runs correctly either way.
+    pub fn decode_cache(mut self, enabled: bool) -> Self {
+        self.decode_cache = enabled;
+        self
+    }
 
-            // We need to add 4 bytes to the program counter,
-            // as a single instruction is 4 bytes long.
-            self.pc += Instruction::BYTES as uxlen;
-        }
+    /// Whether data accesses are big-endian in every privilege mode, by setting the `MBE`, `SBE`
+    /// and `UBE` bits of `mstatus`. Instructions are always fetched as little-endian.
+    ///
+    /// Programs can also switch the endianness themselves by writing these bits.
+    pub fn big_endian(mut self, big_endian: bool) -> Self {
+        self.big_endian = big_endian;
+        self
+    }
 
-        Ok(())
+    /// Sets the number of harts, which is 1 by default. The harts share the memory and devices,
+    /// and [Cpu::run] executes one instruction of each hart in turn.
+    ///
+    /// Every hart starts at the entry point with the same registers. A program
+    /// can tell the harts apart by reading their ID from `mhartid`.
+    pub fn harts(mut self, count: usize) -> Self {
+        self.harts = count.max(1);
+        self
     }
 
-    pub fn abort(&self) {
-        self.running.set(false);
+    /// Whether to count how many times each kind of instruction is executed and how often
+    /// each conditional branch is taken, see [Cpu::instruction_counts] and [Cpu::branch_stats].
+    /// Disabled by default.
+    pub fn instruction_stats(mut self, enabled: bool) -> Self {
+        self.instruction_stats = enabled;
+        self
     }
 
-    /// Decodes the u32 we just fetched into an [Instruction].
-    fn decode(&self, inst: uxlen) -> Instruction {
-        Instruction(inst.to_le())
+    /// Sets the number of cycles each kind of instruction takes, which [Cpu::cycles] and the `cycle` CSR add up.
+    /// By default every instruction takes a single cycle, see [CycleCosts::default] for more realistic costs.
+    pub fn cycle_costs(mut self, costs: CycleCosts) -> Self {
+        self.cycle_costs = Some(costs);
+        self
     }
 
-    /// Read the current instruction bytes at the program counter and add step to the next instruction.
-    /// This is the first step in a CPU cycle.
-    fn fetch(&mut self) -> anyhow::Result<u32> {
-        let bytes = [
-            self.rom.read(self.pc),
-            self.rom.read(self.pc + 1),
-            self.rom.read(self.pc + 2),
-            self.rom.read(self.pc + 3),
-        ];
+    /// Records the last `capacity` committed instructions and the register each one changed,
+    /// see [Cpu::history]. Disabled by default.
+    pub fn history(mut self, capacity: usize) -> Self {
+        self.history = Some(capacity);
+        self
+    }
 
-        Ok(u32::from_le_bytes(bytes))
+    /// How to handle stores into memory that has already been fetched as an instruction,
+    /// which usually means a wild pointer or a stack overflowing into the code.
+    /// Allowed by default, as self-modifying code is legitimate.
+    pub fn code_writes(mut self, policy: CodeWritePolicy) -> Self {
+        self.code_writes = policy;
+        self
     }
 
-    /// Execute the given [Instruction].
-    /// This is the third step in a CPU cycle.
-    fn execute(&mut self, inst: Instruction, addr: uxlen) {
-        if self.verbose {
-            eprintln!("${:08x?}: ({:#010x?}) {:?}", self.pc, inst.0, inst);
-        }
+    /// Sets the maximum number of instructions to retire. Once the program
+    /// has retired this many, [Cpu::run] returns [HaltReason::InstructionLimit].
+    ///
+    /// This keeps a program that hangs from running forever.
+    pub fn max_instructions(mut self, limit: u64) -> Self {
+        self.max_instructions = Some(limit);
+        self
+    }
 
-        match inst.kind() {
-            InstructionKind::Lui => {
-                // SPEC: LUI (load upper immediate) is used to build 32-bit constants and uses the U-type format. LUI places
-                //       the 32-bit U-immediate value into the destination register rd, filling in the lowest 12 bits with zeros.
-                let value = inst.imm_u() & 0x7ffff000;
+    /// Sets the initial stack pointer, the exclusive top of the stack as it grows down.
+    ///
+    /// By default this is the top of the RAM mapped with [CpuBuilder::ram],
+    /// or the end of the ROM if there is none.
+    pub fn stack_pointer(mut self, sp: uxlen) -> Self {
+        self.stack_pointer = Some(sp);
+        self
+    }
 
-                // SPEC: The 32-bit result is sign-extended to 64 bits.
-                let value = value as i64;
+    /// Guards the stack, which takes up `size` bytes below the initial stack pointer.
+    ///
+    /// A store through `sp` below the stack raises a store access fault, and is reported on stderr,
+    /// instead of silently overwriting the code or data below it. Unguarded by default.
+    pub fn stack_size(mut self, size: uxlen) -> Self {
+        self.stack_size = Some(size);
+        self
+    }
 
-                self.regs[inst.rd() as usize] = value as uxlen;
-            }
-            InstructionKind::Auipc => {
-                // SPEC: AUIPC (add upper immediate to pc) is used to build pc-relative addresses and uses the U-type format.
-                //       AUIPC forms a 32-bit offset from the U-immediate, filling in the lowest 12 bits with zeros,
-                let offset = (inst.imm_u() & 0x7ffff000) as ixlen;
+    /// Sets the initial value of a register, given as a [Reg] or its index.
+    ///
+    /// By default every register starts at zero, except `sp`, see [CpuBuilder::stack_pointer].
+    /// Values set here take precedence over that, and every hart starts with them,
+    /// so a kernel can for example be booted with its hart ID in `a0` and a device tree pointer in `a1`.
+    /// Setting `x0` has no effect.
+    pub fn register(mut self, reg: impl Into<usize>, value: uxlen) -> Self {
+        self.registers.push((reg.into(), value));
+        self
+    }
 
-                // SPEC: sign-extends the result to 64 bits,
-                let offset = offset as i64;
+    /// Lays out the initial stack of a Linux user-mode process below the stack pointer,
+    /// with the arguments, environment and auxiliary vector of the [InitialStack],
+    /// and points `sp` at the `argc` at its bottom.
+    pub fn initial_stack(mut self, stack: InitialStack) -> Self {
+        self.initial_stack = Some(stack);
+        self
+    }
 
-                // SPEC: adds this offset to the address of the AUIPC instruction,
-                let target_addr = (addr as ixlen).wrapping_add(offset as ixlen) as uxlen;
+    /// Maps a device at the given address range. Loads and stores
+    /// within this range are handled by the device instead of the ROM.
+    pub fn device(self, range: Range<uxlen>, device: impl Device + 'static) -> Self {
+        self.map(range, device, Permissions::RW)
+    }
 
-                // SPEC: then places the result in register rd.
-                self.regs[inst.rd() as usize] = target_addr;
-            }
+    /// Maps a device at the given address range, which only allows the given kinds of access.
+    /// Other accesses raise an access fault, see [MemoryMap].
+    pub fn map(
+        mut self,
+        range: Range<uxlen>,
+        device: impl Device + 'static,
+        permissions: Permissions,
+    ) -> Self {
+        let size = range.end.wrapping_sub(range.start);
+        self.devices.push((range.start, size, Box::new(device), permissions));
+        self
+    }
 
-            InstructionKind::Jal => {
-                // SPEC: The jump and link (JAL) instruction uses the J-type format, where the J-immediate encodes a signed
-                //       offset in multiples of 2 bytes.
-                // NOTE: This is because RISC-V instructions are always aligned on 2-byte (16-bit) or 4-byte (32-bit) boundaries.
-                let byte_offset = inst.imm_j() * 2;
+    /// Sets the kinds of access the ROM allows. By default it can be read, written and executed,
+    /// as programs keep their data next to their code.
+    pub fn rom_permissions(mut self, permissions: Permissions) -> Self {
+        self.rom_permissions = permissions;
+        self
+    }
 
-                // SPEC: The offset is sign-extended and added to the address of
-                //       the jump instruction to form the jump target address.
-                //       Jumps can therefore target a ±1 MiB range.
-                let target_addr = (addr as ixlen).wrapping_add(byte_offset) as uxlen;
+    /// Registers a callback that is run when the CPU encounters an ECALL instruction.
+    ///
+    /// The callback gets mutable access to the CPU, so it can write a return value into `a0`
+    /// with [Cpu::registers_mut] and access memory with [Cpu::write_byte] and [Cpu::read_byte].
+    /// It can also stop the CPU with [Cpu::abort]. The callback itself is taken out of the CPU
+    /// while it runs, so registering callbacks from within it has no effect.
+    ///
+    /// Without a callback, ECALL raises an environment-call exception,
+    /// so the program can handle it in its own trap handler.
+    /// If it has none, the CPU stops with [EmuError::Ecall].
+    pub fn on_ecall(mut self, f: Box<HandleECall>) -> Self {
+        self.handle_ecall = Some(f);
+        self
+    }
 
-                // SPEC: JAL stores the address of the instruction following the jump ('pc'+4) into register rd.
-                self.regs[inst.rd() as usize] = self.pc + Instruction::BYTES as uxlen;
-                self.pc = target_addr;
-            }
+    /// Registers a callback that is run when the CPU encounters an EBREAK instruction,
+    /// after which execution continues with the next instruction.
+    ///
+    /// Without a callback, EBREAK raises a breakpoint exception for the program's trap handler.
+    /// If it has none, the CPU halts at the EBREAK instruction, see [StepOutcome::breakpoint].
+    pub fn on_ebreak(mut self, f: Box<HandleEBreak>) -> Self {
+        self.handle_ebreak = Some(f);
+        self
+    }
 
-            // SPEC: All branch instructions use the B-type instruction format. The 12-bit B-immediate encodes signed
-            //       offsets in multiples of 2 bytes. The offset is sign-extended and added to the address of the branch
-            //       instruction to give the target address. The conditional branch range is ±4 KiB.
-            //
-            //       Branch instructions compare two registers.
-            //
-            // FIXME: The conditional branch instructions will generate an instruction-address-misaligned exception if the
-            //        target address is not aligned to a four-byte boundary and the branch condition evaluates to true. If the
-            //        branch condition evaluates to false, the instruction-address-misaligned exception will not be raised
-            InstructionKind::Beq => {
-                // SPEC: BEQ takes the branch if registers rs1 and rs2 are equal.
+    /// Registers a callback that is run before each instruction is executed,
+    /// with the program counter still pointing at that instruction.
+    ///
+    /// The callback is run before the instruction is printed in verbose mode.
+    pub fn on_step(mut self, f: Box<HandleStep>) -> Self {
+        self.handle_step = Some(f);
+        self
+    }
 
-                if self.regs[inst.rs1() as usize] == self.regs[inst.rs2() as usize] {
-                    let target_addr = self.pc.wrapping_add(inst.imm_b() as uxlen);
-                    self.pc = target_addr;
-                }
-            }
-            InstructionKind::Bne => {
-                // SPEC: BNE takes the branch if registers rs1 and rs2 are unequal.
+    /// Registers a callback that is run after each instruction is committed,
+    /// with the register it changed and the value before and after.
+    ///
+    /// Instructions that trap are not committed, so the callback is not run for them.
+    pub fn on_commit(mut self, f: Box<HandleCommit>) -> Self {
+        self.handle_commit = Some(f);
+        self
+    }
 
-                if self.regs[inst.rs1() as usize] != self.regs[inst.rs2() as usize] {
-                    let target_addr = self.pc.wrapping_add(inst.imm_b() as uxlen);
-                    self.pc = target_addr;
-                }
-            }
-            InstructionKind::Blt => {
-                // SPEC: BLT takes the branch if registers rs1 is less than rs2.
+    /// Whether to handle the newlib system calls `read`, `write`, `exit` and `brk` on the host.
+    ///
+    /// The system call number is read from `a7`. Other system calls are
+    /// passed on to the ECALL callback, or raise an environment-call exception.
+    /// The heap starts at the end of the ROM, or at [CpuBuilder::heap_start], and can grow into mapped memory,
+    /// like [CpuBuilder::ram].
+    #[cfg(feature = "std")]
+    pub fn semihosting(mut self, enabled: bool) -> Self {
+        self.semihosting = enabled;
+        self
+    }
 
-                if self.regs[inst.rs1() as usize] < self.regs[inst.rs2() as usize] {
-                    let target_addr = self.pc.wrapping_add(inst.imm_b() as uxlen);
-                    self.pc = target_addr;
-                }
-            }
-            InstructionKind::Bge => {
-                // SPEC: BGE takes the branch if registers rs1 is greater than or equal to rs2.
+    /// Whether to handle the Linux system calls of statically linked user-mode programs on the host,
+    /// like [CpuBuilder::semihosting] does for newlib, see [SyscallAbi::Linux].
+    #[cfg(feature = "std")]
+    pub fn linux_syscalls(mut self, enabled: bool) -> Self {
+        self.linux_syscalls = enabled;
+        self
+    }
 
-                if self.regs[inst.rs1() as usize] >= self.regs[inst.rs2() as usize] {
-                    let target_addr = self.pc.wrapping_add(inst.imm_b() as uxlen);
-                    self.pc = target_addr;
-                }
-            }
-            InstructionKind::Bltu => {
-                // SPEC: BLTU takes the branch if registers rs1 is less than rs2.
+    /// Sets where the heap that the `brk` system call moves starts. By default this is the end of the ROM,
+    /// but programs with their data in separate segments need it to start past those.
+    #[cfg(feature = "std")]
+    pub fn heap_start(mut self, addr: uxlen) -> Self {
+        self.heap_start = Some(addr);
+        self
+    }
 
-                if self.regs[inst.rs1() as usize] < self.regs[inst.rs2() as usize] {
-                    let target_addr = self.pc.wrapping_add(inst.imm_b() as uxlen);
-                    self.pc = target_addr;
-                }
-            }
-            InstructionKind::Bgeu => {
-                // SPEC: BGEU takes the branch if registers rs1 is greater than or equal to rs2.
+    /// Maps a 16550-compatible [UartDevice] at `base`, which is conventionally [UART_BASE][crate::uart::UART_BASE].
+    /// Use [UartDevice::stdio] for a UART connected to the host's console.
+    #[cfg(feature = "std")]
+    pub fn uart(mut self, base: uxlen, uart: UartDevice) -> Self {
+        self.devices.push((base, UART_SIZE, Box::new(uart), Permissions::RW));
+        self
+    }
 
-                if self.regs[inst.rs1() as usize] >= self.regs[inst.rs2() as usize] {
-                    let target_addr = self.pc.wrapping_add(inst.imm_b() as uxlen);
-                    self.pc = target_addr;
-                }
-            }
+    /// Sets where the semihosting `read` system call gets its input. By default this is the host's stdin.
+    #[cfg(feature = "std")]
+    pub fn stdin(mut self, stdin: impl Read + 'static) -> Self {
+        self.stdin = Some(Box::new(stdin));
+        self
+    }
 
-            InstructionKind::Jalr => todo!("JALR instruction not implemented"),
+    /// Creates the configured [Cpu].
+    ///
+    /// Fails if the stack pointer does not point just past mapped memory,
+    /// or if the RAM, the CLINT or the UART extends past the end of the address space.
+    pub fn build(self) -> Result<Cpu<'rom>, EmuError> {
+        let ram = self.ram.map(|(base, size)| region(base, size)).transpose()?;
+        let clint = self.clint.map(|base| region(base, CLINT_SIZE)).transpose()?;
+        let sp =
+            self.stack_pointer.or(ram.as_ref().map(|ram| ram.end)).unwrap_or(self.rom.end_addr());
+        let mut regs = Registers::new(sp);
+        for (ix, value) in self.registers {
+            regs.write(ix, value);
+        }
 
-            InstructionKind::Lb => todo!("LB instruction not implemented"),
-            InstructionKind::Lh => todo!("LBU instruction not implemented"),
-            InstructionKind::Lw => todo!("LW instruction not implemented"),
-            InstructionKind::Lbu => todo!("LBU instruction not implemented"),
-            InstructionKind::Lhu => todo!("LHU instruction not implemented"),
+        let rom_size = self.rom.size();
+        let mut memory = MemoryMap::new(self.rom);
+        memory.set_rom_permissions(self.rom_permissions);
+        for (base, size, device, permissions) in self.devices {
+            memory.map(region(base, size)?, device, permissions);
+        }
+        if let Some(ram) = ram {
+            let size = (ram.end - ram.start) as usize;
+            memory.map(ram, Box::new(Ram::new(size)), Permissions::RW);
+        }
 
-            InstructionKind::Addi => {
-                // SPEC: ADDI adds the sign-extended 12-bit immediate to register rs1. Arithmetic overflow is ignored and the
-                //       result is simply the low XLEN bits of the result.
+        // NOTE: The stack is written before the initial state is taken, so a stack in the ROM is restored on reset.
+        let initial_stack = self.initial_stack.map(|stack| stack.layout(regs[Reg::Sp]));
+        if let Some((stack_sp, bytes)) = &initial_stack {
+            write_initial_stack(&mut memory, *stack_sp, bytes)?;
+            regs[Reg::Sp] = *stack_sp;
+        }
 
-                let imm = inst.imm_i() as ixlen;
-                let rs1 = self.regs[inst.rs1() as usize] as ixlen;
-                let value = rs1.wrapping_add(imm);
-                self.regs[inst.rd() as usize] = value as uxlen;
-            }
+        let decode_cache = self
+            .decode_cache
+            .then(|| vec![None; (rom_size as usize).div_ceil(CInstruction::BYTES)]);
+        let fetched = (self.code_writes != CodeWritePolicy::Allow)
+            .then(|| vec![false; (rom_size as usize).div_ceil(CInstruction::BYTES)]);
 
-            InstructionKind::Slti => todo!("SLTI instruction not implemented"),
-            InstructionKind::Sltiu => todo!("SLTIU instruction not implemented"),
-            InstructionKind::Xori => {
-                // SPEC: XORI is a logical operations that perform bitwise XOR on register rs1 and
-                //       the sign-extended 12-bit immediate and place the result in rd.
+        #[cfg(feature = "std")]
+        let semihosting = (self.semihosting || self.linux_syscalls).then(|| {
+            let stdin = self.stdin.unwrap_or_else(|| Box::new(std::io::stdin()));
+            let abi = if self.linux_syscalls { SyscallAbi::Linux } else { SyscallAbi::Newlib };
+            let heap_start = self.heap_start.unwrap_or(memory.rom().end_addr());
+            Semihosting::new(heap_start, stdin, abi)
+        });
 
-                let rs1 = self.regs[inst.rs1() as usize] as ixlen;
-                let imm = inst.imm_i() as ixlen;
-                self.regs[inst.rd() as usize] = (rs1 ^ imm) as uxlen;
-            }
-            InstructionKind::Ori => {
-                // SPEC: ORI is a logical operations that perform bitwise OR on register rs1 and
-                //       the sign-extended 12-bit immediate and place the result in rd.
+        let mut csrs = Csrs::new();
+        if self.big_endian {
+            csrs.set_mstatus(MSTATUS_UBE);
+            csrs.set_mstatush(MSTATUSH_SBE | MSTATUSH_MBE);
+        }
 
-                let rs1 = self.regs[inst.rs1() as usize] as ixlen;
-                let imm = inst.imm_i() as ixlen;
-                self.regs[inst.rd() as usize] = (rs1 | imm) as uxlen;
-            }
-            InstructionKind::Andi => {
-                // SPEC: ANDI is a logical operations that perform bitwise AND on register rs1 and
-                //       the sign-extended 12-bit immediate and place the result in rd.
+        // Keep a pristine copy of the program for Cpu::reset.
+        let pc = self.entry.unwrap_or(memory.rom().start_addr());
+        let initial_state = CpuState {
+            regs,
+            pc,
+            csrs: csrs.clone(),
+            privilege: PrivilegeMode::Machine,
+            instret: 0,
+            cycles: 0,
+            reservation: None,
+            running: true,
+            exit_code: None,
+            memory: memory.rom().bytes().to_vec(),
+        };
 
-                let rs1 = self.regs[inst.rs1() as usize] as ixlen;
-                let imm = inst.imm_i() as ixlen;
-                self.regs[inst.rd() as usize] = (rs1 & imm) as uxlen;
-            }
+        let harts = (0..self.harts).map(|_| Hart::new(&initial_state)).collect();
 
-            InstructionKind::Sb => todo!("SB instruction not implemented"),
-            InstructionKind::Sh => todo!("SH instruction not implemented"),
-            InstructionKind::Sw => todo!("SW instruction not implemented"),
+        let cpu = Cpu {
+            regs,
+            pc,
+            csrs: Box::new(csrs),
+            privilege: PrivilegeMode::Machine,
+            instret: 0,
+            cycles: 0,
+            hart_id: 0,
+            harts,
+            memory,
+            clint: clint.map(|range| (range, Clint::with_harts(self.harts))),
+            extensions: self.extensions,
+            rv32e: self.rv32e,
+            strict_decode: self.strict_decode,
+            reservation: None,
+            jump_target: None,
+            decode_cache,
+            instruction_counts: self.instruction_stats.then(BTreeMap::new),
+            cycle_costs: self.cycle_costs,
+            branch_stats: self.instruction_stats.then(BTreeMap::new),
+            history: self.history.map(|capacity| (VecDeque::with_capacity(capacity), capacity)),
+            code_write_policy: self.code_writes,
+            fetched,
+            code_writes: Vec::new(),
+            running: Cell::new(true),
+            exit_code: None,
+            tohost: self.tohost,
+            handle_ecall: self.handle_ecall,
+            handle_ebreak: self.handle_ebreak,
+            handle_step: self.handle_step,
+            handle_commit: self.handle_commit,
+            #[cfg(feature = "std")]
+            semihosting,
+            max_instructions: self.max_instructions,
+            stack_limit: self.stack_size.map(|size| sp.wrapping_sub(size)),
+            initial_stack,
+            breakpoints: BTreeSet::new(),
+            stopped_at_breakpoint: None,
+            watchpoints: BTreeMap::new(),
+            watchpoint_hit: None,
+            initial_state,
+            verbose: self.verbose,
+            symbols: self.symbols,
+            trace_spike: self.trace_spike,
+            #[cfg(feature = "std")]
+            trace_output: self.trace_output.unwrap_or_else(|| Box::new(std::io::stderr())),
+        };
 
-            InstructionKind::Slli => {
-                // SPEC: Shifts by a constant are encoded as a specialization of the I-type format.
-                //       The operand to be shifted is in rs1, and the shift amount is encoded in
-                //       the lower 5 bits of the I-immediate field. The right shift type is
-                //       encoded in bit 30.
+        // NOTE: The stack pointer is the exclusive top of the stack, so the first push is just below it.
+        if !cpu.is_mapped(sp.wrapping_sub(1)) {
+            return Err(EmuError::InvalidStackPointer { sp });
+        }
 
-                // SPEC: SLLI is a logical left shift (zeros are shifted into the lower bits);
-                let shamt = inst.imm_i() & 0b11111;
-                let value = self.regs[inst.rs1() as usize] << shamt;
-                self.regs[inst.rd() as usize] = value;
-            }
-            InstructionKind::Srli => {
-                // SPEC: SRLI is a logical right shift (zeros are shifted into the upper bits);
-                todo!("SRLI instruction not implemented");
-            }
-            InstructionKind::Srai => {
-                // SPEC: SRAI is an arithmetic right shift (the original sign bit is copied into the vacated upper bits).
-                todo!("SRAI instruction not implemented");
-            }
+        Ok(cpu)
+    }
+}
 
-            InstructionKind::Add => todo!("ADD instruction not implemented"),
-            InstructionKind::Sub => todo!("SUB instruction not implemented"),
-            InstructionKind::Sll => todo!("SLL instruction not implemented"),
-            InstructionKind::Slt => todo!("SLT instruction not implemented"),
-            InstructionKind::Sltu => todo!("SLTU instruction not implemented"),
-            InstructionKind::Xor => todo!("XOR instruction not implemented"),
-            InstructionKind::Srl => todo!("SRL instruction not implemented"),
-            InstructionKind::Sra => todo!("SRA instruction not implemented"),
-            InstructionKind::Or => todo!("OR instruction not implemented"),
-            InstructionKind::And => todo!("AND instruction not implemented"),
+impl<'rom> Cpu<'rom> {
+    /// Creates a new [Cpu] struct with the given ROM.
+    ///
+    /// Use [Cpu::builder] for more configuration options.
+    /// Fails if the ROM is empty, as there is no room for the stack.
+    pub fn new(rom: impl Into<Rom<'rom>>, verbose: bool) -> Result<Self, EmuError> {
+        Self::builder(rom).verbose(verbose).build()
+    }
 
-            InstructionKind::Fence => {}
-            InstructionKind::ECall => {
-                self.handle_ecall.as_ref().map(|f| f(self));
-            }
-            InstructionKind::EBreak => {}
+    /// Creates a [CpuBuilder] to configure a [Cpu] with the given ROM.
+    pub fn builder(rom: impl Into<Rom<'rom>>) -> CpuBuilder<'rom> {
+        CpuBuilder::new(rom)
+    }
 
-            InstructionKind::Unknown => {}
-        }
+    pub fn registers(&self) -> &Registers {
+        &self.regs
+    }
+
+    /// The registers of the selected hart, to set up the inputs of a program before [Cpu::step] or [Cpu::run].
+    /// Indexing writes any register, so use [Registers::write] to keep `x0` zero.
+    pub fn registers_mut(&mut self) -> &mut Registers {
+        &mut self.regs
+    }
+
+    pub fn csrs(&self) -> &Csrs {
+        &self.csrs
+    }
+
+    /// The privilege mode the hart is currently executing in. It starts in machine mode.
+    pub fn privilege(&self) -> PrivilegeMode {
+        self.privilege
+    }
+
+    /// The number of instructions that have been retired.
+    pub fn instret(&self) -> u64 {
+        self.instret
+    }
+
+    /// The number of cycles the retired instructions took according to the [CycleCosts]
+    /// set with [CpuBuilder::cycle_costs]. Without them, every instruction takes a single cycle.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn pc(&self) -> uxlen {
+        self.pc
+    }
+
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub(crate) fn set_pc(&mut self, pc: uxlen) {
+        self.pc = pc;
+    }
+
+    /// The ID of the selected hart, whose registers, program counter and CSRs the other methods refer to.
+    pub fn hart_id(&self) -> usize {
+        self.hart_id
+    }
+
+    /// The number of harts, see [CpuBuilder::harts].
+    pub fn hart_count(&self) -> usize {
+        self.harts.len()
+    }
+
+    /// Selects the hart that [Cpu::step] executes and whose state the other methods refer to.
+    ///
+    /// Panics if there is no hart with the given ID.
+    pub fn select_hart(&mut self, id: usize) {
+        assert!(id < self.harts.len(), "there is no hart with ID {id}");
+        if id == self.hart_id {
+            return;
+        }
+
+        // Store the state of the selected hart in its entry, and take the state of the new one from its entry.
+        for ix in [self.hart_id, id] {
+            let hart = &mut self.harts[ix];
+            core::mem::swap(&mut self.regs, &mut hart.regs);
+            core::mem::swap(&mut self.pc, &mut hart.pc);
+            core::mem::swap(&mut self.csrs, &mut hart.csrs);
+            core::mem::swap(&mut self.privilege, &mut hart.privilege);
+            core::mem::swap(&mut self.instret, &mut hart.instret);
+            core::mem::swap(&mut self.cycles, &mut hart.cycles);
+            core::mem::swap(&mut self.reservation, &mut hart.reservation);
+        }
+        self.hart_id = id;
+    }
+
+    /// How many times each kind of instruction has been executed, including instructions
+    /// that trapped, if enabled with [CpuBuilder::instruction_stats].
+    pub fn instruction_counts(&self) -> Option<&BTreeMap<InstructionKind, u64>> {
+        self.instruction_counts.as_ref()
+    }
+
+    /// How often each conditional branch was taken and not taken, by the address of the branch,
+    /// if enabled with [CpuBuilder::instruction_stats].
+    pub fn branch_stats(&self) -> Option<&BTreeMap<uxlen, BranchStats>> {
+        self.branch_stats.as_ref()
+    }
+
+    /// The last committed instructions, oldest first, if enabled with [CpuBuilder::history].
+    /// Instructions that trapped are not included.
+    pub fn history(&self) -> Option<&VecDeque<HistoryEntry>> {
+        self.history.as_ref().map(|(history, _)| history)
+    }
+
+    /// The stores into memory that had already been fetched as an instruction,
+    /// if detected with [CpuBuilder::code_writes].
+    pub fn code_writes(&self) -> &[CodeWrite] {
+        &self.code_writes
+    }
+
+    /// The symbols of the program, if set with [CpuBuilder::symbols].
+    pub fn symbols(&self) -> Option<&SymbolMap> {
+        self.symbols.as_ref()
+    }
+
+    /// The core-local interruptor, if one is mapped.
+    pub fn clint(&self) -> Option<&Clint> {
+        self.clint.as_ref().map(|(_, clint)| clint)
+    }
+
+    pub fn rom(&self) -> &Rom<'rom> {
+        self.memory.rom()
+    }
+
+    /// The memory map with the ROM, RAM and devices.
+    pub fn memory(&self) -> &MemoryMap<'rom> {
+        &self.memory
+    }
+
+    pub fn running(&self) -> bool {
+        self.running.get()
+    }
+
+    /// The exit code the program reported, through the HTIF `tohost` interface, a system call or [Cpu::exit].
+    pub fn exit_code(&self) -> Option<uxlen> {
+        self.exit_code
+    }
+
+    /// Takes a snapshot of the registers, CSRs and memory of the CPU.
+    /// With multiple harts, only the state of the selected hart is included.
+    pub fn snapshot(&self) -> CpuState {
+        CpuState {
+            regs: self.regs,
+            pc: self.pc,
+            csrs: (*self.csrs).clone(),
+            privilege: self.privilege,
+            instret: self.instret,
+            cycles: self.cycles,
+            reservation: self.reservation,
+            running: self.running(),
+            exit_code: self.exit_code,
+            memory: self.memory.rom().bytes().to_vec(),
+        }
+    }
+
+    /// Restores a snapshot taken with [Cpu::snapshot].
+    /// The snapshot has to be taken from a CPU with a ROM of the same size.
+    pub fn restore(&mut self, state: CpuState) -> Result<(), EmuError> {
+        if state.memory.len() != self.memory.rom().bytes().len() {
+            return Err(EmuError::SnapshotSize {
+                snapshot: state.memory.len(),
+                rom: self.memory.rom().bytes().len(),
+            });
+        }
+
+        self.regs = state.regs;
+        self.pc = state.pc;
+        *self.csrs = state.csrs;
+        self.privilege = state.privilege;
+        self.instret = state.instret;
+        self.cycles = state.cycles;
+        self.reservation = state.reservation;
+        self.running.set(state.running);
+        self.exit_code = state.exit_code;
+        self.memory.rom_mut().bytes_mut().copy_from_slice(&state.memory);
+
+        // The memory has been replaced, so all cached instructions are stale.
+        if let Some(cache) = &mut self.decode_cache {
+            cache.fill(None);
+        }
+        if let Some(fetched) = &mut self.fetched {
+            fetched.fill(false);
+        }
+
+        Ok(())
+    }
+
+    /// Resets the CPU to the state it was built in, so the program can be run again
+    /// without loading it anew.
+    ///
+    /// This restores the registers, CSRs and the ROM, clears the history,
+    /// and resets the CLINT and the other mapped devices.
+    /// Breakpoints, watchpoints and callbacks are kept.
+    pub fn reset(&mut self) {
+        // NOTE: The initial state is a snapshot of this CPU, so its memory always has the size of the ROM.
+        self.hart_id = 0;
+        let _ = self.restore(self.initial_state.clone());
+        for hart in &mut self.harts {
+            *hart = Hart::new(&self.initial_state);
+        }
+
+        self.stopped_at_breakpoint = None;
+        self.watchpoint_hit = None;
+        if let Some((history, _)) = &mut self.history {
+            history.clear();
+        }
+        self.code_writes.clear();
+        self.memory.reset();
+        if let Some((sp, bytes)) = &self.initial_stack {
+            // NOTE: Writing the stack succeeded when the CPU was built, and the memory map has not changed since.
+            let _ = write_initial_stack(&mut self.memory, *sp, bytes);
+        }
+        if let Some((_, clint)) = &mut self.clint {
+            *clint = Clint::with_harts(self.harts.len());
+        }
+        #[cfg(feature = "std")]
+        if let Some(semihosting) = &mut self.semihosting {
+            semihosting.reset();
+        }
+    }
+
+    /// Adds a breakpoint at the given address. [Cpu::run] will stop
+    /// before executing the instruction at this address.
+    pub fn add_breakpoint(&mut self, addr: uxlen) {
+        self.breakpoints.insert(addr);
+    }
+
+    /// Removes the breakpoint at the given address, if any.
+    pub fn remove_breakpoint(&mut self, addr: uxlen) {
+        self.breakpoints.remove(&addr);
+    }
+
+    pub fn breakpoints(&self) -> &BTreeSet<uxlen> {
+        &self.breakpoints
+    }
+
+    /// Adds a watchpoint at the given address. [Cpu::run] will stop after executing
+    /// a load or store that accesses the byte at this address in the given way.
+    pub fn add_watchpoint(&mut self, addr: uxlen, kind: AccessKind) {
+        self.watchpoints.insert(addr, kind);
+    }
+
+    /// Removes the watchpoint at the given address, if any.
+    pub fn remove_watchpoint(&mut self, addr: uxlen) {
+        self.watchpoints.remove(&addr);
+    }
+
+    pub fn watchpoints(&self) -> &BTreeMap<uxlen, AccessKind> {
+        &self.watchpoints
+    }
+
+    /// Starts the CPU cycle loop. It will infinitely run
+    /// the 'fetch, decode, execute' cycle until
+    /// the user stops the emulator explicitly,
+    /// or an unrecoverable error is encountered.
+    ///
+    /// With multiple harts, every step executes an instruction of the next hart in turn.
+    ///
+    /// If the program counter reaches a breakpoint, the loop stops before
+    /// executing that instruction. If an instruction triggers a watchpoint, the loop
+    /// stops right after executing it. Calling [Cpu::run] again resumes from there.
+    ///
+    /// Returns why the loop stopped, or an error when the program raises an exception
+    /// without having a trap handler, see [Cpu::step].
+    pub fn run(&mut self) -> Result<HaltReason, EmuError> {
+        while self.is_runnable() {
+            if self.breakpoints.contains(&self.pc) && self.stopped_at_breakpoint != Some(self.pc) {
+                self.stopped_at_breakpoint = Some(self.pc);
+                return Ok(HaltReason::Breakpoint(self.pc));
+            }
+
+            if self.max_instructions.is_some_and(|limit| self.instret >= limit) {
+                return Ok(HaltReason::InstructionLimit(self.pc));
+            }
+
+            let outcome = self.step()?;
+            if let Some(hit) = outcome.watchpoint {
+                return Ok(HaltReason::Watchpoint(hit));
+            }
+            if outcome.breakpoint {
+                return Ok(HaltReason::Ebreak);
+            }
+
+            // Stay at the hart that stopped the CPU, so its state can be inspected.
+            if self.harts.len() > 1 && self.is_runnable() {
+                self.select_hart((self.hart_id + 1) % self.harts.len());
+            }
+        }
+
+        Ok(match self.exit_code {
+            Some(exit_code) => HaltReason::Exited(exit_code),
+            None if !self.running() => HaltReason::Aborted,
+            None => HaltReason::EndOfRom,
+        })
+    }
+
+    /// Runs the CPU like [Cpu::run] until the program counter reaches `addr`, stopping before
+    /// the instruction at that address is executed. All state is left intact, so the program can be
+    /// inspected and resumed from there.
+    ///
+    /// Returns whether `addr` was reached, which is not the case if the program stopped before,
+    /// or if it stopped at a breakpoint or watchpoint first.
+    pub fn run_until(&mut self, addr: uxlen) -> Result<bool, EmuError> {
+        let is_breakpoint = self.breakpoints.contains(&addr);
+        self.add_breakpoint(addr);
+        let result = self.run();
+        if !is_breakpoint {
+            self.remove_breakpoint(addr);
+        }
+
+        Ok(result? == HaltReason::Breakpoint(addr))
+    }
+
+    /// Runs a single 'fetch, decode, execute' cycle of the selected hart.
+    ///
+    /// While an instruction executes, the program counter holds its address, so jumps and branches are relative
+    /// to it. Afterwards it is set to the jump target, if the instruction jumped, or to the next instruction.
+    ///
+    /// Exceptions are handled by the program's trap handler in `mtvec`.
+    /// If it has not installed one, the exception is returned as an [EmuError].
+    pub fn step(&mut self) -> Result<StepOutcome, EmuError> {
+        self.stopped_at_breakpoint = None;
+        self.watchpoint_hit = None;
+
+        // Hard-wire the zero register to 0.
+        self.regs.set_zero(0);
+
+        self.tick_clint();
+        if let Some(interrupt) = self.pending_interrupt() {
+            self.interrupt(interrupt);
+            return Ok(StepOutcome {
+                running: self.is_runnable(),
+                instruction: None,
+                trap: None,
+                interrupt: Some(interrupt),
+                breakpoint: false,
+                watchpoint: None,
+                pc: self.pc,
+            });
+        }
+
+        let instruction_addr = self.pc;
+
+        let decoded = match self.cached_instruction(instruction_addr) {
+            Some(decoded) => decoded,
+            None => {
+                // *Fetch* the current instruction.
+                let Ok((inst, len)) = self.fetch() else {
+                    let trap = Trap::new(Exception::InstructionAccessFault, instruction_addr);
+                    self.raise(trap)?;
+                    return Ok(StepOutcome {
+                        running: self.is_runnable(),
+                        instruction: None,
+                        trap: Some(trap),
+                        interrupt: None,
+                        breakpoint: false,
+                        watchpoint: None,
+                        pc: self.pc,
+                    });
+                };
+
+                // *Decode* the current instruction.
+                let raw = inst;
+                let Some(inst) = self.decode(raw, len) else {
+                    let trap = Trap::new(Exception::IllegalInstruction, raw);
+                    self.raise(trap)?;
+                    return Ok(StepOutcome {
+                        running: self.is_runnable(),
+                        instruction: None,
+                        trap: Some(trap),
+                        interrupt: None,
+                        breakpoint: false,
+                        watchpoint: None,
+                        pc: self.pc,
+                    });
+                };
+
+                let decoded = Decoded { inst, raw, kind: inst.kind(), hint: inst.is_hint(), len };
+                self.cache_instruction(instruction_addr, decoded);
+                decoded
+            }
+        };
+        let instruction = decoded.inst;
+        self.mark_fetched(instruction_addr, decoded.len);
+
+        if let Some(counts) = &mut self.instruction_counts {
+            *counts.entry(decoded.kind).or_default() += 1;
+        }
+
+        if let Some(handle_step) = &self.handle_step {
+            handle_step(self, &instruction);
+        }
+
+        // *Execute* the current instruction.
+        let mut breakpoint = false;
+        let previous_regs =
+            (self.history.is_some() || self.handle_commit.is_some()).then_some(self.regs);
+        let result = self.execute(decoded, instruction_addr);
+        let jump_target = self.jump_target.take();
+        let trap = match result {
+            Ok(()) => {
+                if let Some(previous) = previous_regs {
+                    self.commit(instruction_addr, decoded, &previous);
+                }
+
+                // The program counter points at the instruction while it is executed. Only afterwards it moves on,
+                // to where the instruction jumped, or to the next instruction, which is 2 bytes further
+                // for compressed instructions and 4 bytes otherwise.
+                self.pc = jump_target.unwrap_or(self.pc.wrapping_add(decoded.len));
+
+                #[cfg(feature = "std")]
+                if self.trace_spike {
+                    let line = disasm::spike_trace_line(
+                        instruction_addr,
+                        decoded.raw,
+                        decoded.len as usize,
+                        &instruction,
+                    );
+                    let _ = writeln!(self.trace_output, "{line}");
+                }
+
+                // Instructions that trap are not retired, so only count them here.
+                self.instret += 1;
+                self.cycles += self.cycle_costs.as_ref().map_or(1, |costs| costs.get(decoded.kind));
+                None
+            }
+            Err(trap)
+                if trap.cause == Exception::Breakpoint
+                    && !self.has_trap_handler(trap.cause.code()) =>
+            {
+                // There is nothing to handle the breakpoint, so halt at the EBREAK instruction for tooling to react.
+                breakpoint = true;
+                self.abort();
+                None
+            }
+            Err(trap) => {
+                self.raise(trap)?;
+                Some(trap)
+            }
+        };
+
+        Ok(StepOutcome {
+            running: self.is_runnable(),
+            instruction: Some(instruction),
+            trap,
+            interrupt: None,
+            breakpoint,
+            watchpoint: self.watchpoint_hit,
+            pc: self.pc,
+        })
+    }
+
+    /// Passes a committed instruction to the commit callback and adds it to the history,
+    /// dropping the oldest entry if the history is full.
+    fn commit(&mut self, pc: uxlen, decoded: Decoded, previous: &Registers) {
+        let rd = Reg::ALL.into_iter().find(|reg| previous[*reg] != self.regs[*reg]);
+        let (old, new) = rd.map_or((0, 0), |reg| (previous[reg], self.regs[reg]));
+        let entry = HistoryEntry { pc, raw: decoded.raw, rd, old, new };
+
+        if let Some(handle_commit) = &self.handle_commit {
+            handle_commit(self, &decoded.inst, &entry);
+        }
+
+        let Some((history, capacity)) = &mut self.history else { return };
+        if *capacity == 0 {
+            return;
+        }
+        if history.len() == *capacity {
+            history.pop_front();
+        }
+        history.push_back(entry);
+    }
+
+    /// Takes a trap for an exception raised while executing the program.
+    ///
+    /// Without a trap handler in `mtvec`, or in `stvec` for exceptions delegated to supervisor mode,
+    /// there is nowhere to go, so the exception is returned as an error instead,
+    /// leaving the program counter at the faulting instruction.
+    fn raise(&mut self, trap: Trap) -> Result<(), EmuError> {
+        if !self.has_trap_handler(trap.cause.code()) {
+            return Err(trap.into());
+        }
+        self.trap(trap.cause, trap.tval);
+        Ok(())
+    }
+
+    /// Whether the program has installed a trap handler for the given cause,
+    /// in `stvec` if it is delegated to supervisor mode or in `mtvec` otherwise.
+    fn has_trap_handler(&self, cause: uxlen) -> bool {
+        let tvec = match self.is_delegated(cause) {
+            true => self.csrs.stvec(),
+            false => self.csrs.mtvec(),
+        };
+        tvec & !0b11 != 0
+    }
+
+    /// Whether a trap with the given cause, as written to `mcause`, is handled in supervisor mode.
+    fn is_delegated(&self, cause: uxlen) -> bool {
+        // SPEC: By default, all traps at any privilege level are handled in machine mode. When a trap is delegated
+        //       to S-mode, it is handled in S-mode if it occurs in S-mode or U-mode. Traps never transition from a
+        //       more-privileged mode to a less-privileged mode.
+        let deleg = match cause & INTERRUPT_BIT != 0 {
+            true => self.csrs.mideleg(),
+            false => self.csrs.medeleg(),
+        };
+        self.privilege != PrivilegeMode::Machine && deleg & (1 << (cause & !INTERRUPT_BIT)) != 0
+    }
+
+    /// Takes a trap into machine mode. This saves the current program counter to `mepc`,
+    /// records the cause in `mcause` and `mtval`, and redirects
+    /// the program counter to the trap handler in `mtvec`.
+    ///
+    /// Exceptions delegated with `medeleg` that are raised in supervisor or user mode are taken into
+    /// supervisor mode instead, using `sepc`, `scause`, `stval` and `stvec`.
+    pub fn trap(&mut self, cause: Exception, tval: uxlen) {
+        self.enter_trap(cause.code(), tval);
+    }
+
+    /// Takes an interrupt into machine mode, or into supervisor mode if it is delegated with `mideleg`,
+    /// like [Cpu::trap]. The interrupted instruction has not been executed yet, so `mepc` points at it.
+    pub fn interrupt(&mut self, interrupt: Interrupt) {
+        self.enter_trap(INTERRUPT_BIT | interrupt.code(), 0);
+    }
+
+    fn enter_trap(&mut self, cause: uxlen, tval: uxlen) {
+        if self.is_delegated(cause) {
+            self.enter_supervisor_trap(cause, tval);
+            return;
+        }
+
+        self.csrs.set_mepc(self.pc);
+        self.csrs.set_mcause(cause);
+        self.csrs.set_mtval(tval);
+
+        // Push the interrupt-enable stack: MPIE is set to MIE, MIE is cleared
+        // and MPP is set to the privilege mode the trap was taken from.
+        let mstatus = self.csrs.mstatus();
+        let mut new_mstatus = mstatus & !(MSTATUS_MIE | MSTATUS_MPIE | MSTATUS_MPP);
+        new_mstatus |= self.privilege.bits() << MSTATUS_MPP_SHIFT;
+        if mstatus & MSTATUS_MIE != 0 {
+            new_mstatus |= MSTATUS_MPIE;
+        }
+        self.csrs.set_mstatus(new_mstatus);
+
+        self.privilege = PrivilegeMode::Machine;
+        self.pc = trap_vector(self.csrs.mtvec(), cause);
+    }
+
+    fn enter_supervisor_trap(&mut self, cause: uxlen, tval: uxlen) {
+        self.csrs.set_sepc(self.pc);
+        self.csrs.set_scause(cause);
+        self.csrs.set_stval(tval);
+
+        // SPEC: When a trap is taken into supervisor mode, SPIE is set to SIE, and SIE is set to 0. SPP is set to 0
+        //       if the trap originated from user mode, or 1 otherwise.
+        let mstatus = self.csrs.mstatus();
+        let mut new_mstatus = mstatus & !(MSTATUS_SIE | MSTATUS_SPIE | MSTATUS_SPP);
+        if self.privilege == PrivilegeMode::Supervisor {
+            new_mstatus |= MSTATUS_SPP;
+        }
+        if mstatus & MSTATUS_SIE != 0 {
+            new_mstatus |= MSTATUS_SPIE;
+        }
+        self.csrs.set_mstatus(new_mstatus);
+
+        self.privilege = PrivilegeMode::Supervisor;
+        self.pc = trap_vector(self.csrs.stvec(), cause);
+    }
+
+    /// Advances the time of the CLINT, if any, and updates the interrupts it makes pending in `mip`.
+    fn tick_clint(&mut self) {
+        let Some((_, clint)) = &mut self.clint else { return };
+        clint.tick();
+
+        let mut mip =
+            self.csrs.mip() & !(Interrupt::MachineTimer.mask() | Interrupt::MachineSoftware.mask());
+        if clint.timer_pending(self.hart_id) {
+            mip |= Interrupt::MachineTimer.mask();
+        }
+        if clint.software_pending(self.hart_id) {
+            mip |= Interrupt::MachineSoftware.mask();
+        }
+        self.csrs.set_mip(mip);
+    }
+
+    /// The interrupt that should be taken before executing the next instruction, if any.
+    fn pending_interrupt(&self) -> Option<Interrupt> {
+        let pending = self.csrs.mip() & self.csrs.mie();
+        if pending == 0 {
+            return None;
+        }
+
+        // SPEC: Interrupts for higher-privilege modes are always globally enabled regardless of the setting of the
+        //       global yIE bit for the higher-privilege mode. Interrupts for the current mode are enabled when its
+        //       xIE bit is set. Interrupts for lower-privilege modes are always disabled.
+        let mstatus = self.csrs.mstatus();
+        let machine_enabled =
+            self.privilege != PrivilegeMode::Machine || mstatus & MSTATUS_MIE != 0;
+        let supervisor_enabled = self.privilege == PrivilegeMode::User
+            || (self.privilege == PrivilegeMode::Supervisor && mstatus & MSTATUS_SIE != 0);
+        let mideleg = self.csrs.mideleg();
+        let enabled = match (machine_enabled, supervisor_enabled) {
+            (true, true) => !0,
+            (true, false) => !mideleg,
+            (false, true) => mideleg,
+            (false, false) => 0,
+        };
+        let pending = pending & enabled;
+
+        // SPEC: Multiple simultaneous interrupts destined for M-mode are handled in the following decreasing
+        //       priority order: MEI, MSI, MTI, SEI, SSI, STI.
+        [
+            Interrupt::MachineExternal,
+            Interrupt::MachineSoftware,
+            Interrupt::MachineTimer,
+            Interrupt::SupervisorExternal,
+            Interrupt::SupervisorSoftware,
+            Interrupt::SupervisorTimer,
+        ]
+        .into_iter()
+        .find(|interrupt| pending & interrupt.mask() != 0)
+    }
+
+    /// Whether the CPU has not been aborted and the program has not run off the end of the ROM.
+    ///
+    /// Only a program counter right at the end of the ROM ends the program. Any other address outside
+    /// of it raises an instruction access fault when fetching, so a stray jump is not mistaken for an exit.
+    pub(crate) fn is_runnable(&self) -> bool {
+        self.running() && self.pc != self.memory.rom().end_addr()
+    }
+
+    /// Stops the CPU after the current instruction.
+    /// This only needs a shared reference, so it can be called from any callback.
+    pub fn abort(&self) {
+        self.running.set(false);
+    }
+
+    /// Stops the CPU because the program exited with the given exit code.
+    /// Like [Cpu::abort], this can be called from a callback, for example to implement an exit ECALL.
+    pub fn exit(&mut self, exit_code: uxlen) {
+        self.exit_code = Some(exit_code);
+        self.abort();
+    }
+
+    /// Decodes the instruction we just fetched into an [Instruction].
+    /// Compressed instructions are expanded into their 32-bit equivalent.
+    ///
+    /// Returns `None` if the instruction is an illegal compressed instruction.
+    fn decode(&self, inst: uxlen, len: uxlen) -> Option<Instruction> {
+        let inst = if len == CInstruction::BYTES as uxlen {
+            if !self.extensions.c {
+                return None;
+            }
+            CInstruction(inst as u16).expand()?
+        } else {
+            // NOTE: `fetch` already assembled the instruction from its little-endian bytes,
+            //       so it is used as is regardless of the endianness of the host.
+            Instruction(inst)
+        };
+
+        // SPEC: RV32E reduces the integer register count to 16 general-purpose registers (x0–x15).
+        //       Instruction encodings that reference x16–x31 are reserved.
+        if self.rv32e && inst.registers().into_iter().flatten().any(|reg| reg >= 16) {
+            return None;
+        }
+
+        Some(inst)
+    }
+
+    /// Read the current instruction bytes at the program counter, returning them with the instruction length.
+    /// This is the first step in a CPU cycle.
+    ///
+    /// Fails if any byte of the instruction is outside of the ROM.
+    fn fetch(&mut self) -> Result<(u32, uxlen), MemError> {
+        // NOTE: The lowest two bits of the first halfword determine the length of the instruction,
+        //       so that halfword is read first. A compressed instruction can be the last halfword of the ROM.
+        self.memory.check(self.pc, Access::Execute)?;
+        let low = self.memory.rom().read_u16(self.pc)?;
+        if CInstruction::is_compressed(low) {
+            return Ok((low as u32, CInstruction::BYTES as uxlen));
+        }
+
+        // The whole instruction has to be within the ROM, not just its first halfword.
+        let inst = self.memory.rom().read_u32(self.pc)?;
+        Ok((inst, Instruction::BYTES as uxlen))
+    }
+
+    /// Looks up the already decoded instruction at the given address in the decode cache.
+    fn cached_instruction(&self, addr: uxlen) -> Option<Decoded> {
+        let cache = self.decode_cache.as_ref()?;
+        let offset = addr.checked_sub(self.memory.rom().start_addr())?;
+        cache.get(offset as usize / CInstruction::BYTES).copied().flatten()
+    }
+
+    /// Stores a decoded instruction in the decode cache, if it is enabled.
+    fn cache_instruction(&mut self, addr: uxlen, decoded: Decoded) {
+        let Some(cache) = &mut self.decode_cache else { return };
+        // NOTE: Instructions are only cached at halfword-aligned addresses, as they are indexed by halfword.
+        if !addr.is_multiple_of(CInstruction::BYTES as uxlen) {
+            return;
+        }
+        let Some(offset) = addr.checked_sub(self.memory.rom().start_addr()) else { return };
+        if let Some(entry) = cache.get_mut(offset as usize / CInstruction::BYTES) {
+            *entry = Some(decoded);
+        }
+    }
+
+    /// Marks the halfwords of the instruction at the given address as fetched, if code writes are detected.
+    fn mark_fetched(&mut self, addr: uxlen, len: uxlen) {
+        let Some(fetched) = &mut self.fetched else { return };
+        let Some(offset) = addr.checked_sub(self.memory.rom().start_addr()) else { return };
+        let ix = offset as usize / CInstruction::BYTES;
+        let count = len as usize / CInstruction::BYTES;
+        for entry in fetched.iter_mut().skip(ix).take(count) {
+            *entry = true;
+        }
+    }
+
+    /// Whether the byte at the given address has been fetched as part of an instruction.
+    fn was_fetched(&self, addr: uxlen) -> bool {
+        let Some(fetched) = &self.fetched else { return false };
+        addr.checked_sub(self.memory.rom().start_addr())
+            .and_then(|offset| fetched.get(offset as usize / CInstruction::BYTES))
+            .is_some_and(|fetched| *fetched)
+    }
+
+    /// Records a store into a fetched instruction and reports it according to the [CodeWritePolicy].
+    fn code_write(&mut self, addr: uxlen, fault: Trap) -> Result<(), Trap> {
+        let write = CodeWrite { pc: self.pc, addr };
+        self.code_writes.push(write);
+        match self.code_write_policy {
+            CodeWritePolicy::Allow => Ok(()),
+            CodeWritePolicy::Warn => {
+                #[cfg(feature = "std")]
+                std::eprintln!(
+                    "warning: store at {:#010x} writes to fetched instruction at {addr:#010x}",
+                    write.pc
+                );
+                Ok(())
+            }
+            CodeWritePolicy::Fault => Err(fault),
+        }
+    }
+
+    /// Raises a store access fault if a store through `rs1` is a push below the guarded stack,
+    /// see [CpuBuilder::stack_size].
+    fn check_stack(&self, rs1: usize, addr: uxlen) -> Result<(), Trap> {
+        let Some(limit) = self.stack_limit else { return Ok(()) };
+        if rs1 != usize::from(Reg::Sp) || addr >= limit {
+            return Ok(());
+        }
+
+        #[cfg(feature = "std")]
+        std::eprintln!(
+            "error: stack overflow: store at {:#010x} writes to {addr:#010x}, below the stack at {limit:#010x}",
+            self.pc
+        );
+        Err(Trap::new(Exception::StoreAccessFault, addr))
+    }
+
+    /// Invalidates the cached instructions that contain the byte at the given address.
+    fn invalidate_cached_instructions(&mut self, addr: uxlen) {
+        let Some(cache) = &mut self.decode_cache else { return };
+        let Some(offset) = addr.checked_sub(self.memory.rom().start_addr()) else { return };
+        // A four-byte instruction starting at the previous halfword also contains this byte.
+        let ix = offset as usize / CInstruction::BYTES;
+        for entry in cache.iter_mut().take(ix + 1).skip(ix.saturating_sub(1)) {
+            *entry = None;
+        }
+    }
+
+    /// Reads the CSR with the given number, taking care of the CSRs that are backed by CPU state.
+    fn read_csr(&self, csr: usize) -> uxlen {
+        match csr {
+            // NOTE: Without cycle costs every instruction takes a single cycle,
+            //       so the cycle counter equals the instruction counter.
+            CYCLE => self.cycles as uxlen,
+            INSTRET => self.instret as uxlen,
+            // SPEC: On RV32 only, reads of the cycleh, timeh, and instreth CSRs return bits 63–32 of the corresponding
+            //       counter.
+            // NOTE: Instructions are executed one at a time, so reading the low half and then the high half
+            //       can only see a carry between them if the counter actually advanced in between.
+            CYCLEH => (self.cycles >> 32) as uxlen,
+            INSTRETH => (self.instret >> 32) as uxlen,
+            TIME => self.time() as uxlen,
+            TIMEH => (self.time() >> 32) as uxlen,
+            SSTATUS => self.csrs.sstatus(),
+            MHARTID => self.hart_id as uxlen,
+            _ => self.csrs[csr],
+        }
+    }
+
+    /// The value of the `time` counter, which is `mtime` of the CLINT if one is mapped.
+    ///
+    /// Without a CLINT, time advances with every instruction, just like `mtime` would.
+    fn time(&self) -> u64 {
+        self.clint.as_ref().map_or(self.instret, |(_, clint)| clint.mtime())
+    }
+
+    /// Writes a CSR, keeping the CSRs that are views of others consistent.
+    fn write_csr(&mut self, csr: usize, value: uxlen) {
+        match csr {
+            SSTATUS => self.csrs.set_sstatus(value),
+            // SPEC: If satp is written with an unsupported MODE, the entire write has no effect; no fields in satp
+            //       are modified.
+            SATP if value & SATP_MODE != 0 => {}
+            // SPEC: medeleg[11] is read-only zero, since an environment call from M-mode can not be delegated.
+            MEDELEG => {
+                self.csrs.set_medeleg(value & !(1 << Exception::EnvironmentCallFromMMode.code()))
+            }
+            // NOTE: Only the supervisor interrupts can be delegated, machine interrupts are always taken in M-mode.
+            MIDELEG => {
+                let supervisor = Interrupt::SupervisorSoftware.mask()
+                    | Interrupt::SupervisorTimer.mask()
+                    | Interrupt::SupervisorExternal.mask();
+                self.csrs.set_mideleg(value & supervisor);
+            }
+            _ => self.csrs[csr] = value,
+        }
+    }
+
+    /// Reads a byte of memory, either from a mapped device or from the ROM.
+    pub fn read_byte(&mut self, addr: uxlen) -> Result<u8, EmuError> {
+        if let Some((range, clint)) = &mut self.clint
+            && range.contains(&addr)
+        {
+            return Ok(clint.read(addr - range.start));
+        }
+
+        Ok(self.memory.read(addr)?)
+    }
+
+    /// Writes a byte of memory, either to a mapped device or to the ROM.
+    pub fn write_byte(&mut self, addr: uxlen, value: u8) -> Result<(), EmuError> {
+        if let Some((range, clint)) = &mut self.clint
+            && range.contains(&addr)
+        {
+            clint.write(addr - range.start, value);
+            return Ok(());
+        }
+
+        self.memory.write(addr, value)?;
+        self.invalidate_cached_instructions(addr);
+        Ok(())
+    }
+
+    /// Reads `len` bytes of memory starting at `addr`, through the same devices and ROM as loads.
+    ///
+    /// Fails at the first address that is not mapped.
+    pub fn read_mem(&mut self, addr: uxlen, len: usize) -> Result<Vec<u8>, EmuError> {
+        (0..len).map(|i| self.read_byte(addr.wrapping_add(i as uxlen))).collect()
+    }
+
+    /// Writes `bytes` to memory starting at `addr`, through the same devices and ROM as stores.
+    ///
+    /// Nothing is written if any of the addresses is not mapped.
+    pub fn write_mem(&mut self, addr: uxlen, bytes: &[u8]) -> Result<(), EmuError> {
+        if let Some(unmapped) =
+            (0..bytes.len()).map(|i| addr.wrapping_add(i as uxlen)).find(|a| !self.is_mapped(*a))
+        {
+            return Err(EmuError::MemoryFault { addr: unmapped });
+        }
+
+        for (i, byte) in bytes.iter().enumerate() {
+            self.write_byte(addr.wrapping_add(i as uxlen), *byte)?;
+        }
+        Ok(())
+    }
+
+    /// Whether the address is backed by either a mapped device or the ROM.
+    pub(crate) fn is_mapped(&self, addr: uxlen) -> bool {
+        self.in_clint(addr) || self.memory.is_mapped(addr)
+    }
+
+    /// Whether the address is mapped and allows the given kind of access.
+    fn allows(&self, addr: uxlen, access: Access) -> bool {
+        self.in_clint(addr) || self.memory.check(addr, access).is_ok()
+    }
+
+    fn in_clint(&self, addr: uxlen) -> bool {
+        self.clint.as_ref().is_some_and(|(range, _)| range.contains(&addr))
+    }
+
+    /// Whether every address in the range is backed by either a mapped device or the ROM.
+    #[cfg(feature = "std")]
+    pub(crate) fn is_mapped_range(&self, range: Range<uxlen>) -> bool {
+        let rom = self.rom().start_addr()..self.rom().end_addr();
+        let mut addr = range.start;
+        while addr < range.end {
+            // Skip to the end of the region containing the address, as the rest of it is mapped too.
+            let clint = self.clint.as_ref().map(|(range, _)| range.clone());
+            let regions = core::iter::once(rom.clone())
+                .chain(clint)
+                .chain(self.memory.regions().map(|(range, _)| range));
+            match regions.filter(|region| region.contains(&addr)).map(|region| region.end).max() {
+                Some(end) => addr = end,
+                None => return false,
+            }
+        }
+        true
+    }
+
+    /// Whether data accesses in the current privilege mode are big-endian.
+    fn big_endian_data(&self) -> bool {
+        // SPEC: The MBE, SBE, and UBE bits in mstatus and mstatush are WARL fields that control the endianness of
+        //       memory accesses other than instruction fetches. Instruction fetches are always little-endian.
+        match self.privilege {
+            PrivilegeMode::Machine => self.csrs.mstatush() & MSTATUSH_MBE != 0,
+            PrivilegeMode::Supervisor => self.csrs.mstatush() & MSTATUSH_SBE != 0,
+            PrivilegeMode::User => self.csrs.mstatus() & MSTATUS_UBE != 0,
+        }
+    }
+
+    /// Reads `N` bytes starting at `addr`, raising a load access fault if any of them is out of bounds.
+    /// The bytes are returned in little-endian order, so they are reversed for big-endian accesses.
+    fn load<const N: usize>(&mut self, addr: uxlen) -> Result<[u8; N], Trap> {
+        let mut bytes = [0; N];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = self
+                .read_byte(addr.wrapping_add(i as uxlen))
+                .map_err(|_| Trap::new(Exception::LoadAccessFault, addr))?;
+        }
+        if self.big_endian_data() {
+            bytes.reverse();
+        }
+
+        if self.is_watched(addr, N, AccessKind::Read) {
+            let value = le_value(&bytes);
+            self.watchpoint_hit =
+                Some(WatchpointHit { addr, kind: AccessKind::Read, old: value, new: value });
+        }
+
+        Ok(bytes)
+    }
+
+    /// Whether a watchpoint triggers on the given kind of access to any of the `len` bytes starting at `addr`.
+    fn is_watched(&self, addr: uxlen, len: usize, kind: AccessKind) -> bool {
+        !self.watchpoints.is_empty()
+            && (0..len).any(|i| {
+                self.watchpoints
+                    .get(&addr.wrapping_add(i as uxlen))
+                    .is_some_and(|watched| watched.includes(kind))
+            })
+    }
+
+    /// Writes `N` bytes starting at `addr`, raising a store access fault if any of them is out of bounds.
+    /// The bytes are given in little-endian order and reversed for big-endian accesses.
+    fn store<const N: usize>(&mut self, addr: uxlen, bytes: [u8; N]) -> Result<(), Trap> {
+        let fault = Trap::new(Exception::StoreAccessFault, addr);
+
+        // Make sure the whole range is accessible before modifying any memory.
+        if !(0..N).all(|i| self.allows(addr.wrapping_add(i as uxlen), Access::Write)) {
+            return Err(fault);
+        }
+
+        if self.fetched.is_some()
+            && let Some(code_addr) =
+                (0..N).map(|i| addr.wrapping_add(i as uxlen)).find(|a| self.was_fetched(*a))
+        {
+            self.code_write(code_addr, fault)?;
+        }
+
+        // NOTE: Reading the old value can have side effects on devices, so it is only read when watched.
+        if self.is_watched(addr, N, AccessKind::Write) {
+            let old = le_value(&self.load::<N>(addr).map_err(|_| fault)?);
+            self.watchpoint_hit =
+                Some(WatchpointHit { addr, kind: AccessKind::Write, old, new: le_value(&bytes) });
+        }
+
+        let mut bytes = bytes;
+        if self.big_endian_data() {
+            bytes.reverse();
+        }
+        for (i, byte) in bytes.into_iter().enumerate() {
+            self.write_byte(addr.wrapping_add(i as uxlen), byte).map_err(|_| fault)?;
+        }
+        self.clear_reservations(addr, N);
+
+        if let Some(tohost) = self.tohost {
+            // `tohost` is a 64-bit register, which a 32-bit program writes as two words:
+            // first the lower and then the upper half. The command is complete once the upper half is written.
+            let upper_half = tohost.wrapping_add(4);
+            if addr <= upper_half && upper_half < addr.wrapping_add(N as uxlen) {
+                self.handle_tohost(tohost);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Invalidates the reservations that a store of `len` bytes at `addr` breaks.
+    fn clear_reservations(&mut self, addr: uxlen, len: usize) {
+        // SPEC: An SC may succeed only if no store from another hart to the reservation set can be observed to have
+        //       occurred between the LR and the SC.
+        // NOTE: The spec also allows an SC to fail for other reasons, so any store by this hart invalidates its own
+        //       reservation, which makes an LR/SC sequence with a store in between fail as it does on most hardware.
+        self.reservation = None;
+
+        let overlaps = |reserved: uxlen| {
+            let end = addr.wrapping_add(len as uxlen);
+            reserved.wrapping_add(4) > addr && reserved < end
+        };
+        for (id, hart) in self.harts.iter_mut().enumerate() {
+            // NOTE: The entry of the selected hart is stale, its state lives in the CPU itself.
+            if id != self.hart_id && hart.reservation.is_some_and(overlaps) {
+                hart.reservation = None;
+            }
+        }
+    }
+
+    /// Handles a command written to the HTIF `tohost` register.
+    fn handle_tohost(&mut self, tohost: uxlen) {
+        let Ok(bytes) = self.load::<8>(tohost) else { return };
+        let value = u64::from_le_bytes(bytes);
+        if value == 0 {
+            return;
+        }
+
+        // The command is encoded as `device[63:56] | command[55:48] | payload[47:0]`.
+        let device = (value >> 56) as u8;
+        let command = (value >> 48) as u8;
+        let payload = value & 0xffff_ffff_ffff;
+
+        match (device, command) {
+            // The syscall device with the lowest bit set signals the program exited.
+            (0, 0) if payload & 1 == 1 => self.exit((payload >> 1) as uxlen),
+            // The console device writes a single character.
+            // NOTE: Without the host's stdout, the character is dropped.
+            (1, 1) => {
+                #[cfg(feature = "std")]
+                {
+                    let mut stdout = std::io::stdout();
+                    let _ = stdout.write_all(&[payload as u8]).and_then(|_| stdout.flush());
+                }
+            }
+            _ =>
+            {
+                #[cfg(feature = "std")]
+                if self.verbose {
+                    let _ = writeln!(self.trace_output, "Unsupported HTIF command: {value:#018x}");
+                }
+            }
+        }
+
+        // Acknowledge the command by clearing `tohost`.
+        let _ = self.store(tohost, [0; 8]);
+    }
+
+    /// Annotates the program counter and the target of a jump or branch with the nearest symbols
+    /// for verbose output, each prefixed with a space, or empty if unknown.
+    #[cfg(feature = "std")]
+    fn annotate(&self, kind: InstructionKind, inst: Instruction) -> (String, String) {
+        use InstructionKind as I;
+
+        let Some(symbols) = &self.symbols else { return (String::new(), String::new()) };
+        let annotate = |addr| symbols.annotate(addr).map(|s| format!(" {s}")).unwrap_or_default();
+        let target = match kind {
+            I::Jal => Some(self.pc.wrapping_add(inst.imm_j() as uxlen)),
+            I::Beq | I::Bne | I::Blt | I::Bge | I::Bltu | I::Bgeu => {
+                Some(self.pc.wrapping_add(inst.imm_b() as uxlen))
+            }
+            _ => None,
+        };
+        (annotate(self.pc), target.map(annotate).unwrap_or_default())
+    }
+
+    /// Whether the signed result of an arithmetic instruction wraps around, which is annotated in verbose mode.
+    /// The high halves of a multiplication never wrap, so only MUL is checked of the multiplications.
+    #[cfg(feature = "std")]
+    fn overflows(&self, kind: InstructionKind, inst: Instruction) -> bool {
+        let rs1 = self.regs[inst.rs1() as usize] as ixlen;
+        let rs2 = self.regs[inst.rs2() as usize] as ixlen;
+        match kind {
+            InstructionKind::Add => rs1.checked_add(rs2).is_none(),
+            InstructionKind::Addi => rs1.checked_add(inst.imm_i()).is_none(),
+            InstructionKind::Sub => rs1.checked_sub(rs2).is_none(),
+            InstructionKind::Mul => rs1.checked_mul(rs2).is_none(),
+            _ => false,
+        }
+    }
+
+    /// Counts a conditional branch at the program counter as taken or not, if enabled.
+    fn count_branch(&mut self, taken: bool) {
+        let Some(stats) = &mut self.branch_stats else { return };
+        let stats = stats.entry(self.pc).or_default();
+        if taken {
+            stats.taken += 1;
+        } else {
+            stats.not_taken += 1;
+        }
+    }
+
+    /// Jumps to the target of a taken branch or jump, once the instruction has been executed.
+    fn branch(&mut self, target_addr: uxlen) -> Result<(), Trap> {
+        // SPEC: The conditional branch instructions will generate an instruction-address-misaligned exception if the
+        //       target address is not aligned to a four-byte boundary and the branch condition evaluates to true. If the
+        //       branch condition evaluates to false, the instruction-address-misaligned exception will not be raised.
+        // NOTE: With the C extension, instructions only have to be aligned to a two-byte boundary.
+        let alignment = if self.extensions.c { CInstruction::BYTES } else { Instruction::BYTES };
+        if !target_addr.is_multiple_of(alignment as uxlen) {
+            return Err(Trap::new(Exception::InstructionAddressMisaligned, target_addr));
+        }
+
+        self.jump_target = Some(target_addr);
+        Ok(())
+    }
+
+    /// Executes an atomic memory operation on the word at the address in rs1,
+    /// storing `op(loaded, rs2)` back to memory and the loaded value in rd.
+    fn amo(&mut self, inst: Instruction, op: impl Fn(uxlen, uxlen) -> uxlen) -> Result<(), Trap> {
+        let addr = self.regs[inst.rs1() as usize];
+        check_atomic_alignment(addr, Exception::StoreAddressMisaligned)?;
+        let rs2 = self.regs[inst.rs2() as usize];
+        let loaded = uxlen::from_le_bytes(self.load(addr)?);
+        self.store(addr, op(loaded, rs2).to_le_bytes())?;
+        self.regs.write(inst.rd() as usize, loaded);
+        Ok(())
+    }
+
+    /// Execute the given decoded [Instruction].
+    /// This is the third step in a CPU cycle.
+    fn execute(&mut self, decoded: Decoded, addr: uxlen) -> Result<(), Trap> {
+        let Decoded { inst, kind, hint, len, .. } = decoded;
+
+        #[cfg(feature = "std")]
+        if self.verbose {
+            let note = if hint {
+                " (hint)"
+            } else if self.overflows(kind, inst) {
+                " (overflow)"
+            } else {
+                ""
+            };
+            let (location, target) = self.annotate(kind, inst);
+            let _ = writeln!(
+                self.trace_output,
+                "${:08x?}{location}: ({:#010x?}) {:?}{target}{note}",
+                self.pc, inst.0, inst
+            );
+        }
+
+        if !self.extensions.supports(kind) {
+            return Err(Trap::new(Exception::IllegalInstruction, inst.0));
+        }
+
+        // SPEC: Attempts to access a CSR without appropriate privilege level raise illegal-instruction exceptions.
+        use InstructionKind as I;
+        let is_csr =
+            matches!(kind, I::Csrrw | I::Csrrs | I::Csrrc | I::Csrrwi | I::Csrrsi | I::Csrrci);
+        if is_csr && PrivilegeMode::required_for_csr(inst.csr() as usize) > self.privilege {
+            return Err(Trap::new(Exception::IllegalInstruction, inst.0));
+        }
+
+        // SPEC: HINTs are usually used to communicate performance hints to the microarchitecture. Like NOPs,
+        //       HINTs do not change any architecturally visible state, except for advancing the pc.
+        if hint {
+            return Ok(());
+        }
+
+        match kind {
+            InstructionKind::Lui => {
+                // SPEC: LUI (load upper immediate) is used to build 32-bit constants and uses the U-type format. LUI places
+                //       the 32-bit U-immediate value into the destination register rd, filling in the lowest 12 bits with zeros.
+                // NOTE: On RV32 the value already is XLEN bits wide, so there is nothing to sign-extend.
+                let value = (inst.imm_u() as uxlen) << 12;
+
+                self.regs.write(inst.rd() as usize, value);
+            }
+            InstructionKind::Auipc => {
+                // SPEC: AUIPC (add upper immediate to pc) is used to build pc-relative addresses and uses the U-type format.
+                //       AUIPC forms a 32-bit offset from the U-immediate, filling in the lowest 12 bits with zeros,
+                let offset = (inst.imm_u() as uxlen) << 12;
+
+                // SPEC: adds this offset to the address of the AUIPC instruction,
+                // NOTE: On RV32 the offset already is XLEN bits wide, so there is nothing to sign-extend.
+                let target_addr = addr.wrapping_add(offset);
+
+                // SPEC: then places the result in register rd.
+                self.regs.write(inst.rd() as usize, target_addr);
+            }
+
+            InstructionKind::Jal => {
+                // SPEC: The jump and link (JAL) instruction uses the J-type format, where the J-immediate encodes a signed
+                //       offset in multiples of 2 bytes.
+                // NOTE: This is because RISC-V instructions are always aligned on 2-byte (16-bit) or 4-byte (32-bit) boundaries.
+                //       The decoded immediate is already the offset in bytes, with its lowest bit zero.
+                let byte_offset = inst.imm_j();
+
+                // SPEC: The offset is sign-extended and added to the address of
+                //       the jump instruction to form the jump target address.
+                //       Jumps can therefore target a ±1 MiB range.
+                let target_addr = addr.wrapping_add(byte_offset as uxlen);
+
+                // SPEC: JAL stores the address of the instruction following the jump ('pc'+4) into register rd.
+                // NOTE: For the compressed C.JAL the following instruction is at 'pc'+2.
+                let link_addr = addr.wrapping_add(len);
+                // NOTE: The jump has to be checked for misalignment before writing rd,
+                //       as an instruction that raises an exception must not have any effect.
+                self.branch(target_addr)?;
+                self.regs.write(inst.rd() as usize, link_addr);
+            }
+
+            // SPEC: All branch instructions use the B-type instruction format. The 12-bit B-immediate encodes signed
+            //       offsets in multiples of 2 bytes. The offset is sign-extended and added to the address of the branch
+            //       instruction to give the target address. The conditional branch range is ±4 KiB.
+            //
+            //       Branch instructions compare two registers.
+            InstructionKind::Beq => {
+                // SPEC: BEQ takes the branch if registers rs1 and rs2 are equal.
+
+                let taken = self.regs[inst.rs1() as usize] == self.regs[inst.rs2() as usize];
+                self.count_branch(taken);
+                if taken {
+                    let target_addr = self.pc.wrapping_add(inst.imm_b() as uxlen);
+                    self.branch(target_addr)?;
+                }
+            }
+            InstructionKind::Bne => {
+                // SPEC: BNE takes the branch if registers rs1 and rs2 are unequal.
+
+                let taken = self.regs[inst.rs1() as usize] != self.regs[inst.rs2() as usize];
+                self.count_branch(taken);
+                if taken {
+                    let target_addr = self.pc.wrapping_add(inst.imm_b() as uxlen);
+                    self.branch(target_addr)?;
+                }
+            }
+            InstructionKind::Blt => {
+                // SPEC: BLT takes the branch if registers rs1 is less than rs2, using signed comparison.
+
+                let taken = (self.regs[inst.rs1() as usize] as ixlen)
+                    < (self.regs[inst.rs2() as usize] as ixlen);
+                self.count_branch(taken);
+                if taken {
+                    let target_addr = self.pc.wrapping_add(inst.imm_b() as uxlen);
+                    self.branch(target_addr)?;
+                }
+            }
+            InstructionKind::Bge => {
+                // SPEC: BGE takes the branch if registers rs1 is greater than or equal to rs2,
+                //       using signed comparison.
+
+                let taken = (self.regs[inst.rs1() as usize] as ixlen)
+                    >= (self.regs[inst.rs2() as usize] as ixlen);
+                self.count_branch(taken);
+                if taken {
+                    let target_addr = self.pc.wrapping_add(inst.imm_b() as uxlen);
+                    self.branch(target_addr)?;
+                }
+            }
+            InstructionKind::Bltu => {
+                // SPEC: BLTU takes the branch if registers rs1 is less than rs2, using unsigned comparison.
+
+                let taken = self.regs[inst.rs1() as usize] < self.regs[inst.rs2() as usize];
+                self.count_branch(taken);
+                if taken {
+                    let target_addr = self.pc.wrapping_add(inst.imm_b() as uxlen);
+                    self.branch(target_addr)?;
+                }
+            }
+            InstructionKind::Bgeu => {
+                // SPEC: BGEU takes the branch if registers rs1 is greater than or equal to rs2,
+                //       using unsigned comparison.
+
+                let taken = self.regs[inst.rs1() as usize] >= self.regs[inst.rs2() as usize];
+                self.count_branch(taken);
+                if taken {
+                    let target_addr = self.pc.wrapping_add(inst.imm_b() as uxlen);
+                    self.branch(target_addr)?;
+                }
+            }
+
+            InstructionKind::Jalr => {
+                // SPEC: The indirect jump instruction JALR (jump and link register) uses the I-type encoding. The target
+                //       address is obtained by adding the sign-extended 12-bit I-immediate to the register rs1, then
+                //       setting the least-significant bit of the result to zero.
+                let rs1 = self.regs[inst.rs1() as usize];
+                let target_addr = rs1.wrapping_add(inst.imm_i() as uxlen) & !1;
+
+                // SPEC: The address of the instruction following the jump (pc+4) is written to register rd.
+                let link_addr = addr.wrapping_add(len);
+
+                // SPEC: The JAL and JALR instructions will generate an instruction-address-misaligned exception if the
+                //       target address is not aligned to a four-byte boundary.
+                self.branch(target_addr)?;
+                self.regs.write(inst.rd() as usize, link_addr);
+            }
+
+            // SPEC: Load and store instructions transfer a value between the registers and memory. Loads are encoded in
+            //       the I-type format and stores are S-type. The effective address is obtained by adding register rs1 to
+            //       the sign-extended 12-bit offset. Loads copy a value from memory to register rd. Stores copy the value
+            //       in register rs2 to memory.
+            InstructionKind::Lb => {
+                // SPEC: LB loads an 8-bit value from memory, then sign-extends to 32-bits before storing in rd.
+                // NOTE: Loads into x0 still access memory and can fault, but the loaded value is discarded.
+                let addr = self.regs[inst.rs1() as usize].wrapping_add(inst.imm_i() as uxlen);
+                let value = i8::from_le_bytes(self.load(addr)?);
+                self.regs.write(inst.rd() as usize, value as ixlen as uxlen);
+            }
+            InstructionKind::Lh => {
+                // SPEC: LH loads a 16-bit value from memory, then sign-extends to 32-bits before storing in rd.
+                let addr = self.regs[inst.rs1() as usize].wrapping_add(inst.imm_i() as uxlen);
+                let value = i16::from_le_bytes(self.load(addr)?);
+                self.regs.write(inst.rd() as usize, value as ixlen as uxlen);
+            }
+            InstructionKind::Lw => {
+                // SPEC: The LW instruction loads a 32-bit value from memory into rd.
+                let addr = self.regs[inst.rs1() as usize].wrapping_add(inst.imm_i() as uxlen);
+                let value = u32::from_le_bytes(self.load(addr)?);
+                self.regs.write(inst.rd() as usize, value as uxlen);
+            }
+            InstructionKind::Lbu => {
+                // SPEC: LBU loads an 8-bit value from memory but then zero extends to 32-bits before storing in rd.
+                let addr = self.regs[inst.rs1() as usize].wrapping_add(inst.imm_i() as uxlen);
+                let value = u8::from_le_bytes(self.load(addr)?);
+                self.regs.write(inst.rd() as usize, value as uxlen);
+            }
+            InstructionKind::Lhu => {
+                // SPEC: LHU loads a 16-bit value from memory but then zero extends to 32-bits before storing in rd.
+                let addr = self.regs[inst.rs1() as usize].wrapping_add(inst.imm_i() as uxlen);
+                let value = u16::from_le_bytes(self.load(addr)?);
+                self.regs.write(inst.rd() as usize, value as uxlen);
+            }
+
+            InstructionKind::Addi => {
+                // SPEC: ADDI adds the sign-extended 12-bit immediate to register rs1. Arithmetic overflow is ignored and the
+                //       result is simply the low XLEN bits of the result.
+
+                let imm = inst.imm_i() as ixlen;
+                let rs1 = self.regs[inst.rs1() as usize] as ixlen;
+                let value = rs1.wrapping_add(imm);
+                self.regs.write(inst.rd() as usize, value as uxlen);
+            }
+
+            InstructionKind::Slti => {
+                // SPEC: SLTI (set less than immediate) places the value 1 in register rd if register rs1 is less than
+                //       the sign-extended immediate when both are treated as signed numbers, else 0 is written to rd.
+                let rs1 = self.regs[inst.rs1() as usize] as ixlen;
+                let imm = inst.imm_i() as ixlen;
+                self.regs.write(inst.rd() as usize, (rs1 < imm) as uxlen);
+            }
+            InstructionKind::Sltiu => {
+                // SPEC: SLTIU is similar but compares the values as unsigned numbers (i.e., the immediate is first
+                //       sign-extended to XLEN bits then treated as an unsigned number).
+                let rs1 = self.regs[inst.rs1() as usize];
+                let imm = inst.imm_i() as ixlen as uxlen;
+                self.regs.write(inst.rd() as usize, (rs1 < imm) as uxlen);
+            }
+            InstructionKind::Xori => {
+                // SPEC: XORI is a logical operations that perform bitwise XOR on register rs1 and
+                //       the sign-extended 12-bit immediate and place the result in rd.
+
+                let rs1 = self.regs[inst.rs1() as usize] as ixlen;
+                let imm = inst.imm_i() as ixlen;
+                self.regs.write(inst.rd() as usize, (rs1 ^ imm) as uxlen);
+            }
+            InstructionKind::Ori => {
+                // SPEC: ORI is a logical operations that perform bitwise OR on register rs1 and
+                //       the sign-extended 12-bit immediate and place the result in rd.
+
+                let rs1 = self.regs[inst.rs1() as usize] as ixlen;
+                let imm = inst.imm_i() as ixlen;
+                self.regs.write(inst.rd() as usize, (rs1 | imm) as uxlen);
+            }
+            InstructionKind::Andi => {
+                // SPEC: ANDI is a logical operations that perform bitwise AND on register rs1 and
+                //       the sign-extended 12-bit immediate and place the result in rd.
+
+                let rs1 = self.regs[inst.rs1() as usize] as ixlen;
+                let imm = inst.imm_i() as ixlen;
+                self.regs.write(inst.rd() as usize, (rs1 & imm) as uxlen);
+            }
+
+            // SPEC: The SW, SH, and SB instructions store 32-bit, 16-bit, and 8-bit values from the low bits of register
+            //       rs2 to memory.
+            InstructionKind::Sb => {
+                let addr = self.regs[inst.rs1() as usize].wrapping_add(inst.imm_s() as uxlen);
+                let value = self.regs[inst.rs2() as usize] as u8;
+                self.check_stack(inst.rs1() as usize, addr)?;
+                self.store(addr, value.to_le_bytes())?;
+            }
+            InstructionKind::Sh => {
+                let addr = self.regs[inst.rs1() as usize].wrapping_add(inst.imm_s() as uxlen);
+                let value = self.regs[inst.rs2() as usize] as u16;
+                self.check_stack(inst.rs1() as usize, addr)?;
+                self.store(addr, value.to_le_bytes())?;
+            }
+            InstructionKind::Sw => {
+                let addr = self.regs[inst.rs1() as usize].wrapping_add(inst.imm_s() as uxlen);
+                let value = self.regs[inst.rs2() as usize];
+                self.check_stack(inst.rs1() as usize, addr)?;
+                self.store(addr, value.to_le_bytes())?;
+            }
+
+            InstructionKind::Slli => {
+                // SPEC: Shifts by a constant are encoded as a specialization of the I-type format.
+                //       The operand to be shifted is in rs1, and the shift amount is encoded in
+                //       the lower 5 bits of the I-immediate field. The right shift type is
+                //       encoded in bit 30.
+
+                // SPEC: SLLI is a logical left shift (zeros are shifted into the lower bits);
+                let shamt = inst.imm_i() & 0b11111;
+                let value = self.regs[inst.rs1() as usize] << shamt;
+                self.regs.write(inst.rd() as usize, value);
+            }
+            InstructionKind::Srli => {
+                // SPEC: SRLI is a logical right shift (zeros are shifted into the upper bits);
+                let shamt = inst.imm_i() & 0b11111;
+                let value = self.regs[inst.rs1() as usize] >> shamt;
+                self.regs.write(inst.rd() as usize, value);
+            }
+            InstructionKind::Srai => {
+                // SPEC: SRAI is an arithmetic right shift (the original sign bit is copied into the vacated upper bits).
+                let shamt = inst.imm_i() & 0b11111;
+                let value = (self.regs[inst.rs1() as usize] as ixlen) >> shamt;
+                self.regs.write(inst.rd() as usize, value as uxlen);
+            }
+
+            // SPEC: RV32I defines several arithmetic R-type operations. All operations read the rs1 and rs2 registers
+            //       as source operands and write the result into register rd.
+            InstructionKind::Add => {
+                // SPEC: ADD performs the addition of rs1 and rs2. Overflows are ignored and the low XLEN bits of
+                //       results are written to the destination rd.
+                let rs1 = self.regs[inst.rs1() as usize];
+                let rs2 = self.regs[inst.rs2() as usize];
+                self.regs.write(inst.rd() as usize, rs1.wrapping_add(rs2));
+            }
+            InstructionKind::Sub => {
+                // SPEC: SUB performs the subtraction of rs2 from rs1.
+                let rs1 = self.regs[inst.rs1() as usize];
+                let rs2 = self.regs[inst.rs2() as usize];
+                self.regs.write(inst.rd() as usize, rs1.wrapping_sub(rs2));
+            }
+            // SPEC: SLL, SRL, and SRA perform logical left, logical right, and arithmetic right shifts on the value in
+            //       register rs1 by the shift amount held in the lower 5 bits of register rs2.
+            InstructionKind::Sll => {
+                let rs1 = self.regs[inst.rs1() as usize];
+                let shamt = self.regs[inst.rs2() as usize] & 0b11111;
+                self.regs.write(inst.rd() as usize, rs1 << shamt);
+            }
+            InstructionKind::Srl => {
+                let rs1 = self.regs[inst.rs1() as usize];
+                let shamt = self.regs[inst.rs2() as usize] & 0b11111;
+                self.regs.write(inst.rd() as usize, rs1 >> shamt);
+            }
+            InstructionKind::Sra => {
+                let rs1 = self.regs[inst.rs1() as usize] as ixlen;
+                let shamt = self.regs[inst.rs2() as usize] & 0b11111;
+                self.regs.write(inst.rd() as usize, (rs1 >> shamt) as uxlen);
+            }
+            // SPEC: SLT and SLTU perform signed and unsigned compares respectively, writing 1 to rd if rs1 < rs2, 0
+            //       otherwise.
+            InstructionKind::Slt => {
+                let rs1 = self.regs[inst.rs1() as usize] as ixlen;
+                let rs2 = self.regs[inst.rs2() as usize] as ixlen;
+                self.regs.write(inst.rd() as usize, (rs1 < rs2) as uxlen);
+            }
+            InstructionKind::Sltu => {
+                let rs1 = self.regs[inst.rs1() as usize];
+                let rs2 = self.regs[inst.rs2() as usize];
+                self.regs.write(inst.rd() as usize, (rs1 < rs2) as uxlen);
+            }
+            // SPEC: AND, OR, and XOR perform bitwise logical operations.
+            InstructionKind::Xor => {
+                let rs1 = self.regs[inst.rs1() as usize];
+                let rs2 = self.regs[inst.rs2() as usize];
+                self.regs.write(inst.rd() as usize, rs1 ^ rs2);
+            }
+            InstructionKind::Or => {
+                let rs1 = self.regs[inst.rs1() as usize];
+                let rs2 = self.regs[inst.rs2() as usize];
+                self.regs.write(inst.rd() as usize, rs1 | rs2);
+            }
+            InstructionKind::And => {
+                let rs1 = self.regs[inst.rs1() as usize];
+                let rs2 = self.regs[inst.rs2() as usize];
+                self.regs.write(inst.rd() as usize, rs1 & rs2);
+            }
+
+            InstructionKind::Mul => {
+                // SPEC: MUL performs an XLEN-bit×XLEN-bit multiplication of rs1 by rs2 and places the lower XLEN bits
+                //       in the destination register.
+                let rs1 = self.regs[inst.rs1() as usize];
+                let rs2 = self.regs[inst.rs2() as usize];
+                self.regs.write(inst.rd() as usize, rs1.wrapping_mul(rs2));
+            }
+            // SPEC: MULH, MULHU, and MULHSU perform the same multiplication but return the upper XLEN bits of the
+            //       full 2×XLEN-bit product, for signed×signed, unsigned×unsigned, and signed rs1×unsigned rs2
+            //       multiplication, respectively.
+            InstructionKind::Mulh => {
+                let rs1 = self.regs[inst.rs1() as usize] as ixlen as i64;
+                let rs2 = self.regs[inst.rs2() as usize] as ixlen as i64;
+                let product = rs1 * rs2;
+                self.regs.write(inst.rd() as usize, (product >> uxlen::BITS) as uxlen);
+            }
+            InstructionKind::Mulhsu => {
+                // NOTE: rs1 is sign-extended while rs2 is zero-extended, so the product has to be computed in i64.
+                let rs1 = self.regs[inst.rs1() as usize] as ixlen as i64;
+                let rs2 = self.regs[inst.rs2() as usize] as u64 as i64;
+                let product = rs1.wrapping_mul(rs2);
+                self.regs.write(inst.rd() as usize, (product >> uxlen::BITS) as uxlen);
+            }
+            InstructionKind::Mulhu => {
+                let rs1 = self.regs[inst.rs1() as usize] as u64;
+                let rs2 = self.regs[inst.rs2() as usize] as u64;
+                let product = rs1 * rs2;
+                self.regs.write(inst.rd() as usize, (product >> uxlen::BITS) as uxlen);
+            }
+
+            // SPEC: DIV and DIVU perform an XLEN bits by XLEN bits signed and unsigned integer division of rs1 by
+            //       rs2, rounding towards zero. REM and REMU provide the remainder of the corresponding division
+            //       operation.
+            //
+            //       The quotient of division by zero has all bits set, and the remainder of division by zero equals
+            //       the dividend. Signed division overflow occurs only when the most-negative integer is divided by
+            //       −1. The quotient of a signed division with overflow is equal to the dividend, and the remainder
+            //       is zero.
+            InstructionKind::Div => {
+                let rs1 = self.regs[inst.rs1() as usize] as ixlen;
+                let rs2 = self.regs[inst.rs2() as usize] as ixlen;
+                let value = match rs2 {
+                    0 => -1,
+                    // NOTE: `wrapping_div` yields the dividend for `ixlen::MIN / -1`, as required.
+                    _ => rs1.wrapping_div(rs2),
+                };
+                self.regs.write(inst.rd() as usize, value as uxlen);
+            }
+            InstructionKind::Divu => {
+                let rs1 = self.regs[inst.rs1() as usize];
+                let rs2 = self.regs[inst.rs2() as usize];
+                let value = rs1.checked_div(rs2).unwrap_or(uxlen::MAX);
+                self.regs.write(inst.rd() as usize, value);
+            }
+            InstructionKind::Rem => {
+                let rs1 = self.regs[inst.rs1() as usize] as ixlen;
+                let rs2 = self.regs[inst.rs2() as usize] as ixlen;
+                let value = match rs2 {
+                    0 => rs1,
+                    // NOTE: `wrapping_rem` yields zero for `ixlen::MIN % -1`, as required.
+                    _ => rs1.wrapping_rem(rs2),
+                };
+                self.regs.write(inst.rd() as usize, value as uxlen);
+            }
+            InstructionKind::Remu => {
+                let rs1 = self.regs[inst.rs1() as usize];
+                let rs2 = self.regs[inst.rs2() as usize];
+                let value = rs1.checked_rem(rs2).unwrap_or(rs1);
+                self.regs.write(inst.rd() as usize, value);
+            }
+
+            InstructionKind::LrW => {
+                // SPEC: LR.W loads a word from the address in rs1, places the sign-extended value in rd, and registers a
+                //       reservation set—a set of bytes that subsumes the bytes in the addressed word.
+                let addr = self.regs[inst.rs1() as usize];
+                check_atomic_alignment(addr, Exception::LoadAddressMisaligned)?;
+                let value = u32::from_le_bytes(self.load(addr)?);
+                self.reservation = Some(addr);
+                self.regs.write(inst.rd() as usize, value as uxlen);
+            }
+            InstructionKind::ScW => {
+                // SPEC: SC.W conditionally writes a word in rs2 to the address in rs1: the SC.W succeeds only if the
+                //       reservation is still valid and the reservation set contains the bytes being written. If the
+                //       SC.W succeeds, the instruction writes the word in rs2 to memory, and it writes zero to rd. If the
+                //       SC.W fails, the instruction does not write to memory, and it writes a nonzero value to rd.
+                //       Regardless of success or failure, executing an SC.W instruction invalidates any reservation
+                //       held by this hart.
+                let addr = self.regs[inst.rs1() as usize];
+                check_atomic_alignment(addr, Exception::StoreAddressMisaligned)?;
+                let reserved = self.reservation.take() == Some(addr);
+                if reserved {
+                    let value = self.regs[inst.rs2() as usize];
+                    self.store(addr, value.to_le_bytes())?;
+                }
+                self.regs.write(inst.rd() as usize, if reserved { 0 } else { 1 });
+            }
+
+            // SPEC: The atomic memory operation (AMO) instructions perform read-modify-write operations for
+            //       multiprocessor synchronization. These AMO instructions atomically load a data value from the address
+            //       in rs1, place the value into register rd, apply a binary operator to the loaded value and the
+            //       original value in rs2, then store the result back to the original address in rs1.
+            // NOTE: Harts are stepped round-robin, one instruction at a time, so no other hart can access memory
+            //       between the load and the store, and executing them back to back is atomic.
+            InstructionKind::AmoswapW => self.amo(inst, |_, rs2| rs2)?,
+            InstructionKind::AmoaddW => self.amo(inst, |loaded, rs2| loaded.wrapping_add(rs2))?,
+            InstructionKind::AmoxorW => self.amo(inst, |loaded, rs2| loaded ^ rs2)?,
+            InstructionKind::AmoandW => self.amo(inst, |loaded, rs2| loaded & rs2)?,
+            InstructionKind::AmoorW => self.amo(inst, |loaded, rs2| loaded | rs2)?,
+            // SPEC: AMOMIN/AMOMAX perform signed min/max operations,
+            InstructionKind::AmominW => {
+                self.amo(inst, |loaded, rs2| (loaded as ixlen).min(rs2 as ixlen) as uxlen)?
+            }
+            InstructionKind::AmomaxW => {
+                self.amo(inst, |loaded, rs2| (loaded as ixlen).max(rs2 as ixlen) as uxlen)?
+            }
+            // SPEC: and AMOMINU/AMOMAXU perform unsigned min/max operations.
+            InstructionKind::AmominuW => self.amo(inst, |loaded, rs2| loaded.min(rs2))?,
+            InstructionKind::AmomaxuW => self.amo(inst, |loaded, rs2| loaded.max(rs2))?,
+
+            InstructionKind::Fence => {}
+            InstructionKind::FenceI => {
+                // SPEC: The FENCE.I instruction is used to synchronize the instruction and data streams.
+                // NOTE: Instructions are fetched from memory in every cycle, so stores are always visible
+                //       to subsequent instruction fetches and there is nothing to synchronize.
+            }
+            InstructionKind::ECall => {
+                #[cfg(feature = "std")]
+                if let Some(mut semihosting) = self.semihosting.take() {
+                    let handled = semihosting.syscall(self);
+                    self.semihosting = Some(semihosting);
+                    if handled {
+                        return Ok(());
+                    }
+                }
+
+                match self.handle_ecall.take() {
+                    // NOTE: A registered handler services the call on the host instead of the program.
+                    //       It is taken out while it runs, as it borrows the CPU mutably.
+                    Some(handle_ecall) => {
+                        handle_ecall(self);
+                        self.handle_ecall = Some(handle_ecall);
+                    }
+                    // SPEC: The ECALL instruction is used to make a service request to the execution environment. The EEI
+                    //       will define how parameters for the service request are passed, but usually these will be in
+                    //       defined locations in the integer register file.
+                    None => {
+                        let cause = match self.privilege {
+                            PrivilegeMode::User => Exception::EnvironmentCallFromUMode,
+                            PrivilegeMode::Supervisor => Exception::EnvironmentCallFromSMode,
+                            PrivilegeMode::Machine => Exception::EnvironmentCallFromMMode,
+                        };
+                        return Err(Trap::new(cause, 0));
+                    }
+                }
+            }
+            InstructionKind::EBreak => match &self.handle_ebreak {
+                // NOTE: A registered handler lets a debugger on the host react to the breakpoint.
+                Some(handle_ebreak) => handle_ebreak(self),
+                // SPEC: The EBREAK instruction is used to return control to a debugging environment.
+                // NOTE: Like the faulting address for memory exceptions, mtval holds the address of the breakpoint.
+                None => return Err(Trap::new(Exception::Breakpoint, addr)),
+            },
+
+            InstructionKind::Sret => {
+                // SPEC: When TSR=1, attempts to execute SRET while executing in S-mode will raise an illegal
+                //       instruction exception.
+                // NOTE: Executing SRET in user mode raises an illegal-instruction exception as well.
+                let mstatus = self.csrs.mstatus();
+                if self.privilege == PrivilegeMode::User
+                    || (self.privilege == PrivilegeMode::Supervisor && mstatus & MSTATUS_TSR != 0)
+                {
+                    return Err(Trap::new(Exception::IllegalInstruction, inst.0));
+                }
+
+                // SPEC: SPP is set to 0 if the trap originated from user mode, or 1 otherwise. When an SRET instruction
+                //       is executed to return from the trap handler, the privilege level is set to user mode if the SPP
+                //       bit is 0, or supervisor mode if the SPP bit is 1; SPP is then set to 0.
+                self.privilege = if mstatus & MSTATUS_SPP != 0 {
+                    PrivilegeMode::Supervisor
+                } else {
+                    PrivilegeMode::User
+                };
+
+                let mut new_mstatus = (mstatus & !MSTATUS_SPP) | MSTATUS_SPIE;
+                if mstatus & MSTATUS_SPIE != 0 {
+                    new_mstatus |= MSTATUS_SIE;
+                } else {
+                    new_mstatus &= !MSTATUS_SIE;
+                }
+                self.csrs.set_mstatus(new_mstatus);
+
+                // SPEC: The pc is set to the value stored in sepc.
+                self.jump_target = Some(self.csrs.sepc());
+            }
+            InstructionKind::Mret => {
+                // SPEC: An MRET instruction is used to return from a trap in M-mode. When executing an xRET instruction,
+                //       supposing xPP holds the value y, xIE is set to xPIE; the privilege mode is changed to y; xPIE is
+                //       set to 1; and xPP is set to the least-privileged supported mode.
+                // NOTE: Executing MRET in a lower privilege mode raises an illegal-instruction exception.
+                if self.privilege != PrivilegeMode::Machine {
+                    return Err(Trap::new(Exception::IllegalInstruction, inst.0));
+                }
+
+                let mstatus = self.csrs.mstatus();
+                // NOTE: MPP can not hold the reserved encoding on real hardware, so it is treated as user mode.
+                self.privilege =
+                    PrivilegeMode::from_bits((mstatus & MSTATUS_MPP) >> MSTATUS_MPP_SHIFT)
+                        .unwrap_or(PrivilegeMode::User);
+
+                let mut new_mstatus = (mstatus & !MSTATUS_MPP) | MSTATUS_MPIE;
+                if mstatus & MSTATUS_MPIE != 0 {
+                    new_mstatus |= MSTATUS_MIE;
+                } else {
+                    new_mstatus &= !MSTATUS_MIE;
+                }
+                self.csrs.set_mstatus(new_mstatus);
+
+                // SPEC: The pc is set to the value stored in mepc.
+                self.jump_target = Some(self.csrs.mepc());
+            }
+            InstructionKind::Wfi => {
+                // SPEC: The Wait for Interrupt instruction (WFI) informs the implementation that the current hart can
+                //       be stalled until an interrupt might need servicing. It is also legal to implement WFI as a NOP.
+                // NOTE: WFI is implemented as a NOP. Pending interrupts are taken before the next instruction,
+                //       so a program waiting in a loop around WFI still sees them, once `mtime` reaches `mtimecmp`
+                //       or another hart sets `msip`.
+            }
+
+            // SPEC: The CSR instructions atomically read-modify-write a single CSR, whose CSR specifier is encoded in
+            //       the 12-bit csr field of the instruction held in bits 31–20.
+            InstructionKind::Csrrw => {
+                // SPEC: CSRRW reads the old value of the CSR, zero-extends the value to XLEN bits, then writes it to
+                //       integer register rd. The initial value in rs1 is written to the CSR.
+                let csr = inst.csr() as usize;
+                let rs1 = self.regs[inst.rs1() as usize];
+                check_csr_writable(inst)?;
+                let old = self.read_csr(csr);
+                self.write_csr(csr, rs1);
+                self.regs.write(inst.rd() as usize, old);
+            }
+            InstructionKind::Csrrs => {
+                // SPEC: CSRRS reads the value of the CSR, zero-extends the value to XLEN bits, and writes it to integer
+                //       register rd. The initial value in integer register rs1 is treated as a bit mask that specifies
+                //       bit positions to be set in the CSR.
+                let csr = inst.csr() as usize;
+                let rs1 = self.regs[inst.rs1() as usize];
+                let old = self.read_csr(csr);
+                // SPEC: If rs1=x0, then the instruction will not write to the CSR at all.
+                if inst.rs1() != 0 {
+                    check_csr_writable(inst)?;
+                    self.write_csr(csr, old | rs1);
+                }
+                self.regs.write(inst.rd() as usize, old);
+            }
+            InstructionKind::Csrrc => {
+                // SPEC: CSRRC reads the value of the CSR, zero-extends the value to XLEN bits, and writes it to integer
+                //       register rd. The initial value in integer register rs1 is treated as a bit mask that specifies
+                //       bit positions to be cleared in the CSR.
+                let csr = inst.csr() as usize;
+                let rs1 = self.regs[inst.rs1() as usize];
+                let old = self.read_csr(csr);
+                // SPEC: If rs1=x0, then the instruction will not write to the CSR at all.
+                if inst.rs1() != 0 {
+                    check_csr_writable(inst)?;
+                    self.write_csr(csr, old & !rs1);
+                }
+                self.regs.write(inst.rd() as usize, old);
+            }
+
+            // SPEC: The CSRRWI, CSRRSI, and CSRRCI variants are similar to CSRRW, CSRRS, and CSRRC respectively,
+            //       except they update the CSR using an XLEN-bit value obtained by zero-extending a 5-bit unsigned
+            //       immediate (uimm[4:0]) field encoded in the rs1 field instead of a value from an integer register.
+            InstructionKind::Csrrwi => {
+                let csr = inst.csr() as usize;
+                check_csr_writable(inst)?;
+                let old = self.read_csr(csr);
+                self.write_csr(csr, inst.imm_csr() as uxlen);
+                self.regs.write(inst.rd() as usize, old);
+            }
+            InstructionKind::Csrrsi => {
+                let csr = inst.csr() as usize;
+                let old = self.read_csr(csr);
+                // SPEC: For CSRRSI and CSRRCI, if the uimm[4:0] field is zero, then these instructions will not write
+                //       to the CSR.
+                if inst.imm_csr() != 0 {
+                    check_csr_writable(inst)?;
+                    self.write_csr(csr, old | inst.imm_csr() as uxlen);
+                }
+                self.regs.write(inst.rd() as usize, old);
+            }
+            InstructionKind::Csrrci => {
+                let csr = inst.csr() as usize;
+                let old = self.read_csr(csr);
+                // SPEC: For CSRRSI and CSRRCI, if the uimm[4:0] field is zero, then these instructions will not write
+                //       to the CSR.
+                if inst.imm_csr() != 0 {
+                    check_csr_writable(inst)?;
+                    self.write_csr(csr, old & !(inst.imm_csr() as uxlen));
+                }
+                self.regs.write(inst.rd() as usize, old);
+            }
+
+            InstructionKind::Unknown if self.strict_decode => {
+                // NOTE: Every instruction the decoder recognizes is implemented, so an unknown instruction
+                //       is a reserved or unsupported encoding, and raises an illegal-instruction exception.
+                return Err(Trap::new(Exception::IllegalInstruction, inst.0));
+            }
+            InstructionKind::Unknown => {}
+        }
+
+        Ok(())
+    }
+}
+
+/// The address range of `size` bytes at `base`, which must not extend past the end of the address space.
+fn region(base: uxlen, size: uxlen) -> Result<Range<uxlen>, EmuError> {
+    base.checked_add(size).map(|end| base..end).ok_or(EmuError::RegionOverflow { base, size })
+}
+
+/// Raises an illegal-instruction exception if the CSR instruction writes to a read-only CSR.
+fn check_csr_writable(inst: Instruction) -> Result<(), Trap> {
+    // SPEC: The top two bits (csr[11:10]) indicate whether the register is read/write (00, 01, or 10) or
+    //       read-only (11). Attempts to write a read-only register raise an illegal-instruction exception.
+    match inst.csr() >> 10 == 0b11 {
+        true => Err(Trap::new(Exception::IllegalInstruction, inst.0)),
+        false => Ok(()),
+    }
+}
+
+/// Raises the given address-misaligned exception if the address of an LR, SC or AMO is not word-aligned.
+fn check_atomic_alignment(addr: uxlen, cause: Exception) -> Result<(), Trap> {
+    // SPEC: For LR and SC, the A extension requires that the address held in rs1 be naturally aligned to the size
+    //       of the operand. If the address is not naturally aligned, an address-misaligned exception or an
+    //       access-fault exception will be generated. The same holds for AMOs.
+    // NOTE: Unlike ordinary loads and stores, which may be misaligned, atomics are always checked.
+    match addr.is_multiple_of(size_of::<u32>() as uxlen) {
+        true => Ok(()),
+        false => Err(Trap::new(cause, addr)),
+    }
+}
+
+/// Writes the bytes of an initial stack laid out by [InitialStack::layout] to memory, starting at `sp`.
+fn write_initial_stack(memory: &mut MemoryMap, sp: uxlen, bytes: &[u8]) -> Result<(), MemError> {
+    for (i, byte) in bytes.iter().enumerate() {
+        memory.write(sp.wrapping_add(i as uxlen), *byte)?;
+    }
+    Ok(())
+}
+
+/// The address a trap with the given cause jumps to, given the `mtvec` or `stvec` it is taken through.
+fn trap_vector(tvec: uxlen, cause: uxlen) -> uxlen {
+    // The lowest two bits of the vector encode its mode.
+    let base = tvec & !0b11;
+    // SPEC: When MODE=Direct, all traps into machine mode cause the pc to be set to the address in the BASE
+    //       field. When MODE=Vectored, all synchronous exceptions into machine mode cause the pc to be set to
+    //       the address in the BASE field, whereas interrupts cause the pc to be set to the address in the BASE
+    //       field plus four times the interrupt cause number.
+    match (tvec & 0b11, cause & INTERRUPT_BIT != 0) {
+        (MTVEC_MODE_VECTORED, true) => base.wrapping_add(4 * (cause & !INTERRUPT_BIT)),
+        _ => base,
+    }
+}
+
+/// Interprets up to four little-endian bytes as an unsigned value.
+fn le_value(bytes: &[u8]) -> uxlen {
+    bytes.iter().rev().fold(0, |value, byte| (value << 8) | *byte as uxlen)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{asm::Assembler, clint::CLINT_BASE, reg::Reg};
+
+    const BASE: uxlen = 0x8000_0000;
+
+    /// Lays out the instruction words of a program in little-endian order.
+    fn program(words: &[u32]) -> Vec<u8> {
+        words.iter().flat_map(|word| word.to_le_bytes()).collect()
+    }
+
+    /// Runs a program until it falls off the end of the ROM and returns the final registers.
+    fn run(words: &[u32]) -> Registers {
+        let mut bytes = program(words);
+        let end = BASE + bytes.len() as uxlen;
+        let mut rom = Rom::new(&mut bytes, BASE, end);
+        let mut cpu = Cpu::new(&mut rom, false).unwrap();
+        cpu.run().unwrap();
+        *cpu.registers()
+    }
+
+    #[test]
+    fn runs_assembled_program() {
+        let mut bytes = Assembler::new()
+            .addi(Reg::A0, Reg::Zero, 6)
+            .addi(Reg::A1, Reg::Zero, 7)
+            .add(Reg::A2, Reg::A0, Reg::A1)
+            .slli(Reg::A3, Reg::A2, 4)
+            .sub(Reg::A4, Reg::A3, Reg::A0)
+            .into_bytes();
+        let end = BASE + bytes.len() as uxlen;
+        let mut rom = Rom::new(&mut bytes, BASE, end);
+        let mut cpu = Cpu::new(&mut rom, false).unwrap();
+        cpu.run().unwrap();
+        assert_eq!(cpu.pc(), end);
+        assert_eq!(cpu.registers()[Reg::A2], 13);
+        assert_eq!(cpu.registers()[Reg::A3], 13 << 4);
+        assert_eq!(cpu.registers()[Reg::A4], (13 << 4) - 6);
+    }
+
+    #[test]
+    fn stops_at_breakpoint_in_loop() {
+        let mut bytes = program(&[
+            0x00300113, // addi x2, x0, 3
+            0x00108093, // addi x1, x1, 1
+            0xfe209ee3, // bne  x1, x2, -4
+        ]);
+        let end = BASE + bytes.len() as uxlen;
+        let mut rom = Rom::new(&mut bytes, BASE, end);
+        let mut cpu = Cpu::new(&mut rom, false).unwrap();
+        cpu.add_breakpoint(BASE + 4);
+
+        // Every iteration stops before the instruction at the breakpoint.
+        for iteration in 0..3 {
+            cpu.run().unwrap();
+            assert_eq!(cpu.pc(), BASE + 4);
+            assert_eq!(cpu.registers()[1], iteration);
+        }
+
+        cpu.remove_breakpoint(BASE + 4);
+        cpu.run().unwrap();
+        assert_eq!(cpu.pc(), end);
+        assert_eq!(cpu.registers()[1], 3);
+    }
+
+    #[test]
+    fn multiply_high_with_negative_and_large_unsigned_operands() {
+        const MULTIPLY: [u32; 4] = [
+            0x02628533, // mul    x10, x5, x6
+            0x026295b3, // mulh   x11, x5, x6
+            0x0262a633, // mulhsu x12, x5, x6
+            0x0262b6b3, // mulhu  x13, x5, x6
+        ];
+
+        // -2 * 0xffffffff, where rs2 is -1 when signed.
+        let regs = run(&[
+            &[
+                0xffe00293, // addi x5, x0, -2
+                0xfff00313, // addi x6, x0, -1
+            ][..],
+            &MULTIPLY,
+        ]
+        .concat());
+        assert_eq!(regs[10], 2);
+        assert_eq!(regs[11], 0);
+        assert_eq!(regs[12], 0xffff_fffe);
+        assert_eq!(regs[13], 0xffff_fffd);
+
+        // -1 * 0x80000000, where rs2 is INT_MIN when signed.
+        let regs = run(&[
+            &[
+                0xfff00293, // addi x5, x0, -1
+                0x00100313, // addi x6, x0, 1
+                0x01f31313, // slli x6, x6, 31
+            ][..],
+            &MULTIPLY,
+        ]
+        .concat());
+        assert_eq!(regs[10], 0x8000_0000);
+        assert_eq!(regs[11], 0);
+        assert_eq!(regs[12], 0xffff_ffff);
+        assert_eq!(regs[13], 0x7fff_ffff);
+    }
+
+    #[test]
+    fn division_edge_cases() {
+        const DIVIDE: [u32; 4] = [
+            0x0262c533, // div  x10, x5, x6
+            0x0262d5b3, // divu x11, x5, x6
+            0x0262e633, // rem  x12, x5, x6
+            0x0262f6b3, // remu x13, x5, x6
+        ];
+
+        // SPEC: Division by zero gives a quotient with all bits set and the dividend as remainder.
+        let regs = run(&[
+            &[
+                0xff900293, // addi x5, x0, -7
+            ][..],
+            &DIVIDE,
+        ]
+        .concat());
+        assert_eq!(regs[10], uxlen::MAX);
+        assert_eq!(regs[11], uxlen::MAX);
+        assert_eq!(regs[12], -7i32 as uxlen);
+        assert_eq!(regs[13], -7i32 as uxlen);
+
+        // SPEC: Signed overflow gives the dividend as quotient and a remainder of zero.
+        let regs = run(&[
+            &[
+                0x00100293, // addi x5, x0, 1
+                0x01f29293, // slli x5, x5, 31
+                0xfff00313, // addi x6, x0, -1
+            ][..],
+            &DIVIDE,
+        ]
+        .concat());
+        assert_eq!(regs[10], ixlen::MIN as uxlen);
+        assert_eq!(regs[11], 0);
+        assert_eq!(regs[12], 0);
+        assert_eq!(regs[13], ixlen::MIN as uxlen);
+    }
+
+    #[test]
+    fn illegal_instruction_traps_to_mtvec() {
+        let mut bytes = program(&[
+            0x00000297, // auipc x5, 0
+            0x01028293, // addi  x5, x5, 16
+            0x30529073, // csrrw x0, mtvec, x5
+            0xffffffff, // (illegal)
+        ]);
+        let end = BASE + bytes.len() as uxlen;
+        let mut rom = Rom::new(&mut bytes, BASE, end);
+        let mut cpu = Cpu::new(&mut rom, false).unwrap();
+        cpu.run().unwrap();
+
+        // The handler is the end of the ROM, so the CPU stops right after taking the trap.
+        assert_eq!(cpu.pc(), end);
+        assert_eq!(cpu.csrs().mcause(), Exception::IllegalInstruction.code());
+        assert_eq!(cpu.csrs().mepc(), BASE + 12);
+        assert_eq!(cpu.csrs().mtval(), 0xffffffff);
+    }
+
+    #[test]
+    fn mret_returns_past_illegal_instruction() {
+        let mut bytes = program(&[
+            0x00000297, // auipc x5, 0
+            0x03028393, // addi  x7, x5, 48
+            0x02028293, // addi  x5, x5, 32
+            0x30529073, // csrrw x0, mtvec, x5
+            0xffffffff, // (illegal)
+            0x00100513, // addi  x10, x0, 1
+            0x34139073, // csrrw x0, mepc, x7
+            0x30200073, // mret
+            // Trap handler: skip the faulting instruction.
+            0x34102373, // csrrs x6, mepc, x0
+            0x00430313, // addi  x6, x6, 4
+            0x34131073, // csrrw x0, mepc, x6
+            0x30200073, // mret
+        ]);
+        let end = BASE + bytes.len() as uxlen;
+        let mut rom = Rom::new(&mut bytes, BASE, end);
+        let mut cpu = Cpu::new(&mut rom, false).unwrap();
+        cpu.run().unwrap();
+
+        // The handler returned to the instruction after the illegal one,
+        // which then used MRET to leave the program.
+        assert_eq!(cpu.pc(), end);
+        assert_eq!(cpu.registers()[10], 1);
+        assert_eq!(cpu.csrs().mcause(), Exception::IllegalInstruction.code());
+        assert_ne!(cpu.csrs().mstatus() & MSTATUS_MPIE, 0);
+    }
+
+    #[test]
+    fn counts_retired_instructions_of_loop() {
+        let mut bytes = program(&[
+            0x00300113, // addi  x2, x0, 3
+            0x00108093, // addi  x1, x1, 1
+            0xfe209ee3, // bne   x1, x2, -4
+            0xc02021f3, // csrrs x3, instret, x0
+            0xc0002273, // csrrs x4, cycle, x0
+        ]);
+        let end = BASE + bytes.len() as uxlen;
+        let mut rom = Rom::new(&mut bytes, BASE, end);
+        let mut cpu = Cpu::new(&mut rom, false).unwrap();
+        cpu.run().unwrap();
+
+        // One setup instruction and three iterations of two instructions each.
+        assert_eq!(cpu.registers()[3], 7);
+        assert_eq!(cpu.registers()[4], 8);
+        assert_eq!(cpu.instret(), 9);
+    }
+
+    #[test]
+    fn store_conditional_succeeds_once_per_reservation() {
+        let mut bytes = program(&[
+            0x00100293, // addi x5, x0, 1
+            0x01c29293, // slli x5, x5, 28
+            0x00700313, // addi x6, x0, 7
+            0x1002a3af, // lr.w x7, (x5)
+            0x1862a52f, // sc.w x10, x6, (x5)
+            0x1862a5af, // sc.w x11, x6, (x5)
+            0x0002a603, // lw   x12, 0(x5)
+        ]);
+        let end = BASE + bytes.len() as uxlen;
+        let mut rom = Rom::new(&mut bytes, BASE, end);
+        let mut cpu = Cpu::builder(&mut rom).ram(0x1000_0000, 0x100).build().unwrap();
+        cpu.run().unwrap();
+
+        // The first store-conditional consumes the reservation, so the second one fails.
+        assert_eq!(cpu.registers()[10], 0);
+        assert_eq!(cpu.registers()[11], 1);
+        assert_eq!(cpu.registers()[12], 7);
+    }
+
+    #[test]
+    fn amo_min_and_max_respect_signedness() {
+        let mut bytes = program(&[
+            0x00100293, // addi      x5, x0, 1
+            0x01c29293, // slli      x5, x5, 28
+            0xfff00313, // addi      x6, x0, -1
+            0x00100393, // addi      x7, x0, 1
+            0x0062a023, // sw        x6, 0(x5)
+            0x8072a52f, // amomin.w  x10, x7, (x5)
+            0x0002a583, // lw        x11, 0(x5)
+            0x0062a023, // sw        x6, 0(x5)
+            0xa072a62f, // amomax.w  x12, x7, (x5)
+            0x0002a683, // lw        x13, 0(x5)
+            0x0062a023, // sw        x6, 0(x5)
+            0xc072a72f, // amominu.w x14, x7, (x5)
+            0x0002a783, // lw        x15, 0(x5)
+            0x0062a023, // sw        x6, 0(x5)
+            0xe072a82f, // amomaxu.w x16, x7, (x5)
+            0x0002a883, // lw        x17, 0(x5)
+        ]);
+        let end = BASE + bytes.len() as uxlen;
+        let mut rom = Rom::new(&mut bytes, BASE, end);
+        let mut cpu = Cpu::builder(&mut rom).ram(0x1000_0000, 0x100).build().unwrap();
+        cpu.run().unwrap();
+
+        // Each AMO returns the original -1 and stores the minimum or maximum of -1 and 1.
+        let values: Vec<uxlen> = (10..18).map(|ix| cpu.registers()[ix]).collect();
+        assert_eq!(
+            values,
+            [uxlen::MAX, uxlen::MAX, uxlen::MAX, 1, uxlen::MAX, 1, uxlen::MAX, uxlen::MAX]
+        );
+    }
+
+    #[test]
+    fn fetching_past_the_rom_is_a_single_access_fault() {
+        // The last instruction is cut off after its first halfword.
+        let mut bytes = program(&[
+            0x00000013, // addi x0, x0, 0
+            0x00100513, // addi x10, x0, 1
+        ]);
+        bytes.truncate(6);
+        let mut rom = Rom::new(&mut bytes, BASE, BASE + 6);
+        let mut cpu = Cpu::new(&mut rom, false).unwrap();
+
+        assert_eq!(cpu.run(), Err(EmuError::MemoryFault { addr: BASE + 4 }));
+        assert_eq!(cpu.pc(), BASE + 4);
+        assert_eq!(cpu.instret(), 1);
+        assert_eq!(cpu.registers()[10], 0);
+    }
+
+    #[test]
+    fn self_modifying_code_runs_the_new_instruction() {
+        // Both iterations replace the instruction at the start of the loop, which has been cached by then.
+        let run = |store: u32, value: uxlen| {
+            let mut bytes = program(&[
+                0x00200293, // addi x5, x0, 2
+                0x00000317, // auipc x6, 0
+                0x00150513, // addi x10, x10, 1
+                store, 0xfff28293, // addi x5, x5, -1
+                0xfe029ae3, // bne  x5, x0, -12
+            ]);
+            let end = BASE + bytes.len() as uxlen;
+            let mut rom = Rom::new(&mut bytes, BASE, end);
+            let mut cpu =
+                Cpu::builder(&mut rom).decode_cache(true).register(Reg::X7, value).build().unwrap();
+            cpu.run().unwrap();
+            cpu.registers()[10]
+        };
+
+        let new = 0x00a50513; // addi x10, x10, 10
+        assert_eq!(run(0x00732223 /* sw x7, 4(x6) */, new), 1 + 10);
+
+        // Only overwriting the upper halfword also invalidates the instruction that starts before it.
+        assert_eq!(run(0x00731323 /* sh x7, 6(x6) */, new >> 16), 1 + 10);
+    }
+
+    #[test]
+    fn restored_snapshot_continues_identically() {
+        // The loop counts to 5, storing the count in the data word at the start of the ROM.
+        let words = [
+            0x00000000, // (data)
+            0x00500113, // addi  x2, x0, 5
+            0x00000197, // auipc x3, 0
+            0x00108093, // addi  x1, x1, 1
+            0xfe11ac23, // sw    x1, -8(x3)
+            0xfe209ce3, // bne   x1, x2, -8
+        ];
+        let mut bytes = program(&words);
+        let end = BASE + bytes.len() as uxlen;
+        let mut rom = Rom::new(&mut bytes, BASE, end);
+        let mut cpu = Cpu::builder(&mut rom).entry(BASE + 4).build().unwrap();
+        for _ in 0..7 {
+            cpu.step().unwrap();
+        }
+        let snapshot = cpu.snapshot();
+        assert_eq!(snapshot.memory[..4], [2, 0, 0, 0]);
+
+        let mut other_bytes = program(&words);
+        let mut other_rom = Rom::new(&mut other_bytes, BASE, end);
+        let mut other = Cpu::builder(&mut other_rom).build().unwrap();
+        other.restore(snapshot).unwrap();
+
+        cpu.run().unwrap();
+        other.run().unwrap();
+        assert_eq!(cpu.snapshot(), other.snapshot());
+        assert_eq!(other.registers()[1], 5);
+        assert_eq!(other.snapshot().memory[..4], [5, 0, 0, 0]);
+    }
+
+    #[test]
+    fn only_taken_branches_to_misaligned_targets_trap() {
+        let run = |branch: u32| {
+            let mut bytes = program(&[
+                branch,     // (branch)
+                0x00100513, // addi x10, x0, 1
+            ]);
+            let end = BASE + bytes.len() as uxlen;
+            let mut rom = Rom::new(&mut bytes, BASE, end);
+            let extensions = Extensions { m: true, a: true, c: false };
+            let mut cpu = Cpu::builder(&mut rom).extensions(extensions).build().unwrap();
+            let result = cpu.run();
+            (result, cpu.registers()[10])
+        };
+
+        assert_eq!(run(0x00001163 /* bne x0, x0, 2 */), (Ok(HaltReason::EndOfRom), 1));
+        assert_eq!(
+            run(0x00000163 /* beq x0, x0, 2 */),
+            (Err(EmuError::Misaligned { addr: BASE + 2 }), 0)
+        );
+    }
+
+    #[test]
+    fn ecall_runs_handler_or_traps() {
+        let words = [
+            0x00000297, // auipc x5, 0
+            0x01428293, // addi  x5, x5, 20
+            0x30529073, // csrrw x0, mtvec, x5
+            0x00000073, // ecall
+            0x00100513, // addi  x10, x0, 1
+            // Trap handler.
+            0x342025f3, // csrrs x11, mcause, x0
+        ];
+        let end = BASE + 4 * words.len() as uxlen;
+
+        // A registered handler services the call, after which the program continues.
+        let calls = std::rc::Rc::new(Cell::new(0));
+        let mut bytes = program(&words);
+        let mut rom = Rom::new(&mut bytes, BASE, end);
+        let handler_calls = calls.clone();
+        let mut cpu = Cpu::builder(&mut rom)
+            .on_ecall(Box::new(move |_| handler_calls.set(handler_calls.get() + 1)))
+            .build()
+            .unwrap();
+        cpu.run().unwrap();
+        assert_eq!(calls.get(), 1);
+        assert_eq!(cpu.registers()[10], 1);
+        assert_eq!(cpu.registers()[11], 0);
+
+        // Without a handler, the program's own trap handler is entered.
+        let mut bytes = program(&words);
+        let mut rom = Rom::new(&mut bytes, BASE, end);
+        let mut cpu = Cpu::builder(&mut rom).build().unwrap();
+        cpu.run().unwrap();
+        assert_eq!(cpu.registers()[10], 0);
+        assert_eq!(cpu.registers()[11], Exception::EnvironmentCallFromMMode.code());
+        assert_eq!(cpu.csrs().mepc(), BASE + 12);
+    }
+
+    #[test]
+    fn stack_pointer_starts_where_requested() {
+        let mut bytes = program(&[0x00000013]);
+        let mut rom = Rom::new(&mut bytes, BASE, BASE + 4);
+        let cpu = Cpu::builder(&mut rom).ram(0x1000_0000, 0x1000).build().unwrap();
+        assert_eq!(cpu.registers()[Reg::Sp], 0x1000_1000);
+
+        let mut bytes = program(&[0x00000013]);
+        let mut rom = Rom::new(&mut bytes, BASE, BASE + 4);
+        let cpu = Cpu::builder(&mut rom)
+            .ram(0x1000_0000, 0x1000)
+            .stack_pointer(0x1000_0800)
+            .build()
+            .unwrap();
+        assert_eq!(cpu.registers()[Reg::Sp], 0x1000_0800);
+
+        // The stack has to grow down into mapped memory.
+        let mut bytes = program(&[0x00000013]);
+        let mut rom = Rom::new(&mut bytes, BASE, BASE + 4);
+        let err = Cpu::builder(&mut rom).stack_pointer(0x1000_0000).build().err();
+        assert_eq!(err, Some(EmuError::InvalidStackPointer { sp: 0x1000_0000 }));
+    }
+
+    #[test]
+    fn ebreak_halts_with_breakpoint_outcome() {
+        let mut bytes = program(&[
+            0x00100073, // ebreak
+            0x00100513, // addi x10, x0, 1
+        ]);
+        let end = BASE + bytes.len() as uxlen;
+        let mut rom = Rom::new(&mut bytes, BASE, end);
+        let mut cpu = Cpu::new(&mut rom, false).unwrap();
+
+        let outcome = cpu.step().unwrap();
+        assert!(outcome.breakpoint);
+        assert!(!outcome.running);
+        assert_eq!(cpu.pc(), BASE);
+
+        // The CPU stays halted at the EBREAK instruction.
+        cpu.run().unwrap();
+        assert_eq!(cpu.pc(), BASE);
+        assert_eq!(cpu.registers()[10], 0);
+    }
+
+    #[test]
+    fn user_mode_write_to_machine_csr_traps() {
+        let mut bytes = program(&[
+            0x00000297, // auipc x5, 0
+            0x02828313, // addi  x6, x5, 40
+            0x30531073, // csrrw x0, mtvec, x6
+            0x02428313, // addi  x6, x5, 36
+            0x34131073, // csrrw x0, mepc, x6
+            0x00300393, // addi  x7, x0, 3
+            0x00b39393, // slli  x7, x7, 11
+            0x3003b073, // csrrc x0, mstatus, x7
+            0x30200073, // mret
+            // User mode.
+            0x34029073, // csrrw x0, mscratch, x5
+            // Trap handler.
+            0x342025f3, // csrrs x11, mcause, x0
+        ]);
+        let end = BASE + bytes.len() as uxlen;
+        let mut rom = Rom::new(&mut bytes, BASE, end);
+        let mut cpu = Cpu::new(&mut rom, false).unwrap();
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.registers()[11], Exception::IllegalInstruction.code());
+        assert_eq!(cpu.csrs().mepc(), BASE + 36);
+        assert_eq!(cpu.csrs().mscratch(), 0);
+        // The trap was taken from user mode into machine mode.
+        assert_eq!(cpu.csrs().mstatus() & MSTATUS_MPP, 0);
+        assert_eq!(cpu.privilege(), PrivilegeMode::Machine);
+    }
+
+    #[test]
+    fn delegated_user_ecall_is_taken_in_supervisor_mode() {
+        let mut bytes = program(&[
+            0x00000297, // auipc x5, 0
+            0x03028313, // addi  x6, x5, 48
+            0x10531073, // csrrw x0, stvec, x6
+            0x10000393, // addi  x7, x0, 256
+            0x30239073, // csrrw x0, medeleg, x7
+            0x02c28313, // addi  x6, x5, 44
+            0x34131073, // csrrw x0, mepc, x6
+            0x00300393, // addi  x7, x0, 3
+            0x00b39393, // slli  x7, x7, 11
+            0x3003b073, // csrrc x0, mstatus, x7
+            0x30200073, // mret
+            // User mode.
+            0x00000073, // ecall
+            // Supervisor trap handler.
+            0x142025f3, // csrrs x11, scause, x0
+        ]);
+        let end = BASE + bytes.len() as uxlen;
+        let mut rom = Rom::new(&mut bytes, BASE, end);
+        let mut cpu = Cpu::new(&mut rom, false).unwrap();
+        cpu.run().unwrap();
+
+        // There is no handler in `mtvec`, so the ECALL only reaches the handler through `stvec`.
+        assert_eq!(cpu.registers()[11], Exception::EnvironmentCallFromUMode.code());
+        assert_eq!(cpu.csrs().sepc(), BASE + 44);
+        assert_eq!(cpu.csrs().mcause(), 0);
+        assert_eq!(cpu.csrs().mstatus() & MSTATUS_SPP, 0);
+        assert_eq!(cpu.privilege(), PrivilegeMode::Supervisor);
+    }
+
+    #[test]
+    fn timer_interrupt_ends_wait_for_interrupt_loop() {
+        let mut bytes = program(&[
+            // Raise a timer interrupt once `mtime` reaches 20.
+            0x00100293, // addi  x5, x0, 1
+            0x00b29293, // slli  x5, x5, 11
+            0x00128293, // addi  x5, x5, 1
+            0x00e29293, // slli  x5, x5, 14
+            0x01400313, // addi  x6, x0, 20
+            0x0062a023, // sw    x6, 0(x5)
+            0x0002a223, // sw    x0, 4(x5)
+            // Trap to the handler at the end of the program.
+            0x00000397, // auipc x7, 0
+            0x02438393, // addi  x7, x7, 36
+            0x30539073, // csrrw x0, mtvec, x7
+            // Enable the machine timer interrupt, and interrupts in M-mode.
+            0x08000e13, // addi  x28, x0, 0x80
+            0x304e2073, // csrrs x0, mie, x28
+            0x00800e13, // addi  x28, x0, 8
+            0x300e2073, // csrrs x0, mstatus, x28
+            0x10500073, // wfi
+            0xfe000ee3, // beq   x0, x0, -4
+            // Trap handler.
+            0x34202573, // csrrs x10, mcause, x0
+            0x341025f3, // csrrs x11, mepc, x0
+        ]);
+        let end = BASE + bytes.len() as uxlen;
+        let mut rom = Rom::new(&mut bytes, BASE, end);
+        let mut cpu = Cpu::builder(&mut rom).clint(CLINT_BASE).build().unwrap();
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.registers()[10], INTERRUPT_BIT | Interrupt::MachineTimer.code());
+        assert!([BASE + 56, BASE + 60].contains(&cpu.registers()[11]));
+        assert!(cpu.clint().unwrap().mtime() >= 20);
+    }
+
+    #[test]
+    fn instruction_limit_stops_infinite_loop() {
+        let mut bytes = program(&[
+            0x00100293, // addi  x5, x0, 1
+            // Loop forever.
+            0x00000063, // beq   x0, x0, 0
+        ]);
+        let end = BASE + bytes.len() as uxlen;
+        let mut rom = Rom::new(&mut bytes, BASE, end);
+        let mut cpu = Cpu::builder(&mut rom).max_instructions(10).build().unwrap();
+
+        assert_eq!(cpu.run(), Ok(HaltReason::InstructionLimit(BASE + 4)));
+        assert_eq!(cpu.instret(), 10);
+    }
+
+    #[test]
+    fn writes_to_x0_are_discarded() {
+        assert_eq!(run(&[0x00100013])[0], 0); // addi x0, x0, 1
+    }
+
+    #[test]
+    fn load_into_x0_is_discarded() {
+        let mut bytes = program(&[
+            0xff010113, // addi x2, x2, -16
+            0xfff00293, // addi x5, x0, -1
+            0x00512023, // sw   x5, 0(x2)
+            0x00012003, // lw   x0, 0(x2)
+        ]);
+        let end = BASE + bytes.len() as uxlen;
+        let mut rom = Rom::new(&mut bytes, BASE, end);
+        let mut cpu = Cpu::builder(&mut rom).ram(0x1000_0000, 0x100).build().unwrap();
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.registers()[0], 0);
+    }
+
+    #[test]
+    fn store_from_x0_writes_zero() {
+        let mut bytes = program(&[
+            0xff010113, // addi x2, x2, -16
+            0xfff00293, // addi x5, x0, -1
+            0x00512023, // sw   x5, 0(x2)
+            0x00012023, // sw   x0, 0(x2)
+            0x00012303, // lw   x6, 0(x2)
+        ]);
+        let end = BASE + bytes.len() as uxlen;
+        let mut rom = Rom::new(&mut bytes, BASE, end);
+        let mut cpu = Cpu::builder(&mut rom).ram(0x1000_0000, 0x100).build().unwrap();
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.registers()[6], 0);
+    }
+
+    #[test]
+    fn watchpoint_stops_after_the_store_to_the_watched_address() {
+        let mut bytes = program(&[
+            0x00100293, // addi x5, x0, 1
+            0x01c29293, // slli x5, x5, 28
+            0x00700313, // addi x6, x0, 7
+            0x0062a223, // sw   x6, 4(x5)
+            0x0062a023, // sw   x6, 0(x5)
+            0x00100393, // addi x7, x0, 1
+        ]);
+        let end = BASE + bytes.len() as uxlen;
+
+        // The store to the neighbouring word does not trigger the watchpoint.
+        let mut rom = Rom::new(&mut bytes, BASE, end);
+        let mut cpu = Cpu::builder(&mut rom).ram(0x1000_0000, 0x100).build().unwrap();
+        cpu.add_watchpoint(0x1000_0000, AccessKind::Write);
+        for _ in 0..4 {
+            assert_eq!(cpu.step().unwrap().watchpoint, None);
+        }
+        let outcome = cpu.step().unwrap();
+        let hit = WatchpointHit { addr: 0x1000_0000, kind: AccessKind::Write, old: 0, new: 7 };
+        assert_eq!(outcome.watchpoint, Some(hit));
+        assert_eq!(outcome.pc, BASE + 20);
+
+        // Running stops right after the store, before the next instruction.
+        let mut rom = Rom::new(&mut bytes, BASE, end);
+        let mut cpu = Cpu::builder(&mut rom).ram(0x1000_0000, 0x100).build().unwrap();
+        cpu.add_watchpoint(0x1000_0000, AccessKind::Write);
+        cpu.run().unwrap();
+        assert_eq!(cpu.pc(), BASE + 20);
+        assert_eq!(cpu.registers()[7], 0);
+
+        cpu.run().unwrap();
+        assert_eq!(cpu.registers()[7], 1);
+    }
+
+    #[test]
+    fn sret_returns_to_supervisor_and_then_user_mode() {
+        let mut bytes = program(&[
+            0x00000297, // auipc x5, 0
+            0x03428313, // addi  x6, x5, 52
+            0x30531073, // csrrw x0, mtvec, x6
+            // Return to supervisor mode with interrupts enabled there.
+            0x02028313, // addi  x6, x5, 32
+            0x14131073, // csrrw x0, sepc, x6
+            0x12000393, // addi  x7, x0, 0x120
+            0x1003a073, // csrrs x0, sstatus, x7
+            0x10200073, // sret
+            // Supervisor mode, which returns to user mode.
+            0x10002573, // csrrs x10, sstatus, x0
+            0x03028313, // addi  x6, x5, 48
+            0x14131073, // csrrw x0, sepc, x6
+            0x10200073, // sret
+            // User mode.
+            0x10200073, // sret
+            // Trap handler.
+            0x342025f3, // csrrs x11, mcause, x0
+        ]);
+        let end = BASE + bytes.len() as uxlen;
+        let mut rom = Rom::new(&mut bytes, BASE, end);
+        let mut cpu = Cpu::new(&mut rom, false).unwrap();
+        cpu.run().unwrap();
+
+        // The first SRET enabled supervisor interrupts and cleared SPP.
+        assert_eq!(cpu.registers()[10], MSTATUS_SIE | MSTATUS_SPIE);
+        // SRET in user mode is illegal.
+        assert_eq!(cpu.registers()[11], Exception::IllegalInstruction.code());
+        assert_eq!(cpu.csrs().mepc(), BASE + 48);
+        assert_eq!(cpu.csrs().mstatus() & MSTATUS_MPP, 0);
+        assert_eq!(cpu.privilege(), PrivilegeMode::Machine);
+    }
+
+    #[test]
+    fn reset_restores_memory_and_registers() {
+        let mut bytes = program(&[
+            0x00100293, // addi  x5, x0, 1
+            0x01c29293, // slli  x5, x5, 28
+            0x00700313, // addi  x6, x0, 7
+            0x0062a023, // sw    x6, 0(x5)
+            // Overwrite the first instruction of the program.
+            0x00000397, // auipc x7, 0
+            0xfe63a823, // sw    x6, -16(x7)
+        ]);
+        let end = BASE + bytes.len() as uxlen;
+        let mut rom = Rom::new(&mut bytes, BASE, end);
+        let mut cpu = Cpu::builder(&mut rom).ram(0x1000_0000, 0x100).build().unwrap();
+        cpu.run().unwrap();
+        assert_eq!(cpu.read_byte(BASE), Ok(7));
+        assert_eq!(cpu.read_byte(0x1000_0000), Ok(7));
+
+        cpu.reset();
+        assert_eq!(cpu.pc(), BASE);
+        assert_eq!(cpu.instret(), 0);
+        assert_eq!(cpu.registers()[5], 0);
+        assert_eq!(cpu.read_byte(BASE), Ok(0x93));
+        assert_eq!(cpu.read_byte(0x1000_0000), Ok(0));
+
+        // The program runs the same way again.
+        cpu.run().unwrap();
+        assert_eq!(cpu.registers()[6], 7);
+        assert_eq!(cpu.read_byte(BASE), Ok(7));
+    }
+
+    #[test]
+    fn counts_executed_instructions_by_kind() {
+        let mut bytes = program(&[
+            0x00300113, // addi x2, x0, 3
+            0x00108093, // addi x1, x1, 1
+            0xfe209ee3, // bne  x1, x2, -4
+        ]);
+        let end = BASE + bytes.len() as uxlen;
+        let mut rom = Rom::new(&mut bytes, BASE, end);
+        let mut cpu = Cpu::builder(&mut rom).instruction_stats(true).build().unwrap();
+        cpu.run().unwrap();
+
+        let counts = cpu.instruction_counts().unwrap();
+        assert_eq!(counts.get(&InstructionKind::Addi), Some(&4));
+        assert_eq!(counts.get(&InstructionKind::Bne), Some(&3));
+        assert_eq!(counts.len(), 2);
+    }
+
+    #[test]
+    fn reserved_encodings_trap_instead_of_running_as_nop() {
+        let reserved = [
+            0x00000000, // (all zeros)
+            0x000000f3, // ecall with rd = x1
+            0x00008073, // ecall with rs1 = x1
+            0x40101093, // slli with funct7 = 0b0100000
+        ];
+        for raw in reserved {
+            let mut bytes = program(&[
+                0x00000297, // auipc x5, 0
+                0x01028313, // addi  x6, x5, 16
+                0x30531073, // csrrw x0, mtvec, x6
+                raw,        // Trap handler.
+                0x34302573, // csrrs x10, mtval, x0
+            ]);
+            let end = BASE + bytes.len() as uxlen;
+            let mut rom = Rom::new(&mut bytes, BASE, end);
+            let mut cpu = Cpu::new(&mut rom, false).unwrap();
+            cpu.run().unwrap();
+
+            assert_eq!(cpu.csrs().mcause(), Exception::IllegalInstruction.code(), "{raw:#010x}");
+            assert_eq!(cpu.csrs().mepc(), BASE + 12, "{raw:#010x}");
+            assert_eq!(cpu.registers()[10], raw);
+        }
+    }
+
+    #[test]
+    fn big_endian_data_accesses_assemble_bytes_in_reverse() {
+        let mut bytes = program(&[
+            0x00100293, // addi  x5, x0, 1
+            0x01c29293, // slli  x5, x5, 28
+            0x12300313, // addi  x6, x0, 0x123
+            0x0062a023, // sw    x6, 0(x5)
+            0x0002a503, // lw    x10, 0(x5)
+            // Switch machine-mode data accesses to big-endian.
+            0x02000393, // addi  x7, x0, 0x20
+            0x3103a073, // csrrs x0, mstatush, x7
+            0x0002a583, // lw    x11, 0(x5)
+            0x0002d603, // lhu   x12, 0(x5)
+            0x0062a223, // sw    x6, 4(x5)
+            0x0072c683, // lbu   x13, 7(x5)
+        ]);
+        let end = BASE + bytes.len() as uxlen;
+        let mut rom = Rom::new(&mut bytes, BASE, end);
+        let mut cpu = Cpu::builder(&mut rom).ram(0x1000_0000, 0x100).build().unwrap();
+        cpu.run().unwrap();
+
+        // The bytes 23 01 00 00 stored little-endian.
+        assert_eq!(cpu.registers()[10], 0x0000_0123);
+        assert_eq!(cpu.registers()[11], 0x2301_0000);
+        assert_eq!(cpu.registers()[12], 0x2301);
+        // A big-endian store puts the least significant byte last.
+        assert_eq!(cpu.registers()[13], 0x23);
+    }
+
+    #[test]
+    fn reads_and_writes_memory_across_rom_and_ram() {
+        let mut bytes = program(&[
+            0x00000013, // nop
+            0x00000013, // nop
+        ]);
+        let end = BASE + bytes.len() as uxlen;
+        let mut rom = Rom::new(&mut bytes, BASE, end);
+        let mut cpu = Cpu::builder(&mut rom).ram(end, 8).build().unwrap();
+
+        let pattern = [1, 2, 3, 4, 5, 6, 7, 8];
+        cpu.write_mem(BASE + 4, &pattern).unwrap();
+        assert_eq!(cpu.read_mem(BASE + 4, 8), Ok(pattern.to_vec()));
+
+        // Nothing is written if part of the range is not mapped.
+        assert_eq!(
+            cpu.write_mem(end + 4, &[0xAA; 8]),
+            Err(EmuError::MemoryFault { addr: end + 8 })
+        );
+        assert_eq!(cpu.read_mem(end + 4, 4), Ok([0; 4].to_vec()));
+        assert_eq!(cpu.read_mem(end + 4, 8), Err(EmuError::MemoryFault { addr: end + 8 }));
+    }
+
+    #[test]
+    fn history_keeps_the_last_committed_instructions() {
+        let mut bytes = program(&[
+            0x00100293, // addi  x5, x0, 1
+            0x00200313, // addi  x6, x0, 2
+            0x00500013, // addi  x0, x0, 5
+            0x00300393, // addi  x7, x0, 3
+            0x00000073, // ecall
+            0x00400413, // addi  x8, x0, 4
+        ]);
+        let end = BASE + bytes.len() as uxlen;
+        let mut rom = Rom::new(&mut bytes, BASE, end);
+        let mut cpu = Cpu::builder(&mut rom)
+            .history(3)
+            .on_ecall(Box::new(|cpu| cpu.abort()))
+            .build()
+            .unwrap();
+        cpu.run().unwrap();
+        assert_eq!(cpu.registers()[8], 0);
+
+        let history: Vec<HistoryEntry> = cpu.history().unwrap().iter().copied().collect();
+        assert_eq!(
+            history,
+            [
+                // Writes to x0 do not change any register.
+                HistoryEntry { pc: BASE + 8, raw: 0x00500013, rd: None, old: 0, new: 0 },
+                HistoryEntry { pc: BASE + 12, raw: 0x00300393, rd: Some(Reg::X7), old: 0, new: 3 },
+                HistoryEntry { pc: BASE + 16, raw: 0x00000073, rd: None, old: 0, new: 0 },
+            ]
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn annotates_signed_overflow_without_changing_the_result() {
+        const ADD: u32 = 0x00628533; // add  x10, x5, x6
+        const SUB: u32 = 0x406285b3; // sub  x11, x5, x6
+        const ADDI: u32 = 0x00128613; // addi x12, x5, 1
+        const MUL: u32 = 0x026286b3; // mul  x13, x5, x6
+
+        let mut bytes = program(&[
+            0x00100293, // addi x5, x0, 1
+            0x01f29293, // slli x5, x5, 31
+            0xfff28293, // addi x5, x5, -1
+            0x00100313, // addi x6, x0, 1
+            ADD, SUB, ADDI, MUL,
+        ]);
+        let end = BASE + bytes.len() as uxlen;
+        let mut rom = Rom::new(&mut bytes, BASE, end);
+        let mut cpu = Cpu::new(&mut rom, false).unwrap();
+        for _ in 0..4 {
+            cpu.step().unwrap();
+        }
+
+        // x5 is INT_MAX and x6 is 1.
+        let overflows = |cpu: &Cpu, raw| cpu.overflows(Instruction(raw).kind(), Instruction(raw));
+        assert!(overflows(&cpu, ADD));
+        assert!(!overflows(&cpu, SUB));
+        assert!(overflows(&cpu, ADDI));
+        assert!(!overflows(&cpu, MUL));
+
+        cpu.run().unwrap();
+        assert_eq!(cpu.registers()[10], 0x8000_0000);
+        assert_eq!(cpu.registers()[11], 0x7fff_fffe);
+        assert_eq!(cpu.registers()[12], 0x8000_0000);
+        assert_eq!(cpu.registers()[13], 0x7fff_ffff);
+    }
+
+    #[test]
+    fn fetching_outside_the_rom_raises_an_access_fault() {
+        let mut bytes = program(&[
+            0x00000297, // auipc x5, 0
+            0x01028313, // addi  x6, x5, 16
+            0x30531073, // csrrw x0, mtvec, x6
+            0x10000067, // jalr  x0, x0, 0x100
+            0x34302573, // csrrs x10, mtval, x0 (trap handler)
+        ]);
+        let end = BASE + bytes.len() as uxlen;
+        let mut rom = Rom::new(&mut bytes, BASE, end);
+        let mut cpu = Cpu::new(&mut rom, false).unwrap();
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.csrs().mcause(), Exception::InstructionAccessFault.code());
+        assert_eq!(cpu.csrs().mepc(), 0x100);
+        assert_eq!(cpu.registers()[10], 0x100);
+        assert_eq!(cpu.pc(), end);
+    }
+
+    #[test]
+    fn vectored_mtvec_jumps_to_the_entry_of_the_interrupt_cause() {
+        let mut bytes = program(&[
+            // Use the vector table at the end of the program.
+            0x00000397, // auipc x7, 0
+            0x02d38393, // addi  x7, x7, 45
+            0x30539073, // csrrw x0, mtvec, x7
+            // Raise a machine software interrupt through `msip`.
+            0x00100293, // addi  x5, x0, 1
+            0x01929293, // slli  x5, x5, 25
+            0x00100313, // addi  x6, x0, 1
+            0x00800e13, // addi  x28, x0, 8
+            0x304e2073, // csrrs x0, mie, x28
+            0x300e2073, // csrrs x0, mstatus, x28
+            0x0062a023, // sw    x6, 0(x5)
+            0x00100613, // addi  x12, x0, 1
+            // Vector table, entries 0 to 2.
+            0xfff00593, // addi  x11, x0, -1
+            0xfff00593, // addi  x11, x0, -1
+            0xfff00593, // addi  x11, x0, -1
+            // Entry 3: machine software interrupt.
+            0x34202573, // csrrs x10, mcause, x0
+        ]);
+        let end = BASE + bytes.len() as uxlen;
+        let mut rom = Rom::new(&mut bytes, BASE, end);
+        let mut cpu = Cpu::builder(&mut rom).clint(CLINT_BASE).build().unwrap();
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.registers()[10], INTERRUPT_BIT | Interrupt::MachineSoftware.code());
+        assert_eq!(cpu.registers()[11], 0);
+        assert_eq!(cpu.registers()[12], 0);
+        assert_eq!(cpu.csrs().mepc(), BASE + 40);
+    }
+
+    #[test]
+    fn effective_addresses_wrap_around_the_address_space() {
+        let mut bytes = program(&[
+            0xffc00293, // addi  x5, x0, -4
+            0x02a00313, // addi  x6, x0, 42
+            0x0062a423, // sw    x6, 8(x5)
+            0x0082a503, // lw    x10, 8(x5)
+        ]);
+        let end = BASE + bytes.len() as uxlen;
+        let mut rom = Rom::new(&mut bytes, BASE, end);
+        let mut cpu = Cpu::builder(&mut rom).ram(0, 0x10).build().unwrap();
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.registers()[10], 42);
+        assert_eq!(cpu.read_mem(4, 4).unwrap(), [42, 0, 0, 0]);
+    }
+
+    #[test]
+    fn run_until_stops_before_the_address() {
+        let mut bytes = program(&[
+            0x00100293, // addi  x5, x0, 1
+            0x00200313, // addi  x6, x0, 2
+            0x00300393, // addi  x7, x0, 3
+        ]);
+        let end = BASE + bytes.len() as uxlen;
+        let mut rom = Rom::new(&mut bytes, BASE, end);
+        let mut cpu = Cpu::new(&mut rom, false).unwrap();
+
+        assert_eq!(cpu.run_until(BASE + 8), Ok(true));
+        assert_eq!(cpu.pc(), BASE + 8);
+        assert_eq!(cpu.registers()[6], 2);
+        assert_eq!(cpu.registers()[7], 0);
+        assert!(cpu.breakpoints().is_empty());
+
+        // The program ends before an address that is never reached.
+        assert_eq!(cpu.run_until(BASE + 0x100), Ok(false));
+        assert_eq!(cpu.registers()[7], 3);
+    }
+
+    #[test]
+    fn software_interrupt_between_harts() {
+        let mut bytes = program(&[
+            0xf1402ef3, // csrrs x29, mhartid, x0
+            0x00100293, // addi  x5, x0, 1
+            0x01929293, // slli  x5, x5, 25
+            0x000e9863, // bne   x29, x0, 16
+            // Hart 0 sets the `msip` register of hart 1, and then spins.
+            0x00100313, // addi  x6, x0, 1
+            0x0062a223, // sw    x6, 4(x5)
+            0x00000063, // beq   x0, x0, 0
+            // Hart 1 enables the machine software interrupt, and waits for it.
+            0x00000397, // auipc x7, 0
+            0x02038393, // addi  x7, x7, 32
+            0x30539073, // csrrw x0, mtvec, x7
+            0x00800e13, // addi  x28, x0, 8
+            0x304e2073, // csrrs x0, mie, x28
+            0x300e2073, // csrrs x0, mstatus, x28
+            0x10500073, // wfi
+            0xfe000ee3, // beq   x0, x0, -4
+            // Its handler clears `msip` and runs off the end of the ROM.
+            0x34202573, // csrrs x10, mcause, x0
+            0x0002a223, // sw    x0, 4(x5)
+        ]);
+        let end = BASE + bytes.len() as uxlen;
+        let mut rom = Rom::new(&mut bytes, BASE, end);
+        let mut cpu = Cpu::builder(&mut rom).harts(2).clint(CLINT_BASE).build().unwrap();
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.hart_id(), 1);
+        assert_eq!(cpu.registers()[10], INTERRUPT_BIT | Interrupt::MachineSoftware.code());
+        assert!(!cpu.clint().unwrap().software_pending(1));
+        cpu.select_hart(0);
+        assert_eq!(cpu.registers()[10], 0);
+        assert_eq!(cpu.pc(), BASE + 24);
+    }
+
+    #[test]
+    fn detects_stores_into_fetched_instructions() {
+        for policy in [CodeWritePolicy::Warn, CodeWritePolicy::Fault] {
+            let mut bytes = program(&[
+                0x00000297, // auipc x5, 0
+                0x01428393, // addi  x7, x5, 20
+                0x30539073, // csrrw x0, mtvec, x7
+                // Write the first instruction back to itself.
+                0x0002a303, // lw    x6, 0(x5)
+                0x0062a023, // sw    x6, 0(x5)
+                0x34202573, // csrrs x10, mcause, x0 (trap handler)
+            ]);
+            let end = BASE + bytes.len() as uxlen;
+            let mut rom = Rom::new(&mut bytes, BASE, end);
+            let mut cpu = Cpu::builder(&mut rom).code_writes(policy).build().unwrap();
+            cpu.run().unwrap();
+
+            assert_eq!(cpu.code_writes(), [CodeWrite { pc: BASE + 16, addr: BASE }], "{policy:?}");
+            let expected_cause = match policy {
+                CodeWritePolicy::Fault => Exception::StoreAccessFault.code(),
+                _ => 0,
+            };
+            assert_eq!(cpu.registers()[10], expected_cause, "{policy:?}");
+        }
+    }
+
+    #[test]
+    fn counts_taken_and_not_taken_branches() {
+        let mut bytes = program(&[
+            0x00300293, // addi  x5, x0, 3
+            0xfff28293, // addi  x5, x5, -1
+            0xfe029ee3, // bne   x5, x0, -4
+        ]);
+        let end = BASE + bytes.len() as uxlen;
+        let mut rom = Rom::new(&mut bytes, BASE, end);
+        let mut cpu = Cpu::builder(&mut rom).instruction_stats(true).build().unwrap();
+        cpu.run().unwrap();
+
+        let stats = cpu.branch_stats().unwrap();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[&(BASE + 8)], BranchStats { taken: 2, not_taken: 1 });
+    }
+
+    #[test]
+    fn signed_and_unsigned_branches_with_negative_operands() {
+        let taken = |rs1: ixlen, rs2: ixlen| {
+            // Each branch skips the instruction that marks it as not taken.
+            let mut bytes = program(&[
+                0x0062c463, // blt   x5, x6, 8
+                0x00100513, // addi  x10, x0, 1
+                0x0062d463, // bge   x5, x6, 8
+                0x00100593, // addi  x11, x0, 1
+                0x0062e463, // bltu  x5, x6, 8
+                0x00100613, // addi  x12, x0, 1
+                0x0062f463, // bgeu  x5, x6, 8
+                0x00100693, // addi  x13, x0, 1
+            ]);
+            let end = BASE + bytes.len() as uxlen;
+            let mut rom = Rom::new(&mut bytes, BASE, end);
+            let mut cpu = Cpu::new(&mut rom, false).unwrap();
+            cpu.registers_mut()[5] = rs1 as uxlen;
+            cpu.registers_mut()[6] = rs2 as uxlen;
+            cpu.run().unwrap();
+            [10, 11, 12, 13].map(|ix| cpu.registers()[ix] == 0)
+        };
+
+        // [blt, bge, bltu, bgeu]
+        assert_eq!(taken(-1, 1), [true, false, false, true]);
+        assert_eq!(taken(1, -1), [false, true, true, false]);
+        assert_eq!(taken(-2, -1), [true, false, true, false]);
+        assert_eq!(taken(-1, -1), [false, true, false, true]);
+        assert_eq!(taken(ixlen::MIN, ixlen::MAX), [true, false, false, true]);
+    }
+
+    #[test]
+    fn writing_read_only_csrs_is_illegal() {
+        for raw in [
+            0xc0009073, // csrrw  x0, cycle, x1
+            0xf1409073, // csrrw  x0, mhartid, x1
+            0xc000e073, // csrrsi x0, cycle, 1
+        ] {
+            let mut bytes = program(&[
+                0x00000297, // auipc x5, 0
+                0x01028313, // addi  x6, x5, 16
+                0x30531073, // csrrw x0, mtvec, x6
+                raw,        // Trap handler.
+                0x34302573, // csrrs x10, mtval, x0
+            ]);
+            let end = BASE + bytes.len() as uxlen;
+            let mut rom = Rom::new(&mut bytes, BASE, end);
+            let mut cpu = Cpu::new(&mut rom, false).unwrap();
+            cpu.run().unwrap();
+
+            assert_eq!(cpu.csrs().mcause(), Exception::IllegalInstruction.code(), "{raw:#010x}");
+            assert_eq!(cpu.registers()[10], raw);
+        }
+
+        // Setting or clearing no bits is not a write.
+        let regs = run(&[
+            0xc0002573, // csrrs  x10, cycle, x0
+            0xf14035f3, // csrrc  x11, mhartid, x0
+            0xc0006673, // csrrsi x12, cycle, 0
+        ]);
+        assert_eq!((regs[10], regs[11], regs[12]), (0, 0, 2));
+    }
+
+    #[test]
+    fn reads_cycle_counter_split_across_halves() {
+        // The usual RV32 loop for reading a 64-bit counter, which retries if the low half wrapped in between.
+        let mut bytes = program(&[
+            0xc80025f3, // csrrs x11, cycleh, x0
+            0xc0002573, // csrrs x10, cycle, x0
+            0xc80022f3, // csrrs x5, cycleh, x0
+            0xfe559ae3, // bne   x11, x5, -12
+        ]);
+        let end = BASE + bytes.len() as uxlen;
+        let mut rom = Rom::new(&mut bytes, BASE, end);
+        let mut cpu = Cpu::new(&mut rom, false).unwrap();
+        cpu.cycles = 0xffff_fffe;
+        cpu.run().unwrap();
+
+        let value = (cpu.registers()[11] as u64) << 32 | cpu.registers()[10] as u64;
+        assert_eq!(cpu.cycles, 0xffff_fffe + 8);
+        assert_eq!(value, 0x1_0000_0003);
+    }
+
+    #[test]
+    fn store_between_lr_and_sc_makes_sc_fail() {
+        let mut bytes = program(&[
+            0x00100293, // addi  x5, x0, 1
+            0x01c29293, // slli  x5, x5, 28
+            0x00700313, // addi  x6, x0, 7
+            0x1002a52f, // lr.w  x10, (x5)
+            0x1862a5af, // sc.w  x11, x6, (x5)
+            0x1002a52f, // lr.w  x10, (x5)
+            0x0002a023, // sw    x0, 0(x5)
+            0x1862a62f, // sc.w  x12, x6, (x5)
+            0x0002a683, // lw    x13, 0(x5)
+        ]);
+        let end = BASE + bytes.len() as uxlen;
+        let mut rom = Rom::new(&mut bytes, BASE, end);
+        let mut cpu = Cpu::builder(&mut rom).ram(0x1000_0000, 0x10).build().unwrap();
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.registers()[11], 0, "sc.w without a store in between succeeds");
+        assert_eq!(cpu.registers()[10], 7);
+        assert_eq!(cpu.registers()[12], 1, "sc.w after a store fails");
+        assert_eq!(cpu.registers()[13], 0);
+    }
+
+    #[test]
+    fn regions_past_the_address_space_are_rejected() {
+        let mut bytes = program(&[0x00000013]); // nop
+        let mut rom = Rom::new(&mut bytes, BASE, BASE + 4);
+        let overflow = |base, size| Err(EmuError::RegionOverflow { base, size });
+
+        let result = Cpu::builder(&mut rom).ram(0xffff_0000, 0x1_0000).build().map(|_| ());
+        assert_eq!(result, overflow(0xffff_0000, 0x1_0000));
+        let result = Cpu::builder(&mut rom).clint(0xffff_8000).build().map(|_| ());
+        assert_eq!(result, overflow(0xffff_8000, CLINT_SIZE));
+        #[cfg(feature = "std")]
+        {
+            let uart = UartDevice::new(std::io::empty(), std::io::sink());
+            let result = Cpu::builder(&mut rom).uart(0xffff_fffc, uart).build().map(|_| ());
+            assert_eq!(result, overflow(0xffff_fffc, UART_SIZE));
+        }
+
+        // A region may end at the last address.
+        let cpu = Cpu::builder(&mut rom).ram(0xffff_0000, 0xffff).build().unwrap();
+        assert!(cpu.is_mapped(0xffff_fffe));
+    }
+
+    #[test]
+    fn rv32e_rejects_registers_above_x15() {
+        let mut bytes = program(&[
+            0x00000297, // auipc x5, 0
+            0x01428313, // addi  x6, x5, 20
+            0x30531073, // csrrw x0, mtvec, x6
+            0x00100793, // addi  x15, x0, 1
+            0x00100a13, // addi  x20, x0, 1
+            0x34302573, // csrrs x10, mtval, x0 (trap handler)
+        ]);
+        let end = BASE + bytes.len() as uxlen;
+        let mut rom = Rom::new(&mut bytes, BASE, end);
+        let mut cpu = Cpu::builder(&mut rom).rv32e(true).build().unwrap();
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.registers()[15], 1);
+        assert_eq!(cpu.registers()[20], 0);
+        assert_eq!(cpu.csrs().mcause(), Exception::IllegalInstruction.code());
+        assert_eq!(cpu.csrs().mepc(), BASE + 16);
+        assert_eq!(cpu.registers()[10], 0x00100a13);
+    }
+
+    #[test]
+    fn initial_registers_reach_the_first_instruction() {
+        let mut bytes = program(&[0x00b502b3]); // add x5, x10, x11
+        let end = BASE + bytes.len() as uxlen;
+        let mut rom = Rom::new(&mut bytes, BASE, end);
+        let mut cpu = Cpu::builder(&mut rom)
+            .register(Reg::A0, 1)
+            .register(11usize, 0x8700_0000)
+            .register(Reg::X0, 5)
+            .build()
+            .unwrap();
+        assert_eq!(cpu.registers()[Reg::A0], 1);
+        assert_eq!(cpu.registers()[Reg::A1], 0x8700_0000);
+        assert_eq!(cpu.registers()[Reg::X0], 0);
+        assert_eq!(cpu.registers()[Reg::Sp], end);
+
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers()[Reg::T0], 0x8700_0001);
+    }
+
+    /// An output that can still be inspected after it has been moved into the CPU.
+    #[cfg(feature = "std")]
+    #[derive(Clone, Default)]
+    struct Output(std::rc::Rc<core::cell::RefCell<Vec<u8>>>);
+
+    #[cfg(feature = "std")]
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn trace_is_written_to_the_configured_output() {
+        let output = Output::default();
+        let mut bytes = program(&[0x00500293]); // addi x5, x0, 5
+        let end = BASE + bytes.len() as uxlen;
+        let mut rom = Rom::new(&mut bytes, BASE, end);
+        let mut cpu = Cpu::builder(&mut rom)
+            .verbose(true)
+            .trace_spike(true)
+            .trace_output(output.clone())
+            .build()
+            .unwrap();
+        cpu.run().unwrap();
+
+        let trace = String::from_utf8(output.0.borrow().clone()).unwrap();
+        let lines: Vec<&str> = trace.lines().collect();
+        assert_eq!(lines.len(), 2, "{trace}");
+        assert!(lines.iter().all(|line| line.contains("0x00500293")), "{trace}");
+    }
+
+    #[test]
+    fn upper_immediates_keep_their_top_bit() {
+        let regs = run(&[
+            0x12345537, // lui   x10, 0x12345
+            0xfffff5b7, // lui   x11, 0xfffff
+            0x80000617, // auipc x12, 0x80000
+            0xfffff697, // auipc x13, 0xfffff
+        ]);
+        assert_eq!(regs[10], 0x1234_5000);
+        assert_eq!(regs[11], 0xffff_f000);
+        // The offsets are relative to the AUIPC instruction itself and wrap around the address space.
+        assert_eq!(regs[12], (BASE + 8).wrapping_add(0x8000_0000));
+        assert_eq!(regs[13], BASE + 12 - 0x1000);
+    }
+
+    #[test]
+    fn cycles_add_up_the_cost_of_each_instruction() {
+        let mut bytes = program(&[
+            0x00600293, // addi  x5, x0, 6
+            0x00700313, // addi  x6, x0, 7
+            0x026283b3, // mul   x7, x5, x6
+            0xfe712e23, // sw    x7, -4(x2)
+            0xffc12403, // lw    x8, -4(x2)
+            0xc0002573, // csrrs x10, cycle, x0
+        ]);
+        let end = BASE + bytes.len() as uxlen;
+        let mut rom = Rom::new(&mut bytes, BASE, end);
+        let costs =
+            CycleCosts::uniform(1).cost(InstructionKind::Mul, 4).cost(InstructionKind::Lw, 10);
+        let mut cpu = Cpu::builder(&mut rom).ram(end, 0x100).cycle_costs(costs).build().unwrap();
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.instret(), 6);
+        assert_eq!(cpu.cycles(), 1 + 1 + 4 + 1 + 10 + 1);
+        // The CSR read sees the cycles of the instructions before it.
+        assert_eq!(cpu.registers()[10], 17);
+        assert_eq!(cpu.registers()[8], 42);
+    }
+
+    #[test]
+    fn run_returns_why_it_halted() {
+        let mut bytes = program(&[
+            0x00000073, // ecall
+            0x00100293, // addi x5, x0, 1
+        ]);
+        let end = BASE + bytes.len() as uxlen;
+        let mut rom = Rom::new(&mut bytes, BASE, end);
+        let mut run = |on_ecall: fn(&mut Cpu)| {
+            let mut cpu = Cpu::builder(&mut rom).on_ecall(Box::new(on_ecall)).build().unwrap();
+            cpu.run()
+        };
+
+        assert_eq!(run(|_| {}), Ok(HaltReason::EndOfRom));
+        assert_eq!(run(|cpu| cpu.abort()), Ok(HaltReason::Aborted));
+        assert_eq!(run(|cpu| cpu.exit(3)), Ok(HaltReason::Exited(3)));
+
+        let mut cpu = Cpu::builder(&mut rom).on_ecall(Box::new(|_| {})).build().unwrap();
+        cpu.add_breakpoint(BASE + 4);
+        assert_eq!(cpu.run(), Ok(HaltReason::Breakpoint(BASE + 4)));
+        assert_eq!(cpu.run(), Ok(HaltReason::EndOfRom));
+    }
+
+    #[test]
+    fn stack_guard_stops_deep_recursion() {
+        // A function that calls itself forever, pushing its return address every time.
+        let run = |stack_size: Option<uxlen>| {
+            let mut bytes = program(&[
+                0x00000297, // auipc x5, 0
+                0xff010113, // addi  x2, x2, -16
+                0x00112623, // sw    x1, 12(x2)
+                0x004280e7, // jalr  x1, 4(x5)
+            ]);
+            let end = BASE + bytes.len() as uxlen;
+            let mut rom = Rom::new(&mut bytes, BASE, end);
+            let mut builder = Cpu::builder(&mut rom).ram(0x1000_0000, 0x1000);
+            if let Some(size) = stack_size {
+                builder = builder.stack_size(size);
+            }
+            let mut cpu = builder.build().unwrap();
+            (cpu.run(), cpu.registers()[Reg::Sp])
+        };
+
+        // The 17th frame is the first one below the guarded 256 bytes of stack.
+        let (result, sp) = run(Some(0x100));
+        assert_eq!(result, Err(EmuError::MemoryFault { addr: 0x1000_0efc }));
+        assert_eq!(sp, 0x1000_1000 - 17 * 16);
+
+        // Without the guard the recursion only stops at the end of the RAM.
+        let (result, sp) = run(None);
+        assert_eq!(result, Err(EmuError::MemoryFault { addr: 0x0fff_fffc }));
+        assert_eq!(sp, 0x0fff_fff0);
+    }
+
+    #[test]
+    fn misaligned_lr_raises_an_address_misaligned_exception() {
+        let mut bytes = program(&[
+            0x00000297, // auipc x5, 0
+            0x01c28313, // addi  x6, x5, 28
+            0x30531073, // csrrw x0, mtvec, x6
+            0x100003b7, // lui   x7, 0x10000
+            0x00238393, // addi  x7, x7, 2
+            0x1003a52f, // lr.w  x10, (x7)
+            0x00100513, // addi  x10, x0, 1
+            0x342025f3, // csrrs x11, mcause, x0 (trap handler)
+        ]);
+        let end = BASE + bytes.len() as uxlen;
+        let mut rom = Rom::new(&mut bytes, BASE, end);
+        let mut cpu = Cpu::builder(&mut rom).ram(0x1000_0000, 0x10).build().unwrap();
+        cpu.run().unwrap();
+
+        // An ordinary load from the same address would be allowed.
+        assert_eq!(cpu.registers()[11], Exception::LoadAddressMisaligned.code());
+        assert_eq!(cpu.csrs().mepc(), BASE + 20);
+        assert_eq!(cpu.csrs().mtval(), 0x1000_0002);
+        assert_eq!(cpu.registers()[10], 0);
+    }
+
+    #[test]
+    fn unknown_instructions_trap_unless_decoding_is_lenient() {
+        let mut bytes = program(&[
+            0xffffffff, // (unknown)
+            0x00100513, // addi x10, x0, 1
+        ]);
+        let end = BASE + bytes.len() as uxlen;
+        let mut rom = Rom::new(&mut bytes, BASE, end);
+
+        let mut cpu = Cpu::new(&mut rom, false).unwrap();
+        assert_eq!(cpu.run(), Err(EmuError::IllegalInstruction { raw: 0xffffffff }));
+        assert_eq!(cpu.pc(), BASE);
+        assert_eq!(cpu.registers()[10], 0);
+
+        let mut cpu = Cpu::builder(&mut rom).strict_decode(false).build().unwrap();
+        assert_eq!(cpu.run(), Ok(HaltReason::EndOfRom));
+        assert_eq!(cpu.registers()[10], 1);
+        assert_eq!(cpu.instret(), 2);
+    }
+
+    #[test]
+    fn registers_can_be_seeded_before_stepping() {
+        let mut bytes = program(&[0x00b50633]); // add x12, x10, x11
+        let end = BASE + bytes.len() as uxlen;
+        let mut rom = Rom::new(&mut bytes, BASE, end);
+        let mut cpu = Cpu::new(&mut rom, false).unwrap();
+        cpu.registers_mut()[Reg::A0] = 40;
+        cpu.registers_mut().set_a1(2);
+        cpu.registers_mut().write(0, 7);
+
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers()[Reg::A2], 42);
+        assert_eq!(cpu.registers()[Reg::X0], 0);
+    }
+
+    #[test]
+    fn jumps_and_branches_are_relative_to_their_own_address() {
+        let mut bytes = program(&[
+            0x008000ef, // jal   x1, 8
+            0x00c082e7, // jalr  x5, 12(x1)
+            0xfe000ee3, // beq   x0, x0, -4
+            0x00100513, // addi  x10, x0, 1
+            0xfe0018e3, // bne   x0, x0, -16
+        ]);
+        let end = BASE + bytes.len() as uxlen;
+        let mut rom = Rom::new(&mut bytes, BASE, end);
+        let mut cpu = Cpu::new(&mut rom, false).unwrap();
+
+        cpu.step().unwrap();
+        assert_eq!((cpu.pc(), cpu.registers()[1]), (BASE + 8, BASE + 4));
+        // A taken branch.
+        cpu.step().unwrap();
+        assert_eq!(cpu.pc(), BASE + 4);
+        cpu.step().unwrap();
+        assert_eq!((cpu.pc(), cpu.registers()[5]), (BASE + 16, BASE + 8));
+        // A branch that is not taken continues with the next instruction.
+        cpu.step().unwrap();
+        assert_eq!(cpu.pc(), BASE + 20);
+        assert_eq!(cpu.registers()[10], 0);
     }
 }
@@ -0,0 +1,238 @@
+use core::ops::{Deref, DerefMut};
+
+use crate::uxlen;
+
+/// The number of addressable control and status registers.
+pub const CSR_COUNT: usize = 4096;
+
+/// The CSR number of `sstatus`, which is a restricted view of `mstatus`.
+pub const SSTATUS: usize = 0x100;
+/// The CSR number of `satp`.
+pub const SATP: usize = 0x180;
+/// The CSR number of `medeleg`, which delegates exceptions to supervisor mode.
+pub const MEDELEG: usize = 0x302;
+/// The CSR number of `mideleg`, which delegates interrupts to supervisor mode.
+pub const MIDELEG: usize = 0x303;
+/// The CSR number of the `cycle` counter.
+pub const CYCLE: usize = 0xC00;
+/// The CSR number of the `time` counter.
+pub const TIME: usize = 0xC01;
+/// The CSR number of the `instret` counter.
+pub const INSTRET: usize = 0xC02;
+/// The CSR number of `cycleh`, the upper 32 bits of the `cycle` counter.
+pub const CYCLEH: usize = 0xC80;
+/// The CSR number of `timeh`, the upper 32 bits of the `time` counter.
+pub const TIMEH: usize = 0xC81;
+/// The CSR number of `instreth`, the upper 32 bits of the `instret` counter.
+pub const INSTRETH: usize = 0xC82;
+/// The CSR number of `mhartid`, the ID of the hart.
+pub const MHARTID: usize = 0xF14;
+
+/// Supervisor interrupt-enable bit in `mstatus` and `sstatus`.
+pub const MSTATUS_SIE: uxlen = 1 << 1;
+/// Supervisor previous interrupt-enable bit in `mstatus` and `sstatus`.
+pub const MSTATUS_SPIE: uxlen = 1 << 5;
+/// Supervisor previous privilege mode bit in `mstatus` and `sstatus`.
+pub const MSTATUS_SPP: uxlen = 1 << 8;
+/// User-mode big-endian bit in `mstatus`. When set, data accesses in user mode are big-endian.
+pub const MSTATUS_UBE: uxlen = 1 << 6;
+/// Supervisor-mode big-endian bit in `mstatush`. When set, data accesses in supervisor mode are big-endian.
+pub const MSTATUSH_SBE: uxlen = 1 << 4;
+/// Machine-mode big-endian bit in `mstatush`. When set, data accesses in machine mode are big-endian.
+pub const MSTATUSH_MBE: uxlen = 1 << 5;
+/// Trap SRET bit in `mstatus`. When set, SRET raises an illegal-instruction exception in supervisor mode.
+pub const MSTATUS_TSR: uxlen = 1 << 22;
+/// The bits of `mstatus` that are visible in `sstatus`.
+pub const SSTATUS_MASK: uxlen = MSTATUS_SIE | MSTATUS_SPIE | MSTATUS_SPP;
+/// The address translation mode field in `satp`. Only the `Bare` mode (0) is supported.
+pub const SATP_MODE: uxlen = 1 << 31;
+/// Machine interrupt-enable bit in `mstatus`.
+pub const MSTATUS_MIE: uxlen = 1 << 3;
+/// Machine previous interrupt-enable bit in `mstatus`.
+pub const MSTATUS_MPIE: uxlen = 1 << 7;
+/// Machine previous privilege mode field in `mstatus`.
+pub const MSTATUS_MPP: uxlen = 0b11 << 11;
+/// The offset of the MPP field in `mstatus`.
+pub const MSTATUS_MPP_SHIFT: uxlen = 11;
+/// The vectored mode in the lowest two bits of `mtvec`, where interrupts jump to `BASE + 4 * cause`.
+pub const MTVEC_MODE_VECTORED: uxlen = 1;
+
+/// A privilege level the hart executes in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PrivilegeMode {
+    User = 0,
+    Supervisor = 1,
+    Machine = 3,
+}
+
+impl PrivilegeMode {
+    /// The encoding of the privilege mode, as used in `mstatus.MPP`.
+    pub fn bits(self) -> uxlen {
+        self as uxlen
+    }
+
+    /// Decodes a privilege mode. The encoding `0b10` is reserved.
+    pub fn from_bits(bits: uxlen) -> Option<Self> {
+        match bits {
+            0 => Some(Self::User),
+            1 => Some(Self::Supervisor),
+            3 => Some(Self::Machine),
+            _ => None,
+        }
+    }
+
+    /// The lowest privilege mode that can access the CSR with the given number.
+    pub fn required_for_csr(csr: usize) -> Self {
+        // SPEC: The next two bits (csr[9:8]) encode the lowest privilege level that can access the CSR.
+        // NOTE: The encoding `0b10` is used for hypervisor CSRs, which are only accessible in machine mode
+        //       as the hypervisor extension is not supported.
+        Self::from_bits((csr as uxlen >> 8) & 0b11).unwrap_or(Self::Machine)
+    }
+}
+
+impl core::fmt::Display for PrivilegeMode {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::User => f.write_str("U"),
+            Self::Supervisor => f.write_str("S"),
+            Self::Machine => f.write_str("M"),
+        }
+    }
+}
+
+/// A representation of the control and status registers (CSRs) in the [Cpu][crate::cpu::Cpu],
+/// indexed by their 12-bit CSR number.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Csrs([uxlen; CSR_COUNT]);
+
+impl Csrs {
+    pub fn new() -> Self {
+        Self([0; CSR_COUNT])
+    }
+
+    /// Supervisor status register
+    ///
+    /// Get the `sstatus` CSR (0x100), which holds the supervisor-visible bits of `mstatus`.
+    #[inline]
+    pub fn sstatus(&self) -> uxlen {
+        self.mstatus() & SSTATUS_MASK
+    }
+
+    /// Supervisor status register
+    ///
+    /// Set the `sstatus` CSR (0x100), which only changes the supervisor-visible bits of `mstatus`.
+    #[inline]
+    pub fn set_sstatus(&mut self, sstatus: uxlen) {
+        self.set_mstatus((self.mstatus() & !SSTATUS_MASK) | (sstatus & SSTATUS_MASK));
+    }
+}
+
+impl Default for Csrs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// NOTE: serde only implements its traits for arrays of up to 32 elements,
+//       so the CSRs are (de)serialized as a sequence instead.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Csrs {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.0.iter())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Csrs {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let csrs = alloc::vec::Vec::<uxlen>::deserialize(deserializer)?;
+        let len = csrs.len();
+        let csrs = csrs.try_into().map_err(|_| {
+            serde::de::Error::invalid_length(len, &alloc::format!("{CSR_COUNT} CSRs").as_str())
+        })?;
+        Ok(Self(csrs))
+    }
+}
+
+macro_rules! impl_csrs {
+    [$({
+        addr: $addr:literal,
+        name: {
+            get: $get:ident,
+            set: $set:ident
+        },
+        desc: $desc:literal
+    }),*] => {
+        impl Csrs {
+            /// The name of the CSR with the given number, if it is a known CSR.
+            pub fn name(csr: usize) -> Option<&'static str> {
+                match csr {
+                    SSTATUS => Some("sstatus"),
+                    CYCLE => Some("cycle"),
+                    TIME => Some("time"),
+                    INSTRET => Some("instret"),
+                    CYCLEH => Some("cycleh"),
+                    TIMEH => Some("timeh"),
+                    INSTRETH => Some("instreth"),
+                    $($addr => Some(stringify!($get)),)*
+                    _ => None,
+                }
+            }
+
+            $(
+                #[doc = $desc]
+                #[doc = "\n"]
+                #[doc = concat!("Get the `", stringify!($get), "` CSR (", stringify!($addr), ")")]
+                #[inline]
+                pub fn $get(&self) -> crate::uxlen {
+                    self.0[$addr]
+                }
+
+                #[doc = $desc]
+                #[doc = "\n"]
+                #[doc = concat!("Set the `", stringify!($get), "` CSR (", stringify!($addr), ")")]
+                #[inline]
+                pub fn $set(&mut self, $get: crate::uxlen) {
+                    self.0[$addr] = $get;
+                }
+            )*
+        }
+    };
+}
+
+impl_csrs![
+    { addr: 0x105, name: { get: stvec,    set: set_stvec    }, desc: "Supervisor trap-handler base address" },
+    { addr: 0x140, name: { get: sscratch, set: set_sscratch }, desc: "Scratch register for supervisor trap handlers" },
+    { addr: 0x141, name: { get: sepc,     set: set_sepc     }, desc: "Supervisor exception program counter" },
+    { addr: 0x142, name: { get: scause,   set: set_scause   }, desc: "Supervisor trap cause" },
+    { addr: 0x143, name: { get: stval,    set: set_stval    }, desc: "Supervisor bad address or instruction" },
+    { addr: 0x180, name: { get: satp,     set: set_satp     }, desc: "Supervisor address translation and protection" },
+    { addr: 0x300, name: { get: mstatus,  set: set_mstatus  }, desc: "Machine status register" },
+    { addr: 0x301, name: { get: misa,     set: set_misa     }, desc: "ISA and extensions" },
+    { addr: 0x302, name: { get: medeleg,  set: set_medeleg  }, desc: "Machine exception delegation register" },
+    { addr: 0x303, name: { get: mideleg,  set: set_mideleg  }, desc: "Machine interrupt delegation register" },
+    { addr: 0x304, name: { get: mie,      set: set_mie      }, desc: "Machine interrupt-enable register" },
+    { addr: 0x305, name: { get: mtvec,    set: set_mtvec    }, desc: "Machine trap-handler base address" },
+    { addr: 0x310, name: { get: mstatush, set: set_mstatush }, desc: "Additional machine status register" },
+    { addr: 0x340, name: { get: mscratch, set: set_mscratch }, desc: "Scratch register for machine trap handlers" },
+    { addr: 0x341, name: { get: mepc,     set: set_mepc     }, desc: "Machine exception program counter" },
+    { addr: 0x342, name: { get: mcause,   set: set_mcause   }, desc: "Machine trap cause" },
+    { addr: 0x343, name: { get: mtval,    set: set_mtval    }, desc: "Machine bad address or instruction" },
+    { addr: 0x344, name: { get: mip,      set: set_mip      }, desc: "Machine interrupt pending" },
+    { addr: 0xF14, name: { get: mhartid,  set: set_mhartid  }, desc: "Hardware thread ID" }
+];
+
+impl Deref for Csrs {
+    type Target = [uxlen; CSR_COUNT];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for Csrs {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
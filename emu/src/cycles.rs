@@ -0,0 +1,66 @@
+use alloc::collections::BTreeMap;
+
+use crate::inst::InstructionKind;
+
+/// The number of cycles each kind of instruction takes, which [Cpu::cycles][crate::cpu::Cpu::cycles] adds up.
+///
+/// This is not a pipeline model. Every instruction takes its own cost, regardless of the instructions around it,
+/// which is only good enough to approximate the runtime of a program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CycleCosts {
+    /// The cost of the kinds of instructions that do not take the default.
+    costs: BTreeMap<InstructionKind, u64>,
+    default: u64,
+}
+
+impl CycleCosts {
+    /// Creates a cost table where every instruction takes the given number of cycles.
+    pub fn uniform(cycles: u64) -> Self {
+        Self { costs: BTreeMap::new(), default: cycles }
+    }
+
+    /// Sets the number of cycles the given kind of instruction takes.
+    pub fn cost(mut self, kind: InstructionKind, cycles: u64) -> Self {
+        self.costs.insert(kind, cycles);
+        self
+    }
+
+    /// Sets the number of cycles each of the given kinds of instruction takes.
+    pub fn costs(mut self, kinds: impl IntoIterator<Item = InstructionKind>, cycles: u64) -> Self {
+        for kind in kinds {
+            self.costs.insert(kind, cycles);
+        }
+        self
+    }
+
+    /// The number of cycles the given kind of instruction takes.
+    pub fn get(&self, kind: InstructionKind) -> u64 {
+        self.costs.get(&kind).copied().unwrap_or(self.default)
+    }
+}
+
+impl Default for CycleCosts {
+    /// Loads and atomic memory operations take 2 cycles, multiplications 3 and divisions 30.
+    /// Everything else takes a single cycle.
+    fn default() -> Self {
+        use InstructionKind as I;
+        Self::uniform(1)
+            .costs([I::Lb, I::Lh, I::Lw, I::Lbu, I::Lhu, I::LrW, I::ScW], 2)
+            .costs(
+                [
+                    I::AmoswapW,
+                    I::AmoaddW,
+                    I::AmoxorW,
+                    I::AmoandW,
+                    I::AmoorW,
+                    I::AmominW,
+                    I::AmomaxW,
+                    I::AmominuW,
+                    I::AmomaxuW,
+                ],
+                2,
+            )
+            .costs([I::Mul, I::Mulh, I::Mulhsu, I::Mulhu], 3)
+            .costs([I::Div, I::Divu, I::Rem, I::Remu], 30)
+    }
+}
@@ -0,0 +1,76 @@
+use alloc::{vec, vec::Vec};
+
+use crate::uxlen;
+
+/// A memory-mapped device. Loads and stores to the address range a device
+/// is mapped at are handled by the device instead of by memory.
+pub trait Device {
+    /// Reads a byte at `offset` from the start of the device's address range.
+    fn read(&mut self, offset: uxlen) -> u8;
+
+    /// Writes a byte at `offset` from the start of the device's address range.
+    fn write(&mut self, offset: uxlen, value: u8);
+
+    /// Puts the device back into its initial state when the [Cpu][crate::cpu::Cpu] is reset.
+    /// Devices without state do not need to do anything.
+    fn reset(&mut self) {}
+}
+
+/// Random-access memory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ram {
+    bytes: Vec<u8>,
+    /// The bytes the RAM was created with, if it was not zero-initialized. They are restored on reset.
+    initial: Option<Vec<u8>>,
+}
+
+impl Ram {
+    /// Creates `size` bytes of zero-initialized RAM.
+    pub fn new(size: usize) -> Self {
+        Self { bytes: vec![0; size], initial: None }
+    }
+
+    /// Creates RAM initialized with `bytes`.
+    pub fn with_bytes(bytes: Vec<u8>) -> Self {
+        Self { initial: Some(bytes.clone()), bytes }
+    }
+}
+
+// NOTE: A RAM can be mapped at a range that is larger than the RAM itself. Reads past its end return
+//       zero and writes past its end are ignored, instead of indexing out of bounds.
+impl Device for Ram {
+    fn read(&mut self, offset: uxlen) -> u8 {
+        self.bytes.get(offset as usize).copied().unwrap_or(0)
+    }
+
+    fn write(&mut self, offset: uxlen, value: u8) {
+        if let Some(byte) = self.bytes.get_mut(offset as usize) {
+            *byte = value;
+        }
+    }
+
+    fn reset(&mut self) {
+        match &self.initial {
+            Some(initial) => self.bytes.copy_from_slice(initial),
+            None => self.bytes.fill(0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reset_restores_initial_bytes() {
+        let mut ram = Ram::new(2);
+        ram.write(1, 0xAA);
+        ram.reset();
+        assert_eq!(ram.read(1), 0);
+
+        let mut ram = Ram::with_bytes(vec![1, 2]);
+        ram.write(1, 0xAA);
+        ram.reset();
+        assert_eq!(ram.read(1), 2);
+    }
+}
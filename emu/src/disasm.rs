@@ -0,0 +1,192 @@
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+
+use crate::{
+    compressed::CInstruction,
+    csr::Csrs,
+    inst::{Instruction, InstructionKind, fence_set},
+    reg::Registers,
+    rom::Rom,
+    uxlen,
+};
+
+/// Walks the ROM from its start to its end address and disassembles every instruction,
+/// yielding the address, the instruction and its assembly text.
+///
+/// Compressed instructions are yielded as their expanded 32-bit equivalent.
+/// Illegal compressed instructions are yielded as a zeroed instruction with a `.half` directive as text.
+///
+/// If the ROM ends in the middle of an instruction, the remaining bytes
+/// are yielded as a zero-padded instruction with a `.byte` directive as text.
+pub fn disassemble<'a>(rom: &'a Rom) -> impl Iterator<Item = (uxlen, Instruction, String)> + 'a {
+    // NOTE: `None` once the last instruction ends at the top of the address space.
+    let mut next_addr = Some(rom.start_addr());
+    core::iter::from_fn(move || {
+        let addr = next_addr.filter(|addr| *addr < rom.end_addr())?;
+
+        let mut bytes = [0; Instruction::BYTES];
+        let mut len = 0;
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            match rom.read(addr.wrapping_add(i as uxlen)) {
+                Ok(b) => {
+                    *byte = b;
+                    len += 1;
+                }
+                Err(_) => break,
+            }
+        }
+
+        let half = u16::from_le_bytes([bytes[0], bytes[1]]);
+        let inst_len = if CInstruction::is_compressed(half) {
+            CInstruction::BYTES
+        } else {
+            Instruction::BYTES
+        };
+
+        let item = if len < inst_len {
+            let inst = Instruction(u32::from_le_bytes(bytes));
+            let bytes = bytes[..len].iter().map(|b| format!("{b:#04x}")).collect::<Vec<_>>();
+            (addr, inst, format!(".byte {}", bytes.join(", ")))
+        } else if inst_len == CInstruction::BYTES {
+            match CInstruction(half).expand() {
+                Some(inst) => (addr, inst, format!("{inst:?}")),
+                None => (addr, Instruction(0), format!(".half {half:#06x}")),
+            }
+        } else {
+            let inst = Instruction(u32::from_le_bytes(bytes));
+            (addr, inst, format!("{inst:?}"))
+        };
+
+        next_addr = addr.checked_add(inst_len as uxlen);
+        Some(item)
+    })
+}
+
+/// Disassembles an instruction the way the Spike reference simulator does in its
+/// instruction log, using ABI register names and `pc`-relative jump targets.
+pub fn spike_disassemble(inst: &Instruction) -> String {
+    use InstructionKind as I;
+
+    let x = |ix: u32| Registers::ABI_NAMES[ix as usize];
+    let (rd, rs1, rs2) = (x(inst.rd()), x(inst.rs1()), x(inst.rs2()));
+    let pc_relative = |offset: i32| match offset {
+        0.. => format!("pc + {:#x}", offset),
+        _ => format!("pc - {:#x}", offset.unsigned_abs()),
+    };
+
+    let kind = inst.kind();
+    let args = match kind {
+        I::Lui | I::Auipc => vec![rd.to_string(), format!("{:#x}", inst.imm_u() as u32 & 0xfffff)],
+        I::Jal => vec![rd.to_string(), pc_relative(inst.imm_j())],
+        I::Beq | I::Bne | I::Blt | I::Bge | I::Bltu | I::Bgeu => {
+            vec![rs1.to_string(), rs2.to_string(), pc_relative(inst.imm_b())]
+        }
+        I::Jalr | I::Lb | I::Lh | I::Lw | I::Lbu | I::Lhu => {
+            vec![rd.to_string(), format!("{}({rs1})", inst.imm_i())]
+        }
+        I::Sb | I::Sh | I::Sw => vec![rs2.to_string(), format!("{}({rs1})", inst.imm_s())],
+        I::Addi | I::Slti | I::Sltiu | I::Xori | I::Ori | I::Andi => {
+            vec![rd.to_string(), rs1.to_string(), inst.imm_i().to_string()]
+        }
+        I::Slli | I::Srli | I::Srai => {
+            vec![rd.to_string(), rs1.to_string(), (inst.imm_i() & 0b11111).to_string()]
+        }
+        I::Add
+        | I::Sub
+        | I::Sll
+        | I::Slt
+        | I::Sltu
+        | I::Xor
+        | I::Srl
+        | I::Sra
+        | I::Or
+        | I::And
+        | I::Mul
+        | I::Mulh
+        | I::Mulhsu
+        | I::Mulhu
+        | I::Div
+        | I::Divu
+        | I::Rem
+        | I::Remu => vec![rd.to_string(), rs1.to_string(), rs2.to_string()],
+        I::LrW => vec![rd.to_string(), format!("({rs1})")],
+        I::ScW
+        | I::AmoswapW
+        | I::AmoaddW
+        | I::AmoxorW
+        | I::AmoandW
+        | I::AmoorW
+        | I::AmominW
+        | I::AmomaxW
+        | I::AmominuW
+        | I::AmomaxuW => vec![rd.to_string(), rs2.to_string(), format!("({rs1})")],
+        I::Csrrw | I::Csrrs | I::Csrrc => {
+            vec![rd.to_string(), csr_name(inst.csr()), rs1.to_string()]
+        }
+        I::Csrrwi | I::Csrrsi | I::Csrrci => {
+            vec![rd.to_string(), csr_name(inst.csr()), inst.imm_csr().to_string()]
+        }
+        I::Fence => vec![fence_set(inst.pred()), fence_set(inst.succ())],
+        I::FenceI | I::ECall | I::EBreak | I::Sret | I::Mret | I::Wfi | I::Unknown => {
+            vec![]
+        }
+    };
+
+    let name = match kind {
+        I::Unknown => "unknown".to_string(),
+        I::Fence if inst.is_pause() => return "pause".to_string(),
+        _ => kind.to_string(),
+    };
+
+    match args.is_empty() {
+        true => name,
+        // NOTE: Spike pads the mnemonic to 8 characters, with at least one space.
+        false => format!("{name:<8}{}{}", if name.len() >= 8 { " " } else { "" }, args.join(", ")),
+    }
+}
+
+/// Formats a committed instruction as a line of Spike's instruction log:
+/// `core   0: 0x80000000 (0x00000093) addi    ra, zero, 0`.
+///
+/// `raw` is the encoding as it was fetched, which is only 16 bits for compressed instructions.
+pub fn spike_trace_line(pc: uxlen, raw: u32, len: usize, inst: &Instruction) -> String {
+    let encoding = match len {
+        CInstruction::BYTES => format!("{raw:#06x}"),
+        _ => format!("{raw:#010x}"),
+    };
+    format!("core   0: {pc:#010x} ({encoding}) {}", spike_disassemble(inst))
+}
+
+/// The name of a CSR, or its number if it has no known name.
+fn csr_name(csr: u32) -> String {
+    match Csrs::name(csr as usize) {
+        Some(name) => name.to_string(),
+        None => format!("{csr:#x}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stops_at_the_top_of_the_address_space() {
+        let mut bytes = [
+            0x13, 0x05, 0x10, 0x00, // addi x10, x0, 1
+            0x01, 0x00, // c.nop
+            0x13, // The first byte of an instruction that is cut off by the end of the ROM.
+        ];
+        let rom = Rom::new(&mut bytes, 0xffff_fff8, 0xffff_ffff);
+
+        let items: Vec<(uxlen, String)> =
+            disassemble(&rom).map(|(addr, _, text)| (addr, text)).collect();
+        assert_eq!(items.len(), 3);
+        assert_eq!(items[0].0, 0xffff_fff8);
+        assert_eq!(items[1].0, 0xffff_fffc);
+        assert_eq!(items[2], (0xffff_fffe, ".byte 0x13".to_string()));
+    }
+}
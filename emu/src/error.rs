@@ -0,0 +1,85 @@
+use crate::{
+    rom::MemError,
+    trap::{Exception, Trap},
+    uxlen,
+};
+
+/// An error that stops the emulator.
+///
+/// Most errors are exceptions the program raised without having a trap handler installed,
+/// and correspond to the RISC-V exception causes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmuError {
+    /// An access to an address that is not backed by memory or a device,
+    /// or that its region does not allow.
+    /// This is an instruction, load or store/AMO access fault.
+    MemoryFault { addr: uxlen },
+    /// An instruction that could not be decoded or is not supported.
+    IllegalInstruction { raw: uxlen },
+    /// A jump, branch or memory access to a misaligned address.
+    Misaligned { addr: uxlen },
+    /// An EBREAK instruction.
+    Ebreak,
+    /// An ECALL instruction that was not handled by the host.
+    Ecall,
+    /// The initial stack pointer does not point just past mapped memory.
+    InvalidStackPointer { sp: uxlen },
+    /// A snapshot can not be restored, as its memory is a different size than the ROM.
+    SnapshotSize { snapshot: usize, rom: usize },
+    /// The RAM or a device is mapped at a region that extends past the end of the address space.
+    RegionOverflow { base: uxlen, size: uxlen },
+}
+
+impl From<Trap> for EmuError {
+    fn from(trap: Trap) -> Self {
+        match trap.cause {
+            Exception::InstructionAccessFault
+            | Exception::LoadAccessFault
+            | Exception::StoreAccessFault => Self::MemoryFault { addr: trap.tval },
+            Exception::IllegalInstruction => Self::IllegalInstruction { raw: trap.tval },
+            Exception::InstructionAddressMisaligned
+            | Exception::LoadAddressMisaligned
+            | Exception::StoreAddressMisaligned => Self::Misaligned { addr: trap.tval },
+            Exception::Breakpoint => Self::Ebreak,
+            Exception::EnvironmentCallFromUMode
+            | Exception::EnvironmentCallFromSMode
+            | Exception::EnvironmentCallFromMMode => Self::Ecall,
+        }
+    }
+}
+
+impl From<MemError> for EmuError {
+    fn from(err: MemError) -> Self {
+        match err {
+            MemError::OutOfBounds { addr } | MemError::AccessDenied { addr } => {
+                Self::MemoryFault { addr }
+            }
+        }
+    }
+}
+
+impl core::fmt::Display for EmuError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::MemoryFault { addr } => write!(f, "access fault at address {addr:#010x}"),
+            Self::IllegalInstruction { raw } => write!(f, "illegal instruction {raw:#010x}"),
+            Self::Misaligned { addr } => write!(f, "misaligned address {addr:#010x}"),
+            Self::Ebreak => write!(f, "breakpoint"),
+            Self::Ecall => write!(f, "unhandled environment call"),
+            Self::InvalidStackPointer { sp } => {
+                write!(f, "stack pointer {sp:#010x} does not point just past mapped memory")
+            }
+            Self::SnapshotSize { snapshot, rom } => {
+                write!(f, "snapshot memory is {snapshot} bytes, but the ROM is {rom} bytes")
+            }
+            Self::RegionOverflow { base, size } => {
+                write!(
+                    f,
+                    "region of {size:#x} bytes at {base:#010x} extends past the end of the address space"
+                )
+            }
+        }
+    }
+}
+
+impl core::error::Error for EmuError {}
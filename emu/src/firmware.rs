@@ -0,0 +1,298 @@
+use alloc::{vec, vec::Vec};
+
+use crate::{image::MemoryImage, rom::end_addr, uxlen};
+
+/// An error that occurs when parsing an Intel HEX or Motorola S-record file.
+/// Line numbers start at 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FirmwareError {
+    /// The line is not a valid record, because it has no start code,
+    /// contains something other than pairs of hex digits or its length does not match its byte count.
+    Malformed { line: usize },
+    /// The checksum of the record does not match its contents.
+    Checksum { line: usize },
+    /// The record type is not known.
+    UnknownRecord { line: usize },
+    /// The file ended without an end-of-file or termination record.
+    MissingEnd,
+    /// The data at `addr` overlaps data earlier in the address space.
+    Overlap { addr: uxlen },
+    /// The data at `addr` extends past the end of the address space.
+    Overflow { addr: uxlen },
+}
+
+impl core::fmt::Display for FirmwareError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Malformed { line } => write!(f, "malformed record on line {line}"),
+            Self::Checksum { line } => write!(f, "checksum mismatch on line {line}"),
+            Self::UnknownRecord { line } => write!(f, "unknown record type on line {line}"),
+            Self::MissingEnd => write!(f, "missing end-of-file record"),
+            Self::Overlap { addr } => write!(f, "data at {addr:#010x} overlaps other data"),
+            Self::Overflow { addr } => {
+                write!(f, "data at {addr:#010x} extends past the end of the address space")
+            }
+        }
+    }
+}
+
+impl core::error::Error for FirmwareError {}
+
+/// A block of data to be loaded at an address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataBlock {
+    pub addr: uxlen,
+    pub data: Vec<u8>,
+}
+
+/// A firmware image read from an Intel HEX or Motorola S-record file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Firmware {
+    /// The data records, in the order they appear in the file.
+    pub blocks: Vec<DataBlock>,
+    /// The start address given by the file, if any.
+    pub entry: Option<uxlen>,
+}
+
+impl Firmware {
+    /// Parses an Intel HEX file.
+    ///
+    /// Supports the data, end-of-file, extended segment address, start segment address,
+    /// extended linear address and start linear address records.
+    pub fn parse_ihex(text: &str) -> Result<Self, FirmwareError> {
+        let mut firmware = Self::default();
+        // The base address set by the last extended address record.
+        let mut base: uxlen = 0;
+
+        for (ix, line) in text.lines().enumerate() {
+            let (line, record) = (ix + 1, line.trim());
+            if record.is_empty() {
+                continue;
+            }
+
+            let malformed = FirmwareError::Malformed { line };
+            let bytes = record.strip_prefix(':').and_then(decode_hex).ok_or(malformed)?;
+
+            // NOTE: A record consists of a byte count, a 16-bit address, the record type,
+            //       the data and a checksum byte.
+            if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+                return Err(malformed);
+            }
+            // NOTE: The checksum is the two's complement of the sum of all other bytes.
+            if bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0 {
+                return Err(FirmwareError::Checksum { line });
+            }
+
+            let offset = u16::from_be_bytes([bytes[1], bytes[2]]) as uxlen;
+            let data = &bytes[4..bytes.len() - 1];
+            match (bytes[3], data.len()) {
+                (0x00, _) => firmware
+                    .blocks
+                    .push(DataBlock { addr: base.wrapping_add(offset), data: data.to_vec() }),
+                (0x01, _) => return Ok(firmware),
+                (0x02, 2) => base = be_uint(data) << 4,
+                (0x03, 4) => {
+                    let segment = be_uint(&data[..2]);
+                    let ip = be_uint(&data[2..]);
+                    firmware.entry = Some((segment << 4).wrapping_add(ip));
+                }
+                (0x04, 2) => base = be_uint(data) << 16,
+                (0x05, 4) => firmware.entry = Some(be_uint(data)),
+                (0x02..=0x05, _) => return Err(malformed),
+                _ => return Err(FirmwareError::UnknownRecord { line }),
+            }
+        }
+
+        Err(FirmwareError::MissingEnd)
+    }
+
+    /// Parses a Motorola S-record file.
+    ///
+    /// Data is read from S1, S2 and S3 records and the start address from the
+    /// S7, S8 or S9 termination record. Header and count records are skipped.
+    pub fn parse_srec(text: &str) -> Result<Self, FirmwareError> {
+        let mut firmware = Self::default();
+
+        for (ix, line) in text.lines().enumerate() {
+            let (line, record) = (ix + 1, line.trim());
+            if record.is_empty() {
+                continue;
+            }
+
+            let malformed = FirmwareError::Malformed { line };
+            let (kind, hex) = record
+                .strip_prefix('S')
+                .and_then(|rest| rest.split_at_checked(1))
+                .ok_or(malformed)?;
+            let addr_len = match kind {
+                "0" | "1" | "5" | "9" => 2,
+                "2" | "6" | "8" => 3,
+                "3" | "7" => 4,
+                _ => return Err(FirmwareError::UnknownRecord { line }),
+            };
+            let bytes = decode_hex(hex).ok_or(malformed)?;
+
+            // NOTE: A record consists of a byte count, an address of 2, 3 or 4 bytes,
+            //       the data and a checksum byte. The byte count includes all but itself.
+            if bytes.len() < addr_len + 2 || bytes.len() != bytes[0] as usize + 1 {
+                return Err(malformed);
+            }
+            // NOTE: The checksum is the one's complement of the sum of all other bytes.
+            if bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0xFF {
+                return Err(FirmwareError::Checksum { line });
+            }
+
+            let addr = be_uint(&bytes[1..1 + addr_len]);
+            let data = &bytes[1 + addr_len..bytes.len() - 1];
+            match kind {
+                "1" | "2" | "3" => firmware.blocks.push(DataBlock { addr, data: data.to_vec() }),
+                "7" | "8" | "9" => {
+                    firmware.entry = Some(addr);
+                    return Ok(firmware);
+                }
+                _ => {}
+            }
+        }
+
+        Err(FirmwareError::MissingEnd)
+    }
+
+    /// Creates a memory image for every contiguous run of data blocks, ordered by address.
+    ///
+    /// Fails if blocks overlap or extend past the end of the address space.
+    pub fn to_images(&self) -> Result<Vec<MemoryImage>, FirmwareError> {
+        let mut blocks: Vec<&DataBlock> =
+            self.blocks.iter().filter(|block| !block.data.is_empty()).collect();
+        blocks.sort_by_key(|block| block.addr);
+
+        // The start address and bytes of each contiguous run of blocks.
+        let mut runs: Vec<(uxlen, Vec<u8>)> = Vec::new();
+        for block in blocks {
+            end_addr(block.addr, block.data.len())
+                .map_err(|_| FirmwareError::Overflow { addr: block.addr })?;
+
+            match runs.last_mut() {
+                Some((start, data)) if block.addr < *start + data.len() as uxlen => {
+                    return Err(FirmwareError::Overlap { addr: block.addr });
+                }
+                Some((start, data)) if block.addr == *start + data.len() as uxlen => {
+                    data.extend_from_slice(&block.data);
+                }
+                _ => runs.push((block.addr, block.data.clone())),
+            }
+        }
+
+        Ok(runs.into_iter().map(|(start, data)| MemoryImage::with_bytes(start, data)).collect())
+    }
+}
+
+/// Decodes pairs of hex digits into bytes.
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let mut bytes = vec![0; hex.len() / 2];
+    for (ix, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(ix * 2..ix * 2 + 2)?, 16).ok()?;
+    }
+    Some(bytes)
+}
+
+/// Interprets up to four bytes as a big-endian unsigned integer.
+fn be_uint(bytes: &[u8]) -> uxlen {
+    bytes.iter().fold(0, |value, byte| (value << 8) | *byte as uxlen)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_intel_hex_with_extended_addresses() {
+        let text = concat!(
+            ":0200000480007A\n",     // extended linear address 0x8000_0000
+            ":0400100013000000D9\n", // data at 0x0010
+            ":020000021000EC\n",     // extended segment address 0x1000
+            ":02000400AABB95\n",     // data at 0x0004
+            ":040000058000001067\n", // start linear address 0x8000_0010
+            ":00000001FF\n",         // end of file
+        );
+
+        let firmware = Firmware::parse_ihex(text).unwrap();
+        assert_eq!(
+            firmware.blocks,
+            [
+                DataBlock { addr: 0x8000_0010, data: vec![0x13, 0x00, 0x00, 0x00] },
+                DataBlock { addr: 0x0001_0004, data: vec![0xAA, 0xBB] },
+            ]
+        );
+        assert_eq!(firmware.entry, Some(0x8000_0010));
+    }
+
+    #[test]
+    fn rejects_corrupt_intel_hex() {
+        let parse = |text| Firmware::parse_ihex(text);
+        assert_eq!(parse(":0400100013000000D8\n"), Err(FirmwareError::Checksum { line: 1 }));
+        assert_eq!(parse(":0500100013000000D9\n"), Err(FirmwareError::Malformed { line: 1 }));
+        assert_eq!(
+            parse(":0400100013000000D9\n:0400100013000000\n"),
+            Err(FirmwareError::Malformed { line: 2 })
+        );
+        assert_eq!(parse(":0400100013000000D9\n"), Err(FirmwareError::MissingEnd));
+        assert_eq!(parse(":0100000210ED\n"), Err(FirmwareError::Malformed { line: 1 }));
+    }
+
+    #[test]
+    fn parses_srec() {
+        let text = concat!(
+            "S0050000686929\n",         // header "hi"
+            "S309800000001300000063\n", // data at 0x8000_0000
+            "S1041000AA41\n",           // data at 0x1000
+            "S705800000007A\n",         // start address 0x8000_0000
+        );
+
+        let firmware = Firmware::parse_srec(text).unwrap();
+        assert_eq!(
+            firmware.blocks,
+            [
+                DataBlock { addr: 0x8000_0000, data: vec![0x13, 0x00, 0x00, 0x00] },
+                DataBlock { addr: 0x1000, data: vec![0xAA] },
+            ]
+        );
+        assert_eq!(firmware.entry, Some(0x8000_0000));
+    }
+
+    #[test]
+    fn rejects_corrupt_srec() {
+        let parse = |text| Firmware::parse_srec(text);
+        assert_eq!(parse("S1041000AA40\nS9030000FC\n"), Err(FirmwareError::Checksum { line: 1 }));
+        assert_eq!(parse("S1051000AA41\nS9030000FC\n"), Err(FirmwareError::Malformed { line: 1 }));
+        assert_eq!(parse("S4041000AA41\n"), Err(FirmwareError::UnknownRecord { line: 1 }));
+        assert_eq!(parse("S1041000AA41\n"), Err(FirmwareError::MissingEnd));
+    }
+
+    #[test]
+    fn images_are_split_at_gaps_and_must_not_overlap() {
+        let block = |addr, data: &[u8]| DataBlock { addr, data: data.to_vec() };
+
+        let firmware = Firmware {
+            blocks: vec![block(0xFFFF_0000, &[3]), block(0x0, &[1]), block(0x1, &[2])],
+            entry: None,
+        };
+        assert_eq!(
+            firmware.to_images(),
+            Ok(vec![
+                MemoryImage::with_bytes(0x0, vec![1, 2]),
+                MemoryImage::with_bytes(0xFFFF_0000, vec![3]),
+            ])
+        );
+
+        let firmware =
+            Firmware { blocks: vec![block(0x0, &[1, 2]), block(0x1, &[3])], entry: None };
+        assert_eq!(firmware.to_images(), Err(FirmwareError::Overlap { addr: 0x1 }));
+
+        let firmware = Firmware { blocks: vec![block(0xFFFF_FFFF, &[1, 2])], entry: None };
+        assert_eq!(firmware.to_images(), Err(FirmwareError::Overflow { addr: 0xFFFF_FFFF }));
+    }
+}
@@ -0,0 +1,433 @@
+//! A minimal GDB remote serial protocol (RSP) server.
+//!
+//! Supports reading and writing registers and memory, single stepping,
+//! continuing and software breakpoints, which is enough for `target remote`.
+
+use std::{
+    eprintln, format,
+    io::{BufReader, ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream},
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use anyhow::Context;
+
+use crate::{cpu::Cpu, error::EmuError, uxlen};
+
+/// The signal reported when the CPU stops at a breakpoint or after a step (`SIGTRAP`).
+const SIGTRAP: u8 = 5;
+/// The signal reported when GDB interrupted a continue (`SIGINT`).
+const SIGINT: u8 = 2;
+/// The signal reported when the program executed an illegal instruction (`SIGILL`).
+const SIGILL: u8 = 4;
+/// The signal reported when the program accessed a misaligned address (`SIGBUS`).
+const SIGBUS: u8 = 7;
+/// The signal reported when the program accessed unmapped memory (`SIGSEGV`).
+const SIGSEGV: u8 = 11;
+/// The number of registers in the `g` packet: x0-x31 followed by the pc.
+const REGISTER_COUNT: usize = 33;
+/// The maximum size of a packet, which GDB is told in the `qSupported` reply.
+const PACKET_SIZE: usize = 0x4000;
+/// How many instructions are executed between checks for an interrupt from GDB while continuing.
+const INTERRUPT_CHECK_INTERVAL: usize = 4096;
+
+/// Listens on the given port, waits for GDB to connect and serves
+/// its requests until it detaches, kills the program, or the program exits.
+///
+/// The CPU stays halted until GDB tells it to step or continue.
+pub fn serve(cpu: &mut Cpu, port: u16) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))
+        .with_context(|| format!("Failed to bind to port {port}"))?;
+    eprintln!("Waiting for GDB to connect on port {port}...");
+    let (stream, addr) = listener.accept().context("Failed to accept GDB connection")?;
+    eprintln!("GDB connected from {addr}");
+
+    GdbConnection::new(stream)?.run(cpu)
+}
+
+/// What to do after a packet has been handled.
+enum Action {
+    Reply(String),
+    Stop,
+}
+
+struct GdbConnection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl GdbConnection {
+    fn new(stream: TcpStream) -> anyhow::Result<Self> {
+        stream.set_nodelay(true)?;
+        Ok(Self { reader: BufReader::new(stream.try_clone()?), writer: stream })
+    }
+
+    fn run(&mut self, cpu: &mut Cpu) -> anyhow::Result<()> {
+        while let Some(packet) = self.read_packet()? {
+            match self.handle_packet(cpu, &packet)? {
+                Action::Reply(reply) => self.write_packet(&reply)?,
+                Action::Stop => return Ok(()),
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_packet(&mut self, cpu: &mut Cpu, packet: &str) -> anyhow::Result<Action> {
+        let reply = match packet.as_bytes().first() {
+            Some(b'?') => stop_reply(cpu, SIGTRAP),
+            Some(b'g') => (0..REGISTER_COUNT).map(|ix| hex_u32(read_register(cpu, ix))).collect(),
+            Some(b'G') => {
+                let values = packet.as_bytes()[1..].chunks(8).map(parse_hex_u32);
+                for (ix, value) in values.take(REGISTER_COUNT).enumerate() {
+                    let Some(value) = value else { return Ok(Action::Reply("E01".to_string())) };
+                    write_register(cpu, ix, value);
+                }
+                "OK".to_string()
+            }
+            Some(b'p') => match usize::from_str_radix(&packet[1..], 16) {
+                Ok(ix) if ix < REGISTER_COUNT => hex_u32(read_register(cpu, ix)),
+                _ => "E01".to_string(),
+            },
+            Some(b'P') => {
+                let parsed = packet[1..].split_once('=').and_then(|(ix, value)| {
+                    Some((usize::from_str_radix(ix, 16).ok()?, parse_hex_u32(value.as_bytes())?))
+                });
+                match parsed {
+                    Some((ix, value)) if ix < REGISTER_COUNT => {
+                        write_register(cpu, ix, value);
+                        "OK".to_string()
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            Some(b'm') => {
+                let Some((addr, len)) = parse_addr_len(&packet[1..]) else {
+                    return Ok(Action::Reply("E01".to_string()));
+                };
+                // NOTE: The length comes from the client, so the reply is limited to what fits in a packet.
+                let len = len.min((PACKET_SIZE / 2) as uxlen);
+                let mut reply = String::with_capacity(len as usize * 2);
+                for offset in 0..len {
+                    match cpu.read_byte(addr.wrapping_add(offset)) {
+                        Ok(byte) => reply.push_str(&format!("{byte:02x}")),
+                        // SPEC: The reply may contain fewer addressable memory units than requested if the
+                        //       server was able to read only part of the region of memory.
+                        Err(_) if offset > 0 => break,
+                        Err(_) => return Ok(Action::Reply("E14".to_string())),
+                    }
+                }
+                reply
+            }
+            Some(b'M') => {
+                let parsed = packet[1..].split_once(':').and_then(|(addr_len, data)| {
+                    let (addr, len) = parse_addr_len(addr_len)?;
+                    let bytes = parse_hex_bytes(data)?;
+                    (bytes.len() == len as usize).then_some((addr, bytes))
+                });
+                let Some((addr, bytes)) = parsed else {
+                    return Ok(Action::Reply("E01".to_string()));
+                };
+                if cpu.write_mem(addr, &bytes).is_ok() { "OK" } else { "E14" }.to_string()
+            }
+            Some(b's') => {
+                if !cpu.is_runnable() {
+                    return Ok(Action::Reply(stop_reply(cpu, SIGTRAP)));
+                }
+                match cpu.step() {
+                    // NOTE: The CPU halted at an EBREAK, but it has not exited.
+                    Ok(outcome) if outcome.breakpoint => format!("S{SIGTRAP:02x}"),
+                    Ok(_) => stop_reply(cpu, SIGTRAP),
+                    Err(err) => stop_reply(cpu, error_signal(&err)),
+                }
+            }
+            Some(b'c') => self.resume(cpu)?,
+            Some(b'Z') | Some(b'z') => {
+                let mut fields = packet[1..].split(',');
+                let kind = fields.next();
+                let addr = fields.next().and_then(|addr| uxlen::from_str_radix(addr, 16).ok());
+                match (kind, addr) {
+                    // Only software breakpoints are supported.
+                    (Some("0"), Some(addr)) => {
+                        if packet.starts_with('Z') {
+                            cpu.add_breakpoint(addr);
+                        } else {
+                            cpu.remove_breakpoint(addr);
+                        }
+                        "OK".to_string()
+                    }
+                    (Some("0"), None) => "E01".to_string(),
+                    _ => String::new(),
+                }
+            }
+            Some(b'H') => "OK".to_string(),
+            Some(b'k') => {
+                cpu.abort();
+                return Ok(Action::Stop);
+            }
+            Some(b'D') => {
+                self.write_packet("OK")?;
+                return Ok(Action::Stop);
+            }
+            Some(b'q') => match packet {
+                _ if packet.starts_with("qSupported") => format!("PacketSize={PACKET_SIZE:x}"),
+                "qAttached" => "1".to_string(),
+                "qC" => "QC1".to_string(),
+                "qfThreadInfo" => "m1".to_string(),
+                "qsThreadInfo" => "l".to_string(),
+                _ => String::new(),
+            },
+            // An empty reply tells GDB the packet is not supported.
+            _ => String::new(),
+        };
+
+        Ok(Action::Reply(reply))
+    }
+
+    /// Runs the CPU until it reaches a breakpoint, stops running, or GDB interrupts it.
+    /// Returns the stop reply to report.
+    fn resume(&mut self, cpu: &mut Cpu) -> anyhow::Result<String> {
+        // NOTE: The first instruction is always executed, so continuing
+        //       from a breakpoint does not immediately stop at it again.
+        let mut steps = 0;
+        while cpu.is_runnable() {
+            if steps > 0 && cpu.breakpoints().contains(&cpu.pc()) {
+                return Ok(stop_reply(cpu, SIGTRAP));
+            }
+
+            match cpu.step() {
+                // NOTE: The CPU halted at an EBREAK, but it has not exited.
+                Ok(outcome) if outcome.breakpoint => return Ok(format!("S{SIGTRAP:02x}")),
+                Ok(_) => {}
+                Err(err) => return Ok(stop_reply(cpu, error_signal(&err))),
+            }
+            steps += 1;
+
+            if steps % INTERRUPT_CHECK_INTERVAL == 0 && self.interrupted()? {
+                return Ok(stop_reply(cpu, SIGINT));
+            }
+        }
+
+        Ok(stop_reply(cpu, SIGTRAP))
+    }
+
+    /// Checks, without blocking, whether GDB sent an interrupt (`0x03`).
+    fn interrupted(&mut self) -> anyhow::Result<bool> {
+        let stream = self.reader.get_ref();
+        stream.set_nonblocking(true)?;
+        let mut byte = [0];
+        let result = stream.peek(&mut byte);
+        stream.set_nonblocking(false)?;
+
+        match result {
+            Ok(1) if byte[0] == 0x03 => {
+                self.reader.read_exact(&mut byte)?;
+                Ok(true)
+            }
+            Ok(_) => Ok(false),
+            Err(err) if err.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Reads the next packet, acknowledging it. Returns `None` when GDB disconnected.
+    fn read_packet(&mut self) -> anyhow::Result<Option<String>> {
+        let mut byte = [0];
+        loop {
+            // Skip everything up to the start of a packet, like acknowledgements and stray interrupts.
+            loop {
+                match self.reader.read(&mut byte)? {
+                    0 => return Ok(None),
+                    _ if byte[0] == b'$' => break,
+                    _ => {}
+                }
+            }
+
+            let mut data = Vec::new();
+            loop {
+                if self.reader.read(&mut byte)? == 0 {
+                    return Ok(None);
+                }
+                if byte[0] == b'#' {
+                    break;
+                }
+                data.push(byte[0]);
+            }
+
+            let mut checksum = [0; 2];
+            self.reader.read_exact(&mut checksum)?;
+            let expected = parse_hex_bytes(std::str::from_utf8(&checksum).unwrap_or_default());
+            let actual = data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+
+            if expected.as_deref() == Some(&[actual]) {
+                self.writer.write_all(b"+")?;
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+            }
+
+            // Ask GDB to retransmit the corrupted packet.
+            self.writer.write_all(b"-")?;
+        }
+    }
+
+    fn write_packet(&mut self, data: &str) -> anyhow::Result<()> {
+        let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        write!(self.writer, "${data}#{checksum:02x}")?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// The reply telling GDB why the CPU stopped, or that the program exited.
+fn stop_reply(cpu: &Cpu, signal: u8) -> String {
+    match cpu.is_runnable() {
+        true => format!("S{signal:02x}"),
+        false => format!("W{:02x}", cpu.exit_code().unwrap_or(0) as u8),
+    }
+}
+
+/// The signal reported when the CPU stopped with an error, like a Unix kernel would for the same fault.
+fn error_signal(err: &EmuError) -> u8 {
+    match err {
+        EmuError::MemoryFault { .. } => SIGSEGV,
+        EmuError::IllegalInstruction { .. } => SIGILL,
+        EmuError::Misaligned { .. } => SIGBUS,
+        EmuError::Ebreak
+        | EmuError::Ecall
+        | EmuError::InvalidStackPointer { .. }
+        | EmuError::SnapshotSize { .. }
+        | EmuError::RegionOverflow { .. } => SIGTRAP,
+    }
+}
+
+/// Reads register `ix` in GDB's numbering, where x0-x31 are followed by the pc.
+fn read_register(cpu: &Cpu, ix: usize) -> uxlen {
+    match ix {
+        32 => cpu.pc(),
+        _ => cpu.registers()[ix],
+    }
+}
+
+fn write_register(cpu: &mut Cpu, ix: usize, value: uxlen) {
+    match ix {
+        32 => cpu.set_pc(value),
+        // NOTE: x0 is hard-wired to zero, so writes to it are ignored.
+        0 => {}
+        _ => cpu.registers_mut()[ix] = value,
+    }
+}
+
+/// Formats a register value as GDB expects it: in target (little-endian) byte order.
+fn hex_u32(value: u32) -> String {
+    value.to_le_bytes().iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn parse_hex_u32(hex: &[u8]) -> Option<u32> {
+    let bytes = parse_hex_bytes(std::str::from_utf8(hex).ok()?)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+fn parse_hex_bytes(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
+
+/// Parses the `addr,length` arguments of the memory packets.
+fn parse_addr_len(args: &str) -> Option<(uxlen, uxlen)> {
+    let (addr, len) = args.split_once(',')?;
+    Some((uxlen::from_str_radix(addr, 16).ok()?, uxlen::from_str_radix(len, 16).ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::Rom;
+
+    const BASE: uxlen = 0x8000_0000;
+    const RAM_BASE: uxlen = 0x1000_0000;
+
+    /// Connects to a local listener, so packets can be handled without GDB.
+    /// The client end has to be kept alive for as long as the connection is used.
+    fn connection() -> (GdbConnection, TcpStream) {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        (GdbConnection::new(server).unwrap(), client)
+    }
+
+    fn reply(connection: &mut GdbConnection, cpu: &mut Cpu, packet: &str) -> String {
+        match connection.handle_packet(cpu, packet).unwrap() {
+            Action::Reply(reply) => reply,
+            Action::Stop => panic!("unexpected stop after {packet:?}"),
+        }
+    }
+
+    #[test]
+    fn reads_and_writes_registers() {
+        let mut bytes = [0x13, 0, 0, 0];
+        let mut rom = Rom::new(&mut bytes, BASE, BASE + 4);
+        let mut cpu = Cpu::new(&mut rom, false).unwrap();
+        let (mut gdb, _client) = connection();
+
+        assert_eq!(reply(&mut gdb, &mut cpu, "P5=78563412"), "OK");
+        assert_eq!(cpu.registers()[5], 0x12345678);
+        assert_eq!(reply(&mut gdb, &mut cpu, "p5"), "78563412");
+        assert_eq!(reply(&mut gdb, &mut cpu, "p20"), "00000080");
+        assert_eq!(reply(&mut gdb, &mut cpu, "p21"), "E01");
+
+        // x0 is hard-wired to zero.
+        assert_eq!(reply(&mut gdb, &mut cpu, "P0=01000000"), "OK");
+        assert_eq!(reply(&mut gdb, &mut cpu, "p0"), "00000000");
+
+        let registers = reply(&mut gdb, &mut cpu, "g");
+        assert_eq!(registers.len(), REGISTER_COUNT * 8);
+        assert_eq!(&registers[5 * 8..6 * 8], "78563412");
+
+        let mut registers = "01000000".repeat(REGISTER_COUNT - 1);
+        registers.push_str("04000080");
+        assert_eq!(reply(&mut gdb, &mut cpu, &format!("G{registers}")), "OK");
+        assert_eq!(cpu.registers()[0], 0);
+        assert_eq!(cpu.registers()[31], 1);
+        assert_eq!(cpu.pc(), BASE + 4);
+        assert_eq!(reply(&mut gdb, &mut cpu, "G0100"), "E01");
+    }
+
+    #[test]
+    fn reads_and_writes_memory() {
+        let mut bytes = [0x13, 0, 0, 0];
+        let mut rom = Rom::new(&mut bytes, BASE, BASE + 4);
+        let mut cpu = Cpu::builder(&mut rom).ram(RAM_BASE, 0x10000).build().unwrap();
+        let (mut gdb, _client) = connection();
+
+        assert_eq!(reply(&mut gdb, &mut cpu, "m80000000,4"), "13000000");
+        assert_eq!(reply(&mut gdb, &mut cpu, "M80000001,2:aabb"), "OK");
+        assert_eq!(reply(&mut gdb, &mut cpu, "m80000000,4"), "13aabb00");
+        assert_eq!(reply(&mut gdb, &mut cpu, "M80000000,2:aa"), "E01");
+
+        // A read that runs off the end of memory returns the part that could be read.
+        assert_eq!(reply(&mut gdb, &mut cpu, "m80000002,8"), "bb00");
+        assert_eq!(reply(&mut gdb, &mut cpu, "M80000003,2:aabb"), "E14");
+        assert_eq!(reply(&mut gdb, &mut cpu, "m0,4"), "E14");
+
+        // The client can not make the reply larger than a packet.
+        assert_eq!(reply(&mut gdb, &mut cpu, "m10000000,ffffffff").len(), PACKET_SIZE);
+    }
+
+    #[test]
+    fn sets_and_removes_software_breakpoints() {
+        let mut bytes = [0x13, 0, 0, 0];
+        let mut rom = Rom::new(&mut bytes, BASE, BASE + 4);
+        let mut cpu = Cpu::new(&mut rom, false).unwrap();
+        let (mut gdb, _client) = connection();
+
+        assert_eq!(reply(&mut gdb, &mut cpu, "Z0,80000000,4"), "OK");
+        assert!(cpu.breakpoints().contains(&BASE));
+        assert_eq!(reply(&mut gdb, &mut cpu, "z0,80000000,4"), "OK");
+        assert!(cpu.breakpoints().is_empty());
+
+        assert_eq!(reply(&mut gdb, &mut cpu, "Z0,nope,4"), "E01");
+        // Hardware breakpoints are not supported.
+        assert_eq!(reply(&mut gdb, &mut cpu, "Z1,80000000,4"), "");
+    }
+}
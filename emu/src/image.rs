@@ -0,0 +1,101 @@
+use alloc::{vec, vec::Vec};
+
+use crate::{
+    device::Ram,
+    rom::{MemError, Rom, end_addr},
+    uxlen,
+};
+
+/// An owned, zero-initialized memory image that the segments of a program are loaded into.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryImage {
+    bytes: Vec<u8>,
+    start_addr: uxlen,
+}
+
+impl MemoryImage {
+    /// Creates a zero-initialized memory image of `size` bytes, starting at `start_addr`.
+    pub fn new(start_addr: uxlen, size: usize) -> Self {
+        MemoryImage { bytes: vec![0; size], start_addr }
+    }
+
+    /// Creates a memory image holding `bytes`, starting at `start_addr`.
+    pub fn with_bytes(start_addr: uxlen, bytes: Vec<u8>) -> Self {
+        MemoryImage { bytes, start_addr }
+    }
+
+    /// Loads a segment of `mem_size` bytes at `addr`. The `data` is copied to the start
+    /// of the segment and the remaining bytes are zeroed, which is where `.bss` lives.
+    pub fn load_segment(
+        &mut self,
+        addr: uxlen,
+        data: &[u8],
+        mem_size: usize,
+    ) -> Result<(), MemError> {
+        let out_of_bounds = MemError::OutOfBounds { addr };
+
+        let start = addr.checked_sub(self.start_addr).ok_or(out_of_bounds)? as usize;
+        let data_end = start + data.len();
+        let end = start + mem_size.max(data.len());
+        if end > self.bytes.len() {
+            return Err(out_of_bounds);
+        }
+
+        self.bytes[start..data_end].copy_from_slice(data);
+        self.bytes[data_end..end].fill(0);
+        Ok(())
+    }
+
+    /// Creates a [Rom] backed by this memory image.
+    ///
+    /// Fails if the image extends past the end of the address space.
+    pub fn as_rom(&mut self) -> Result<Rom<'_>, MemError> {
+        Rom::from_raw(&mut self.bytes, self.start_addr)
+    }
+
+    /// Turns this memory image into a [Rom] that owns its bytes.
+    ///
+    /// Fails if the image extends past the end of the address space.
+    pub fn into_rom(self) -> Result<Rom<'static>, MemError> {
+        let end_addr = end_addr(self.start_addr, self.bytes.len())?;
+        Ok(Rom::from_vec(self.bytes, self.start_addr, end_addr))
+    }
+
+    /// Creates [Ram] holding the bytes of this memory image, to be mapped at its start address.
+    pub fn into_ram(self) -> Ram {
+        Ram::with_bytes(self.bytes)
+    }
+
+    #[inline]
+    pub fn size(&self) -> usize {
+        self.bytes.len()
+    }
+
+    #[inline]
+    pub fn start_addr(&self) -> uxlen {
+        self.start_addr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn as_rom_rejects_images_past_the_address_space() {
+        let mut image = MemoryImage::new(0xffff_fff0, 20);
+        assert_eq!(image.as_rom().unwrap_err(), MemError::OutOfBounds { addr: 0xffff_fff0 });
+
+        let mut image = MemoryImage::new(0xffff_fff0, 15);
+        assert_eq!(image.as_rom().unwrap().end_addr(), 0xffff_ffff);
+    }
+
+    #[test]
+    fn into_rom_rejects_images_past_the_address_space() {
+        let image = MemoryImage::new(0xffff_fff0, 20);
+        assert_eq!(image.into_rom().unwrap_err(), MemError::OutOfBounds { addr: 0xffff_fff0 });
+
+        let rom = MemoryImage::new(0xffff_fff0, 15).into_rom().unwrap();
+        assert_eq!(rom.end_addr(), 0xffff_ffff);
+    }
+}
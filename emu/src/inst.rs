@@ -1,5 +1,10 @@
+use alloc::{
+    format,
+    string::{String, ToString},
+};
+
 /// Represents a RISC-V instruction.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum InstructionKind {
     /// Load upper immediate.
     Lui,
@@ -84,23 +89,98 @@ pub enum InstructionKind {
     /// AND.
     And,
 
+    /// Multiply.
+    Mul,
+    /// Multiply high, signed × signed.
+    Mulh,
+    /// Multiply high, signed × unsigned.
+    Mulhsu,
+    /// Multiply high, unsigned × unsigned.
+    Mulhu,
+    /// Divide.
+    Div,
+    /// Divide, unsigned.
+    Divu,
+    /// Remainder.
+    Rem,
+    /// Remainder, unsigned.
+    Remu,
+
+    /// Load reserved word.
+    LrW,
+    /// Store conditional word.
+    ScW,
+    /// Atomic swap word.
+    AmoswapW,
+    /// Atomic add word.
+    AmoaddW,
+    /// Atomic XOR word.
+    AmoxorW,
+    /// Atomic AND word.
+    AmoandW,
+    /// Atomic OR word.
+    AmoorW,
+    /// Atomic minimum word.
+    AmominW,
+    /// Atomic maximum word.
+    AmomaxW,
+    /// Atomic minimum word, unsigned.
+    AmominuW,
+    /// Atomic maximum word, unsigned.
+    AmomaxuW,
+
     Fence,
+    /// Instruction-fetch fence.
+    FenceI,
     ECall,
     EBreak,
 
+    /// Supervisor-mode trap return.
+    Sret,
+    /// Machine-mode trap return.
+    Mret,
+    /// Wait for interrupt.
+    Wfi,
+
+    /// Atomic read/write CSR.
+    Csrrw,
+    /// Atomic read and set bits in CSR.
+    Csrrs,
+    /// Atomic read and clear bits in CSR.
+    Csrrc,
+    /// Atomic read/write CSR, immediate.
+    Csrrwi,
+    /// Atomic read and set bits in CSR, immediate.
+    Csrrsi,
+    /// Atomic read and clear bits in CSR, immediate.
+    Csrrci,
+
     /// Unknown.
     Unknown,
 }
-impl std::fmt::Display for InstructionKind {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl core::fmt::Display for InstructionKind {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             Self::Unknown => f.write_str("<unknown>"),
+            Self::FenceI => f.write_str("fence.i"),
+            Self::LrW => f.write_str("lr.w"),
+            Self::ScW => f.write_str("sc.w"),
+            Self::AmoswapW => f.write_str("amoswap.w"),
+            Self::AmoaddW => f.write_str("amoadd.w"),
+            Self::AmoxorW => f.write_str("amoxor.w"),
+            Self::AmoandW => f.write_str("amoand.w"),
+            Self::AmoorW => f.write_str("amoor.w"),
+            Self::AmominW => f.write_str("amomin.w"),
+            Self::AmomaxW => f.write_str("amomax.w"),
+            Self::AmominuW => f.write_str("amominu.w"),
+            Self::AmomaxuW => f.write_str("amomaxu.w"),
             other => f.write_str(&format!("{other:?}").to_ascii_lowercase()),
         }
     }
 }
 
 bitfield::bitfield! {
+    #[derive(Clone, Copy)]
     pub struct Instruction(u32);
 
     #[inline] pub u32, opcode, _: 6,  0;
@@ -111,67 +191,176 @@ bitfield::bitfield! {
     #[inline] pub u32, funct7, _: 31, 25;
 
     #[inline] pub u32, funct12, _: 31, 20;
+    #[inline] pub u32, csr,     _: 31, 20;
+    #[inline] pub u32, funct5,  _: 31, 27;
+
+    #[inline] pub u32, fm,   _: 31, 28;
+    #[inline] pub u32, pred, _: 27, 24;
+    #[inline] pub u32, succ, _: 23, 20;
 }
 
 impl Instruction {
     pub const BYTES: usize = size_of::<u32>();
 
-    pub fn kind(&self) -> InstructionKind {
-        match (self.opcode(), self.funct3(), self.funct7()) {
-            (0b0110111, _, _) => InstructionKind::Lui,
-            (0b0010111, _, _) => InstructionKind::Auipc,
-
-            (0b1101111, _, _) => InstructionKind::Jal,
-
-            (0b1100011, 0b000, _) => InstructionKind::Beq,
-            (0b1100011, 0b001, _) => InstructionKind::Bne,
-            (0b1100011, 0b100, _) => InstructionKind::Blt,
-            (0b1100011, 0b101, _) => InstructionKind::Bge,
-            (0b1100011, 0b110, _) => InstructionKind::Bltu,
-            (0b1100011, 0b111, _) => InstructionKind::Bgeu,
-
-            (0b1100111, 0b000, _) => InstructionKind::Jalr,
-
-            (0b0000011, 0b000, _) => InstructionKind::Lb,
-            (0b0000011, 0b001, _) => InstructionKind::Lh,
-            (0b0000011, 0b010, _) => InstructionKind::Lw,
-            (0b0000011, 0b100, _) => InstructionKind::Lbu,
-            (0b0000011, 0b101, _) => InstructionKind::Lhu,
-
-            (0b0010011, 0b000, _) => InstructionKind::Addi,
-            (0b0010011, 0b010, _) => InstructionKind::Slti,
-            (0b0010011, 0b011, _) => InstructionKind::Sltiu,
-            (0b0010011, 0b100, _) => InstructionKind::Xori,
-            (0b0010011, 0b110, _) => InstructionKind::Ori,
-            (0b0010011, 0b111, _) => InstructionKind::Andi,
+    /// Decodes the kind of the given 32-bit instruction, like [Instruction::kind].
+    ///
+    /// Decoding is total: it never panics, and encodings that are not recognized,
+    /// including compressed ones, are decoded as [InstructionKind::Unknown]. This makes it
+    /// suitable for fuzzing, independently of whether every instruction can be executed.
+    pub fn try_decode(raw: u32) -> InstructionKind {
+        Self(raw).kind()
+    }
 
-            (0b0100011, 0b000, _) => InstructionKind::Sb,
-            (0b0100011, 0b001, _) => InstructionKind::Sh,
-            (0b0100011, 0b010, _) => InstructionKind::Sw,
+    pub fn kind(&self) -> InstructionKind {
+        // NOTE: This is evaluated every cycle. Most instructions are fully determined by their opcode and funct3
+        //       fields, so those are looked up in a table and only the remaining ones are matched on.
+        let index = self.opcode() | self.funct3() << 7;
+        if let Some(kind) = DECODE_TABLE[index as usize] {
+            return kind;
+        }
 
+        match (self.opcode(), self.funct3(), self.funct7()) {
             (0b0010011, 0b001, 0b0000000) => InstructionKind::Slli,
             (0b0010011, 0b101, 0b0000000) => InstructionKind::Srli,
             (0b0010011, 0b101, 0b0100000) => InstructionKind::Srai,
 
-            (0b0000000, 0b000, 0b0110011) => InstructionKind::Add,
-            (0b0100000, 0b000, 0b0110011) => InstructionKind::Sub,
-            (0b0000000, 0b001, 0b0110011) => InstructionKind::Sll,
-            (0b0000000, 0b010, 0b0110011) => InstructionKind::Slt,
-            (0b0000000, 0b011, 0b0110011) => InstructionKind::Sltu,
-            (0b0000000, 0b100, 0b0110011) => InstructionKind::Xor,
-            (0b0000000, 0b101, 0b0110011) => InstructionKind::Srl,
-            (0b0100000, 0b101, 0b0110011) => InstructionKind::Sra,
-            (0b0000000, 0b110, 0b0110011) => InstructionKind::Or,
-            (0b0000000, 0b111, 0b0110011) => InstructionKind::And,
-
-            (0b0001111, _, _) => InstructionKind::Fence,
+            (0b0110011, 0b000, 0b0000000) => InstructionKind::Add,
+            (0b0110011, 0b000, 0b0100000) => InstructionKind::Sub,
+            (0b0110011, 0b001, 0b0000000) => InstructionKind::Sll,
+            (0b0110011, 0b010, 0b0000000) => InstructionKind::Slt,
+            (0b0110011, 0b011, 0b0000000) => InstructionKind::Sltu,
+            (0b0110011, 0b100, 0b0000000) => InstructionKind::Xor,
+            (0b0110011, 0b101, 0b0000000) => InstructionKind::Srl,
+            (0b0110011, 0b101, 0b0100000) => InstructionKind::Sra,
+            (0b0110011, 0b110, 0b0000000) => InstructionKind::Or,
+            (0b0110011, 0b111, 0b0000000) => InstructionKind::And,
+
+            (0b0110011, 0b000, 0b0000001) => InstructionKind::Mul,
+            (0b0110011, 0b001, 0b0000001) => InstructionKind::Mulh,
+            (0b0110011, 0b010, 0b0000001) => InstructionKind::Mulhsu,
+            (0b0110011, 0b011, 0b0000001) => InstructionKind::Mulhu,
+            (0b0110011, 0b100, 0b0000001) => InstructionKind::Div,
+            (0b0110011, 0b101, 0b0000001) => InstructionKind::Divu,
+            (0b0110011, 0b110, 0b0000001) => InstructionKind::Rem,
+            (0b0110011, 0b111, 0b0000001) => InstructionKind::Remu,
+
+            (0b0101111, 0b010, _) if self.funct5() == 0b00010 && self.rs2() == 0 => {
+                InstructionKind::LrW
+            }
+            (0b0101111, 0b010, _) if self.funct5() == 0b00011 => InstructionKind::ScW,
+            (0b0101111, 0b010, _) if self.funct5() == 0b00001 => InstructionKind::AmoswapW,
+            (0b0101111, 0b010, _) if self.funct5() == 0b00000 => InstructionKind::AmoaddW,
+            (0b0101111, 0b010, _) if self.funct5() == 0b00100 => InstructionKind::AmoxorW,
+            (0b0101111, 0b010, _) if self.funct5() == 0b01100 => InstructionKind::AmoandW,
+            (0b0101111, 0b010, _) if self.funct5() == 0b01000 => InstructionKind::AmoorW,
+            (0b0101111, 0b010, _) if self.funct5() == 0b10000 => InstructionKind::AmominW,
+            (0b0101111, 0b010, _) if self.funct5() == 0b10100 => InstructionKind::AmomaxW,
+            (0b0101111, 0b010, _) if self.funct5() == 0b11000 => InstructionKind::AmominuW,
+            (0b0101111, 0b010, _) if self.funct5() == 0b11100 => InstructionKind::AmomaxuW,
+
+            // NOTE: The rd and rs1 fields of these instructions are reserved and have to be zero.
+            (0b1110011, 0b000, _) if self.rd() != 0 || self.rs1() != 0 => InstructionKind::Unknown,
             (0b1110011, 0b000, _) if self.funct12() == 0 => InstructionKind::ECall,
             (0b1110011, 0b000, _) if self.funct12() == 1 => InstructionKind::EBreak,
+            (0b1110011, 0b000, 0b0001000) if self.rs2() == 0b00010 => InstructionKind::Sret,
+            (0b1110011, 0b000, 0b0011000) if self.rs2() == 0b00010 => InstructionKind::Mret,
+            (0b1110011, 0b000, 0b0001000) if self.rs2() == 0b00101 => InstructionKind::Wfi,
 
             _ => InstructionKind::Unknown,
         }
     }
 
+    /// Whether this is the PAUSE hint, a FENCE with only the predecessor write bit set.
+    pub fn is_pause(&self) -> bool {
+        self.kind() == InstructionKind::Fence
+            && self.fm() == 0
+            && self.pred() == 0b0001
+            && self.succ() == 0
+            && self.rd() == 0
+            && self.rs1() == 0
+    }
+
+    /// The registers the instruction names in its `rd`, `rs1` and `rs2` fields, in that order,
+    /// or `None` for fields that its format does not use as a register.
+    pub fn registers(&self) -> [Option<u32>; 3] {
+        use InstructionKind as I;
+        let (rd, rs1, rs2) = (Some(self.rd()), Some(self.rs1()), Some(self.rs2()));
+        match self.kind() {
+            I::Lui | I::Auipc | I::Jal | I::Csrrwi | I::Csrrsi | I::Csrrci => [rd, None, None],
+            I::Jalr
+            | I::Lb
+            | I::Lh
+            | I::Lw
+            | I::Lbu
+            | I::Lhu
+            | I::Addi
+            | I::Slti
+            | I::Sltiu
+            | I::Xori
+            | I::Ori
+            | I::Andi
+            | I::Slli
+            | I::Srli
+            | I::Srai
+            | I::LrW
+            | I::Csrrw
+            | I::Csrrs
+            | I::Csrrc => [rd, rs1, None],
+            I::Beq | I::Bne | I::Blt | I::Bge | I::Bltu | I::Bgeu | I::Sb | I::Sh | I::Sw => {
+                [None, rs1, rs2]
+            }
+            I::Fence
+            | I::FenceI
+            | I::ECall
+            | I::EBreak
+            | I::Sret
+            | I::Mret
+            | I::Wfi
+            | I::Unknown => [None, None, None],
+            _ => [rd, rs1, rs2],
+        }
+    }
+
+    /// Whether this instruction is in the standard HINT space, which executes as a nop.
+    pub fn is_hint(&self) -> bool {
+        // SPEC: Most RV32I HINTs are encoded as integer computational instructions with rd=x0. The FENCE
+        //       instructions with pred=0 or succ=0 are HINTs as well, like PAUSE.
+        use InstructionKind as I;
+        match self.kind() {
+            // NOTE: `addi x0, x0, 0` is the canonical NOP, not a HINT.
+            I::Addi => self.rd() == 0 && (self.rs1() != 0 || self.imm_i() != 0),
+            I::Lui
+            | I::Auipc
+            | I::Slti
+            | I::Sltiu
+            | I::Xori
+            | I::Ori
+            | I::Andi
+            | I::Slli
+            | I::Srli
+            | I::Srai
+            | I::Add
+            | I::Sub
+            | I::Sll
+            | I::Slt
+            | I::Sltu
+            | I::Xor
+            | I::Srl
+            | I::Sra
+            | I::Or
+            | I::And => self.rd() == 0,
+            I::Fence => self.pred() == 0 || self.succ() == 0,
+            _ => false,
+        }
+    }
+
+    /// Zero-extended immediate value for CSR instructions, encoded in the rs1 field.
+    ///
+    /// (`uimm[4:0]`)
+    pub fn imm_csr(&self) -> u32 {
+        self.rs1()
+    }
+
     /// Sign-extended immediate value for I-type instructions.
     ///
     /// (`imm[11:0]`)
@@ -185,8 +374,8 @@ impl Instruction {
     /// (`imm[11:5|4:0]`)
     #[rustfmt::skip]
     pub fn imm_s(&self) -> i32 {
-        let imm11_5 = ((self.0 & 0b10000000000000000000000000000000) >> 31) << 12; // imm[11:5]
-        let imm4_0 =  ((self.0 & 0b01111110000000000000000000000000) >> 25) << 5; // imm[4:0]
+        let imm11_5 = ((self.0 & 0b11111110000000000000000000000000) >> 25) << 5;  // imm[11:5]
+        let imm4_0 =   (self.0 & 0b00000000000000000000111110000000) >> 7;        // imm[4:0]
         let imm = imm11_5 | imm4_0;
         sign_extend(imm, 12)
     }
@@ -201,7 +390,7 @@ impl Instruction {
         let imm4_1 =  ((self.0 & 0b00000000000000000000111100000000) >> 8 ) << 1;  // imm[4:1]
         let imm11 =   ((self.0 & 0b00000000000000000000000010000000) >> 7 ) << 11; // imm[11]
         let imm = imm12 | imm11 | imm10_5 | imm4_1;
-        sign_extend(imm, 12)
+        sign_extend(imm, 13)
     }
 
     /// Sign-extended immediate value for U-type instructions.
@@ -222,18 +411,95 @@ impl Instruction {
         let imm11 =    ((self.0 & 0b00000000000100000000000000000000) >> 20) << 11; // imm[11]
         let imm19_12 = ((self.0 & 0b00000000000011111111000000000000) >> 12) << 12; // imm[19:12]
         let imm = imm20 | imm11 | imm10_1 | imm19_12;
-        sign_extend(imm, 20)
+        sign_extend(imm, 21)
+    }
+}
+
+/// The instruction kinds that are fully determined by the opcode and funct3 fields,
+/// indexed by `opcode | funct3 << 7`. Other instructions have a `None` entry.
+static DECODE_TABLE: [Option<InstructionKind>; 1 << 10] = {
+    let mut table = [None; 1 << 10];
+    let mut index = 0;
+    while index < table.len() {
+        table[index] = decode_opcode_funct3(index as u32 & 0b1111111, index as u32 >> 7);
+        index += 1;
     }
+    table
+};
+
+const fn decode_opcode_funct3(opcode: u32, funct3: u32) -> Option<InstructionKind> {
+    let kind = match (opcode, funct3) {
+        (0b0110111, _) => InstructionKind::Lui,
+        (0b0010111, _) => InstructionKind::Auipc,
+
+        (0b1101111, _) => InstructionKind::Jal,
+
+        (0b1100011, 0b000) => InstructionKind::Beq,
+        (0b1100011, 0b001) => InstructionKind::Bne,
+        (0b1100011, 0b100) => InstructionKind::Blt,
+        (0b1100011, 0b101) => InstructionKind::Bge,
+        (0b1100011, 0b110) => InstructionKind::Bltu,
+        (0b1100011, 0b111) => InstructionKind::Bgeu,
+
+        (0b1100111, 0b000) => InstructionKind::Jalr,
+
+        (0b0000011, 0b000) => InstructionKind::Lb,
+        (0b0000011, 0b001) => InstructionKind::Lh,
+        (0b0000011, 0b010) => InstructionKind::Lw,
+        (0b0000011, 0b100) => InstructionKind::Lbu,
+        (0b0000011, 0b101) => InstructionKind::Lhu,
+
+        (0b0010011, 0b000) => InstructionKind::Addi,
+        (0b0010011, 0b010) => InstructionKind::Slti,
+        (0b0010011, 0b011) => InstructionKind::Sltiu,
+        (0b0010011, 0b100) => InstructionKind::Xori,
+        (0b0010011, 0b110) => InstructionKind::Ori,
+        (0b0010011, 0b111) => InstructionKind::Andi,
+
+        (0b0100011, 0b000) => InstructionKind::Sb,
+        (0b0100011, 0b001) => InstructionKind::Sh,
+        (0b0100011, 0b010) => InstructionKind::Sw,
+
+        (0b0001111, 0b000) => InstructionKind::Fence,
+        (0b0001111, 0b001) => InstructionKind::FenceI,
+
+        (0b1110011, 0b001) => InstructionKind::Csrrw,
+        (0b1110011, 0b010) => InstructionKind::Csrrs,
+        (0b1110011, 0b011) => InstructionKind::Csrrc,
+        (0b1110011, 0b101) => InstructionKind::Csrrwi,
+        (0b1110011, 0b110) => InstructionKind::Csrrsi,
+        (0b1110011, 0b111) => InstructionKind::Csrrci,
+
+        _ => return None,
+    };
+    Some(kind)
 }
 
-impl std::fmt::Debug for Instruction {
+impl core::fmt::Debug for Instruction {
     #[rustfmt::skip]
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
         let kind = self.kind();
         use InstructionKind as I;
         match kind {
-            I::Lui     => write!(f, "lui   x{}, {:#x}",      self.rd(),  self.imm_u()),
-            I::Auipc   => write!(f, "auipc x{}, {:#x}",      self.rd(),  self.imm_u()),
+            // The canonical pseudo-instructions are printed the way GNU objdump does.
+            I::Addi if self.rd() == 0 && self.rs1() == 0 && self.imm_i() == 0 => write!(f, "nop"),
+            I::Addi if self.rs1() == 0 => write!(f, "li    x{}, {}",  self.rd(),  self.imm_i()),
+            I::Addi if self.imm_i() == 0 => write!(f, "mv    x{}, x{}", self.rd(),  self.rs1()),
+            I::Xori if self.imm_i() == -1 => write!(f, "not   x{}, x{}", self.rd(),  self.rs1()),
+            I::Sub if self.rs1() == 0 => write!(f, "neg   x{}, x{}",   self.rd(),  self.rs2()),
+            I::Sltiu if self.imm_i() == 1 => write!(f, "seqz  x{}, x{}", self.rd(), self.rs1()),
+            I::Sltu if self.rs1() == 0 => write!(f, "snez  x{}, x{}",  self.rd(),  self.rs2()),
+            I::Beq if self.rs2() == 0 => write!(f, "beqz  x{}, {:#x}", self.rs1(), self.imm_b()),
+            I::Bne if self.rs2() == 0 => write!(f, "bnez  x{}, {:#x}", self.rs1(), self.imm_b()),
+            I::Jal if self.rd() == 0 => write!(f, "j     {:#x}",      self.imm_j()),
+            I::Jal if self.rd() == 1 => write!(f, "jal   {:#x}",      self.imm_j()),
+            I::Jalr if self.rd() == 0 && self.rs1() == 1 && self.imm_i() == 0 => write!(f, "ret"),
+            I::Jalr if self.rd() == 0 && self.imm_i() == 0 => write!(f, "jr    x{}", self.rs1()),
+            I::Jalr if self.rd() == 1 && self.imm_i() == 0 => write!(f, "jalr  x{}", self.rs1()),
+
+            // NOTE: The U-immediate is printed as the 20-bit field written in assembly, like GNU as and objdump do.
+            I::Lui     => write!(f, "lui   x{}, {:#x}",      self.rd(),  self.imm_u() as u32 & 0xfffff),
+            I::Auipc   => write!(f, "auipc x{}, {:#x}",      self.rd(),  self.imm_u() as u32 & 0xfffff),
             I::Jal     => write!(f, "jal   x{}, {:#x}",      self.rd(),  self.imm_j()),
             I::Beq     => write!(f, "beq   x{}, x{}, {:#x}", self.rs1(), self.rs2(),   self.imm_b()),
             I::Bne     => write!(f, "bne   x{}, x{}, {:#x}", self.rs1(), self.rs2(),   self.imm_b()),
@@ -269,16 +535,247 @@ impl std::fmt::Debug for Instruction {
             I::Sra     => write!(f, "sra   x{}, x{}, x{}",   self.rd(),  self.rs1(),   self.rs2()),
             I::Or      => write!(f, "or    x{}, x{}, x{}",   self.rd(),  self.rs1(),   self.rs2()),
             I::And     => write!(f, "and   x{}, x{}, x{}",   self.rd(),  self.rs1(),   self.rs2()),
-            I::Fence   => write!(f, "fence"),
+            I::Mul     => write!(f, "mul   x{}, x{}, x{}",   self.rd(),  self.rs1(),   self.rs2()),
+            I::Mulh    => write!(f, "mulh  x{}, x{}, x{}",   self.rd(),  self.rs1(),   self.rs2()),
+            I::Mulhsu  => write!(f, "mulhsu x{}, x{}, x{}",  self.rd(),  self.rs1(),   self.rs2()),
+            I::Mulhu   => write!(f, "mulhu x{}, x{}, x{}",   self.rd(),  self.rs1(),   self.rs2()),
+            I::Div     => write!(f, "div   x{}, x{}, x{}",   self.rd(),  self.rs1(),   self.rs2()),
+            I::Divu    => write!(f, "divu  x{}, x{}, x{}",   self.rd(),  self.rs1(),   self.rs2()),
+            I::Rem     => write!(f, "rem   x{}, x{}, x{}",   self.rd(),  self.rs1(),   self.rs2()),
+            I::Remu    => write!(f, "remu  x{}, x{}, x{}",   self.rd(),  self.rs1(),   self.rs2()),
+            I::LrW     => write!(f, "lr.w  x{}, (x{})",      self.rd(),  self.rs1()),
+            I::ScW     => write!(f, "sc.w  x{}, x{}, (x{})", self.rd(),  self.rs2(),   self.rs1()),
+            I::AmoswapW => write!(f, "amoswap.w x{}, x{}, (x{})", self.rd(), self.rs2(), self.rs1()),
+            I::AmoaddW  => write!(f, "amoadd.w x{}, x{}, (x{})",  self.rd(), self.rs2(), self.rs1()),
+            I::AmoxorW  => write!(f, "amoxor.w x{}, x{}, (x{})",  self.rd(), self.rs2(), self.rs1()),
+            I::AmoandW  => write!(f, "amoand.w x{}, x{}, (x{})",  self.rd(), self.rs2(), self.rs1()),
+            I::AmoorW   => write!(f, "amoor.w x{}, x{}, (x{})",   self.rd(), self.rs2(), self.rs1()),
+            I::AmominW  => write!(f, "amomin.w x{}, x{}, (x{})",  self.rd(), self.rs2(), self.rs1()),
+            I::AmomaxW  => write!(f, "amomax.w x{}, x{}, (x{})",  self.rd(), self.rs2(), self.rs1()),
+            I::AmominuW => write!(f, "amominu.w x{}, x{}, (x{})", self.rd(), self.rs2(), self.rs1()),
+            I::AmomaxuW => write!(f, "amomaxu.w x{}, x{}, (x{})", self.rd(), self.rs2(), self.rs1()),
+            I::Fence if self.is_pause() => write!(f, "pause"),
+            I::Fence   => write!(f, "fence {}, {}",          fence_set(self.pred()), fence_set(self.succ())),
+            I::FenceI  => write!(f, "fence.i"),
             I::ECall   => write!(f, "ecall"),
             I::EBreak  => write!(f, "ebreak"),
+            I::Sret    => write!(f, "sret"),
+            I::Mret    => write!(f, "mret"),
+            I::Wfi     => write!(f, "wfi"),
+            I::Csrrw   => write!(f, "csrrw  x{}, {:#x}, x{}", self.rd(),  self.csr(),   self.rs1()),
+            I::Csrrs   => write!(f, "csrrs  x{}, {:#x}, x{}", self.rd(),  self.csr(),   self.rs1()),
+            I::Csrrc   => write!(f, "csrrc  x{}, {:#x}, x{}", self.rd(),  self.csr(),   self.rs1()),
+            I::Csrrwi  => write!(f, "csrrwi x{}, {:#x}, {}",  self.rd(),  self.csr(),   self.imm_csr()),
+            I::Csrrsi  => write!(f, "csrrsi x{}, {:#x}, {}",  self.rd(),  self.csr(),   self.imm_csr()),
+            I::Csrrci  => write!(f, "csrrci x{}, {:#x}, {}",  self.rd(),  self.csr(),   self.imm_csr()),
             I::Unknown => write!(f, "<unknown instruction>"),
         }
     }
 }
 
+/// Formats the predecessor or successor set of a FENCE instruction, like `iorw`.
+/// An empty set is formatted as `0`.
+pub fn fence_set(bits: u32) -> String {
+    // SPEC: The predecessor and successor sets are encoded as the PI, PO, PR, PW and SI, SO, SR, SW bits.
+    let set: String = ['i', 'o', 'r', 'w']
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| bits & (0b1000 >> i) != 0)
+        .map(|(_, c)| *c)
+        .collect();
+    if set.is_empty() { "0".to_string() } else { set }
+}
+
 /// Helper function to sign-extend a value after n bits.
 fn sign_extend(value: u32, bits: u32) -> i32 {
     let shift = 32 - bits;
     ((value as i32) << shift) >> shift
 }
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    /// Decodes an instruction with a single match, without the table [Instruction::kind] uses.
+    fn decode_reference(inst: Instruction) -> InstructionKind {
+        use InstructionKind as I;
+        match (inst.opcode(), inst.funct3(), inst.funct7()) {
+            (0b0110111, _, _) => I::Lui,
+            (0b0010111, _, _) => I::Auipc,
+
+            (0b1101111, _, _) => I::Jal,
+
+            (0b1100011, 0b000, _) => I::Beq,
+            (0b1100011, 0b001, _) => I::Bne,
+            (0b1100011, 0b100, _) => I::Blt,
+            (0b1100011, 0b101, _) => I::Bge,
+            (0b1100011, 0b110, _) => I::Bltu,
+            (0b1100011, 0b111, _) => I::Bgeu,
+
+            (0b1100111, 0b000, _) => I::Jalr,
+
+            (0b0000011, 0b000, _) => I::Lb,
+            (0b0000011, 0b001, _) => I::Lh,
+            (0b0000011, 0b010, _) => I::Lw,
+            (0b0000011, 0b100, _) => I::Lbu,
+            (0b0000011, 0b101, _) => I::Lhu,
+
+            (0b0010011, 0b000, _) => I::Addi,
+            (0b0010011, 0b010, _) => I::Slti,
+            (0b0010011, 0b011, _) => I::Sltiu,
+            (0b0010011, 0b100, _) => I::Xori,
+            (0b0010011, 0b110, _) => I::Ori,
+            (0b0010011, 0b111, _) => I::Andi,
+
+            (0b0100011, 0b000, _) => I::Sb,
+            (0b0100011, 0b001, _) => I::Sh,
+            (0b0100011, 0b010, _) => I::Sw,
+
+            (0b0010011, 0b001, 0b0000000) => I::Slli,
+            (0b0010011, 0b101, 0b0000000) => I::Srli,
+            (0b0010011, 0b101, 0b0100000) => I::Srai,
+
+            (0b0110011, 0b000, 0b0000000) => I::Add,
+            (0b0110011, 0b000, 0b0100000) => I::Sub,
+            (0b0110011, 0b001, 0b0000000) => I::Sll,
+            (0b0110011, 0b010, 0b0000000) => I::Slt,
+            (0b0110011, 0b011, 0b0000000) => I::Sltu,
+            (0b0110011, 0b100, 0b0000000) => I::Xor,
+            (0b0110011, 0b101, 0b0000000) => I::Srl,
+            (0b0110011, 0b101, 0b0100000) => I::Sra,
+            (0b0110011, 0b110, 0b0000000) => I::Or,
+            (0b0110011, 0b111, 0b0000000) => I::And,
+
+            (0b0110011, 0b000, 0b0000001) => I::Mul,
+            (0b0110011, 0b001, 0b0000001) => I::Mulh,
+            (0b0110011, 0b010, 0b0000001) => I::Mulhsu,
+            (0b0110011, 0b011, 0b0000001) => I::Mulhu,
+            (0b0110011, 0b100, 0b0000001) => I::Div,
+            (0b0110011, 0b101, 0b0000001) => I::Divu,
+            (0b0110011, 0b110, 0b0000001) => I::Rem,
+            (0b0110011, 0b111, 0b0000001) => I::Remu,
+
+            (0b0101111, 0b010, _) if inst.funct5() == 0b00010 && inst.rs2() == 0 => I::LrW,
+            (0b0101111, 0b010, _) if inst.funct5() == 0b00011 => I::ScW,
+            (0b0101111, 0b010, _) if inst.funct5() == 0b00001 => I::AmoswapW,
+            (0b0101111, 0b010, _) if inst.funct5() == 0b00000 => I::AmoaddW,
+            (0b0101111, 0b010, _) if inst.funct5() == 0b00100 => I::AmoxorW,
+            (0b0101111, 0b010, _) if inst.funct5() == 0b01100 => I::AmoandW,
+            (0b0101111, 0b010, _) if inst.funct5() == 0b01000 => I::AmoorW,
+            (0b0101111, 0b010, _) if inst.funct5() == 0b10000 => I::AmominW,
+            (0b0101111, 0b010, _) if inst.funct5() == 0b10100 => I::AmomaxW,
+            (0b0101111, 0b010, _) if inst.funct5() == 0b11000 => I::AmominuW,
+            (0b0101111, 0b010, _) if inst.funct5() == 0b11100 => I::AmomaxuW,
+
+            (0b0001111, 0b000, _) => I::Fence,
+            (0b0001111, 0b001, _) => I::FenceI,
+            (0b1110011, 0b000, _) if inst.rd() != 0 || inst.rs1() != 0 => I::Unknown,
+            (0b1110011, 0b000, _) if inst.funct12() == 0 => I::ECall,
+            (0b1110011, 0b000, _) if inst.funct12() == 1 => I::EBreak,
+            (0b1110011, 0b000, 0b0001000) if inst.rs2() == 0b00010 => I::Sret,
+            (0b1110011, 0b000, 0b0011000) if inst.rs2() == 0b00010 => I::Mret,
+            (0b1110011, 0b000, 0b0001000) if inst.rs2() == 0b00101 => I::Wfi,
+
+            (0b1110011, 0b001, _) => I::Csrrw,
+            (0b1110011, 0b010, _) => I::Csrrs,
+            (0b1110011, 0b011, _) => I::Csrrc,
+            (0b1110011, 0b101, _) => I::Csrrwi,
+            (0b1110011, 0b110, _) => I::Csrrsi,
+            (0b1110011, 0b111, _) => I::Csrrci,
+
+            _ => I::Unknown,
+        }
+    }
+
+    #[test]
+    fn decode_table_matches_reference_decoder() {
+        let mut kinds = Vec::new();
+        for opcode in 0..1 << 7 {
+            for funct3 in 0..1 << 3 {
+                for funct7 in 0..1 << 7 {
+                    // NOTE: Only LR.W and the SYSTEM instructions decode the rs2 field,
+                    //       and only the latter decode the rs1 and rd fields.
+                    for rs2 in [0b00000, 0b00001, 0b00010, 0b00101, 0b11111] {
+                        for (rd, rs1) in [(0, 0), (1, 0), (0, 1), (5, 7)] {
+                            let inst = Instruction(
+                                funct7 << 25
+                                    | rs2 << 20
+                                    | rs1 << 15
+                                    | funct3 << 12
+                                    | rd << 7
+                                    | opcode,
+                            );
+                            assert_eq!(inst.kind(), decode_reference(inst), "{:#010x}", inst.0);
+                            if !kinds.contains(&inst.kind()) {
+                                kinds.push(inst.kind());
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        // Every kind of instruction, and Unknown, has been decoded.
+        assert_eq!(kinds.len(), 70);
+    }
+
+    #[test]
+    fn formats_fence_operands() {
+        let fence = Instruction(0x0ff0000f);
+        assert_eq!((fence.fm(), fence.pred(), fence.succ()), (0, 0b1111, 0b1111));
+        assert_eq!(format!("{fence:?}"), "fence iorw, iorw");
+
+        assert_eq!(format!("{:?}", Instruction(0x0210000f)), "fence r, w");
+        assert_eq!(format!("{:?}", Instruction(0x0000000f)), "fence 0, 0");
+
+        // fence.tso
+        let fence_tso = Instruction(0x8330000f);
+        assert_eq!((fence_tso.fm(), fence_tso.pred(), fence_tso.succ()), (0b1000, 0b0011, 0b0011));
+        assert_eq!(format!("{fence_tso:?}"), "fence rw, rw");
+    }
+
+    #[test]
+    fn recognizes_hints() {
+        let pause = Instruction(0x0100000f);
+        assert!(pause.is_pause() && pause.is_hint());
+        assert_eq!(format!("{pause:?}"), "pause");
+
+        assert!(!Instruction(0x00000013).is_hint(), "nop"); // addi x0, x0, 0
+        assert!(Instruction(0x00100013).is_hint(), "addi x0, x0, 1");
+        assert!(Instruction(0x00000033).is_hint(), "add x0, x0, x0");
+        assert!(Instruction(0x00001037).is_hint(), "lui x0, 1");
+        assert!(!Instruction(0x00100093).is_hint(), "addi x1, x0, 1");
+        assert!(!Instruction(0x0ff0000f).is_hint(), "fence iorw, iorw");
+        assert!(!Instruction(0x0ff0000f).is_pause());
+    }
+
+    #[test]
+    fn formats_upper_immediates_as_written() {
+        assert_eq!(format!("{:?}", Instruction(0x123450b7)), "lui   x1, 0x12345");
+        assert_eq!(format!("{:?}", Instruction(0xfffff537)), "lui   x10, 0xfffff");
+        assert_eq!(format!("{:?}", Instruction(0x80000097)), "auipc x1, 0x80000");
+        assert_eq!(format!("{:?}", Instruction(0x00000117)), "auipc x2, 0x0");
+    }
+
+    #[test]
+    fn formats_pseudo_instructions() {
+        assert_eq!(format!("{:?}", Instruction(0x00000013)), "nop");
+        assert_eq!(format!("{:?}", Instruction(0x00058513)), "mv    x10, x11");
+        assert_eq!(format!("{:?}", Instruction(0x0100006f)), "j     0x10");
+        assert_eq!(format!("{:?}", Instruction(0x00008067)), "ret");
+        assert_eq!(format!("{:?}", Instruction(0xffb00513)), "li    x10, -5");
+        assert_eq!(format!("{:?}", Instruction(0x00050463)), "beqz  x10, 0x8");
+        assert_eq!(format!("{:?}", Instruction(0x00028067)), "jr    x5");
+    }
+
+    #[test]
+    fn decoding_and_formatting_never_panic() {
+        // A prime stride visits every opcode, funct3 and register field in many combinations.
+        for raw in (0..=u32::MAX).step_by(4093).chain([u32::MAX]) {
+            let kind = Instruction::try_decode(raw);
+            assert_eq!(kind, Instruction(raw).kind());
+            let _ = format!("{:?}", Instruction(raw));
+        }
+    }
+}
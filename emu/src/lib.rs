@@ -1,7 +1,42 @@
+//! A RISC-V emulator library.
+//!
+//! The library is `no_std` and only needs `alloc`, unless the `std` feature is enabled, which it is by default.
+//! That feature adds everything that needs the host: the [gdb] server, [semihosting], [vcd] dumps,
+//! HTIF console output, the [uart] and the `verbose` and `trace_spike` tracing of the [cpu::Cpu].
+//! The `serde` feature makes [cpu::CpuState] snapshots serializable.
+
+#![no_std]
+
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+
+pub mod asm;
+pub mod clint;
+pub mod compressed;
 pub mod cpu;
+pub mod csr;
+pub mod cycles;
+pub mod device;
+pub mod disasm;
+pub mod error;
+pub mod firmware;
+#[cfg(feature = "std")]
+pub mod gdb;
+pub mod image;
 pub mod inst;
+pub mod linux;
+pub mod memory;
 pub mod reg;
 pub mod rom;
+#[cfg(feature = "std")]
+pub mod semihosting;
+pub mod symbols;
+pub mod trap;
+#[cfg(feature = "std")]
+pub mod uart;
+#[cfg(feature = "std")]
+pub mod vcd;
 
 /// The unsigned width of an x register in bits (either u32 or u64).
 #[allow(non_camel_case_types)]
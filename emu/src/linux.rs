@@ -0,0 +1,161 @@
+//! Support for running statically linked Linux user-mode programs.
+
+use alloc::vec::Vec;
+
+use crate::uxlen;
+
+/// Marks the end of the auxiliary vector.
+pub const AT_NULL: uxlen = 0;
+/// The address of the program headers of the program.
+pub const AT_PHDR: uxlen = 3;
+/// The size of a program header entry.
+pub const AT_PHENT: uxlen = 4;
+/// The number of program headers.
+pub const AT_PHNUM: uxlen = 5;
+/// The system page size.
+pub const AT_PAGESZ: uxlen = 6;
+/// The entry point of the program.
+pub const AT_ENTRY: uxlen = 9;
+/// The real user ID.
+pub const AT_UID: uxlen = 11;
+/// The effective user ID.
+pub const AT_EUID: uxlen = 12;
+/// The real group ID.
+pub const AT_GID: uxlen = 13;
+/// The effective group ID.
+pub const AT_EGID: uxlen = 14;
+/// The address of 16 random bytes, which the C library uses for stack canaries.
+pub const AT_RANDOM: uxlen = 25;
+
+/// The page size reported in [AT_PAGESZ].
+pub const PAGE_SIZE: uxlen = 4096;
+
+/// The size of the bytes [AT_RANDOM] points to.
+const RANDOM_BYTES: usize = 16;
+
+/// The initial stack of a Linux user-mode process, containing the arguments, environment
+/// and auxiliary vector the program starts with.
+///
+/// The stack is laid out as the RISC-V Linux ABI specifies. From `sp` upwards it contains `argc`,
+/// the `argv` pointers, a null pointer, the `envp` pointers, a null pointer and the auxiliary vector
+/// ending in [AT_NULL], followed by the strings they point to.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InitialStack {
+    argv: Vec<Vec<u8>>,
+    envp: Vec<Vec<u8>>,
+    auxv: Vec<(uxlen, uxlen)>,
+}
+
+impl InitialStack {
+    /// Creates a stack without arguments, environment variables or auxiliary vector entries.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends an argument. The first argument is conventionally the name of the program.
+    pub fn arg(mut self, arg: impl Into<Vec<u8>>) -> Self {
+        self.argv.push(arg.into());
+        self
+    }
+
+    /// Appends an environment variable, given as `KEY=VALUE`.
+    pub fn env(mut self, var: impl Into<Vec<u8>>) -> Self {
+        self.envp.push(var.into());
+        self
+    }
+
+    /// Appends an entry to the auxiliary vector, like [AT_PHDR] or [AT_ENTRY].
+    ///
+    /// [AT_PAGESZ], [AT_RANDOM] and the user and group IDs are always included.
+    pub fn aux(mut self, kind: uxlen, value: uxlen) -> Self {
+        self.auxv.push((kind, value));
+        self
+    }
+
+    /// Lays out the stack below `top`, returning the resulting stack pointer and
+    /// the bytes that go from the stack pointer up to `top`.
+    pub fn layout(&self, top: uxlen) -> (uxlen, Vec<u8>) {
+        // The strings and the random bytes go at the top, in that order from the stack pointer upwards.
+        // NOTE: The random bytes are fixed, so runs stay reproducible.
+        let mut info = Vec::new();
+        info.extend((0..RANDOM_BYTES as u8).map(|i| i.wrapping_mul(0x9d) ^ 0x5a));
+        let mut string_offsets = |strings: &[Vec<u8>]| -> Vec<usize> {
+            strings
+                .iter()
+                .map(|string| {
+                    let offset = info.len();
+                    info.extend_from_slice(string);
+                    info.push(0);
+                    offset
+                })
+                .collect()
+        };
+        let argv = string_offsets(&self.argv);
+        let envp = string_offsets(&self.envp);
+        let info_start = top.wrapping_sub(info.len() as uxlen);
+        let info_addr = |offset: usize| info_start.wrapping_add(offset as uxlen);
+
+        let mut words = Vec::new();
+        words.push(self.argv.len() as uxlen);
+        words.extend(argv.into_iter().map(info_addr));
+        words.push(0);
+        words.extend(envp.into_iter().map(info_addr));
+        words.push(0);
+        let auxv = [
+            (AT_PAGESZ, PAGE_SIZE),
+            (AT_RANDOM, info_addr(0)),
+            (AT_UID, 0),
+            (AT_EUID, 0),
+            (AT_GID, 0),
+            (AT_EGID, 0),
+        ];
+        for (kind, value) in auxv.into_iter().chain(self.auxv.iter().copied()) {
+            words.extend([kind, value]);
+        }
+        words.extend([AT_NULL, 0]);
+
+        // SPEC: The stack pointer must be aligned to a 128-bit boundary upon procedure entry.
+        let size = (words.len() * size_of::<uxlen>()) as uxlen;
+        let sp = info_start.wrapping_sub(size) & !0xf;
+
+        let mut bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+        bytes.resize(info_start.wrapping_sub(sp) as usize, 0);
+        bytes.extend_from_slice(&info);
+        (sp, bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lays_out_arguments_environment_and_auxv() {
+        let stack = InitialStack::new().arg("prog").arg("x").env("A=1").aux(AT_ENTRY, 0x8000_0000);
+        let (sp, bytes) = stack.layout(0x1000);
+        assert_eq!(sp, 0xf80);
+        assert_eq!(bytes.len(), 0x80);
+
+        // The random bytes come first, then the strings, ending at the top.
+        let info_start = 0x1000 - 16 - b"prog\0x\0A=1\0".len() as uxlen;
+        let info = &bytes[(info_start - sp) as usize..];
+        assert_eq!(&info[16..], b"prog\0x\0A=1\0");
+
+        let words: Vec<uxlen> = bytes
+            .chunks_exact(4)
+            .map(|word| uxlen::from_le_bytes(word.try_into().unwrap()))
+            .take(22)
+            .collect();
+        let random = info_start;
+        let strings = info_start + 16;
+        #[rustfmt::skip]
+        assert_eq!(words, [
+            2, strings, strings + 5, 0,
+            strings + 7, 0,
+            AT_PAGESZ, PAGE_SIZE, AT_RANDOM, random, AT_UID, 0, AT_EUID, 0, AT_GID, 0, AT_EGID, 0,
+            AT_ENTRY, 0x8000_0000, AT_NULL, 0,
+        ]);
+        // The padding that aligns the stack pointer is zeroed.
+        assert!(bytes[22 * 4..(info_start - sp) as usize].iter().all(|byte| *byte == 0));
+    }
+}
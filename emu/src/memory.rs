@@ -0,0 +1,221 @@
+use alloc::{boxed::Box, vec::Vec};
+use core::ops::Range;
+
+use crate::{
+    device::Device,
+    rom::{MemError, Rom},
+    uxlen,
+};
+
+/// The kinds of access a memory region allows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Permissions {
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+}
+
+impl Permissions {
+    /// Read-only, like a ROM with constant data.
+    pub const R: Self = Self { read: true, write: false, execute: false };
+    /// Readable and writable, like RAM or most device registers.
+    pub const RW: Self = Self { read: true, write: true, execute: false };
+    /// Readable and executable, like a ROM with code.
+    pub const RX: Self = Self { read: true, write: false, execute: true };
+    /// Readable, writable and executable.
+    pub const RWX: Self = Self { read: true, write: true, execute: true };
+
+    /// Whether these permissions allow the given kind of access.
+    pub fn allows(self, access: Access) -> bool {
+        match access {
+            Access::Read => self.read,
+            Access::Write => self.write,
+            Access::Execute => self.execute,
+        }
+    }
+}
+
+/// A kind of memory access, checked against the [Permissions] of the region it goes to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    /// An instruction fetch.
+    Execute,
+}
+
+/// A device mapped at a range of addresses.
+struct Region {
+    range: Range<uxlen>,
+    permissions: Permissions,
+    device: Box<dyn Device>,
+}
+
+/// The memory of a [Cpu][crate::cpu::Cpu]: the ROM containing the program, and the RAM and
+/// memory-mapped devices around it, each with their own [Permissions].
+///
+/// Accesses to an address that no region covers, or that its region does not allow,
+/// fail and raise an access fault in the CPU.
+pub struct MemoryMap<'rom> {
+    rom: Rom<'rom>,
+    rom_permissions: Permissions,
+    /// The mapped regions. When regions overlap, the first one mapped takes precedence,
+    /// and every region takes precedence over the ROM.
+    regions: Vec<Region>,
+}
+
+impl<'rom> MemoryMap<'rom> {
+    /// Creates a memory map containing only the ROM, which can be read, written and executed.
+    pub fn new(rom: Rom<'rom>) -> Self {
+        Self { rom, rom_permissions: Permissions::RWX, regions: Vec::new() }
+    }
+
+    /// Sets the kinds of access the ROM allows.
+    pub fn set_rom_permissions(&mut self, permissions: Permissions) {
+        self.rom_permissions = permissions;
+    }
+
+    /// Maps a device at the given address range with the given permissions.
+    pub fn map(&mut self, range: Range<uxlen>, device: Box<dyn Device>, permissions: Permissions) {
+        self.regions.push(Region { range, permissions, device });
+    }
+
+    #[inline]
+    pub fn rom(&self) -> &Rom<'rom> {
+        &self.rom
+    }
+
+    #[inline]
+    pub fn rom_mut(&mut self) -> &mut Rom<'rom> {
+        &mut self.rom
+    }
+
+    /// The address ranges of the mapped regions and their permissions, excluding the ROM.
+    pub fn regions(&self) -> impl Iterator<Item = (Range<uxlen>, Permissions)> + '_ {
+        self.regions.iter().map(|region| (region.range.clone(), region.permissions))
+    }
+
+    /// Reads a byte, from the region that covers the address or from the ROM.
+    pub fn read(&mut self, addr: uxlen) -> Result<u8, MemError> {
+        let denied = MemError::AccessDenied { addr };
+        match self.regions.iter_mut().find(|region| region.range.contains(&addr)) {
+            Some(region) if region.permissions.read => {
+                Ok(region.device.read(addr - region.range.start))
+            }
+            Some(_) => Err(denied),
+            None => {
+                let value = self.rom.read(addr)?;
+                if !self.rom_permissions.read {
+                    return Err(denied);
+                }
+                Ok(value)
+            }
+        }
+    }
+
+    /// Writes a byte, to the region that covers the address or to the ROM.
+    pub fn write(&mut self, addr: uxlen, value: u8) -> Result<(), MemError> {
+        let denied = MemError::AccessDenied { addr };
+        match self.regions.iter_mut().find(|region| region.range.contains(&addr)) {
+            Some(region) if region.permissions.write => {
+                region.device.write(addr - region.range.start, value);
+                Ok(())
+            }
+            Some(_) => Err(denied),
+            None if self.rom_permissions.write => self.rom.write(addr, value),
+            None => {
+                self.rom.read(addr)?;
+                Err(denied)
+            }
+        }
+    }
+
+    /// Checks that the address is mapped and that its region allows the given kind of access.
+    pub fn check(&self, addr: uxlen, access: Access) -> Result<(), MemError> {
+        let permissions = match self.regions.iter().find(|region| region.range.contains(&addr)) {
+            Some(region) => region.permissions,
+            None => {
+                self.rom.read(addr)?;
+                self.rom_permissions
+            }
+        };
+        match permissions.allows(access) {
+            true => Ok(()),
+            false => Err(MemError::AccessDenied { addr }),
+        }
+    }
+
+    /// Whether the address is covered by a region or the ROM, regardless of permissions.
+    pub fn is_mapped(&self, addr: uxlen) -> bool {
+        self.regions.iter().any(|region| region.range.contains(&addr))
+            || self.rom.read(addr).is_ok()
+    }
+
+    /// Puts every mapped device back into its initial state. The ROM is left as is.
+    pub fn reset(&mut self) {
+        for region in &mut self.regions {
+            region.device.reset();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+    use crate::device::Ram;
+
+    /// A ROM at 0x1000 with RAM at 0x2000 and a read-only region at 0x3000, with a hole between them.
+    fn memory_map() -> MemoryMap<'static> {
+        let mut memory = MemoryMap::new(Rom::from_vec(vec![0xaa; 0x100], 0x1000, 0x1100));
+        memory.map(0x2000..0x2100, Box::new(Ram::new(0x100)), Permissions::RW);
+        memory.map(0x3000..0x3100, Box::new(Ram::new(0x100)), Permissions::R);
+        memory
+    }
+
+    #[test]
+    fn accesses_ram() {
+        let mut memory = memory_map();
+        assert_eq!(memory.read(0x20ff), Ok(0));
+        assert_eq!(memory.write(0x20ff, 0x42), Ok(()));
+        assert_eq!(memory.read(0x20ff), Ok(0x42));
+        assert_eq!(memory.read(0x1000), Ok(0xaa));
+        assert_eq!(
+            memory.check(0x2000, Access::Execute),
+            Err(MemError::AccessDenied { addr: 0x2000 })
+        );
+    }
+
+    #[test]
+    fn accesses_to_holes_fault() {
+        let mut memory = memory_map();
+        for addr in [0x1100, 0x1fff, 0x2100, 0x3100] {
+            assert_eq!(memory.read(addr), Err(MemError::OutOfBounds { addr }));
+            assert_eq!(memory.write(addr, 0), Err(MemError::OutOfBounds { addr }));
+            assert_eq!(memory.check(addr, Access::Read), Err(MemError::OutOfBounds { addr }));
+            assert!(!memory.is_mapped(addr));
+        }
+    }
+
+    #[test]
+    fn writes_to_read_only_regions_fault() {
+        let mut memory = memory_map();
+        assert_eq!(memory.write(0x3000, 0x42), Err(MemError::AccessDenied { addr: 0x3000 }));
+        assert_eq!(memory.read(0x3000), Ok(0));
+
+        memory.set_rom_permissions(Permissions::RX);
+        assert_eq!(memory.write(0x1000, 0x42), Err(MemError::AccessDenied { addr: 0x1000 }));
+        assert_eq!(memory.read(0x1000), Ok(0xaa));
+    }
+
+    #[test]
+    fn ram_smaller_than_its_region_does_not_panic() {
+        let mut memory = memory_map();
+        memory.map(0x4000..0x5000, Box::new(Ram::new(0x10)), Permissions::RW);
+        assert_eq!(memory.write(0x400f, 0x42), Ok(()));
+        assert_eq!(memory.read(0x400f), Ok(0x42));
+        assert_eq!(memory.write(0x4010, 0x42), Ok(()));
+        assert_eq!(memory.read(0x4010), Ok(0));
+    }
+}
@@ -1,36 +1,109 @@
-use std::ops::{Deref, DerefMut};
+use alloc::{format, string::String};
+use core::{
+    fmt::Write,
+    ops::{Deref, DerefMut, Index, IndexMut},
+};
 
 use crate::uxlen;
 
 /// A representation of the registers in the [Cpu][crate::Cpu].
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Registers([uxlen; 32]);
 
 impl Registers {
-    pub fn new(rom_size: uxlen) -> Self {
+    /// Creates the registers with every register set to zero, except the stack pointer.
+    pub fn new(sp: uxlen) -> Self {
         let mut this = Self([0; 32]);
 
         // Make sure the x0 register is set to zero.
-        this.set_zero(0);
-        // Set the stack pointer to the end of the ROM,
-        // as it grows down into the ROM.
-        this.set_sp(rom_size);
+        this[Reg::Zero] = 0;
+        this[Reg::Sp] = sp;
 
         this
     }
+
+    /// Writes the register with the given index, ignoring writes to the hard-wired zero register x0.
+    #[inline]
+    pub fn write(&mut self, ix: usize, value: uxlen) {
+        if ix != 0 {
+            self.0[ix] = value;
+        }
+    }
+
+    /// Formats all registers with their number and ABI name, four per line.
+    pub fn dump(&self) -> String {
+        self.format_dump(None)
+    }
+
+    /// Formats all registers like [Registers::dump], marking
+    /// the registers that changed since `previous` with a `*`.
+    pub fn dump_changes(&self, previous: &Registers) -> String {
+        self.format_dump(Some(previous))
+    }
+
+    fn format_dump(&self, previous: Option<&Registers>) -> String {
+        let mut out = String::new();
+        for (ix, value) in self.0.iter().enumerate() {
+            let name = format!("x{ix}/{}", Self::ABI_NAMES[ix]);
+            let changed = previous.is_some_and(|previous| previous.0[ix] != *value);
+            let marker = if changed { "*" } else { " " };
+            let _ = write!(out, "{name:<7} = {value:#010x}{marker}");
+            if ix % 4 == 3 {
+                out.truncate(out.trim_end().len());
+                out.push('\n');
+            } else {
+                out.push_str("  ");
+            }
+        }
+        out
+    }
+}
+
+impl core::fmt::Display for Registers {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(&self.dump())
+    }
 }
 
 macro_rules! impl_registers {
     [$({
         ix: $ix:literal,
         r: $r:ident,
+        reg: $reg:ident,
+        alias: $alias:ident,
         abi: {
             get: $abi_get:ident,
             set: $abi_set:ident
         },
         desc: $desc:literal
     }),*] => {
+        /// A general purpose register, to index [Registers] with.
+        ///
+        /// Registers can also be named by their ABI name, like `Reg::Sp`.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum Reg {
+            $(
+                #[doc = $desc]
+                $reg = $ix,
+            )*
+        }
+
+        #[allow(non_upper_case_globals)]
+        impl Reg {
+            $(
+                #[doc = $desc]
+                pub const $alias: Reg = Reg::$reg;
+            )*
+
+            /// All registers, indexed by register number.
+            pub const ALL: [Reg; 32] = [$(Reg::$reg),*];
+        }
+
         impl Registers {
+            /// The ABI names of the registers, indexed by register number.
+            pub const ABI_NAMES: [&str; 32] = [$(stringify!($abi_get)),*];
+
             $(
                 #[doc = $desc]
                 #[doc = "\n"]
@@ -53,40 +126,116 @@ macro_rules! impl_registers {
 }
 
 impl_registers![
-    { ix: 0,  r: x0,  abi: { get: zero, set: set_zero }, desc: "Hard-wired zero" },
-    { ix: 1,  r: x1,  abi: { get: ra,   set: set_ra   }, desc: "Return address" },
-    { ix: 2,  r: x2,  abi: { get: sp,   set: set_sp   }, desc: "Stack pointer" },
-    { ix: 3,  r: x3,  abi: { get: gp,   set: set_gp   }, desc: "Global pointer" },
-    { ix: 4,  r: x4,  abi: { get: tp,   set: set_tp   }, desc: "Thread pointer" },
-    { ix: 5,  r: x5,  abi: { get: t0,   set: set_t0   }, desc: "Temporary 0" },
-    { ix: 6,  r: x6,  abi: { get: t1,   set: set_t1   }, desc: "Temporary 1" },
-    { ix: 7,  r: x7,  abi: { get: t2,   set: set_t2   }, desc: "Temporary 2" },
-    { ix: 8,  r: x8,  abi: { get: s0,   set: set_s0   }, desc: "Saved register 0 / Frame pointer" },
-    { ix: 9,  r: x9,  abi: { get: s1,   set: set_s1   }, desc: "Saved register 1" },
-    { ix: 10, r: x10, abi: { get: a0,   set: set_a0   }, desc: "Function argument 0 / Return value 0" },
-    { ix: 11, r: x11, abi: { get: a1,   set: set_a1   }, desc: "Function argument 1 / Return value 1" },
-    { ix: 12, r: x12, abi: { get: a2,   set: set_a2   }, desc: "Function argument 2" },
-    { ix: 13, r: x13, abi: { get: a3,   set: set_a3   }, desc: "Function argument 3" },
-    { ix: 14, r: x14, abi: { get: a4,   set: set_a4   }, desc: "Function argument 4" },
-    { ix: 15, r: x15, abi: { get: a5,   set: set_a5   }, desc: "Function argument 5" },
-    { ix: 16, r: x16, abi: { get: a6,   set: set_a6   }, desc: "Function argument 6" },
-    { ix: 17, r: x17, abi: { get: a7,   set: set_a7   }, desc: "Function argument 7" },
-    { ix: 18, r: x18, abi: { get: s2,   set: set_s2   }, desc: "Saved register 2" },
-    { ix: 19, r: x19, abi: { get: s3,   set: set_s3   }, desc: "Saved register 3" },
-    { ix: 20, r: x20, abi: { get: s4,   set: set_s4   }, desc: "Saved register 4" },
-    { ix: 21, r: x21, abi: { get: s5,   set: set_s5   }, desc: "Saved register 5" },
-    { ix: 22, r: x22, abi: { get: s6,   set: set_s6   }, desc: "Saved register 6" },
-    { ix: 23, r: x23, abi: { get: s7,   set: set_s7   }, desc: "Saved register 7" },
-    { ix: 24, r: x24, abi: { get: s8,   set: set_s8   }, desc: "Saved register 8" },
-    { ix: 25, r: x25, abi: { get: s9,   set: set_s9   }, desc: "Saved register 9" },
-    { ix: 26, r: x26, abi: { get: s10,  set: set_s10  }, desc: "Saved register 10" },
-    { ix: 27, r: x27, abi: { get: s11,  set: set_s11  }, desc: "Saved register 11" },
-    { ix: 28, r: x28, abi: { get: t3,   set: set_t3   }, desc: "Temporary 3" },
-    { ix: 29, r: x29, abi: { get: t4,   set: set_t4   }, desc: "Temporary 4" },
-    { ix: 30, r: x30, abi: { get: t5,   set: set_t5   }, desc: "Temporary 5" },
-    { ix: 31, r: x31, abi: { get: t6,   set: set_t6   }, desc: "Temporary 6" }
+    { ix: 0,  r: x0,  reg: X0,  alias: Zero, abi: { get: zero, set: set_zero }, desc: "Hard-wired zero" },
+    { ix: 1,  r: x1,  reg: X1,  alias: Ra,   abi: { get: ra,   set: set_ra   }, desc: "Return address" },
+    { ix: 2,  r: x2,  reg: X2,  alias: Sp,   abi: { get: sp,   set: set_sp   }, desc: "Stack pointer" },
+    { ix: 3,  r: x3,  reg: X3,  alias: Gp,   abi: { get: gp,   set: set_gp   }, desc: "Global pointer" },
+    { ix: 4,  r: x4,  reg: X4,  alias: Tp,   abi: { get: tp,   set: set_tp   }, desc: "Thread pointer" },
+    { ix: 5,  r: x5,  reg: X5,  alias: T0,   abi: { get: t0,   set: set_t0   }, desc: "Temporary 0" },
+    { ix: 6,  r: x6,  reg: X6,  alias: T1,   abi: { get: t1,   set: set_t1   }, desc: "Temporary 1" },
+    { ix: 7,  r: x7,  reg: X7,  alias: T2,   abi: { get: t2,   set: set_t2   }, desc: "Temporary 2" },
+    { ix: 8,  r: x8,  reg: X8,  alias: S0,   abi: { get: s0,   set: set_s0   }, desc: "Saved register 0 / Frame pointer" },
+    { ix: 9,  r: x9,  reg: X9,  alias: S1,   abi: { get: s1,   set: set_s1   }, desc: "Saved register 1" },
+    { ix: 10, r: x10, reg: X10, alias: A0,   abi: { get: a0,   set: set_a0   }, desc: "Function argument 0 / Return value 0" },
+    { ix: 11, r: x11, reg: X11, alias: A1,   abi: { get: a1,   set: set_a1   }, desc: "Function argument 1 / Return value 1" },
+    { ix: 12, r: x12, reg: X12, alias: A2,   abi: { get: a2,   set: set_a2   }, desc: "Function argument 2" },
+    { ix: 13, r: x13, reg: X13, alias: A3,   abi: { get: a3,   set: set_a3   }, desc: "Function argument 3" },
+    { ix: 14, r: x14, reg: X14, alias: A4,   abi: { get: a4,   set: set_a4   }, desc: "Function argument 4" },
+    { ix: 15, r: x15, reg: X15, alias: A5,   abi: { get: a5,   set: set_a5   }, desc: "Function argument 5" },
+    { ix: 16, r: x16, reg: X16, alias: A6,   abi: { get: a6,   set: set_a6   }, desc: "Function argument 6" },
+    { ix: 17, r: x17, reg: X17, alias: A7,   abi: { get: a7,   set: set_a7   }, desc: "Function argument 7" },
+    { ix: 18, r: x18, reg: X18, alias: S2,   abi: { get: s2,   set: set_s2   }, desc: "Saved register 2" },
+    { ix: 19, r: x19, reg: X19, alias: S3,   abi: { get: s3,   set: set_s3   }, desc: "Saved register 3" },
+    { ix: 20, r: x20, reg: X20, alias: S4,   abi: { get: s4,   set: set_s4   }, desc: "Saved register 4" },
+    { ix: 21, r: x21, reg: X21, alias: S5,   abi: { get: s5,   set: set_s5   }, desc: "Saved register 5" },
+    { ix: 22, r: x22, reg: X22, alias: S6,   abi: { get: s6,   set: set_s6   }, desc: "Saved register 6" },
+    { ix: 23, r: x23, reg: X23, alias: S7,   abi: { get: s7,   set: set_s7   }, desc: "Saved register 7" },
+    { ix: 24, r: x24, reg: X24, alias: S8,   abi: { get: s8,   set: set_s8   }, desc: "Saved register 8" },
+    { ix: 25, r: x25, reg: X25, alias: S9,   abi: { get: s9,   set: set_s9   }, desc: "Saved register 9" },
+    { ix: 26, r: x26, reg: X26, alias: S10,  abi: { get: s10,  set: set_s10  }, desc: "Saved register 10" },
+    { ix: 27, r: x27, reg: X27, alias: S11,  abi: { get: s11,  set: set_s11  }, desc: "Saved register 11" },
+    { ix: 28, r: x28, reg: X28, alias: T3,   abi: { get: t3,   set: set_t3   }, desc: "Temporary 3" },
+    { ix: 29, r: x29, reg: X29, alias: T4,   abi: { get: t4,   set: set_t4   }, desc: "Temporary 4" },
+    { ix: 30, r: x30, reg: X30, alias: T5,   abi: { get: t5,   set: set_t5   }, desc: "Temporary 5" },
+    { ix: 31, r: x31, reg: X31, alias: T6,   abi: { get: t6,   set: set_t6   }, desc: "Temporary 6" }
 ];
 
+#[allow(non_upper_case_globals)]
+impl Reg {
+    /// Frame pointer, the other ABI name of `s0`.
+    pub const Fp: Reg = Reg::X8;
+
+    /// The register number.
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    /// The register with the given number, if it exists.
+    pub fn from_index(ix: usize) -> Option<Reg> {
+        Reg::ALL.get(ix).copied()
+    }
+
+    /// The register with the given name, either its number like `x2` or its ABI name like `sp` or `fp`.
+    pub fn from_name(name: &str) -> Option<Reg> {
+        if name == "fp" {
+            return Some(Reg::Fp);
+        }
+        match name.strip_prefix('x') {
+            Some(ix) if !ix.is_empty() && ix.bytes().all(|c| c.is_ascii_digit()) => {
+                Reg::from_index(ix.parse().ok()?)
+            }
+            _ => Registers::ABI_NAMES.iter().position(|abi| *abi == name).and_then(Reg::from_index),
+        }
+    }
+
+    /// The ABI name of the register, like `sp`.
+    pub fn abi_name(self) -> &'static str {
+        Registers::ABI_NAMES[self.index()]
+    }
+}
+
+impl core::fmt::Display for Reg {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(self.abi_name())
+    }
+}
+
+impl From<Reg> for usize {
+    fn from(reg: Reg) -> Self {
+        reg.index()
+    }
+}
+
+impl Index<Reg> for Registers {
+    type Output = uxlen;
+
+    fn index(&self, reg: Reg) -> &Self::Output {
+        &self.0[reg.index()]
+    }
+}
+
+impl IndexMut<Reg> for Registers {
+    fn index_mut(&mut self, reg: Reg) -> &mut Self::Output {
+        &mut self.0[reg.index()]
+    }
+}
+
+// NOTE: Indexing by register number is implemented explicitly, as the
+//       `Index<Reg>` implementation would otherwise hide the one of the array.
+impl Index<usize> for Registers {
+    type Output = uxlen;
+
+    fn index(&self, ix: usize) -> &Self::Output {
+        &self.0[ix]
+    }
+}
+
+impl IndexMut<usize> for Registers {
+    fn index_mut(&mut self, ix: usize) -> &mut Self::Output {
+        &mut self.0[ix]
+    }
+}
+
 impl Deref for Registers {
     type Target = [uxlen; 32];
 
@@ -100,3 +249,39 @@ impl DerefMut for Registers {
         &mut self.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dumps_known_register_state() {
+        let mut regs = Registers::new(0x1000);
+        regs.set_ra(0x8000_0010);
+        regs.set_a0(42);
+        assert_eq!(
+            regs.dump(),
+            concat!(
+                "x0/zero = 0x00000000   x1/ra   = 0x80000010   x2/sp   = 0x00001000   x3/gp   = 0x00000000\n",
+                "x4/tp   = 0x00000000   x5/t0   = 0x00000000   x6/t1   = 0x00000000   x7/t2   = 0x00000000\n",
+                "x8/s0   = 0x00000000   x9/s1   = 0x00000000   x10/a0  = 0x0000002a   x11/a1  = 0x00000000\n",
+                "x12/a2  = 0x00000000   x13/a3  = 0x00000000   x14/a4  = 0x00000000   x15/a5  = 0x00000000\n",
+                "x16/a6  = 0x00000000   x17/a7  = 0x00000000   x18/s2  = 0x00000000   x19/s3  = 0x00000000\n",
+                "x20/s4  = 0x00000000   x21/s5  = 0x00000000   x22/s6  = 0x00000000   x23/s7  = 0x00000000\n",
+                "x24/s8  = 0x00000000   x25/s9  = 0x00000000   x26/s10 = 0x00000000   x27/s11 = 0x00000000\n",
+                "x28/t3  = 0x00000000   x29/t4  = 0x00000000   x30/t5  = 0x00000000   x31/t6  = 0x00000000\n",
+            )
+        );
+
+        let previous = regs;
+        regs.set_a0(43);
+        let dump = regs.dump_changes(&previous);
+        assert_eq!(
+            dump.lines().nth(2),
+            Some(
+                "x8/s0   = 0x00000000   x9/s1   = 0x00000000   x10/a0  = 0x0000002b*  x11/a1  = 0x00000000"
+            )
+        );
+        assert_eq!(dump.matches('*').count(), 1);
+    }
+}
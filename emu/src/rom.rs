@@ -1,25 +1,107 @@
-use crate::uxlen;
+use alloc::vec::Vec;
+use core::ops::{Deref, DerefMut, Range};
 
+use crate::{compressed::CInstruction, inst::Instruction, uxlen};
+
+/// An error that occurs when accessing memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemError {
+    /// The address is not within the bounds of the memory.
+    OutOfBounds { addr: uxlen },
+    /// The address is mapped, but its region does not allow the access,
+    /// see [Permissions][crate::memory::Permissions].
+    AccessDenied { addr: uxlen },
+}
+
+impl core::fmt::Display for MemError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::OutOfBounds { addr } => write!(f, "address {addr:#010x} is out of bounds"),
+            Self::AccessDenied { addr } => {
+                write!(f, "access to address {addr:#010x} is not allowed")
+            }
+        }
+    }
+}
+
+impl core::error::Error for MemError {}
+
+/// The bytes backing a [Rom], which it either borrows or owns.
+#[derive(Debug)]
+enum RomBytes<'rom> {
+    Borrowed(&'rom mut [u8]),
+    Owned(Vec<u8>),
+}
+
+impl Deref for RomBytes<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Self::Borrowed(bytes) => bytes,
+            Self::Owned(bytes) => bytes,
+        }
+    }
+}
+
+impl DerefMut for RomBytes<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match self {
+            Self::Borrowed(bytes) => bytes,
+            Self::Owned(bytes) => bytes,
+        }
+    }
+}
+
+/// The memory containing the program, either borrowing its bytes from the caller or owning them.
 #[derive(Debug)]
 pub struct Rom<'rom> {
-    bytes: &'rom mut [u8],
+    bytes: RomBytes<'rom>,
     start_addr: uxlen,
     end_addr: uxlen,
 }
 
 impl<'rom> Rom<'rom> {
     pub fn new(bytes: &'rom mut [u8], start_addr: uxlen, end_addr: uxlen) -> Self {
-        Rom { bytes, start_addr, end_addr }
+        Rom { bytes: RomBytes::Borrowed(bytes), start_addr, end_addr }
     }
 
+    /// Creates a ROM from a raw flat binary, loaded at `start_addr`.
+    ///
+    /// Fails if the binary extends past the end of the address space.
+    pub fn from_raw(bytes: &'rom mut [u8], start_addr: uxlen) -> Result<Self, MemError> {
+        let end_addr = end_addr(start_addr, bytes.len())?;
+        Ok(Self::new(bytes, start_addr, end_addr))
+    }
+
+    /// Creates a ROM that owns its bytes, so it does not borrow from the caller.
+    pub fn from_vec(bytes: Vec<u8>, start_addr: uxlen, end_addr: uxlen) -> Rom<'static> {
+        Rom { bytes: RomBytes::Owned(bytes), start_addr, end_addr }
+    }
+
+    #[inline]
+    pub fn read(&self, addr: uxlen) -> Result<u8, MemError> {
+        let offset = self.offset(addr)?;
+        Ok(self.bytes[offset])
+    }
+
+    /// Reads a little-endian halfword, making sure all of its bytes lie within the ROM.
+    #[inline]
+    pub fn read_u16(&self, addr: uxlen) -> Result<u16, MemError> {
+        Ok(u16::from_le_bytes(self.read_bytes(addr)?))
+    }
+
+    /// Reads a little-endian word, making sure all of its bytes lie within the ROM.
     #[inline]
-    pub fn read(&self, addr: uxlen) -> u8 {
-        self.bytes[addr as usize - self.start_addr as usize]
+    pub fn read_u32(&self, addr: uxlen) -> Result<u32, MemError> {
+        Ok(u32::from_le_bytes(self.read_bytes(addr)?))
     }
 
     #[inline]
-    pub fn write(&mut self, addr: uxlen, value: u8) {
-        self.bytes[addr as usize - self.start_addr as usize] = value;
+    pub fn write(&mut self, addr: uxlen, value: u8) -> Result<(), MemError> {
+        let offset = self.offset(addr)?;
+        self.bytes[offset] = value;
+        Ok(())
     }
 
     #[inline]
@@ -36,4 +118,152 @@ impl<'rom> Rom<'rom> {
     pub fn end_addr(&self) -> uxlen {
         self.end_addr
     }
+
+    /// The bytes backing the ROM.
+    #[inline]
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// The bytes backing the ROM, mutably.
+    #[inline]
+    pub fn bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.bytes
+    }
+
+    /// Walks the ROM from its start to its end address, yielding the address of every instruction
+    /// and the instruction itself, without executing anything.
+    ///
+    /// Compressed instructions are yielded as their expanded 32-bit equivalent, and illegal compressed
+    /// instructions as a zeroed, unknown instruction. An incomplete instruction at the end of the ROM is skipped.
+    pub fn instructions(&self) -> impl Iterator<Item = (uxlen, Instruction)> + '_ {
+        self.instructions_in(self.start_addr..self.end_addr)
+    }
+
+    /// Walks the instructions like [Rom::instructions], but only those that start within the given address range.
+    pub fn instructions_in(
+        &self,
+        range: Range<uxlen>,
+    ) -> impl Iterator<Item = (uxlen, Instruction)> + '_ {
+        let mut addr = range.start;
+        core::iter::from_fn(move || {
+            if addr >= range.end {
+                return None;
+            }
+            let half = self.read_u16(addr).ok()?;
+            let (inst, len) = if CInstruction::is_compressed(half) {
+                (CInstruction(half).expand().unwrap_or(Instruction(0)), CInstruction::BYTES)
+            } else {
+                (Instruction(self.read_u32(addr).ok()?), Instruction::BYTES)
+            };
+
+            let item = (addr, inst);
+            addr = addr.wrapping_add(len as uxlen);
+            Some(item)
+        })
+    }
+
+    /// Reads `N` consecutive bytes, checking the bounds of the whole range up front.
+    #[inline]
+    fn read_bytes<const N: usize>(&self, addr: uxlen) -> Result<[u8; N], MemError> {
+        let start = self.offset(addr)?;
+        let end = start + N;
+        if end > self.bytes.len() || addr.wrapping_add(N as uxlen - 1) >= self.end_addr {
+            return Err(MemError::OutOfBounds { addr });
+        }
+
+        let mut bytes = [0; N];
+        bytes.copy_from_slice(&self.bytes[start..end]);
+        Ok(bytes)
+    }
+
+    /// Translates an address into an index into the backing bytes,
+    /// making sure it lies within the ROM.
+    #[inline]
+    fn offset(&self, addr: uxlen) -> Result<usize, MemError> {
+        if addr < self.start_addr || addr >= self.end_addr {
+            return Err(MemError::OutOfBounds { addr });
+        }
+
+        let offset = (addr - self.start_addr) as usize;
+        if offset >= self.bytes.len() {
+            return Err(MemError::OutOfBounds { addr });
+        }
+
+        Ok(offset)
+    }
+}
+
+/// Reborrows a ROM, so a [Cpu][crate::cpu::Cpu] can run it while the caller keeps ownership.
+impl<'a, 'rom: 'a> From<&'a mut Rom<'rom>> for Rom<'a> {
+    fn from(rom: &'a mut Rom<'rom>) -> Self {
+        Rom::new(&mut rom.bytes, rom.start_addr, rom.end_addr)
+    }
+}
+
+/// The exclusive end address of `len` bytes starting at `start_addr`.
+///
+/// Fails if they extend past the end of the address space.
+pub(crate) fn end_addr(start_addr: uxlen, len: usize) -> Result<uxlen, MemError> {
+    uxlen::try_from(len)
+        .ok()
+        .and_then(|len| start_addr.checked_add(len))
+        .ok_or(MemError::OutOfBounds { addr: start_addr })
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+    use crate::inst::InstructionKind;
+
+    #[test]
+    fn from_raw_rejects_binaries_past_the_address_space() {
+        let mut bytes = [0; 20];
+        let err = Rom::from_raw(&mut bytes, 0xffff_fff0).unwrap_err();
+        assert_eq!(err, MemError::OutOfBounds { addr: 0xffff_fff0 });
+
+        let rom = Rom::from_raw(&mut bytes[..15], 0xffff_fff0).unwrap();
+        assert_eq!(rom.end_addr(), 0xffff_ffff);
+    }
+
+    #[test]
+    fn accesses_past_the_end_fail() {
+        let mut bytes = [0x13, 0, 0, 0];
+        let mut rom = Rom::new(&mut bytes, 0x1000, 0x1004);
+        assert_eq!(rom.read(0x1003), Ok(0));
+        assert_eq!(rom.read(0x1004), Err(MemError::OutOfBounds { addr: 0x1004 }));
+        assert_eq!(rom.read(0x0fff), Err(MemError::OutOfBounds { addr: 0x0fff }));
+        assert_eq!(rom.write(0x1004, 0), Err(MemError::OutOfBounds { addr: 0x1004 }));
+    }
+
+    #[test]
+    fn reads_must_lie_within_the_rom() {
+        let mut bytes = [0x13, 0, 0, 0, 0x13, 0];
+        let rom = Rom::new(&mut bytes, 0x1000, 0x1006);
+        assert_eq!(rom.read_u32(0x1000), Ok(0x13));
+        assert_eq!(rom.read_u16(0x1004), Ok(0x13));
+        assert_eq!(rom.read_u32(0x1004), Err(MemError::OutOfBounds { addr: 0x1004 }));
+        assert_eq!(rom.read_u16(0x1006), Err(MemError::OutOfBounds { addr: 0x1006 }));
+    }
+
+    #[test]
+    fn walks_instructions_of_both_lengths() {
+        let mut bytes = [
+            0x13, 0x05, 0x10, 0x00, // addi x10, x0, 1
+            0x01, 0x00, // c.nop
+            0x63, 0x00, 0xb5, 0x00, // beq  x10, x11, 0
+            0x01, 0x00, // c.nop
+            0x13, 0x00, // The first halfword of a nop that is cut off by the end of the ROM.
+        ];
+        let rom = Rom::new(&mut bytes, 0x1000, 0x100e);
+
+        let addrs: Vec<uxlen> = rom.instructions().map(|(addr, _)| addr).collect();
+        assert_eq!(addrs, [0x1000, 0x1004, 0x1006, 0x100a]);
+
+        let kinds: Vec<InstructionKind> = rom.instructions().map(|(_, inst)| inst.kind()).collect();
+        let (addi, beq) = (InstructionKind::Addi, InstructionKind::Beq);
+        assert_eq!(kinds, [addi, addi, beq, addi]);
+    }
 }
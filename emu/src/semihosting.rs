@@ -0,0 +1,331 @@
+//! Host implementations of the newlib system calls, so programs using `printf` and `malloc` can run,
+//! and of the Linux system calls that statically linked user-mode programs need.
+//!
+//! The system call number is passed in `a7`, the arguments in `a0` to `a6`, and the result is returned in `a0`.
+//! Both use the same system call numbers, so the [SyscallAbi] only changes the semantics of some of them.
+
+use std::{
+    boxed::Box,
+    io::{Read, Write},
+    vec,
+};
+
+use crate::{cpu::Cpu, ixlen, uxlen};
+
+/// Control a device.
+pub const SYS_IOCTL: uxlen = 29;
+/// Close a file descriptor.
+pub const SYS_CLOSE: uxlen = 57;
+/// Read from a file descriptor.
+pub const SYS_READ: uxlen = 63;
+/// Write to a file descriptor.
+pub const SYS_WRITE: uxlen = 64;
+/// Write multiple buffers to a file descriptor.
+pub const SYS_WRITEV: uxlen = 66;
+/// Get the status of a file descriptor.
+pub const SYS_FSTAT: uxlen = 80;
+/// Terminate the program.
+pub const SYS_EXIT: uxlen = 93;
+/// Terminate all threads of the program.
+pub const SYS_EXIT_GROUP: uxlen = 94;
+/// Set the pointer to the thread ID that is cleared when the thread exits.
+pub const SYS_SET_TID_ADDRESS: uxlen = 96;
+/// Set the end of the heap.
+pub const SYS_BRK: uxlen = 214;
+
+/// Input/output error.
+const EIO: ixlen = 5;
+/// Bad file descriptor.
+const EBADF: ixlen = 9;
+/// Invalid argument.
+const EINVAL: ixlen = 22;
+/// Inappropriate ioctl for device.
+const ENOTTY: ixlen = 25;
+
+/// The size of the Linux `struct stat` on 32-bit targets.
+const STAT_SIZE: usize = 80;
+/// The offset of `st_mode` in the Linux `struct stat`.
+const STAT_MODE: usize = 8;
+/// The file type of a character device, like a terminal, in `st_mode`.
+const S_IFCHR: uxlen = 0o020000;
+
+/// The conventions of the system calls a program makes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SyscallAbi {
+    /// The system calls of newlib's `libgloss`, for bare-metal programs.
+    #[default]
+    Newlib,
+    /// The system calls of Linux, for statically linked user-mode programs. Besides the newlib
+    /// system calls, this supports `exit_group`, `writev`, `ioctl` and `set_tid_address`,
+    /// and `fstat` describes the standard streams as character devices.
+    Linux,
+}
+
+/// The state of the semihosting layer.
+pub struct Semihosting {
+    /// Where the heap starts, and so where its end is after a reset.
+    heap_start: uxlen,
+    /// The current end of the heap, as set by `brk`.
+    brk: uxlen,
+    /// Where reads from stdin get their input.
+    stdin: Box<dyn Read>,
+    abi: SyscallAbi,
+}
+
+impl Semihosting {
+    /// Creates the semihosting layer with the heap starting at `heap_start`, reading stdin from `stdin`.
+    pub fn new(heap_start: uxlen, stdin: Box<dyn Read>, abi: SyscallAbi) -> Self {
+        Self { heap_start, brk: heap_start, stdin, abi }
+    }
+
+    /// Moves the end of the heap back to its start, for when the program is run again.
+    pub(crate) fn reset(&mut self) {
+        self.brk = self.heap_start;
+    }
+
+    /// Handles the system call the program requested. Returns `false`
+    /// if it is not a supported system call and nothing has been done.
+    pub(crate) fn syscall(&mut self, cpu: &mut Cpu) -> bool {
+        let regs = cpu.registers();
+        let (number, a0, a1, a2) = (regs.a7(), regs.a0(), regs.a1(), regs.a2());
+        let linux = self.abi == SyscallAbi::Linux;
+
+        let result = match number {
+            SYS_READ => self.read(cpu, a0, a1, a2),
+            SYS_WRITE => self.write(cpu, a0, a1, a2),
+            SYS_EXIT => {
+                cpu.exit(a0);
+                return true;
+            }
+            // NOTE: There is only a single thread, so exiting all threads exits the program.
+            SYS_EXIT_GROUP if linux => {
+                cpu.exit(a0);
+                return true;
+            }
+            SYS_BRK => self.brk(cpu, a0),
+            // NOTE: Closing is a no-op, as programs can not open files.
+            SYS_CLOSE => 0,
+            SYS_FSTAT if linux => self.fstat(cpu, a0, a1),
+            // NOTE: newlib only uses this to check whether stdout is a terminal, to decide on buffering.
+            //       Reporting an error makes it fall back to full buffering, which is fine.
+            SYS_FSTAT => -EBADF,
+            SYS_WRITEV if linux => self.writev(cpu, a0, a1, a2),
+            // NOTE: The C library asks for the terminal's window size to decide on buffering,
+            //       and falls back to full buffering when the stream is not a terminal.
+            SYS_IOCTL if linux => -ENOTTY,
+            // NOTE: The thread ID is only used for threads, so it is not stored. The only thread has ID 1.
+            SYS_SET_TID_ADDRESS if linux => 1,
+            _ => return false,
+        };
+
+        cpu.registers_mut().set_a0(result as uxlen);
+        true
+    }
+
+    /// `read(fd, buf, count)`: reads at most `count` bytes from stdin and returns the number of bytes read.
+    /// Zero is returned at the end of the input.
+    fn read(&mut self, cpu: &mut Cpu, fd: uxlen, buf: uxlen, count: uxlen) -> ixlen {
+        if fd != 0 {
+            return -EBADF;
+        }
+        // NOTE: The buffer is checked up front, so no input is lost when it is not writable.
+        if !(0..count).all(|i| cpu.is_mapped(buf.wrapping_add(i))) {
+            return -EINVAL;
+        }
+
+        let mut bytes = vec![0; count as usize];
+        let Ok(read) = self.stdin.read(&mut bytes) else { return -EIO };
+        for (i, byte) in bytes[..read].iter().enumerate() {
+            if cpu.write_byte(buf.wrapping_add(i as uxlen), *byte).is_err() {
+                return -EINVAL;
+            }
+        }
+        read as ixlen
+    }
+
+    /// `write(fd, buf, count)`: writes to stdout or stderr and returns the number of bytes written.
+    fn write(&mut self, cpu: &mut Cpu, fd: uxlen, buf: uxlen, count: uxlen) -> ixlen {
+        let Ok(bytes) = cpu.read_mem(buf, count as usize) else { return -EINVAL };
+
+        let written = match fd {
+            1 => std::io::stdout().write_all(&bytes).and_then(|_| std::io::stdout().flush()),
+            2 => std::io::stderr().write_all(&bytes),
+            _ => return -EBADF,
+        };
+
+        match written {
+            Ok(()) => count as ixlen,
+            Err(_) => -EIO,
+        }
+    }
+
+    /// `writev(fd, iov, iovcnt)`: writes the buffers described by an array of `struct iovec`,
+    /// each a base address followed by a length, and returns the total number of bytes written.
+    fn writev(&mut self, cpu: &mut Cpu, fd: uxlen, iov: uxlen, count: uxlen) -> ixlen {
+        let mut total: ixlen = 0;
+        for i in 0..count {
+            let Ok(entry) = cpu.read_mem(iov.wrapping_add(i * 8), 8) else { return -EINVAL };
+            let base = uxlen::from_le_bytes([entry[0], entry[1], entry[2], entry[3]]);
+            let len = uxlen::from_le_bytes([entry[4], entry[5], entry[6], entry[7]]);
+            let written = self.write(cpu, fd, base, len);
+            if written < 0 {
+                return written;
+            }
+            total = total.wrapping_add(written);
+        }
+        total
+    }
+
+    /// `fstat(fd, statbuf)`: describes the standard streams as character devices, like a terminal.
+    fn fstat(&mut self, cpu: &mut Cpu, fd: uxlen, statbuf: uxlen) -> ixlen {
+        if fd > 2 {
+            return -EBADF;
+        }
+
+        // NOTE: Everything but the file type is left zero, as there is no real file behind the stream.
+        let mut stat = [0; STAT_SIZE];
+        stat[STAT_MODE..STAT_MODE + 4].copy_from_slice(&(S_IFCHR | 0o620).to_le_bytes());
+        match cpu.write_mem(statbuf, &stat) {
+            Ok(()) => 0,
+            Err(_) => -EINVAL,
+        }
+    }
+
+    /// `brk(addr)`: moves the end of the heap to `addr` if that memory exists, and returns the new end.
+    /// Passing zero returns the current end.
+    fn brk(&mut self, cpu: &Cpu, addr: uxlen) -> ixlen {
+        // NOTE: Like Linux, the old end is returned when the heap can not be moved, which the caller detects.
+        //       All of the memory the heap grows into has to exist, not just its new end.
+        if addr != 0 && (addr <= self.brk || cpu.is_mapped_range(self.brk..addr)) {
+            self.brk = addr;
+        }
+        self.brk as ixlen
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    use crate::{device::Ram, rom::Rom};
+
+    const BASE: uxlen = 0x8000_0000;
+
+    #[test]
+    fn writes_hello_to_stdout() {
+        let words: [u32; 10] = [
+            0x00100513, // addi  a0, zero, 1
+            0x00000597, // auipc a1, 0
+            0x01c58593, // addi  a1, a1, 28
+            0x00500613, // addi  a2, zero, 5
+            0x04000893, // addi  a7, zero, 64
+            0x00000073, // ecall
+            0x05d00893, // addi  a7, zero, 93
+            0x00000073, // ecall
+            u32::from_le_bytes(*b"hell"),
+            u32::from_le_bytes(*b"o\0\0\0"),
+        ];
+        let mut bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+        let end = BASE + bytes.len() as uxlen;
+        let mut rom = Rom::new(&mut bytes, BASE, end);
+        let mut cpu = Cpu::builder(&mut rom).semihosting(true).build().unwrap();
+        cpu.run().unwrap();
+
+        // The program exits with the number of bytes that were written.
+        assert_eq!(cpu.exit_code(), Some(5));
+    }
+
+    #[test]
+    fn brk_only_grows_into_mapped_memory() {
+        let mut bytes = [0x13, 0, 0, 0];
+        let mut rom = Rom::new(&mut bytes, BASE, BASE + 4);
+        let cpu = Cpu::builder(&mut rom)
+            .ram(BASE + 4, 0x1000)
+            .device(0x9000_0000..0x9000_1000, Ram::new(0x1000))
+            .build()
+            .unwrap();
+        let mut semihosting =
+            Semihosting::new(BASE + 4, Box::new(std::io::empty()), SyscallAbi::Newlib);
+
+        assert_eq!(semihosting.brk(&cpu, 0), (BASE + 4) as ixlen);
+        assert_eq!(semihosting.brk(&cpu, BASE + 0x800), (BASE + 0x800) as ixlen);
+        assert_eq!(semihosting.brk(&cpu, BASE + 0x1005), (BASE + 0x800) as ixlen);
+        assert_eq!(semihosting.brk(&cpu, BASE + 0x1004), (BASE + 0x1004) as ixlen);
+
+        // The end of the heap lies in mapped memory, but the memory before it does not.
+        assert_eq!(semihosting.brk(&cpu, 0x9000_0010), (BASE + 0x1004) as ixlen);
+
+        // Shrinking the heap always succeeds.
+        assert_eq!(semihosting.brk(&cpu, BASE + 4), (BASE + 4) as ixlen);
+    }
+
+    #[test]
+    fn reads_stdin_into_memory() {
+        let words: [u32; 10] = [
+            0x00000513, // addi  a0, zero, 0
+            0x00000597, // auipc a1, 0
+            0x01c58593, // addi  a1, a1, 28
+            0x00800613, // addi  a2, zero, 8
+            0x03f00893, // addi  a7, zero, 63
+            0x00000073, // ecall
+            0x05d00893, // addi  a7, zero, 93
+            0x00000073, // ecall
+            0x00000000, // (buffer)
+            0x00000000, // (buffer)
+        ];
+        let mut bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+        let end = BASE + bytes.len() as uxlen;
+        let mut rom = Rom::new(&mut bytes, BASE, end);
+        let mut cpu =
+            Cpu::builder(&mut rom).semihosting(true).stdin(&b"hi!\n"[..]).build().unwrap();
+        cpu.run().unwrap();
+
+        // The program exits with the number of bytes that were read.
+        assert_eq!(cpu.exit_code(), Some(4));
+        let buffer: Vec<u8> = (0..8).map(|i| cpu.read_byte(BASE + 32 + i).unwrap()).collect();
+        assert_eq!(buffer, b"hi!\n\0\0\0\0");
+    }
+
+    #[test]
+    fn linux_program_writes_moves_brk_and_exits_group() {
+        let words: [u32; 19] = [
+            0x00100513, // addi  a0, zero, 1
+            0x00000597, // auipc a1, 0
+            0x04058593, // addi  a1, a1, 64
+            0x00500613, // addi  a2, zero, 5
+            0x04000893, // addi  a7, zero, 64
+            0x00000073, // ecall
+            0x00050413, // addi  s0, a0, 0
+            0x00000513, // addi  a0, zero, 0
+            0x0d600893, // addi  a7, zero, 214
+            0x00000073, // ecall
+            0x00050493, // addi  s1, a0, 0
+            0x01050513, // addi  a0, a0, 16
+            0x00000073, // ecall
+            0x00050913, // addi  s2, a0, 0
+            0x00700513, // addi  a0, zero, 7
+            0x05e00893, // addi  a7, zero, 94
+            0x00000073, // ecall
+            u32::from_le_bytes(*b"hell"),
+            u32::from_le_bytes(*b"o\0\0\0"),
+        ];
+        let mut bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+        let end = BASE + bytes.len() as uxlen;
+        let mut rom = Rom::new(&mut bytes, BASE, end);
+        let mut cpu = Cpu::builder(&mut rom)
+            .linux_syscalls(true)
+            .ram(BASE + 0x1000, 0x1000)
+            .heap_start(BASE + 0x1000)
+            .build()
+            .unwrap();
+        cpu.run().unwrap();
+
+        let regs = cpu.registers();
+        // `write` returns the number of bytes written, and `brk` the end of the heap.
+        assert_eq!(regs.s0(), 5);
+        assert_eq!(regs.s1(), BASE + 0x1000);
+        assert_eq!(regs.s2(), BASE + 0x1010);
+        assert_eq!(cpu.exit_code(), Some(7));
+    }
+}
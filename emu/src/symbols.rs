@@ -0,0 +1,88 @@
+use alloc::{
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+};
+
+use crate::uxlen;
+
+/// The symbols of a program by address, used to annotate addresses in traces
+/// with the function or label they belong to.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SymbolMap {
+    /// The name of the symbol at each address. If several symbols share an address, the first one added is kept.
+    by_addr: BTreeMap<uxlen, String>,
+}
+
+impl SymbolMap {
+    /// Creates an empty symbol map.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a symbol, unless there already is one at the same address.
+    pub fn insert(&mut self, name: impl Into<String>, addr: uxlen) {
+        self.by_addr.entry(addr).or_insert_with(|| name.into());
+    }
+
+    /// The nearest symbol at or below the given address, and the offset of the address from it.
+    pub fn lookup(&self, addr: uxlen) -> Option<(&str, uxlen)> {
+        let (sym_addr, name) = self.by_addr.range(..=addr).next_back()?;
+        Some((name, addr - sym_addr))
+    }
+
+    /// Formats the given address relative to the nearest symbol, like `<memcpy+0x10>`,
+    /// or `None` if there is no symbol at or below it.
+    pub fn annotate(&self, addr: uxlen) -> Option<String> {
+        match self.lookup(addr)? {
+            (name, 0) => Some(format!("<{name}>")),
+            (name, offset) => Some(format!("<{name}+{offset:#x}>")),
+        }
+    }
+
+    /// The name of the symbol at exactly the given address, if any.
+    pub fn name_at(&self, addr: uxlen) -> Option<&str> {
+        self.by_addr.get(&addr).map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_addr.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_addr.is_empty()
+    }
+}
+
+impl<S: ToString> FromIterator<(S, uxlen)> for SymbolMap {
+    fn from_iter<I: IntoIterator<Item = (S, uxlen)>>(iter: I) -> Self {
+        let mut map = Self::new();
+        for (name, addr) in iter {
+            map.insert(name.to_string(), addr);
+        }
+        map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn annotates_addresses_with_the_nearest_symbol_below() {
+        let symbols: SymbolMap =
+            [("_start", 0x8000_0000), ("main", 0x8000_0010), ("alias", 0x8000_0010)]
+                .into_iter()
+                .collect();
+        assert_eq!(symbols.len(), 2);
+
+        assert_eq!(symbols.lookup(0x7fff_fffc), None);
+        assert_eq!(symbols.lookup(0x8000_000c), Some(("_start", 0xc)));
+        assert_eq!(symbols.annotate(0x8000_0000).as_deref(), Some("<_start>"));
+        assert_eq!(symbols.annotate(0x8000_0024).as_deref(), Some("<main+0x14>"));
+
+        // The first symbol added at an address is kept.
+        assert_eq!(symbols.name_at(0x8000_0010), Some("main"));
+        assert_eq!(symbols.name_at(0x8000_0014), None);
+    }
+}
@@ -0,0 +1,137 @@
+use crate::uxlen;
+
+/// A synchronous exception, as recorded in the `mcause` CSR.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exception {
+    /// Instruction address misaligned.
+    InstructionAddressMisaligned,
+    /// Instruction access fault.
+    InstructionAccessFault,
+    /// Illegal instruction.
+    IllegalInstruction,
+    /// Breakpoint.
+    Breakpoint,
+    /// Load address misaligned.
+    LoadAddressMisaligned,
+    /// Load access fault.
+    LoadAccessFault,
+    /// Store/AMO address misaligned.
+    StoreAddressMisaligned,
+    /// Store/AMO access fault.
+    StoreAccessFault,
+    /// Environment call from U-mode.
+    EnvironmentCallFromUMode,
+    /// Environment call from S-mode.
+    EnvironmentCallFromSMode,
+    /// Environment call from M-mode.
+    EnvironmentCallFromMMode,
+}
+
+impl Exception {
+    /// The exception code written to `mcause`.
+    pub fn code(&self) -> uxlen {
+        match self {
+            Self::InstructionAddressMisaligned => 0,
+            Self::InstructionAccessFault => 1,
+            Self::IllegalInstruction => 2,
+            Self::Breakpoint => 3,
+            Self::LoadAddressMisaligned => 4,
+            Self::LoadAccessFault => 5,
+            Self::StoreAddressMisaligned => 6,
+            Self::StoreAccessFault => 7,
+            Self::EnvironmentCallFromUMode => 8,
+            Self::EnvironmentCallFromSMode => 9,
+            Self::EnvironmentCallFromMMode => 11,
+        }
+    }
+}
+
+impl core::fmt::Display for Exception {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let description = match self {
+            Self::InstructionAddressMisaligned => "instruction address misaligned",
+            Self::InstructionAccessFault => "instruction access fault",
+            Self::IllegalInstruction => "illegal instruction",
+            Self::Breakpoint => "breakpoint",
+            Self::LoadAddressMisaligned => "load address misaligned",
+            Self::LoadAccessFault => "load access fault",
+            Self::StoreAddressMisaligned => "store/AMO address misaligned",
+            Self::StoreAccessFault => "store/AMO access fault",
+            Self::EnvironmentCallFromUMode => "environment call from U-mode",
+            Self::EnvironmentCallFromSMode => "environment call from S-mode",
+            Self::EnvironmentCallFromMMode => "environment call from M-mode",
+        };
+        f.write_str(description)
+    }
+}
+
+/// An asynchronous interrupt, as recorded in the `mcause` CSR together with [INTERRUPT_BIT].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interrupt {
+    /// Supervisor software interrupt.
+    SupervisorSoftware,
+    /// Machine software interrupt.
+    MachineSoftware,
+    /// Supervisor timer interrupt.
+    SupervisorTimer,
+    /// Machine timer interrupt.
+    MachineTimer,
+    /// Supervisor external interrupt.
+    SupervisorExternal,
+    /// Machine external interrupt.
+    MachineExternal,
+}
+
+/// The bit in `mcause` that is set when the trap was caused by an interrupt.
+pub const INTERRUPT_BIT: uxlen = 1 << (uxlen::BITS - 1);
+
+impl Interrupt {
+    /// The exception code written to `mcause`, without the [INTERRUPT_BIT].
+    /// This is also the bit of the interrupt in `mip` and `mie`.
+    pub fn code(&self) -> uxlen {
+        match self {
+            Self::SupervisorSoftware => 1,
+            Self::MachineSoftware => 3,
+            Self::SupervisorTimer => 5,
+            Self::MachineTimer => 7,
+            Self::SupervisorExternal => 9,
+            Self::MachineExternal => 11,
+        }
+    }
+
+    /// The mask of the interrupt's bit in `mip` and `mie`.
+    pub fn mask(&self) -> uxlen {
+        1 << self.code()
+    }
+}
+
+impl core::fmt::Display for Interrupt {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let description = match self {
+            Self::SupervisorSoftware => "supervisor software interrupt",
+            Self::MachineSoftware => "machine software interrupt",
+            Self::SupervisorTimer => "supervisor timer interrupt",
+            Self::MachineTimer => "machine timer interrupt",
+            Self::SupervisorExternal => "supervisor external interrupt",
+            Self::MachineExternal => "machine external interrupt",
+        };
+        f.write_str(description)
+    }
+}
+
+/// A trap taken by the [Cpu][crate::cpu::Cpu].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Trap {
+    /// The cause of the trap.
+    pub cause: Exception,
+    /// Exception-specific information, written to `mtval`.
+    /// This is the faulting address for memory exceptions
+    /// and the raw instruction for illegal instructions.
+    pub tval: uxlen,
+}
+
+impl Trap {
+    pub fn new(cause: Exception, tval: uxlen) -> Self {
+        Self { cause, tval }
+    }
+}
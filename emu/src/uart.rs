@@ -0,0 +1,214 @@
+use std::{
+    boxed::Box,
+    eprintln,
+    io::{self, Read, Write},
+};
+
+use crate::{device::Device, uxlen};
+
+/// The conventional base address of the UART, as used by QEMU's `virt` machine.
+pub const UART_BASE: uxlen = 0x1000_0000;
+/// The size of the UART's address range.
+pub const UART_SIZE: uxlen = 8;
+
+/// Receiver buffer (read) and transmitter holding register (write), or the low divisor latch byte.
+const RBR_THR_DLL: uxlen = 0;
+/// Interrupt enable register, or the high divisor latch byte.
+const IER_DLM: uxlen = 1;
+/// Interrupt identification (read) and FIFO control register (write).
+const IIR_FCR: uxlen = 2;
+const LCR: uxlen = 3;
+const MCR: uxlen = 4;
+const LSR: uxlen = 5;
+const MSR: uxlen = 6;
+const SCR: uxlen = 7;
+
+/// The divisor latch access bit of the line control register.
+const LCR_DLAB: u8 = 1 << 7;
+/// Data ready: a received byte can be read from the receiver buffer.
+const LSR_DR: u8 = 1 << 0;
+/// The transmitter holding register is empty.
+const LSR_THRE: u8 = 1 << 5;
+/// The transmitter is empty.
+const LSR_TEMT: u8 = 1 << 6;
+/// The value of the interrupt identification register when no interrupt is pending.
+const IIR_NO_INTERRUPT: u8 = 0x01;
+/// The bits of the interrupt identification register that report the FIFOs as enabled.
+const IIR_FIFO_ENABLED: u8 = 0xC0;
+
+/// A 16550-compatible UART. Bytes written to the transmitter holding register are written
+/// to its output, and the receiver buffer reads bytes from its input.
+///
+/// The transmitter is always ready, so `LSR.THRE` and `LSR.TEMT` are always set.
+/// `LSR.DR` is set while a byte from the input is available, which blocks on input
+/// that has none yet, like an interactive stdin. Interrupts are not supported.
+pub struct UartDevice {
+    input: Box<dyn Read>,
+    output: Box<dyn Write>,
+    /// A byte read from the input that the program has not read from the receiver buffer yet.
+    rx: Option<u8>,
+    /// Whether the input has reached its end, after which no more bytes are received.
+    eof: bool,
+    ier: u8,
+    lcr: u8,
+    mcr: u8,
+    scr: u8,
+    fifo_enabled: bool,
+    divisor: u16,
+}
+
+impl UartDevice {
+    /// Creates a UART that receives from `input` and transmits to `output`.
+    pub fn new(input: impl Read + 'static, output: impl Write + 'static) -> Self {
+        Self {
+            input: Box::new(input),
+            output: Box::new(output),
+            rx: None,
+            eof: false,
+            ier: 0,
+            lcr: 0,
+            mcr: 0,
+            scr: 0,
+            fifo_enabled: false,
+            divisor: 0,
+        }
+    }
+
+    /// Creates a UART that receives from the host's stdin and transmits to its stdout.
+    pub fn stdio() -> Self {
+        Self::new(io::stdin(), io::stdout())
+    }
+
+    /// Reads the next byte from the input into the receiver buffer, if it is empty.
+    fn receive(&mut self) {
+        if self.rx.is_some() || self.eof {
+            return;
+        }
+
+        let mut byte = [0];
+        match self.input.read(&mut byte) {
+            Ok(1) => self.rx = Some(byte[0]),
+            Ok(_) => self.eof = true,
+            Err(err) => {
+                eprintln!("Failed to read UART input: {err}");
+                self.eof = true;
+            }
+        }
+    }
+
+    fn transmit(&mut self, value: u8) {
+        // NOTE: Flushing every byte keeps the output in order with the host's own output,
+        //       at the cost of a system call per byte, which is fine for a console.
+        let result = self.output.write_all(&[value]).and_then(|()| self.output.flush());
+        if let Err(err) = result {
+            eprintln!("Failed to write UART output: {err}");
+        }
+    }
+
+    fn dlab(&self) -> bool {
+        self.lcr & LCR_DLAB != 0
+    }
+}
+
+impl Device for UartDevice {
+    fn read(&mut self, offset: uxlen) -> u8 {
+        match offset {
+            RBR_THR_DLL if self.dlab() => self.divisor as u8,
+            RBR_THR_DLL => {
+                self.receive();
+                self.rx.take().unwrap_or(0)
+            }
+            IER_DLM if self.dlab() => (self.divisor >> 8) as u8,
+            IER_DLM => self.ier,
+            IIR_FCR => match self.fifo_enabled {
+                true => IIR_NO_INTERRUPT | IIR_FIFO_ENABLED,
+                false => IIR_NO_INTERRUPT,
+            },
+            LCR => self.lcr,
+            MCR => self.mcr,
+            LSR => {
+                self.receive();
+                let data_ready = if self.rx.is_some() { LSR_DR } else { 0 };
+                LSR_THRE | LSR_TEMT | data_ready
+            }
+            // NOTE: There is no modem, so all of its status lines read as inactive.
+            MSR => 0,
+            SCR => self.scr,
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, offset: uxlen, value: u8) {
+        match offset {
+            RBR_THR_DLL if self.dlab() => self.divisor = self.divisor & 0xFF00 | value as u16,
+            RBR_THR_DLL => self.transmit(value),
+            IER_DLM if self.dlab() => {
+                self.divisor = self.divisor & 0x00FF | (value as u16) << 8;
+            }
+            // SPEC: Only the lower four bits of IER are defined.
+            IER_DLM => self.ier = value & 0x0F,
+            IIR_FCR => self.fifo_enabled = value & 1 != 0,
+            LCR => self.lcr = value,
+            MCR => self.mcr = value & 0x1F,
+            SCR => self.scr = value,
+            _ => {}
+        }
+    }
+
+    fn reset(&mut self) {
+        // NOTE: The input and output streams are not part of the device, so a byte that
+        //       has already been received stays available after the reset.
+        self.ier = 0;
+        self.lcr = 0;
+        self.mcr = 0;
+        self.scr = 0;
+        self.fifo_enabled = false;
+        self.divisor = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::RefCell, rc::Rc, vec::Vec};
+
+    /// An output that can still be inspected after it has been moved into the UART.
+    #[derive(Clone, Default)]
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn transmits_bytes_written_to_thr() {
+        let output = Output::default();
+        let mut uart = UartDevice::new(io::empty(), output.clone());
+        assert_eq!(uart.read(LSR) & (LSR_THRE | LSR_TEMT), LSR_THRE | LSR_TEMT);
+
+        uart.write(RBR_THR_DLL, b'h');
+        uart.write(RBR_THR_DLL, b'i');
+        assert_eq!(*output.0.borrow(), b"hi");
+
+        // With DLAB set, the same offset is the low divisor latch byte instead.
+        uart.write(LCR, LCR_DLAB);
+        uart.write(RBR_THR_DLL, 0x03);
+        assert_eq!(uart.read(RBR_THR_DLL), 0x03);
+        assert_eq!(*output.0.borrow(), b"hi");
+    }
+
+    #[test]
+    fn receives_bytes_from_the_input() {
+        let mut uart = UartDevice::new(&b"ok"[..], io::sink());
+        assert_eq!(uart.read(LSR) & LSR_DR, LSR_DR);
+        assert_eq!(uart.read(RBR_THR_DLL), b'o');
+        assert_eq!(uart.read(RBR_THR_DLL), b'k');
+        assert_eq!(uart.read(LSR) & LSR_DR, 0);
+    }
+}
@@ -0,0 +1,82 @@
+//! A writer for Value Change Dump (VCD) files, which record the program counter
+//! and the registers over time so they can be inspected in a waveform viewer like GTKWave.
+
+use std::{
+    format,
+    io::{self, Write},
+    vec::Vec,
+};
+
+use crate::{
+    reg::{Reg, Registers},
+    uxlen,
+};
+
+/// The identifier of the program counter signal. The registers follow it in order.
+const FIRST_IDENTIFIER: u8 = b'!';
+
+/// Writes the program counter and registers as VCD signals, one timestep per instruction.
+///
+/// Only the signals that changed since the previous timestep are written.
+pub struct VcdWriter<W: Write> {
+    writer: W,
+    /// The values written at the previous timestep: the program counter followed by the registers,
+    /// or `None` if nothing has been written yet.
+    previous: Option<[uxlen; 33]>,
+}
+
+impl<W: Write> VcdWriter<W> {
+    /// Writes the VCD header declaring the signals.
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writeln!(writer, "$version riscv-emu $end")?;
+        writeln!(writer, "$timescale 1ns $end")?;
+        writeln!(writer, "$scope module cpu $end")?;
+        writeln!(writer, "$var wire {} {} pc $end", uxlen::BITS, identifier(0))?;
+        for reg in Reg::ALL {
+            let name = format!("x{}_{}", reg.index(), reg.abi_name());
+            writeln!(
+                writer,
+                "$var wire {} {} {name} $end",
+                uxlen::BITS,
+                identifier(reg.index() + 1)
+            )?;
+        }
+        writeln!(writer, "$upscope $end")?;
+        writeln!(writer, "$enddefinitions $end")?;
+        Ok(Self { writer, previous: None })
+    }
+
+    /// Records the values of the program counter and registers at the given time.
+    pub fn record(&mut self, time: u64, pc: uxlen, regs: &Registers) -> io::Result<()> {
+        let mut values = [0; 33];
+        values[0] = pc;
+        for reg in Reg::ALL {
+            values[reg.index() + 1] = regs[reg];
+        }
+
+        let changed: Vec<usize> = match &self.previous {
+            Some(previous) => (0..values.len()).filter(|&ix| previous[ix] != values[ix]).collect(),
+            None => (0..values.len()).collect(),
+        };
+        if changed.is_empty() {
+            return Ok(());
+        }
+
+        writeln!(self.writer, "#{time}")?;
+        for ix in changed {
+            writeln!(self.writer, "b{:b} {}", values[ix], identifier(ix))?;
+        }
+        self.previous = Some(values);
+        Ok(())
+    }
+
+    /// Flushes the underlying writer.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// The short identifier code of the signal with the given index.
+fn identifier(ix: usize) -> char {
+    (FIRST_IDENTIFIER + ix as u8) as char
+}
@@ -0,0 +1,199 @@
+use std::{fs, path::Path};
+
+use anyhow::{Context, bail};
+use emu::{cpu::Cpu, reg::Reg, uxlen};
+
+use crate::parse_addr;
+
+/// A committed instruction in a golden trace.
+struct TraceStep {
+    /// The line in the trace file, starting at 1.
+    line: usize,
+    pc: uxlen,
+    /// The registers the instruction wrote and their new values.
+    writes: Vec<(Reg, uxlen)>,
+}
+
+/// Runs the CPU one committed instruction at a time and compares every instruction
+/// with the next step in the trace file, stopping at the first divergence.
+///
+/// Every line of the trace is the address of a committed instruction, optionally followed by
+/// its encoding in parentheses and the registers it wrote with their values, like
+/// `0x80000000 (0x00500293) x5 0x00000005`. Lines of Spike's commit log, which are prefixed
+/// with `core   0: 3`, are accepted as well. Empty lines and lines starting with `#` are skipped.
+pub fn run(cpu: &mut Cpu, path: &Path) -> anyhow::Result<()> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read trace file at '{}'", path.display()))?;
+    let steps = parse(&text)?;
+
+    // NOTE: The CPU also stops when the program runs off the end of the ROM, which only the outcome of a step tells.
+    let mut running = cpu.running();
+    for (ix, expected) in steps.iter().enumerate() {
+        let step = ix + 1;
+        let (pc, before) = loop {
+            if !running {
+                bail!(
+                    "Program stopped at step {step}, but the trace continues on line {}",
+                    expected.line
+                );
+            }
+
+            let (pc, before) = (cpu.pc(), *cpu.registers());
+            let outcome =
+                cpu.step().with_context(|| format!("Error in running CPU at step {step}"))?;
+            running = outcome.running;
+            // Instructions that trapped and interrupts are not committed, so they are not in the trace.
+            if outcome.instruction.is_some()
+                && outcome.trap.is_none()
+                && outcome.interrupt.is_none()
+            {
+                break (pc, before);
+            }
+        };
+
+        let mut diff = Vec::new();
+        if pc != expected.pc {
+            diff.push(format!("pc: expected {:#010x}, got {pc:#010x}", expected.pc));
+        }
+        for (reg, value) in &expected.writes {
+            let actual = cpu.registers()[*reg];
+            if actual != *value {
+                diff.push(format!("{reg}: expected {value:#010x}, got {actual:#010x}"));
+            }
+        }
+        for reg in Reg::ALL.into_iter().skip(1) {
+            let actual = cpu.registers()[reg];
+            if actual != before[reg] && !expected.writes.iter().any(|(written, _)| *written == reg)
+            {
+                diff.push(format!("{reg}: unexpected write of {actual:#010x}"));
+            }
+        }
+
+        if !diff.is_empty() {
+            bail!(
+                "Diverged from the trace at step {step} (line {}), instruction at {pc:#010x}:\n  {}",
+                expected.line,
+                diff.join("\n  ")
+            );
+        }
+    }
+
+    eprintln!("Matched all {} steps of the trace", steps.len());
+    Ok(())
+}
+
+/// Parses the steps of a trace file.
+fn parse(text: &str) -> anyhow::Result<Vec<TraceStep>> {
+    let mut steps = Vec::new();
+    for (ix, line) in text.lines().enumerate() {
+        let line_number = ix + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut tokens: Vec<&str> = line.split_whitespace().collect();
+        // Spike's commit log prefixes every line with the hart and the privilege mode.
+        if tokens.first() == Some(&"core") {
+            tokens.drain(..tokens.len().min(3));
+        }
+        tokens.retain(|token| !token.starts_with('('));
+
+        let Some((pc, writes)) = tokens.split_first() else {
+            bail!("Missing address on line {line_number}");
+        };
+        let pc =
+            parse_addr(pc).with_context(|| format!("Invalid address on line {line_number}"))?;
+        if writes.len() % 2 != 0 {
+            bail!("Register without a value on line {line_number}");
+        }
+        let writes = writes
+            .chunks(2)
+            .map(|pair| {
+                let reg = Reg::from_name(pair[0]).with_context(|| {
+                    format!("Unknown register '{}' on line {line_number}", pair[0])
+                })?;
+                let value = parse_addr(pair[1])
+                    .with_context(|| format!("Invalid value on line {line_number}"))?;
+                Ok((reg, value))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        steps.push(TraceStep { line: line_number, pc, writes });
+    }
+    Ok(steps)
+}
+
+#[cfg(test)]
+mod tests {
+    use emu::rom::Rom;
+
+    use super::*;
+
+    /// Runs a two-instruction program against the given trace.
+    fn check(trace: &str) -> anyhow::Result<()> {
+        let bytes: Vec<u8> = [
+            0x00500293u32, // addi x5, x0, 5
+            0x00128313,    // addi x6, x5, 1
+        ]
+        .iter()
+        .flat_map(|word| word.to_le_bytes())
+        .collect();
+        let mut cpu = Cpu::builder(Rom::from_vec(bytes, 0x8000_0000, 0x8000_0008)).build()?;
+
+        let path = std::env::temp_dir().join(format!(
+            "check-trace-{}-{}.log",
+            std::process::id(),
+            trace.len()
+        ));
+        fs::write(&path, trace)?;
+        let result = run(&mut cpu, &path);
+        fs::remove_file(&path)?;
+        result
+    }
+
+    #[test]
+    fn parses_both_trace_formats() {
+        let steps = parse(
+            "# A comment\n\
+             0x80000000 (0x00500293) x5 0x00000005\n\
+             \n\
+             core   0: 3 0x80000004 (0x00128313) x6  0x00000006\n\
+             0x80000008\n",
+        )
+        .unwrap();
+        assert_eq!(steps.len(), 3);
+        assert_eq!(
+            (steps[0].line, steps[0].pc, steps[0].writes.clone()),
+            (2, 0x8000_0000, vec![(Reg::X5, 5)])
+        );
+        assert_eq!(
+            (steps[1].line, steps[1].pc, steps[1].writes.clone()),
+            (4, 0x8000_0004, vec![(Reg::X6, 6)])
+        );
+        assert!(steps[2].writes.is_empty());
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        let err = |text| parse(text).err().map(|err| err.to_string());
+        assert_eq!(err("0x80000000 x5"), Some("Register without a value on line 1".to_string()));
+        assert_eq!(err("0x80000000 q5 1"), Some("Unknown register 'q5' on line 1".to_string()));
+        assert_eq!(err("\nzz"), Some("Invalid address on line 2".to_string()));
+    }
+
+    #[test]
+    fn stops_at_the_first_divergence() {
+        check("0x80000000 x5 5\n0x80000004 x6 6\n").unwrap();
+
+        let err = check("0x80000000 x5 5\n0x80000004 x6 7\n").unwrap_err().to_string();
+        assert!(err.contains("step 2 (line 2)"), "{err}");
+        assert!(err.contains("t1: expected 0x00000007, got 0x00000006"), "{err}");
+
+        let err = check("0x80000000\n").unwrap_err().to_string();
+        assert!(err.contains("t0: unexpected write of 0x00000005"), "{err}");
+
+        let err = check("0x80000000 x5 5\n0x80000004 x6 6\n0x80000008\n").unwrap_err().to_string();
+        assert!(err.contains("stopped at step 3"), "{err}");
+    }
+}
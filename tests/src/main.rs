@@ -1,13 +1,39 @@
 use std::{
+    cell::RefCell,
+    collections::{BTreeMap, VecDeque},
     fs,
+    io::BufWriter,
+    ops::Range,
     os::unix::ffi::OsStrExt,
     path::{Path, PathBuf},
 };
 
 use anyhow::Context;
-use clap::Parser;
-use emu::{cpu::Cpu, rom::Rom, uxlen};
-use goblin::elf::Sym;
+use clap::{Parser, ValueEnum};
+use emu::{
+    compressed::CInstruction,
+    cpu::{BranchStats, CodeWritePolicy, Cpu, CpuBuilder, HaltReason, HistoryEntry},
+    cycles::CycleCosts,
+    device::Ram,
+    disasm::disassemble,
+    firmware::Firmware,
+    image::MemoryImage,
+    inst::InstructionKind,
+    linux::{AT_ENTRY, AT_PHDR, AT_PHENT, AT_PHNUM, InitialStack},
+    reg::Reg,
+    rom::Rom,
+    symbols::SymbolMap,
+    uart::{UART_SIZE, UartDevice},
+    uxlen,
+    vcd::VcdWriter,
+};
+use goblin::elf::program_header::PT_LOAD;
+use status::{RiscvTests, StatusConvention, TestStatus};
+
+mod check_trace;
+mod repl;
+mod status;
+mod trace_json;
 
 /// A RISC-V emulator.
 #[derive(Debug, Parser)]
@@ -20,19 +46,211 @@ struct Args {
     /// Prints information about the current instruction for each cycle.
     #[arg(short, long)]
     verbose: bool,
+
+    /// Prints every retired instruction in the format of Spike's instruction log.
+    #[arg(long)]
+    trace_spike: bool,
+
+    /// Prints a disassembly listing of the program instead of running it.
+    #[arg(long)]
+    disasm: bool,
+
+    /// Decodes every instruction in the executable part of the program instead of running it,
+    /// and reports the ones that are not supported.
+    #[arg(long, conflicts_with = "disasm")]
+    validate: bool,
+
+    /// Runs the program in the given file instead of the riscv-tests.
+    #[arg(long)]
+    raw: Option<PathBuf>,
+
+    /// Runs the given instruction words instead of the riscv-tests, like `0x00100093,0x00200113`,
+    /// loaded at the base address like a raw flat binary, and prints the registers afterwards.
+    #[arg(long, value_delimiter = ',', value_parser = parse_word, value_name = "WORDS", conflicts_with = "raw")]
+    exec_hex: Vec<u32>,
+
+    /// The format of the program given with `--raw`.
+    #[arg(long, value_enum, default_value_t = Format::Bin)]
+    format: Format,
+
+    /// The address at which the raw flat binary is loaded.
+    #[arg(long, value_parser = parse_addr, default_value = "0x80000000")]
+    base: uxlen,
+
+    /// The symbol or address of the first instruction to execute, to run a single function for example.
+    /// Defaults to the entry point in the file, or the start of the program if it has none.
+    #[arg(long, value_name = "SYMBOL")]
+    entry: Option<String>,
+
+    /// Handles the newlib `read`, `write`, `exit` and `brk` system calls of the raw binary on the host.
+    #[arg(long)]
+    semihosting: bool,
+
+    /// Stops with an error after executing this many instructions, so a program that hangs fails.
+    #[arg(long)]
+    max_instructions: Option<u64>,
+
+    /// Guards the stack, which takes up this many bytes below the initial stack pointer,
+    /// so a stack overflowing into the code or data below it stops with an error.
+    #[arg(long, value_parser = parse_addr, value_name = "BYTES")]
+    stack_size: Option<uxlen>,
+
+    /// Prints how many times each kind of instruction was executed when the program stops.
+    #[arg(long)]
+    stats: bool,
+
+    /// Approximates the runtime of the program with the default cost of each kind of instruction,
+    /// like 2 cycles for loads and 30 for divisions, and prints the number of cycles when it stops.
+    #[arg(long)]
+    cycles: bool,
+
+    /// Prints the last N executed instructions and the registers they changed when the program
+    /// stops with an error or is aborted.
+    #[arg(long, value_name = "N")]
+    history: Option<usize>,
+
+    /// Writes the program counter and registers after every instruction to a VCD file.
+    #[arg(long)]
+    vcd: Option<PathBuf>,
+
+    /// Writes every committed instruction to a file as JSON lines, with the program counter,
+    /// the raw encoding, the mnemonic and the register it changed with the old and new value.
+    #[arg(long, value_name = "FILE")]
+    trace_json: Option<PathBuf>,
+
+    /// Writes the memory of the program to a file when it stops.
+    #[arg(long)]
+    dump_memory: Option<PathBuf>,
+
+    /// The address range written with `--dump-memory`, as `start:len`. Defaults to the whole program memory.
+    #[arg(long, value_parser = parse_range, requires = "dump_memory")]
+    dump_range: Option<(uxlen, usize)>,
+
+    /// Runs the program until it reaches the given symbol or address, then prints the registers.
+    #[arg(long, value_name = "SYMBOL", conflicts_with_all = ["gdb", "interactive", "check_trace"])]
+    run_until: Option<String>,
+
+    /// Compares every committed instruction with a golden trace, like Spike's commit log,
+    /// and stops at the first divergence.
+    #[arg(long, conflicts_with_all = ["gdb", "interactive"])]
+    check_trace: Option<PathBuf>,
+
+    /// Starts the emulator halted and reads debugger commands from stdin, like `step`, `regs` and `break`.
+    #[arg(long, conflicts_with = "gdb")]
+    interactive: bool,
+
+    /// Sets the initial value of a register, as `name=value`, like `a0=0` or `x11=0x87e00000`.
+    /// Can be given multiple times. By default every register starts at zero, except `sp`.
+    #[arg(long = "reg", value_parser = parse_register, value_name = "NAME=VALUE")]
+    registers: Vec<(Reg, uxlen)>,
+
+    /// Maps a 16550-compatible UART at the given address, which is usually 0x10000000,
+    /// connected to the host's stdin and stdout.
+    #[arg(long, value_parser = parse_addr, value_name = "ADDR")]
+    uart: Option<uxlen>,
+
+    /// Runs the program on the RV32E base ISA, which only has the registers x0 to x15.
+    #[arg(long)]
+    rv32e: bool,
+
+    /// Executes unknown instructions as a NOP, instead of stopping with an illegal-instruction error.
+    #[arg(long)]
+    lenient_decode: bool,
+
+    /// Reports stores into memory that has already been executed as an instruction,
+    /// which usually means a wild pointer or a stack overflowing into the code.
+    #[arg(long, value_name = "ACTION")]
+    code_writes: Option<CodeWrites>,
+
+    /// Starts the emulator halted and waits for GDB to connect on the given port.
+    #[arg(long)]
+    gdb: Option<u16>,
+
+    /// Runs the program given with `--raw` as a Linux user-mode process, starting with a stack
+    /// that holds its arguments, environment and auxiliary vector, and handling its system calls on the host.
+    /// The emulator exits with the exit code of the program.
+    #[arg(long, requires = "raw")]
+    linux: bool,
+
+    /// Sets an environment variable of the Linux user-mode process, as `KEY=VALUE`.
+    /// Can be given multiple times.
+    #[arg(long, value_name = "KEY=VALUE", requires = "linux")]
+    env: Vec<String>,
+
+    /// The arguments passed to the Linux user-mode process after its name, given after `--`.
+    #[arg(last = true, requires = "linux")]
+    program_args: Vec<String>,
+}
+
+/// The exclusive top of the stack of a Linux user-mode process.
+const LINUX_STACK_TOP: uxlen = 0xC000_0000;
+/// The size of the stack of a Linux user-mode process, which is Linux's default limit.
+const LINUX_STACK_SIZE: uxlen = 8 * 1024 * 1024;
+/// The size of the memory the heap of a Linux user-mode process can grow into.
+const LINUX_HEAP_SIZE: uxlen = 64 * 1024 * 1024;
+
+/// The format of a program file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    /// A raw flat binary.
+    Bin,
+    /// An Intel HEX file.
+    Hex,
+    /// A Motorola S-record file.
+    Srec,
+    /// An ELF executable.
+    Elf,
+}
+
+/// What to do when the program stores into memory that has already been executed as an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum CodeWrites {
+    /// Prints a warning and continues.
+    Warn,
+    /// Raises a store access fault.
+    Fault,
+}
+
+impl From<CodeWrites> for CodeWritePolicy {
+    fn from(action: CodeWrites) -> Self {
+        match action {
+            CodeWrites::Warn => CodeWritePolicy::Warn,
+            CodeWrites::Fault => CodeWritePolicy::Fault,
+        }
+    }
+}
+
+impl std::fmt::Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Bin => f.write_str("raw binary"),
+            Self::Hex => f.write_str("Intel HEX file"),
+            Self::Srec => f.write_str("S-record file"),
+            Self::Elf => f.write_str("ELF file"),
+        }
+    }
 }
 
 fn main() -> anyhow::Result<()> {
     // Get the arguments from the command line.
     let args = Args::parse();
 
+    if let Some(path) = &args.raw {
+        return run_raw(path, &args)
+            .with_context(|| format!("Failed to run program at '{}'", path.display()));
+    }
+    if !args.exec_hex.is_empty() {
+        return run_exec_hex(&args.exec_hex, &args)
+            .context("Failed to run the instructions given with --exec-hex");
+    }
+
     let riscv_tests_path = Path::new("riscv-tests").join("isa");
 
-    match args.test_name {
+    match &args.test_name {
         Some(test_name) => {
             let file_name = format!("rv32ui-p-{test_name}");
             let path = &riscv_tests_path.join(file_name);
-            run_test(path, args.verbose)
+            run_test(path, &args, RiscvTests)
                 .with_context(|| format!("Failed to run test at '{}'", path.display()))?;
         }
         None => {
@@ -48,7 +266,7 @@ fn main() -> anyhow::Result<()> {
                 .map(|entry| entry.path());
 
             for path in test_paths {
-                run_test(&path, args.verbose)
+                run_test(&path, &args, RiscvTests)
                     .with_context(|| format!("Failed to run test at '{}'", path.display()))?;
             }
         }
@@ -57,52 +275,839 @@ fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
-fn run_test(path: &PathBuf, verbose: bool) -> anyhow::Result<()> {
+/// Runs a test program, which reports its result through an ECALL following the given convention.
+fn run_test(
+    path: &PathBuf,
+    args: &Args,
+    convention: impl StatusConvention + 'static,
+) -> anyhow::Result<()> {
     eprintln!("Running test at '{}'...", path.display());
     // Get the binary data from the provided file.
-    let mut bytes = fs::read(&path).context("Could not read file.")?;
-
-    // Prepare to read some symbols from the ELF file.
-    let elf = goblin::elf::Elf::parse(&bytes).context("Failed to parse ELF file")?;
-    let symbols = &elf.syms;
-    let strtab = &elf.strtab;
-    let get_symbol_value = |name: &str| -> anyhow::Result<Sym> {
-        symbols
-            .iter()
-            .find(|sym| strtab.get_at(sym.st_name).is_some_and(|n| n == name))
-            .with_context(|| format!("Could not find symbol '{name}' in ELF file"))
+    let bytes = fs::read(path).context("Could not read file.")?;
+
+    let Program { image, segments, entry, tohost, symbols, code, .. } = load_elf(&bytes)?;
+    // The `tohost` symbol or section is the address of the HTIF `tohost` register.
+    let tohost =
+        tohost.context("Could not find symbol 'tohost' or section '.tohost' in ELF file")?;
+
+    // Create a ROM from the loaded memory image.
+    let rom = image.into_rom().context("Program does not fit in the address space")?;
+
+    if args.disasm {
+        print_disassembly(&rom, &symbols);
+        return Ok(());
+    }
+    if args.validate {
+        return validate(&rom, &code, &symbols);
+    }
+
+    // Create and run the CPU cycle loop.
+    // NOTE: The riscv-tests run without a stack, so the default stack pointer at the end of the image suffices.
+    let entry = match &args.entry {
+        Some(target) => resolve_addr(target, &symbols)?,
+        None => entry,
     };
+    let mut cpu = map_segments(cpu_builder(rom, args, &symbols)?, segments)?
+        .entry(entry)
+        .tohost(tohost)
+        .on_ecall(Box::new(move |cpu| {
+            let Some(status) = convention.decode(cpu.registers()) else { return };
+            cpu.exit(match status {
+                TestStatus::Passed => 0,
+                TestStatus::Failed(test_num) => test_num,
+            });
+        }))
+        .build()?;
+
+    match run_cpu(&mut cpu, args, &symbols)? {
+        Some(HaltReason::Exited(exit_code)) => report_exit_code(exit_code),
+        Some(reason) => anyhow::bail!("Test {reason} without reporting a result"),
+        // A debugger or the trace checker ran the program, which may or may not have finished.
+        None => {
+            if let Some(exit_code) = cpu.exit_code() {
+                report_exit_code(exit_code);
+            }
+        }
+    }
 
-    // The `_start` symbol is the start address of the ELF file.
-    let start = get_symbol_value("_start")?.st_value as usize;
+    Ok(())
+}
 
-    // The `_end` symbol is the end address of the ELF file.
-    let end = get_symbol_value("_end")?.st_value as usize;
+fn run_raw(path: &Path, args: &Args) -> anyhow::Result<()> {
+    eprintln!("Running {} at '{}'...", args.format, path.display());
+    // Get the binary data from the provided file.
+    let bytes = fs::read(path).context("Could not read file.")?;
 
-    // The `_tohost` symbol is the start address of the program that should be run.
-    let tohost = get_symbol_value("tohost")?.st_value as usize;
+    let program = match args.format {
+        Format::Bin => flat_program(args.base, &bytes)?,
+        Format::Hex | Format::Srec => {
+            let text = std::str::from_utf8(&bytes).context("File is not a text file")?;
+            let firmware = match args.format {
+                Format::Hex => Firmware::parse_ihex(text),
+                _ => Firmware::parse_srec(text),
+            }
+            .context("Failed to parse firmware file")?;
+            let images = firmware.to_images().context("Failed to load firmware file")?;
+            anyhow::ensure!(!images.is_empty(), "Firmware file contains no data");
+            let entry = firmware.entry.unwrap_or(images[0].start_addr());
+            Program::from_images(images, entry, None, BTreeMap::new())
+        }
+        Format::Elf => load_elf(&bytes)?,
+    };
+    run_program(program, path, args)
+}
 
-    // Create a ROM from the data in the ELF file.
-    let rom = Rom::new(&mut bytes[(tohost - start)..(end - start)], start as uxlen, end as uxlen);
+/// Runs the instruction words given with `--exec-hex`, which are loaded like a raw flat binary.
+fn run_exec_hex(words: &[u32], args: &Args) -> anyhow::Result<()> {
+    eprintln!("Running {} instructions at {:#010x}...", words.len(), args.base);
+    let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+    run_program(flat_program(args.base, &bytes)?, Path::new("exec-hex"), args)
+}
+
+/// Runs a program given with `--raw` or `--exec-hex`.
+/// A Linux user-mode process gets `path` as the first of its arguments.
+fn run_program(program: Program, path: &Path, args: &Args) -> anyhow::Result<()> {
+    let Program { image, segments, entry, tohost, symbols, code, auxv } = program;
+    let rom = image.into_rom().context("Program does not fit in the address space")?;
+
+    if args.disasm {
+        print_disassembly(&rom, &symbols);
+        return Ok(());
+    }
+    if args.validate {
+        return validate(&rom, &code, &symbols);
+    }
 
     // Create and run the CPU cycle loop.
-    Cpu::new(&rom, verbose)
-        .on_ecall(Box::new(|cpu| {
-            // a7 is the syscall register used, 0x5D indicates test status syscall.
-            if cpu.registers().a7() == 0x5D {
-                // a0 indicates the test status.
-                let status = cpu.registers().a0();
-                if status == 0 {
-                    eprintln!("Test Passed!");
-                } else {
-                    let failed_test_num = (status - 1) / 2;
-                    eprintln!("Test {} Failed!", failed_test_num);
-                    cpu.abort();
-                }
+    let entry = match &args.entry {
+        Some(target) => resolve_addr(target, &symbols)?,
+        None => entry,
+    };
+    // The heap starts past the end of the program, including the segments that are mapped as RAM.
+    let program_end = segments
+        .iter()
+        .map(|segment| segment.start_addr() as u64 + segment.size() as u64)
+        .fold(rom.end_addr() as u64, u64::max);
+    let mut builder = map_segments(cpu_builder(rom, args, &symbols)?, segments)?
+        .entry(entry)
+        .semihosting(args.semihosting);
+    if args.linux {
+        let mut stack = InitialStack::new().arg(path.as_os_str().as_bytes());
+        for arg in &args.program_args {
+            stack = stack.arg(arg.as_str());
+        }
+        for var in &args.env {
+            stack = stack.env(var.as_str());
+        }
+        for (kind, value) in auxv {
+            stack = stack.aux(kind, value);
+        }
+        // The heap grows from the end of the program, as moved with `brk`.
+        let heap = linux_heap(program_end, args.uart)?;
+        builder = builder
+            .ram(LINUX_STACK_TOP - LINUX_STACK_SIZE, LINUX_STACK_SIZE)
+            .heap_start(heap.start)
+            .device(heap, Ram::new(LINUX_HEAP_SIZE as usize))
+            .initial_stack(stack.aux(AT_ENTRY, entry))
+            .linux_syscalls(true);
+    }
+    if let Some(tohost) = tohost {
+        builder = builder.tohost(tohost);
+    }
+    let mut cpu = builder.build()?;
+    run_cpu(&mut cpu, args, &symbols)?;
+
+    // Like a process on the host, the emulator exits with the exit code of the Linux user-mode process.
+    if args.linux
+        && let Some(exit_code) = cpu.exit_code()
+    {
+        std::process::exit(exit_code as i32);
+    }
+    // The instructions given on the command line have no output of their own, so show what they did.
+    if !args.exec_hex.is_empty() {
+        eprintln!("{}", cpu.registers().dump());
+    }
+
+    Ok(())
+}
+
+/// The address range of the heap of a Linux user-mode program that ends at `program_end`.
+/// Fails if it does not fit in the address space, or overlaps the stack or the UART at `uart`.
+fn linux_heap(program_end: u64, uart: Option<uxlen>) -> anyhow::Result<Range<uxlen>> {
+    let heap = uxlen::try_from(program_end)
+        .ok()
+        .and_then(|start| Some(start..start.checked_add(LINUX_HEAP_SIZE)?))
+        .context("The heap does not fit in the address space after the program")?;
+    let overlaps = |other: Range<uxlen>| heap.start < other.end && other.start < heap.end;
+
+    anyhow::ensure!(
+        !overlaps(LINUX_STACK_TOP - LINUX_STACK_SIZE..LINUX_STACK_TOP),
+        "The heap at {:#010x} overlaps the stack",
+        heap.start
+    );
+    if let Some(base) = uart {
+        anyhow::ensure!(
+            !overlaps(base..base.saturating_add(UART_SIZE)),
+            "The heap at {:#010x} overlaps the UART at {base:#010x}",
+            heap.start
+        );
+    }
+    Ok(heap)
+}
+
+/// Creates a program from a raw flat binary, which is loaded at the base address and starts there.
+fn flat_program(base: uxlen, bytes: &[u8]) -> anyhow::Result<Program> {
+    let mut image = MemoryImage::new(base, bytes.len());
+    image.load_segment(base, bytes, bytes.len()).context("Failed to load binary")?;
+    Ok(Program {
+        image,
+        segments: vec![],
+        entry: base,
+        tohost: None,
+        symbols: BTreeMap::new(),
+        code: vec![],
+        auxv: vec![],
+    })
+}
+
+/// A program loaded into memory.
+struct Program {
+    image: MemoryImage,
+    /// Further memory images at other addresses, which are mapped as RAM.
+    segments: Vec<MemoryImage>,
+    /// The address of the first instruction.
+    entry: uxlen,
+    /// The address of the HTIF `tohost` register, if the program has one.
+    tohost: Option<uxlen>,
+    /// The addresses of the named symbols in the program, if it has a symbol table.
+    symbols: BTreeMap<String, uxlen>,
+    /// The address ranges that contain instructions, as opposed to data.
+    /// Empty if it is not known which parts of the program are code.
+    code: Vec<Range<uxlen>>,
+    /// The auxiliary vector entries that describe the program to a Linux user-mode process.
+    auxv: Vec<(uxlen, uxlen)>,
+}
+
+impl Program {
+    /// Creates a program from one or more memory images. The image containing
+    /// the entry point becomes the ROM, and the others are mapped as RAM.
+    fn from_images(
+        mut images: Vec<MemoryImage>,
+        entry: uxlen,
+        tohost: Option<uxlen>,
+        symbols: BTreeMap<String, uxlen>,
+    ) -> Self {
+        let contains_entry = |image: &MemoryImage| {
+            entry
+                .checked_sub(image.start_addr())
+                .is_some_and(|offset| (offset as usize) < image.size())
+        };
+        let image = images.remove(images.iter().position(contains_entry).unwrap_or(0));
+        Program { image, segments: images, entry, tohost, symbols, code: vec![], auxv: vec![] }
+    }
+}
+
+/// Loads all loadable segments of an ELF file into a memory image.
+fn load_elf(bytes: &[u8]) -> anyhow::Result<Program> {
+    let elf = goblin::elf::Elf::parse(bytes).context("Failed to parse ELF file")?;
+
+    let symbols: BTreeMap<String, uxlen> = elf
+        .syms
+        .iter()
+        .filter_map(|sym| {
+            let name = elf.strtab.get_at(sym.st_name).filter(|name| !name.is_empty())?;
+            Some((name.to_string(), sym.st_value as uxlen))
+        })
+        .collect();
+    // The `tohost` symbol is the address of the HTIF `tohost` register.
+    // NOTE: Some builds of the riscv-tests strip it, but keep the `.tohost` section it is the start of.
+    let tohost = symbols.get("tohost").copied().or_else(|| {
+        elf.section_headers
+            .iter()
+            .find(|sh| elf.shdr_strtab.get_at(sh.sh_name) == Some(".tohost"))
+            .map(|sh| sh.sh_addr as uxlen)
+    });
+
+    // Load every loadable segment into a memory image of its own, so the gaps between them take no memory.
+    // Memory that is not backed by the file, like `.bss`, is zeroed.
+    let mut images = vec![];
+    for ph in elf.program_headers.iter().filter(|ph| ph.p_type == PT_LOAD && ph.p_memsz > 0) {
+        let data =
+            bytes.get(ph.file_range()).context("Segment is out of bounds of the ELF file")?;
+        let mut image = MemoryImage::new(ph.p_vaddr as uxlen, ph.p_memsz as usize);
+        image
+            .load_segment(ph.p_vaddr as uxlen, data, ph.p_memsz as usize)
+            .context("Failed to load segment")?;
+        images.push(image);
+    }
+    anyhow::ensure!(!images.is_empty(), "ELF file has no loadable segments");
+
+    // Segments that overlap would silently shadow each other once they are mapped.
+    images.sort_by_key(MemoryImage::start_addr);
+    let end = |image: &MemoryImage| image.start_addr() as u64 + image.size() as u64;
+    if let Some(pair) = images.windows(2).find(|pair| end(&pair[0]) > pair[1].start_addr() as u64) {
+        anyhow::bail!(
+            "Loadable segments at {:#010x} and {:#010x} overlap",
+            pair[0].start_addr(),
+            pair[1].start_addr()
+        );
+    }
+
+    // Only executable segments contain instructions.
+    let code = elf
+        .program_headers
+        .iter()
+        .filter(|ph| ph.p_type == PT_LOAD && ph.is_executable())
+        .map(|ph| ph.p_vaddr as uxlen..(ph.p_vaddr + ph.p_filesz) as uxlen)
+        .collect();
+
+    // The program headers are only visible to the program if a loadable segment contains them.
+    let phoff = elf.header.e_phoff;
+    let mut auxv = vec![];
+    if let Some(ph) = elf
+        .program_headers
+        .iter()
+        .filter(|ph| ph.p_type == PT_LOAD)
+        .find(|ph| (ph.p_offset..ph.p_offset + ph.p_filesz).contains(&phoff))
+    {
+        auxv.extend([
+            (AT_PHDR, (ph.p_vaddr + phoff - ph.p_offset) as uxlen),
+            (AT_PHENT, elf.header.e_phentsize as uxlen),
+            (AT_PHNUM, elf.header.e_phnum as uxlen),
+        ]);
+    }
+
+    Ok(Program { code, auxv, ..Program::from_images(images, elf.entry as uxlen, tohost, symbols) })
+}
+
+/// Maps each of the `segments` of a program as RAM at its start address.
+fn map_segments<'rom>(
+    mut builder: CpuBuilder<'rom>,
+    segments: Vec<MemoryImage>,
+) -> anyhow::Result<CpuBuilder<'rom>> {
+    for segment in segments {
+        let start = segment.start_addr();
+        let end = uxlen::try_from(segment.size())
+            .ok()
+            .and_then(|size| start.checked_add(size))
+            .with_context(|| {
+                format!("Segment at {start:#010x} does not fit in the address space")
+            })?;
+        builder = builder.device(start..end, segment.into_ram());
+    }
+    Ok(builder)
+}
+
+/// Creates a [CpuBuilder] configured with the options shared by all kinds of programs.
+fn cpu_builder(
+    rom: Rom<'static>,
+    args: &Args,
+    symbols: &BTreeMap<String, uxlen>,
+) -> anyhow::Result<CpuBuilder<'static>> {
+    let mut builder = Cpu::builder(rom)
+        .verbose(args.verbose)
+        .trace_spike(args.trace_spike)
+        .instruction_stats(args.stats)
+        .rv32e(args.rv32e)
+        .strict_decode(!args.lenient_decode);
+    if !symbols.is_empty() {
+        builder = builder.symbols(symbols.iter().map(|(name, addr)| (name, *addr)).collect());
+    }
+    if args.cycles {
+        builder = builder.cycle_costs(CycleCosts::default());
+    }
+    if let Some(capacity) = args.history {
+        builder = builder.history(capacity);
+    }
+    if let Some(action) = args.code_writes {
+        builder = builder.code_writes(action.into());
+    }
+    for (reg, value) in &args.registers {
+        builder = builder.register(*reg, *value);
+    }
+    if let Some(base) = args.uart {
+        builder = builder.uart(base, UartDevice::stdio());
+    }
+    if let Some(limit) = args.max_instructions {
+        builder = builder.max_instructions(limit);
+    }
+    if let Some(size) = args.stack_size {
+        builder = builder.stack_size(size);
+    }
+    if let Some(path) = &args.vcd {
+        let file = fs::File::create(path)
+            .with_context(|| format!("Failed to create VCD file at '{}'", path.display()))?;
+        let vcd = VcdWriter::new(BufWriter::new(file)).context("Failed to write VCD file")?;
+
+        // NOTE: The step callback can not return errors, so the writer is dropped
+        //       after reporting the first one, instead of reporting it for every step.
+        let vcd = RefCell::new(Some(vcd));
+        builder = builder.on_step(Box::new(move |cpu, _| {
+            let mut vcd = vcd.borrow_mut();
+            if let Some(writer) = vcd.as_mut()
+                && let Err(err) = writer.record(cpu.instret(), cpu.pc(), cpu.registers())
+            {
+                eprintln!("Failed to write VCD file: {err}");
+                *vcd = None;
             }
-        }))
-        .run()
-        .context("Error in running CPU")?;
+        }));
+    }
+    if let Some(path) = &args.trace_json {
+        builder = builder.on_commit(Box::new(trace_json::writer(path)?));
+    }
+    Ok(builder)
+}
+
+/// Runs the CPU cycle loop, either to the end or to the `--run-until` target,
+/// or hands control to GDB, the interactive debugger or the trace checker if requested.
+///
+/// Returns why the cycle loop stopped, if it ran to the end. Reaching the instruction limit is an error.
+fn run_cpu(
+    cpu: &mut Cpu,
+    args: &Args,
+    symbols: &BTreeMap<String, uxlen>,
+) -> anyhow::Result<Option<HaltReason>> {
+    let result = if let Some(port) = args.gdb {
+        emu::gdb::serve(cpu, port).context("Error in GDB server").map(|()| None)
+    } else if args.interactive {
+        repl::run(cpu).map(|()| None)
+    } else if let Some(path) = &args.check_trace {
+        check_trace::run(cpu, path).map(|()| None)
+    } else if let Some(target) = &args.run_until {
+        run_until(cpu, target, symbols).map(|()| None)
+    } else {
+        match cpu.run().context("Error in running CPU") {
+            Ok(HaltReason::InstructionLimit(pc)) => Err(anyhow::anyhow!(
+                "Instruction limit reached with the program counter at {pc:#010x}"
+            )),
+            result => result.map(Some),
+        }
+    };
+
+    if let Some(counts) = cpu.instruction_counts() {
+        print_instruction_counts(counts);
+    }
+    if let Some(stats) = cpu.branch_stats() {
+        print_branch_stats(stats);
+    }
+    if args.cycles {
+        let (instret, cycles) = (cpu.instret(), cpu.cycles());
+        let cpi = if instret == 0 { 0.0 } else { cycles as f64 / instret as f64 };
+        eprintln!(
+            "Retired {instret} instructions in {cycles} cycles ({cpi:.2} cycles per instruction)"
+        );
+    }
+
+    if let Some(history) = cpu.history()
+        && (result.is_err() || !cpu.running())
+    {
+        print_history(history);
+    }
+
+    if let Some(path) = &args.dump_memory {
+        dump_memory(cpu, path, args.dump_range)?;
+    }
+
+    result
+}
+
+/// Runs the CPU until it reaches the given symbol or address and prints the registers there.
+fn run_until(cpu: &mut Cpu, target: &str, symbols: &BTreeMap<String, uxlen>) -> anyhow::Result<()> {
+    let addr = resolve_addr(target, symbols)?;
+    if !cpu.run_until(addr).context("Error in running CPU")? {
+        anyhow::bail!("Program stopped at {:#010x} before reaching {addr:#010x}", cpu.pc());
+    }
+    eprintln!("Reached '{target}' at {addr:#010x} after {} instructions", cpu.instret());
+    eprintln!("{}", cpu.registers().dump());
+    Ok(())
+}
+
+/// Resolves a symbol in the program, or an address if there is no symbol with that name.
+fn resolve_addr(target: &str, symbols: &BTreeMap<String, uxlen>) -> anyhow::Result<uxlen> {
+    match symbols.get(target) {
+        Some(addr) => Ok(*addr),
+        None => parse_addr(target)
+            .ok()
+            .with_context(|| format!("Could not find symbol '{target}' in the program")),
+    }
+}
+
+/// Writes the memory in the given range, or the whole ROM, to a file.
+fn dump_memory(cpu: &mut Cpu, path: &Path, range: Option<(uxlen, usize)>) -> anyhow::Result<()> {
+    let (start, len) = range.unwrap_or_else(|| {
+        let rom = cpu.rom();
+        (rom.start_addr(), (rom.end_addr() - rom.start_addr()) as usize)
+    });
+    let bytes = cpu.read_mem(start, len).context("Failed to read memory to dump")?;
+    fs::write(path, bytes)
+        .with_context(|| format!("Failed to write memory dump to '{}'", path.display()))
+}
+
+/// Prints the executed instruction counts, the most executed instructions first.
+fn print_instruction_counts(counts: &BTreeMap<InstructionKind, u64>) {
+    let mut counts: Vec<_> = counts.iter().collect();
+    counts.sort_by(|(_, a), (_, b)| b.cmp(a));
 
+    let total: u64 = counts.iter().map(|(_, count)| **count).sum();
+    eprintln!("Executed {total} instructions:");
+    for (kind, count) in counts {
+        let percentage = *count as f64 / total as f64 * 100.0;
+        eprintln!("{:<12} {count:>12} {percentage:>6.2}%", format!("{kind:?}"));
+    }
+}
+
+/// Prints how often the conditional branches were taken, in total and for each branch by address.
+fn print_branch_stats(stats: &BTreeMap<uxlen, BranchStats>) {
+    let taken: u64 = stats.values().map(|stats| stats.taken).sum();
+    let not_taken: u64 = stats.values().map(|stats| stats.not_taken).sum();
+    let total = taken + not_taken;
+    if total == 0 {
+        return;
+    }
+
+    eprintln!(
+        "Executed {total} conditional branches, {taken} taken ({:.2}%), {not_taken} not taken:",
+        taken as f64 / total as f64 * 100.0
+    );
+    for (addr, stats) in stats {
+        eprintln!("{addr:#010x} {:>12} taken {:>12} not taken", stats.taken, stats.not_taken);
+    }
+}
+
+/// Prints the last executed instructions, the oldest first.
+fn print_history(history: &VecDeque<HistoryEntry>) {
+    eprintln!("Last {} executed instructions:", history.len());
+    for entry in history {
+        let raw = match entry.raw {
+            raw if raw <= 0xffff && raw & 0b11 != 0b11 => format!("{raw:04x}    "),
+            raw => format!("{raw:08x}"),
+        };
+        match entry.rd {
+            Some(rd) => eprintln!(
+                "{:#010x}: {raw}  {:<4} {:#010x} -> {:#010x}",
+                entry.pc,
+                rd.abi_name(),
+                entry.old,
+                entry.new
+            ),
+            None => eprintln!("{:#010x}: {raw}", entry.pc),
+        }
+    }
+}
+
+/// Prints an objdump-like listing of every instruction in the ROM, with a label at every symbol.
+fn print_disassembly(rom: &Rom, symbols: &BTreeMap<String, uxlen>) {
+    let symbols: SymbolMap = symbols.iter().map(|(name, addr)| (name, *addr)).collect();
+    for (addr, inst, text) in disassemble(rom) {
+        if let Some(name) = symbols.name_at(addr) {
+            println!("\n{addr:08x} <{name}>:");
+        }
+        println!("{addr:8x}:\t{:08x}\t{text}", inst.0);
+    }
+}
+
+/// Decodes every instruction in the `code` ranges of the program, or in the whole program if there are none,
+/// without executing anything, and fails if any of them is unknown to the decoder.
+fn validate(
+    rom: &Rom,
+    code: &[Range<uxlen>],
+    symbols: &BTreeMap<String, uxlen>,
+) -> anyhow::Result<()> {
+    let symbols: SymbolMap = symbols.iter().map(|(name, addr)| (name, *addr)).collect();
+    let mut total = 0;
+    let mut unknown = 0;
+    let whole = rom.start_addr()..rom.end_addr();
+    let code = if code.is_empty() { std::slice::from_ref(&whole) } else { code };
+    for (addr, inst) in code.iter().flat_map(|range| rom.instructions_in(range.clone())) {
+        total += 1;
+        if inst.kind() != InstructionKind::Unknown {
+            continue;
+        }
+
+        unknown += 1;
+        // Illegal compressed instructions expand to a zeroed instruction, so show the encoding from memory.
+        let half = rom.read_u16(addr).unwrap_or_default();
+        let raw = match CInstruction::is_compressed(half) {
+            true => format!("{half:04x}"),
+            false => format!("{:08x}", inst.0),
+        };
+        let location = symbols.annotate(addr).map(|sym| format!(" {sym}")).unwrap_or_default();
+        println!("{addr:08x}{location}: unknown instruction {raw}");
+    }
+
+    if unknown > 0 {
+        anyhow::bail!("{unknown} of {total} instructions can not be decoded");
+    }
+    eprintln!("All {total} instructions decode.");
     Ok(())
 }
+
+/// Reports the exit code of a test, where 0 means it passed and anything else is the number of the failed test.
+fn report_exit_code(exit_code: uxlen) {
+    if exit_code == 0 {
+        eprintln!("Test Passed!");
+    } else {
+        eprintln!("Test {} Failed!", exit_code);
+    }
+}
+
+/// Parses an address given either in hexadecimal (prefixed with `0x`) or in decimal.
+fn parse_addr(s: &str) -> Result<uxlen, std::num::ParseIntError> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => uxlen::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+}
+
+/// Parses an instruction word in hexadecimal, optionally prefixed with `0x`.
+fn parse_word(s: &str) -> Result<u32, std::num::ParseIntError> {
+    let hex = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")).unwrap_or(s);
+    u32::from_str_radix(hex, 16)
+}
+
+/// Parses an initial register value given as `name=value`, where the value is parsed like [parse_addr].
+fn parse_register(s: &str) -> Result<(Reg, uxlen), String> {
+    let (name, value) = s.split_once('=').ok_or("expected `name=value`")?;
+    let reg = Reg::from_name(name).ok_or_else(|| format!("unknown register '{name}'"))?;
+    let value = parse_addr(value).map_err(|err| format!("invalid value: {err}"))?;
+    Ok((reg, value))
+}
+
+/// Parses an address range given as `start:len`, where both are parsed like [parse_addr].
+fn parse_range(s: &str) -> Result<(uxlen, usize), String> {
+    let (start, len) = s.split_once(':').ok_or("expected `start:len`")?;
+    let start = parse_addr(start).map_err(|err| format!("invalid start address: {err}"))?;
+    let len = parse_addr(len).map_err(|err| format!("invalid length: {err}"))?;
+    Ok((start, len as usize))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: uxlen = 0x8000_0000;
+
+    /// Creates a CPU running the given instruction words from a ROM at [BASE].
+    fn cpu(words: &[u32]) -> CpuBuilder<'static> {
+        let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+        let end = BASE + bytes.len() as uxlen;
+        Cpu::builder(Rom::from_vec(bytes, BASE, end))
+    }
+
+    /// Builds a little-endian RV32 ELF executable with one `PT_LOAD` segment per
+    /// `(vaddr, data, memsz)` triple.
+    fn elf(entry: u32, segments: &[(u32, &[u8], u32)]) -> Vec<u8> {
+        let phoff = 52;
+        let mut offset = phoff + 32 * segments.len() as u32;
+        let mut bytes = vec![0x7f, b'E', b'L', b'F', 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        bytes.extend(2u16.to_le_bytes()); // e_type: ET_EXEC
+        bytes.extend(0xf3u16.to_le_bytes()); // e_machine: EM_RISCV
+        bytes.extend(1u32.to_le_bytes()); // e_version
+        bytes.extend(entry.to_le_bytes());
+        bytes.extend(phoff.to_le_bytes());
+        bytes.extend(0u32.to_le_bytes()); // e_shoff
+        bytes.extend(0u32.to_le_bytes()); // e_flags
+        for half in [52u16, 32, segments.len() as u16, 40, 0, 0] {
+            bytes.extend(half.to_le_bytes());
+        }
+        for &(vaddr, data, memsz) in segments {
+            for word in [PT_LOAD, offset, vaddr, vaddr, data.len() as u32, memsz, 0b111, 4] {
+                bytes.extend(word.to_le_bytes());
+            }
+            offset += data.len() as u32;
+        }
+        for &(_, data, _) in segments {
+            bytes.extend(data);
+        }
+        bytes
+    }
+
+    /// Appends a section header table to an ELF file built with [elf], with an empty `SHT_NOBITS`
+    /// section of 8 bytes at each of the given `(name, addr)` pairs.
+    fn with_sections(mut bytes: Vec<u8>, sections: &[(&str, u32)]) -> Vec<u8> {
+        let mut names = vec![0];
+        let mut name_offsets = vec![];
+        for (name, _) in sections {
+            name_offsets.push(names.len() as u32);
+            names.extend(name.bytes().chain([0]));
+        }
+        let strtab_offset = bytes.len() as u32;
+        bytes.extend(&names);
+
+        let shoff = bytes.len() as u32;
+        bytes[32..36].copy_from_slice(&shoff.to_le_bytes());
+        bytes[48..50].copy_from_slice(&(sections.len() as u16 + 2).to_le_bytes()); // e_shnum
+        bytes[50..52].copy_from_slice(&1u16.to_le_bytes()); // e_shstrndx
+        bytes.extend([0; 40]);
+        // The section name string table itself, which is left unnamed.
+        for word in [0, 3, 0, 0, strtab_offset, names.len() as u32, 0, 0, 1, 0] {
+            bytes.extend(word.to_le_bytes());
+        }
+        for (&(_, addr), name) in sections.iter().zip(name_offsets) {
+            for word in [name, 8, 0b11, addr, 0, 8, 0, 0, 8, 0] {
+                bytes.extend(word.to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn validate_reports_unknown_instructions() {
+        let text: Vec<u8> = [
+            0x00000013u32, // addi x0, x0, 0
+            0xffffffff,    // unknown
+        ]
+        .iter()
+        .flat_map(|word| word.to_le_bytes())
+        .collect();
+        let program = load_elf(&elf(BASE, &[(BASE, &text, 8)])).unwrap();
+        assert_eq!(program.code, vec![BASE..BASE + 8]);
+
+        let rom = program.image.into_rom().unwrap();
+        let err = validate(&rom, &program.code, &program.symbols).unwrap_err();
+        assert_eq!(err.to_string(), "1 of 2 instructions can not be decoded");
+        // Only the given ranges are decoded.
+        let head = BASE..BASE + 4;
+        validate(&rom, std::slice::from_ref(&head), &program.symbols).unwrap();
+    }
+
+    #[test]
+    fn linux_heap_must_fit_between_the_program_and_the_stack() {
+        assert_eq!(linux_heap(0x8000_1000, None).unwrap(), 0x8000_1000..0x8400_1000);
+
+        let err = linux_heap(0xffff_0000, None).unwrap_err();
+        assert_eq!(err.to_string(), "The heap does not fit in the address space after the program");
+        assert!(linux_heap(1 << 32, None).is_err());
+
+        let err =
+            linux_heap((LINUX_STACK_TOP - LINUX_STACK_SIZE - 0x1000) as u64, None).unwrap_err();
+        assert_eq!(err.to_string(), "The heap at 0xbf7ff000 overlaps the stack");
+
+        let err = linux_heap(0x8000_0000, Some(0x8100_0000)).unwrap_err();
+        assert_eq!(err.to_string(), "The heap at 0x80000000 overlaps the UART at 0x81000000");
+    }
+
+    #[test]
+    fn entry_can_be_a_symbol_or_an_address() {
+        let symbols =
+            BTreeMap::from([("_start".to_string(), BASE), ("func".to_string(), BASE + 8)]);
+        assert_eq!(resolve_addr("func", &symbols).unwrap(), BASE + 8);
+        assert_eq!(resolve_addr("0x80000004", &symbols).unwrap(), BASE + 4);
+        let err = resolve_addr("missing", &symbols).unwrap_err();
+        assert_eq!(err.to_string(), "Could not find symbol 'missing' in the program");
+
+        let mut cpu = cpu(&[
+            0x00100513, // addi x10, x0, 1
+            0x00200513, // addi x10, x0, 2
+            0x00300593, // addi x11, x0, 3
+        ])
+        .entry(resolve_addr("func", &symbols).unwrap())
+        .build()
+        .unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.pc(), BASE + 12);
+        assert_eq!((cpu.registers()[10], cpu.registers()[11]), (0, 3));
+    }
+
+    #[test]
+    fn maps_every_loadable_segment_separately() {
+        let text: Vec<u8> = [
+            0x00300293u32, // addi x5, x0, 3
+            0x01e29293,    // slli x5, x5, 30
+            0x0002a503,    // lw   x10, 0(x5)
+            0x0042a583,    // lw   x11, 4(x5)
+        ]
+        .iter()
+        .flat_map(|word| word.to_le_bytes())
+        .collect();
+        let data = 0x1234_5678u32.to_le_bytes();
+        let bytes = elf(BASE, &[(BASE, &text, 16), (0xc000_0000, &data, 8)]);
+
+        let program = load_elf(&bytes).unwrap();
+        assert_eq!((program.image.start_addr(), program.image.size()), (BASE, 16));
+        assert_eq!(program.segments.len(), 1);
+        assert_eq!(
+            (program.segments[0].start_addr(), program.segments[0].size()),
+            (0xc000_0000, 8)
+        );
+
+        let rom = program.image.into_rom().unwrap();
+        let mut cpu = map_segments(Cpu::builder(rom), program.segments).unwrap().build().unwrap();
+        cpu.run().unwrap();
+        assert_eq!(cpu.registers()[10], 0x1234_5678);
+        assert_eq!(cpu.registers()[11], 0);
+    }
+
+    #[test]
+    fn rejects_overlapping_loadable_segments() {
+        let bytes = elf(BASE, &[(BASE, &[0; 16], 16), (BASE + 8, &[0; 16], 16)]);
+        assert!(load_elf(&bytes).is_err());
+    }
+
+    #[test]
+    fn finds_tohost_by_its_section_without_the_symbol() {
+        let text = 0x00000013u32.to_le_bytes(); // addi x0, x0, 0
+        let bytes = elf(BASE, &[(BASE, &text, 0x1008)]);
+        assert_eq!(load_elf(&bytes).unwrap().tohost, None);
+
+        let bytes = with_sections(bytes, &[(".text", BASE), (".tohost", BASE + 0x1000)]);
+        let program = load_elf(&bytes).unwrap();
+        assert!(program.symbols.is_empty());
+        assert_eq!(program.tohost, Some(BASE + 0x1000));
+    }
+
+    #[test]
+    fn parses_addresses_and_ranges() {
+        assert_eq!(parse_addr("0x80000000"), Ok(0x8000_0000));
+        assert_eq!(parse_addr("0X10"), Ok(0x10));
+        assert_eq!(parse_addr("16"), Ok(16));
+        assert!(parse_addr("0xg").is_err());
+
+        assert_eq!(parse_range("0x1000:16"), Ok((0x1000, 16)));
+        assert!(parse_range("0x1000").is_err());
+        assert!(parse_range("0x1000:x").is_err());
+    }
+
+    #[test]
+    fn parses_instruction_words() {
+        assert_eq!(parse_word("0x00100093"), Ok(0x0010_0093));
+        assert_eq!(parse_word("00200113"), Ok(0x0020_0113));
+        assert_eq!(parse_word("0X1"), Ok(1));
+        assert!(parse_word("0xg").is_err());
+    }
+
+    #[test]
+    fn runs_instruction_words_given_as_hex() {
+        let args = Args::try_parse_from(["tests", "--exec-hex", "0x00100093,0x00200113"]).unwrap();
+        assert_eq!(args.exec_hex, [0x0010_0093, 0x0020_0113]);
+
+        let bytes: Vec<u8> = args.exec_hex.iter().flat_map(|word| word.to_le_bytes()).collect();
+        let program = flat_program(BASE, &bytes).unwrap();
+        let mut cpu =
+            Cpu::builder(program.image.into_rom().unwrap()).entry(program.entry).build().unwrap();
+        cpu.run().unwrap();
+        assert_eq!(cpu.registers()[1], 1);
+        assert_eq!(cpu.registers()[2], 2);
+    }
+
+    #[test]
+    fn dumps_memory_stored_by_the_program() {
+        let mut cpu = cpu(&[
+            0x00100293, // addi x5, x0, 1
+            0x01c29293, // slli x5, x5, 28
+            0x12300313, // addi x6, x0, 0x123
+            0x0062a023, // sw   x6, 0(x5)
+            0x0062a423, // sw   x6, 8(x5)
+        ])
+        .ram(0x1000_0000, 0x100)
+        .build()
+        .unwrap();
+        cpu.run().unwrap();
+
+        let path = std::env::temp_dir().join(format!("dump-memory-{}.bin", std::process::id()));
+        dump_memory(&mut cpu, &path, Some((0x1000_0000, 12))).unwrap();
+        let dumped = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(dumped, [0x23, 0x01, 0, 0, 0, 0, 0, 0, 0x23, 0x01, 0, 0]);
+    }
+}
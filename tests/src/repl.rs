@@ -0,0 +1,162 @@
+use std::io::{self, BufRead, Write};
+
+use anyhow::Context;
+use emu::{
+    cpu::{Cpu, HaltReason},
+    disasm::disassemble,
+    uxlen,
+};
+
+use crate::parse_addr;
+
+/// The number of instructions `disasm` prints when no count is given.
+const DEFAULT_DISASM_COUNT: usize = 8;
+
+const HELP: &str = "\
+Commands:
+  step [n]            Executes the next n instructions, or one.
+  continue            Runs until a breakpoint, a watchpoint or the end of the program.
+  regs                Prints the program counter and the registers.
+  mem <addr> <len>    Prints len bytes of memory starting at addr.
+  break <addr>        Adds a breakpoint at addr.
+  disasm <addr> [n]   Disassembles n instructions starting at addr, or 8.
+  help                Prints this message.
+  quit                Stops the emulator.";
+
+/// Reads debugger commands from stdin and runs them on the CPU until the input ends or `quit` is entered.
+pub fn run(cpu: &mut Cpu) -> anyhow::Result<()> {
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("(emu) ");
+        io::stdout().flush().context("Failed to write prompt")?;
+
+        let Some(line) = lines.next() else { break };
+        let line = line.context("Failed to read command")?;
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else { continue };
+        let args: Vec<&str> = words.collect();
+
+        let result = match command {
+            "step" | "s" => step(cpu, &args),
+            "continue" | "c" => run_until_stop(cpu),
+            "regs" | "r" => {
+                println!("pc: {:#010x}", cpu.pc());
+                println!("{}", cpu.registers().dump());
+                Ok(())
+            }
+            "mem" | "m" => print_memory(cpu, &args),
+            "break" | "b" => parse_arg(&args, 0).map(|addr| cpu.add_breakpoint(addr)),
+            "disasm" | "d" => print_disassembly(cpu, &args),
+            "help" | "h" => {
+                println!("{HELP}");
+                Ok(())
+            }
+            "quit" | "q" => break,
+            _ => Err(anyhow::anyhow!("Unknown command '{command}', enter 'help' for a list")),
+        };
+
+        if let Err(err) = result {
+            println!("Error: {err:#}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Executes the given number of instructions, printing each one, and stops early if the CPU stops.
+fn step(cpu: &mut Cpu, args: &[&str]) -> anyhow::Result<()> {
+    let count = if args.is_empty() { 1 } else { parse_arg(args, 0)? };
+    for _ in 0..count {
+        if !cpu.running() {
+            println!("The program has stopped");
+            break;
+        }
+
+        let pc = cpu.pc();
+        let outcome = cpu.step()?;
+        match (outcome.instruction, outcome.trap, outcome.interrupt) {
+            (_, _, Some(interrupt)) => println!("{pc:#010x}: interrupt {interrupt:?}"),
+            (_, Some(trap), _) => println!("{pc:#010x}: trap {:?}", trap.cause),
+            (Some(inst), _, _) => println!("{pc:#010x}: {inst:?}"),
+            (None, None, None) => println!("{pc:#010x}"),
+        }
+    }
+    Ok(())
+}
+
+/// Runs the CPU and reports why it stopped.
+fn run_until_stop(cpu: &mut Cpu) -> anyhow::Result<()> {
+    match cpu.run()? {
+        HaltReason::Breakpoint(addr) => println!("Breakpoint at {addr:#010x}"),
+        HaltReason::Watchpoint(_) => println!("Stopped at {:#010x}", cpu.pc()),
+        HaltReason::InstructionLimit(pc) => println!("Stopped at {pc:#010x}"),
+        reason => println!("The program has stopped: {reason}"),
+    }
+    Ok(())
+}
+
+/// Prints memory as hex, 16 bytes per line.
+fn print_memory(cpu: &mut Cpu, args: &[&str]) -> anyhow::Result<()> {
+    let addr = parse_arg(args, 0)?;
+    let len = parse_arg(args, 1)?;
+    let bytes = cpu.read_mem(addr, len as usize)?;
+    for (i, line) in bytes.chunks(16).enumerate() {
+        let hex: Vec<String> = line.iter().map(|byte| format!("{byte:02x}")).collect();
+        println!("{:#010x}: {}", addr.wrapping_add(i as uxlen * 16), hex.join(" "));
+    }
+    Ok(())
+}
+
+/// Prints the instructions starting at the given address.
+fn print_disassembly(cpu: &Cpu, args: &[&str]) -> anyhow::Result<()> {
+    let addr = parse_arg(args, 0)?;
+    let count = match args.len() {
+        1 => DEFAULT_DISASM_COUNT,
+        _ => parse_arg(args, 1)? as usize,
+    };
+    for (addr, inst, text) in disassemble(cpu.rom()).skip_while(|(a, _, _)| *a < addr).take(count) {
+        println!("{addr:8x}:\t{:08x}\t{text}", inst.0);
+    }
+    Ok(())
+}
+
+/// Parses the argument at the given index as an address or number.
+fn parse_arg(args: &[&str], ix: usize) -> anyhow::Result<uxlen> {
+    let arg = args.get(ix).with_context(|| format!("Missing argument {}", ix + 1))?;
+    parse_addr(arg).with_context(|| format!("Invalid number '{arg}'"))
+}
+
+#[cfg(test)]
+mod tests {
+    use emu::rom::Rom;
+
+    use super::*;
+
+    #[test]
+    fn parses_arguments() {
+        assert_eq!(parse_arg(&["0x10", "8"], 0).unwrap(), 0x10);
+        assert_eq!(parse_arg(&["0x10", "8"], 1).unwrap(), 8);
+        assert_eq!(parse_arg(&["0x10"], 1).unwrap_err().to_string(), "Missing argument 2");
+        assert_eq!(parse_arg(&["zz"], 0).unwrap_err().to_string(), "Invalid number 'zz'");
+    }
+
+    #[test]
+    fn steps_the_given_number_of_instructions() {
+        let bytes: Vec<u8> = [
+            0x00100293u32, // addi x5, x0, 1
+            0x00200313,    // addi x6, x0, 2
+            0x00300393,    // addi x7, x0, 3
+        ]
+        .iter()
+        .flat_map(|word| word.to_le_bytes())
+        .collect();
+        let mut cpu = Cpu::builder(Rom::from_vec(bytes, 0x8000_0000, 0x8000_000c)).build().unwrap();
+
+        step(&mut cpu, &["2"]).unwrap();
+        assert_eq!(cpu.pc(), 0x8000_0008);
+        assert_eq!((cpu.registers()[5], cpu.registers()[6], cpu.registers()[7]), (1, 2, 0));
+        assert!(step(&mut cpu, &["x"]).is_err());
+        assert_eq!(cpu.pc(), 0x8000_0008);
+    }
+}
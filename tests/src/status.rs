@@ -0,0 +1,75 @@
+use emu::{reg::Registers, uxlen};
+
+/// The result a test program reports through an ECALL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestStatus {
+    Passed,
+    /// The test with the given number failed.
+    Failed(uxlen),
+}
+
+/// How a test program reports its result through an ECALL.
+///
+/// This is implemented for closures, so a different convention can be passed
+/// to the test runner without defining a type for it.
+pub trait StatusConvention {
+    /// Decodes the status the program reports with the ECALL it is executing,
+    /// or `None` if the ECALL is not a status report.
+    fn decode(&self, regs: &Registers) -> Option<TestStatus>;
+}
+
+impl<F: Fn(&Registers) -> Option<TestStatus>> StatusConvention for F {
+    fn decode(&self, regs: &Registers) -> Option<TestStatus> {
+        self(regs)
+    }
+}
+
+/// The convention of the `riscv-tests` suite.
+///
+/// The status is reported with system call `0x5D` in `a7` and the status in `a0`, which is zero
+/// when the test passed, or `2 * n + 1` when test `n` failed.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RiscvTests;
+
+impl StatusConvention for RiscvTests {
+    fn decode(&self, regs: &Registers) -> Option<TestStatus> {
+        if regs.a7() != 0x5D {
+            return None;
+        }
+        match regs.a0() {
+            0 => Some(TestStatus::Passed),
+            status => Some(TestStatus::Failed((status - 1) / 2)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use emu::reg::Reg;
+
+    use super::*;
+
+    fn regs(a0: uxlen, a7: uxlen) -> Registers {
+        let mut regs = Registers::new(0);
+        regs[Reg::A0] = a0;
+        regs[Reg::A7] = a7;
+        regs
+    }
+
+    #[test]
+    fn decodes_riscv_tests_status() {
+        assert_eq!(RiscvTests.decode(&regs(0, 0x5D)), Some(TestStatus::Passed));
+        assert_eq!(RiscvTests.decode(&regs(7, 0x5D)), Some(TestStatus::Failed(3)));
+        assert_eq!(RiscvTests.decode(&regs(0, 64)), None);
+    }
+
+    #[test]
+    fn closures_are_conventions() {
+        let convention = |regs: &Registers| match regs[Reg::A7] {
+            1 => Some(TestStatus::Failed(regs[Reg::A0])),
+            _ => None,
+        };
+        assert_eq!(convention.decode(&regs(2, 1)), Some(TestStatus::Failed(2)));
+        assert_eq!(convention.decode(&regs(2, 0x5D)), None);
+    }
+}
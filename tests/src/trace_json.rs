@@ -0,0 +1,102 @@
+use std::{
+    cell::RefCell,
+    fs,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::Context;
+use emu::{
+    cpu::{Cpu, HistoryEntry},
+    inst::Instruction,
+    uxlen,
+};
+use serde::Serialize;
+
+/// One line of the JSON trace, describing a committed instruction.
+#[derive(Debug, Serialize)]
+struct Record {
+    pc: uxlen,
+    raw: u32,
+    mnemonic: String,
+    /// The ABI name of the register the instruction changed, if any.
+    rd: Option<&'static str>,
+    old: Option<uxlen>,
+    new: Option<uxlen>,
+}
+
+impl Record {
+    fn new(inst: &Instruction, entry: &HistoryEntry) -> Self {
+        let changed = entry.rd.is_some();
+        Self {
+            pc: entry.pc,
+            raw: entry.raw,
+            mnemonic: inst.kind().to_string(),
+            rd: entry.rd.map(|reg| reg.abi_name()),
+            old: changed.then_some(entry.old),
+            new: changed.then_some(entry.new),
+        }
+    }
+}
+
+/// Creates a commit callback that writes every committed instruction
+/// to the file at `path` as a JSON object on its own line.
+pub fn writer(path: &Path) -> anyhow::Result<impl Fn(&Cpu, &Instruction, &HistoryEntry) + use<>> {
+    let file = fs::File::create(path)
+        .with_context(|| format!("Failed to create JSON trace at '{}'", path.display()))?;
+
+    // NOTE: The commit callback can not return errors, so the writer is dropped
+    //       after reporting the first one, instead of reporting it for every instruction.
+    let writer = RefCell::new(Some(BufWriter::new(file)));
+    Ok(move |_: &Cpu, inst: &Instruction, entry: &HistoryEntry| {
+        let mut writer = writer.borrow_mut();
+        let Some(out) = writer.as_mut() else { return };
+        let result = serde_json::to_writer(&mut *out, &Record::new(inst, entry))
+            .map_err(std::io::Error::from)
+            .and_then(|()| writeln!(out));
+        if let Err(err) = result {
+            eprintln!("Failed to write JSON trace: {err}");
+            *writer = None;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use emu::rom::Rom;
+
+    use super::*;
+
+    #[test]
+    fn writes_a_record_per_committed_instruction() {
+        let bytes: Vec<u8> = [
+            0x00500013u32, // addi x0, x0, 5
+            0x00500293,    // addi x5, x0, 5
+        ]
+        .iter()
+        .flat_map(|word| word.to_le_bytes())
+        .collect();
+        let path = std::env::temp_dir().join(format!("trace-{}.jsonl", std::process::id()));
+        let mut cpu = Cpu::builder(Rom::from_vec(bytes, 0x8000_0000, 0x8000_0008))
+            .on_commit(Box::new(writer(&path).unwrap()))
+            .build()
+            .unwrap();
+        cpu.run().unwrap();
+        // Dropping the CPU flushes the trace.
+        drop(cpu);
+
+        let trace = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = trace.lines().collect();
+        // The write to `zero` is discarded, so it does not change a register.
+        assert_eq!(
+            lines[0],
+            r#"{"pc":2147483648,"raw":5242899,"mnemonic":"addi","rd":null,"old":null,"new":null}"#
+        );
+        assert_eq!(
+            lines[1],
+            r#"{"pc":2147483652,"raw":5243539,"mnemonic":"addi","rd":"t0","old":0,"new":5}"#
+        );
+        assert_eq!(lines.len(), 2);
+    }
+}